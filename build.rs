@@ -0,0 +1,81 @@
+//! Embeds the git commit, build timestamp, enabled features and compiled-in providers into the
+//! binary.
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Builds without a git checkout (e.g. in nix) can pass the commit explicitly
+    let commit = std::env::var("GIT_COMMIT").ok().or_else(git_commit);
+    println!(
+        "cargo:rustc-env=BUILD_GIT_COMMIT={}",
+        commit.as_deref().unwrap_or("unknown")
+    );
+
+    // Respect reproducible builds
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|it| it.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|it| it.as_secs())
+                .unwrap_or_default()
+        });
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={timestamp}");
+
+    let mut features = std::env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_lowercase))
+        .map(|it| it.replace('_', "-"))
+        .collect::<Vec<_>>();
+    features.sort();
+    println!("cargo:rustc-env=BUILD_FEATURES={}", features.join(","));
+    // The memory provider is always compiled in
+    let providers = features
+        .iter()
+        .filter_map(|it| it.strip_prefix("provider-"))
+        .chain(["memory"])
+        .collect::<Vec<_>>();
+    println!("cargo:rustc-env=BUILD_PROVIDERS={}", providers.join(", "));
+
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    for path in git_watch_paths() {
+        println!("cargo:rerun-if-changed={path}");
+    }
+}
+
+/// The files changing on a commit or checkout: `HEAD`, the branch it points to and
+/// `packed-refs`, which holds the branch instead once git packs its refs. Missing files are left
+/// out, as cargo would rerun the build script on every build otherwise.
+fn git_watch_paths() -> Vec<String> {
+    let mut paths = vec![".git/HEAD".to_string(), ".git/packed-refs".to_string()];
+    if let Ok(head) = std::fs::read_to_string(".git/HEAD")
+        && let Some(reference) = head.trim().strip_prefix("ref: ")
+    {
+        paths.push(format!(".git/{reference}"));
+    }
+    paths.retain(|it| std::path::Path::new(it).exists());
+    paths
+}
+
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    let dirty = Command::new("git")
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .output()
+        .is_ok_and(|it| !it.stdout.is_empty());
+
+    Some(if dirty {
+        format!("{commit}-dirty")
+    } else {
+        commit
+    })
+}
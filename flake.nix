@@ -39,6 +39,15 @@
             ];
             config = {
               Cmd = [ "${self.packages.${system}.default}/bin/speedport-custom-dyndns" ];
+              Healthcheck = {
+                Test = [
+                  "CMD"
+                  "${self.packages.${system}.default}/bin/speedport-custom-dyndns"
+                  "healthcheck"
+                ];
+                Interval = 30000000000;
+                Timeout = 5000000000;
+              };
               ExposedPorts = {
                 "3000/tcp" = { };
               };
@@ -0,0 +1,107 @@
+//! Per-request access logging.
+//!
+//! Every request gets a request ID, taken from `X-Request-Id` or freshly generated. It is
+//! recorded in a span wrapping the whole request, so all events logged while handling it carry
+//! the ID, and echoed back in the response.
+
+use crate::auth::{client_ip, query_credentials};
+use crate::types::AppState;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use derive_more::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+use tracing::{Instrument, Level, event, info_span};
+
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+/// Longer incoming request IDs are replaced, so clients can not bloat every log line.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// The IP of the client, honoring trusted proxies. Available as a request extension.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// The ID of the current request, available as a request extension.
+#[derive(Debug, Clone, Display)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    fn from_request(req: &Request) -> Self {
+        let incoming = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|it| it.to_str().ok())
+            .filter(|it| !it.is_empty() && it.len() <= MAX_REQUEST_ID_LENGTH)
+            .filter(|it| it.chars().all(|c| c.is_ascii_graphic()));
+
+        match incoming {
+            Some(id) => Self(id.to_string()),
+            None => Self(uuid::Uuid::new_v4().to_string()),
+        }
+    }
+}
+
+/// Logs `req` once it completed. `base_path` is the path the routes are nested in, if any, see
+/// [`DynDnsServerBuilder::base_path`](crate::DynDnsServerBuilder::base_path).
+pub async fn log_requests(
+    State((state, base_path)): State<(AppState, Option<String>)>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let request_id = RequestId::from_request(&req);
+    let client_ip = client_ip(&req, addr.ip(), &state.auth.trusted_proxies);
+    let method = req.method().clone();
+    let path = redacted_path(&req);
+    let route = req.uri().path();
+    let route = match &base_path {
+        Some(base_path) => route.strip_prefix(base_path.as_str()).unwrap_or(route),
+        None => route,
+    };
+    // Health checks run periodically and would drown out everything else
+    let health_check = route == "/healthz" || route == "/readyz";
+
+    let span = info_span!("request", request_id = %request_id);
+    req.extensions_mut().insert(request_id.clone());
+    req.extensions_mut().insert(ClientIp(client_ip));
+
+    let mut response = next.run(req).instrument(span.clone()).await;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    let _guard = span.enter();
+    let status = response.status().as_u16();
+    let latency_ms = start.elapsed().as_millis();
+    if health_check {
+        event!(Level::DEBUG, %method, %path, status, latency_ms, %client_ip, "request completed");
+    } else {
+        event!(Level::INFO, %method, %path, status, latency_ms, %client_ip, "request completed");
+    }
+
+    response
+}
+
+/// The path and query of `req`, with the values of credential parameters replaced.
+fn redacted_path(req: &Request) -> String {
+    let path = req.uri().path();
+    let Some(query) = req.uri().query() else {
+        return path.to_string();
+    };
+
+    let query = query_credentials(query)
+        .map(|(pair, credential)| match credential {
+            Some(_) => {
+                let name = pair.split_once('=').map_or(pair, |(name, _)| name);
+                format!("{name}=redacted")
+            }
+            None => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{path}?{query}")
+}
@@ -0,0 +1,530 @@
+//! Endpoints for operators, guarded by the admin token instead of the client credentials.
+
+use crate::dyndns::UpdateQuery;
+use crate::ip_update::ParsedIpUpdate;
+use crate::ownership::Ownership;
+use crate::provider::chaos::{ChaosError, ChaosProvider, Operation};
+use crate::provider::{DnsProvider, DnsRecordType, Origin, RecordId, canonical_hostname};
+use crate::types::{AppState, DnsConfig};
+use crate::update::{PlannedChange, UpdateError};
+use axum::Json;
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use futures_util::stream;
+use jiff::Timestamp;
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, report};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::convert::Infallible;
+use std::str::FromStr;
+use tracing::{info, instrument, warn};
+
+pub mod zone_file;
+
+/// A record as returned by `GET /admin/records` and printed by `list-records --format json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListedRecord {
+    pub provider: &'static str,
+    pub origin: Origin,
+    #[serde(rename = "type")]
+    pub record_type: DnsRecordType,
+    pub name: String,
+    pub content: String,
+    pub ttl: Option<u32>,
+    pub id: RecordId,
+}
+
+impl ListedRecord {
+    fn sort_key(&self) -> (&str, &str, &str, &DnsRecordType, &str) {
+        (
+            self.provider,
+            self.origin.as_str(),
+            &self.name,
+            &self.record_type,
+            &self.content,
+        )
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RecordFilter {
+    /// Lists this origin instead of the configured one.
+    pub origin: Option<String>,
+    /// Only lists records with this fully qualified name.
+    pub name: Option<String>,
+}
+
+/// Lists the records of every provider, after applying the origin mappings to the filter.
+pub async fn list_records(
+    dns: &DnsConfig,
+    filter: &RecordFilter,
+) -> Result<Vec<ListedRecord>, Report> {
+    let mut listed = Vec::new();
+    for provider in &dns.dns_providers {
+        let provider = provider.as_ref();
+        let origin = match &filter.origin {
+            Some(origin) => dns.map_origin(Origin::parse(origin)?, provider),
+            None => dns.origin_for(provider),
+        };
+        let name = filter
+            .name
+            .as_ref()
+            .map(|it| dns.map_hostname(it, provider));
+        let records = provider
+            .list_records(&origin)
+            .await
+            .context("Failed to list records")
+            .attach(format!("Provider: {}", provider.name()))
+            .attach(format!("origin: '{origin}'"))?;
+        listed.extend(
+            records
+                .into_iter()
+                .filter(|it| name.as_ref().is_none_or(|name| it.name == *name))
+                .map(|it| ListedRecord {
+                    provider: provider.name(),
+                    origin: origin.clone(),
+                    record_type: it.typ,
+                    name: it.name,
+                    content: it.content,
+                    ttl: it.ttl,
+                    id: it.id,
+                }),
+        );
+    }
+    Ok(listed)
+}
+
+/// Lists the records of all providers, optionally filtered by `origin` and `name`.
+#[instrument(name = "admin_records", skip_all)]
+pub(crate) async fn records(
+    State(state): State<AppState>,
+    Query(filter): Query<RecordFilter>,
+) -> Response {
+    match list_records(&state.dns, &filter).await {
+        Ok(records) => Json(records).into_response(),
+        Err(e) => list_error(e),
+    }
+}
+
+fn list_error(e: Report) -> Response {
+    warn!(error = %e, "failed to list records");
+    let error = e.format_current_context().to_string();
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "error": error })),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// The records as returned by `GET /admin/records`.
+    #[default]
+    Json,
+    /// A zone file, see [`zone_file`].
+    Bind,
+}
+
+/// Exports the records of all providers in the configured origins, sorted so exports can be
+/// diffed. The records are rendered while the response is sent, one at a time.
+#[instrument(name = "admin_export", skip_all)]
+pub(crate) async fn export(
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let mut records = match list_records(&state.dns, &RecordFilter::default()).await {
+        Ok(records) => records,
+        Err(e) => return list_error(e),
+    };
+    records.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+    info!(records = records.len(), format = ?query.format, "exporting zone");
+
+    let (content_type, chunks): (_, Box<dyn Iterator<Item = String> + Send>) = match query.format {
+        ExportFormat::Json => {
+            let end = if records.is_empty() { "[]\n" } else { "\n]\n" };
+            let chunks = records.into_iter().enumerate().map(|(index, record)| {
+                let separator = if index == 0 { "[\n" } else { ",\n" };
+                let record = serde_json::to_string(&record).unwrap_or_default();
+                format!("{separator}{record}")
+            });
+            (
+                "application/json",
+                Box::new(chunks.chain([end.to_string()])),
+            )
+        }
+        ExportFormat::Bind => {
+            // A new block starts whenever the provider or origin changes
+            let mut block = None;
+            let chunks = records.into_iter().map(move |record| {
+                let current = Some((record.provider, record.origin.clone()));
+                let mut chunk = String::new();
+                if block != current {
+                    chunk = zone_file::header(record.provider, &record.origin);
+                    block = current;
+                }
+                chunk + &zone_file::record_line(&record)
+            });
+            ("text/plain; charset=utf-8", Box::new(chunks))
+        }
+    };
+    (
+        [(header::CONTENT_TYPE, content_type)],
+        Body::from_stream(stream::iter(chunks.map(Ok::<_, Infallible>))),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Serialize)]
+struct Plan {
+    hostname: String,
+    changes: Vec<PlannedChange>,
+}
+
+/// Shows what an update with the same parameters as `/nic/update` would change, without
+/// writing anything.
+#[instrument(name = "admin_plan", skip_all)]
+pub(crate) async fn plan(
+    State(state): State<AppState>,
+    Query(query): Query<UpdateQuery>,
+) -> Response {
+    info!(query = ?query, "planning update");
+    let hostname = match canonical_hostname(&query.hostname) {
+        Ok(hostname) => hostname,
+        Err(e) => {
+            let error = e.format_current_context().to_string();
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+        }
+    };
+    let ip = match ParsedIpUpdate::from_str(&query.myip) {
+        Ok(ip) => ip,
+        Err(e) => {
+            let error = format!("invalid 'myip' parameter: {e}");
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+        }
+    };
+
+    match state.updates.plan(&hostname, &ip).await {
+        Ok(changes) => Json(Plan { hostname, changes }).into_response(),
+        Err(e) => {
+            let status = match e {
+                UpdateError::NotInOrigin { .. } | UpdateError::NotOwned { .. } => {
+                    StatusCode::FORBIDDEN
+                }
+                UpdateError::NotReady => StatusCode::SERVICE_UNAVAILABLE,
+                UpdateError::Provider { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (status, Json(json!({ "error": e.to_string() }))).into_response()
+        }
+    }
+}
+
+/// Changes the fault injection of the providers wrapped with `chaos:`, see
+/// [`ChaosProvider`](crate::provider::chaos::ChaosProvider).
+#[derive(Debug, Default, Deserialize)]
+pub struct ChaosRequest {
+    /// Only changes this provider instead of all wrapped ones.
+    pub provider: Option<String>,
+    /// Fails this many of the next calls.
+    pub fail_next: Option<u32>,
+    /// The calls failed by `fail_next`, all of them if unset.
+    pub operation: Option<Operation>,
+    /// The fault injected by `fail_next`, the first configured one if unset.
+    pub error: Option<ChaosError>,
+    pub failure_rate: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Lockout {
+    /// `ip:<client IP>` or `user:<username>`.
+    key: String,
+    locked_until: Timestamp,
+}
+
+/// Lists the client IPs and usernames locked out after too many failed logins.
+#[instrument(name = "admin_lockouts", skip_all)]
+pub(crate) async fn lockouts(State(state): State<AppState>) -> Json<Vec<Lockout>> {
+    let lockouts = state.auth.lockouts.active(Timestamp::now());
+    Json(
+        lockouts
+            .into_iter()
+            .map(|(key, locked_until)| Lockout { key, locked_until })
+            .collect(),
+    )
+}
+
+/// Shows the fault injection state of every provider wrapped with `chaos:`.
+#[instrument(name = "admin_chaos", skip_all)]
+pub(crate) async fn chaos(State(state): State<AppState>) -> Response {
+    chaos_snapshots(&state.dns, None)
+}
+
+/// Scripts faults or changes the failure rate, answering with the new state.
+#[instrument(name = "admin_chaos_update", skip_all)]
+pub(crate) async fn update_chaos(
+    State(state): State<AppState>,
+    Query(request): Query<ChaosRequest>,
+) -> Response {
+    if request
+        .failure_rate
+        .is_some_and(|it| !(0.0..=1.0).contains(&it))
+    {
+        let error = "'failure_rate' must be between 0 and 1";
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+    }
+    info!(request = ?request, "changing fault injection");
+    for provider in chaos_providers(&state.dns, request.provider.as_deref()) {
+        if let Some(count) = request.fail_next.filter(|it| *it > 0) {
+            let error = request.error.unwrap_or_else(|| provider.default_error());
+            provider.fail_next(request.operation, count, error);
+        }
+        if let Some(failure_rate) = request.failure_rate {
+            provider.set_failure_rate(failure_rate);
+        }
+    }
+    chaos_snapshots(&state.dns, request.provider.as_deref())
+}
+
+/// Drops all scripted faults and stops random failures.
+#[instrument(name = "admin_chaos_reset", skip_all)]
+pub(crate) async fn reset_chaos(State(state): State<AppState>) -> Response {
+    info!("resetting fault injection");
+    for provider in chaos_providers(&state.dns, None) {
+        provider.reset();
+    }
+    chaos_snapshots(&state.dns, None)
+}
+
+fn chaos_providers<'a>(
+    dns: &'a DnsConfig,
+    name: Option<&'a str>,
+) -> impl Iterator<Item = &'a ChaosProvider> {
+    dns.dns_providers
+        .iter()
+        .filter_map(|it| it.chaos())
+        .filter(move |it| name.is_none_or(|name| it.name().eq_ignore_ascii_case(name)))
+}
+
+fn chaos_snapshots(dns: &DnsConfig, name: Option<&str>) -> Response {
+    let snapshots = chaos_providers(dns, name)
+        .map(ChaosProvider::snapshot)
+        .collect::<Vec<_>>();
+    if snapshots.is_empty() {
+        let error = "no matching provider is wrapped with 'chaos:'";
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": error }))).into_response();
+    }
+    Json(snapshots).into_response()
+}
+
+/// The hostnames to stamp with or release from ownership markers, see
+/// [`ownership`](crate::ownership).
+#[derive(Debug, Default, Deserialize)]
+pub struct OwnershipRequest {
+    /// Comma-separated hostnames.
+    pub hostnames: String,
+}
+
+/// What happened to the marker of one hostname at one provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum MarkerOutcome {
+    Created,
+    /// The marker was already there.
+    Exists,
+    /// The hostname has no address records at the provider, so it is not stamped.
+    NoRecords,
+    Removed,
+    /// There was no marker of this owner to remove.
+    Absent,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+struct MarkerResult {
+    provider: &'static str,
+    hostname: String,
+    marker: String,
+    outcome: MarkerOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Stamps the given existing hostnames with the ownership marker at every provider, so updates
+/// may write their records. Hostnames without address records are skipped.
+#[instrument(name = "admin_ownership_stamp", skip_all)]
+pub(crate) async fn stamp_ownership(
+    State(state): State<AppState>,
+    Query(request): Query<OwnershipRequest>,
+) -> Response {
+    info!(hostnames = %request.hostnames, "stamping ownership markers");
+    change_markers(&state.dns, &request, MarkerChange::Stamp).await
+}
+
+/// Deletes the ownership markers of the given hostnames at every provider, e.g. after deleting
+/// their records. Markers of other owners are kept.
+#[instrument(name = "admin_ownership_release", skip_all)]
+pub(crate) async fn release_ownership(
+    State(state): State<AppState>,
+    Query(request): Query<OwnershipRequest>,
+) -> Response {
+    info!(hostnames = %request.hostnames, "deleting ownership markers");
+    change_markers(&state.dns, &request, MarkerChange::Release).await
+}
+
+#[derive(Debug, Clone, Copy)]
+enum MarkerChange {
+    Stamp,
+    Release,
+}
+
+impl MarkerChange {
+    async fn apply(
+        self,
+        ownership: &Ownership,
+        provider: &(dyn DnsProvider + Send + Sync),
+        origin: &Origin,
+        hostname: &str,
+    ) -> Result<MarkerOutcome, Report> {
+        match self {
+            Self::Stamp => {
+                let records = provider.list_records(origin).await?;
+                if !records.iter().any(|it| it.name == hostname) {
+                    return Ok(MarkerOutcome::NoRecords);
+                }
+                Ok(match ownership.stamp(provider, origin, hostname).await? {
+                    true => MarkerOutcome::Created,
+                    false => MarkerOutcome::Exists,
+                })
+            }
+            Self::Release => Ok(match ownership.remove(provider, origin, hostname).await? {
+                0 => MarkerOutcome::Absent,
+                _ => MarkerOutcome::Removed,
+            }),
+        }
+    }
+}
+
+/// Applies `change` to every requested hostname at every provider, answering with the outcome
+/// of each. A failure does not stop the other hostnames.
+async fn change_markers(
+    dns: &DnsConfig,
+    request: &OwnershipRequest,
+    change: MarkerChange,
+) -> Response {
+    let Some(ownership) = &dns.ownership else {
+        let error = "ownership markers are disabled, set OWNERSHIP_ID to enable them";
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": error }))).into_response();
+    };
+    let hostnames = match request
+        .hostnames
+        .split(',')
+        .filter(|it| !it.trim().is_empty())
+        .map(canonical_hostname)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(hostnames) => hostnames,
+        Err(e) => {
+            let error = e.format_current_context().to_string();
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+        }
+    };
+    if hostnames.is_empty() {
+        let error = "'hostnames' must list at least one hostname";
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+    }
+
+    let mut results = Vec::new();
+    for provider in &dns.dns_providers {
+        let provider = provider.as_ref();
+        let origin = dns.origin_for(provider);
+        for hostname in &hostnames {
+            let mapped = dns.map_hostname(hostname, provider);
+            let outcome = if origin.is_subdomain(&mapped) {
+                change.apply(ownership, provider, &origin, &mapped).await
+            } else {
+                Err(report!("Hostname is not part of the origin")
+                    .attach(format!("origin: '{origin}'"))
+                    .into_dynamic())
+            };
+            let (outcome, error) = match outcome {
+                Ok(outcome) => (outcome, None),
+                Err(e) => {
+                    warn!(error = %e, domain = %mapped, "failed to change ownership marker");
+                    (
+                        MarkerOutcome::Failed,
+                        Some(e.format_current_context().to_string()),
+                    )
+                }
+            };
+            results.push(MarkerResult {
+                provider: provider.name(),
+                marker: Ownership::marker_name(&mapped),
+                hostname: mapped,
+                outcome,
+                error,
+            });
+        }
+    }
+    let status = if results.iter().any(|it| it.outcome == MarkerOutcome::Failed) {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(results)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::memory::MemoryProvider;
+    use crate::test_support::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn list_records_json_matches_golden_file() {
+        let provider = Arc::new(MemoryProvider::new(nas_records()));
+        let server = builder(&provider).build().unwrap();
+
+        let records = list_records(&server.state().dns, &RecordFilter::default())
+            .await
+            .unwrap();
+        // Printed like this by `list-records --format json`
+        let json = serde_json::to_string_pretty(&records).unwrap();
+
+        assert_eq!(
+            json.trim_end(),
+            include_str!("../tests/golden/list_records.json").trim_end()
+        );
+    }
+
+    #[tokio::test]
+    async fn list_records_filters_by_name() {
+        let mut records = nas_records();
+        records.push(record(
+            "other",
+            DnsRecordType::A,
+            "www.foobar.de",
+            "192.0.2.2",
+        ));
+        let provider = Arc::new(MemoryProvider::new(records));
+        let server = builder(&provider).build().unwrap();
+
+        let filter = RecordFilter {
+            origin: None,
+            name: Some("www.foobar.de".to_string()),
+        };
+        let records = list_records(&server.state().dns, &filter).await.unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id.0, "other");
+    }
+}
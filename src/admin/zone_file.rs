@@ -0,0 +1,113 @@
+//! Renders listed records as a BIND zone file, for `GET /admin/export?format=bind`.
+//!
+//! Every provider and origin gets its own block with a `$ORIGIN` and `$TTL`, the names in it are
+//! relative to the origin. The output can be loaded into a local BIND, or diffed before and after
+//! changes.
+
+use crate::admin::ListedRecord;
+use crate::provider::Origin;
+
+/// The TTL of records without one, which is what Cloudflare uses for "auto".
+pub const DEFAULT_TTL: u32 = 300;
+/// The TTL Cloudflare reports for "auto".
+const AUTOMATIC_TTL: u32 = 1;
+
+/// The lines starting the block of the records of `provider` in `origin`.
+pub fn header(provider: &str, origin: &Origin) -> String {
+    format!("; provider: {provider}\n$ORIGIN {origin}.\n$TTL {DEFAULT_TTL}\n")
+}
+
+/// The line of `record`, relative to the origin of the last [`header`].
+pub fn record_line(record: &ListedRecord) -> String {
+    let name = relative_name(&record.origin, &record.name);
+    match record.ttl.filter(|it| *it != AUTOMATIC_TTL) {
+        Some(ttl) => format!(
+            "{name}\t{ttl}\tIN\t{}\t{}\n",
+            record.record_type, record.content
+        ),
+        None => format!("{name}\t\tIN\t{}\t{}\n", record.record_type, record.content),
+    }
+}
+
+/// `name` relative to `origin`: `@` for the origin itself, and an absolute name with a trailing
+/// dot for names outside of it.
+fn relative_name(origin: &Origin, name: &str) -> String {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name == origin.as_str() {
+        return "@".to_string();
+    }
+    match name.strip_suffix(&format!(".{origin}")) {
+        Some(relative) => relative.to_string(),
+        None => format!("{name}."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{DnsRecordType, RecordId};
+
+    fn origin() -> Origin {
+        Origin::parse("foobar.de").unwrap()
+    }
+
+    fn listed(record_type: DnsRecordType, name: &str, content: &str, ttl: Option<u32>) -> String {
+        record_line(&ListedRecord {
+            provider: "memory",
+            origin: origin(),
+            record_type,
+            name: name.to_string(),
+            content: content.to_string(),
+            ttl,
+            id: RecordId("id".to_string()),
+        })
+    }
+
+    #[test]
+    fn header_sets_origin_and_ttl() {
+        assert_eq!(
+            header("cloudflare", &origin()),
+            "; provider: cloudflare\n$ORIGIN foobar.de.\n$TTL 300\n"
+        );
+    }
+
+    #[test]
+    fn record_lines_are_tab_separated() {
+        assert_eq!(
+            listed(DnsRecordType::A, "nas.foobar.de", "192.0.2.1", Some(60)),
+            "nas\t60\tIN\tA\t192.0.2.1\n"
+        );
+        assert_eq!(
+            listed(DnsRecordType::AAAA, "nas.foobar.de", "2001:db8::1", None),
+            "nas\t\tIN\tAAAA\t2001:db8::1\n"
+        );
+    }
+
+    #[test]
+    fn automatic_ttl_uses_the_default() {
+        assert_eq!(
+            listed(DnsRecordType::A, "nas.foobar.de", "192.0.2.1", Some(1)),
+            "nas\t\tIN\tA\t192.0.2.1\n"
+        );
+    }
+
+    #[test]
+    fn names_are_relative_to_the_origin() {
+        let origin = origin();
+        assert_eq!(relative_name(&origin, "foobar.de"), "@");
+        assert_eq!(relative_name(&origin, "foobar.de."), "@");
+        assert_eq!(relative_name(&origin, "nas.foobar.de"), "nas");
+        assert_eq!(relative_name(&origin, "a.b.foobar.de."), "a.b");
+    }
+
+    #[test]
+    fn names_outside_the_origin_stay_absolute() {
+        let origin = origin();
+        assert_eq!(relative_name(&origin, "example.com"), "example.com.");
+        assert_eq!(relative_name(&origin, "notfoobar.de"), "notfoobar.de.");
+        assert_eq!(
+            relative_name(&origin, "foobar.de.example"),
+            "foobar.de.example."
+        );
+    }
+}
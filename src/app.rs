@@ -0,0 +1,1042 @@
+//! The commands of the binary, configured by the settings.
+
+#[cfg(feature = "acme")]
+use crate::acme::{AcmeConfig, AcmeManager};
+use crate::admin::{RecordFilter, list_records};
+use crate::auth::signed::{DEFAULT_MAX_SKEW, SIGNATURE_PARAM, sign};
+use crate::auth::{
+    AuthMode, ClientPassword, credentials_file, generate_token, hash_password, parse_api_tokens,
+    parse_client_passwords, parse_signing_secrets, split_passwords,
+};
+use crate::cli::{
+    CheckConfigArgs, Cli, Command, HealthcheckArgs, HistoryArgs, ListRecordsArgs, OutputFormat,
+    PushArgs, SignUrlArgs, UpdateArgs, WatchArgs,
+};
+use crate::config::{ConfigFile, parse_aliases};
+use crate::dyndns_client::DyndnsClient;
+use crate::history::{
+    DEFAULT_RETENTION_DAYS, HistoryConfig, HistoryFilter, HistoryReader, parse_time,
+};
+use crate::ip_update::ParsedIpUpdate;
+use crate::limits::RequestLimits;
+use crate::lockout::LockoutConfig;
+use crate::logging::LogOutput;
+use crate::negative_cache::{DEFAULT_MAX_ENTRIES, DEFAULT_TTL, NegativeCache};
+use crate::ownership::Ownership;
+use crate::propagation::{DEFAULT_ATTEMPTS, DEFAULT_RESOLVER, PropagationCheck};
+use crate::provider::chaos::{ChaosConfig, ChaosProvider};
+use crate::provider::failover::{DEFAULT_CHECK_INTERVAL, FailoverProvider};
+use crate::provider::{canonical_hostname, provider_from_env};
+use crate::retry::{DEFAULT_MAX_ATTEMPTS, RetryQueue};
+use crate::server::{
+    DynDnsServerBuilder, REUSE_PORT_SUPPORTED, StartupValidation, ValidationRetry, bind,
+    validate_providers,
+};
+use crate::status::StatusTracker;
+use crate::types::{ConfigProblems, DnsConfig, ensure_env_vars, env_or_default, format_table};
+use crate::update::{UpdateError, UpdateRequest, UpdateService};
+use crate::watch::{self, DEFAULT_IP_URLS, IpDiscovery, IpFamily, WatchConfig};
+use crate::{DnsProvider, DynDnsServer, Origin, healthcheck, logging, settings};
+use clap::{CommandFactory, FromArgMatches};
+use ipnet::IpNet;
+use jiff::{SignedDuration, Timestamp};
+use rootcause::option_ext::OptionExt;
+use rootcause::prelude::ResultExt;
+use rootcause::report_collection::ReportCollection;
+use rootcause::{Report, bail, report};
+use std::collections::HashMap;
+use std::iter;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::select;
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{Instrument, Span, error, info, warn};
+
+/// Parses the command line and runs the command, exiting the process if it fails.
+pub async fn run_cli() {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // Before logging, so --log-format applies
+    let settings = settings::init(&matches).await;
+    let log_output = match &cli.command {
+        Some(command) if command.prints_output() => LogOutput::Stderr,
+        _ => LogOutput::Stdout,
+    };
+    let logging = logging::init(log_output);
+
+    let result = match settings {
+        Err(e) => Err(e),
+        Ok(()) => run(cli.command.unwrap_or(Command::Serve)).await,
+    };
+
+    if let Err(e) = &result {
+        error!(error = %e, "Application error");
+    }
+    logging.shutdown();
+    if result.is_err() {
+        std::process::exit(1);
+    }
+}
+
+async fn run(command: Command) -> Result<(), Report> {
+    match command {
+        Command::Serve => run_server().await,
+        Command::Update(args) => run_update(args).await,
+        Command::Watch(args) => run_watch(args).await,
+        Command::CheckConfig(args) => run_check_config(args).await,
+        Command::ListRecords(args) => run_list_records(args).await,
+        Command::History(args) => run_history(args).await,
+        Command::Healthcheck(args) => run_healthcheck(args).await,
+        Command::HashPassword => hash_password_from_stdin().map(|hash| println!("{hash}")),
+        Command::GenerateToken => {
+            println!("{}", generate_token());
+            Ok(())
+        }
+        Command::SignUrl(args) => sign_url_from_stdin(args).map(|url| println!("{url}")),
+        Command::Push(args) => push_from_stdin(args).await,
+    }
+}
+
+async fn run_update(args: UpdateArgs) -> Result<(), Report> {
+    let Some(ip) = ParsedIpUpdate::new(args.ipv4, args.ipv6) else {
+        bail!("At least one of --ipv4 and --ipv6 is required");
+    };
+    let updates = UpdateService::new(Arc::new(get_dns_config()?), Arc::default());
+    let request = UpdateRequest {
+        hostname: canonical_hostname(&args.hostname)?,
+        ip,
+        client: None,
+        request_id: None,
+        alias_of: None,
+        dry_run: args.dry_run,
+    };
+
+    // Aliases are independent of the hostname, so they are updated even if it failed
+    let mut failures = ReportCollection::new();
+    for request in iter::once(request.clone()).chain(updates.aliases(&request)) {
+        let updated = match updates.apply(&request).await {
+            Ok(updated) if updated.is_empty() => {
+                failures.push(
+                    report!("No existing records found for '{}'", request.hostname)
+                        .into_dynamic()
+                        .into_cloneable(),
+                );
+                continue;
+            }
+            Ok(updated) => updated,
+            Err(UpdateError::Provider { provider, report }) => {
+                failures.push(
+                    report
+                        .attach(format!("Provider: {provider}"))
+                        .into_cloneable(),
+                );
+                continue;
+            }
+            Err(other) => {
+                failures.push(report!("{other}").into_dynamic().into_cloneable());
+                continue;
+            }
+        };
+        for record in updated {
+            println!(
+                "{}{} {} {} {}{}",
+                if args.dry_run { "[dry run] " } else { "" },
+                record.provider,
+                record.record_type,
+                request.hostname,
+                record.content,
+                if record.changed { "" } else { " (unchanged)" }
+            );
+        }
+    }
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+    let count = failures.len();
+    Err(failures
+        .context(format!("Failed to update {count} hostname(s)"))
+        .into_dynamic())
+}
+
+async fn run_watch(args: WatchArgs) -> Result<(), Report> {
+    if args.no_ipv4 && args.no_ipv6 {
+        bail!("--no-ipv4 and --no-ipv6 exclude each other");
+    }
+    let dns = Arc::new(get_dns_config()?);
+    let status = Arc::new(StatusTracker::default());
+    let updates = UpdateService::new(dns.clone(), status.clone());
+    let urls = if args.ip_urls.is_empty() {
+        DEFAULT_IP_URLS.iter().map(|it| it.to_string()).collect()
+    } else {
+        args.ip_urls
+    };
+    let discovery = IpDiscovery::new(urls, Duration::from_secs(args.timeout))?;
+    let config = WatchConfig {
+        hostnames: args.hostnames,
+        interval: Duration::from_secs(args.interval.max(1)),
+        families: [
+            (!args.no_ipv4).then_some(IpFamily::V4),
+            (!args.no_ipv6).then_some(IpFamily::V6),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+        ipv6_source: args.source,
+        interface_id: args.interface_id,
+        state_file: args.state_file,
+        dry_run: args.dry_run,
+    };
+
+    if let Some(addr) = args.metrics_listen {
+        let listener = tokio::net::TcpListener::bind(&addr)
+            .await
+            .context("Failed to bind metrics listen address")
+            .attach(format!("address: {addr}"))?;
+        info!(%addr, "Serving metrics");
+        let router = watch::metrics_router(dns, status);
+        tokio::spawn(
+            async move {
+                if let Err(e) = axum::serve(listener, router).await {
+                    error!(error = %e, "Metrics listener failed");
+                }
+            }
+            .instrument(Span::current()),
+        );
+    }
+
+    watch::run(&config, &updates, &discovery, graceful_shutdown()).await
+}
+
+async fn run_history(args: HistoryArgs) -> Result<(), Report> {
+    let path = settings::var_os("DATABASE_PATH")
+        .filter(|it| !it.is_empty())
+        .ok_or_else(|| report!("DATABASE_PATH is not set"))?;
+    let reader = HistoryReader::open(Path::new(&path))?;
+    let filter = HistoryFilter {
+        hostname: args.hostname,
+        request_id: args.request_id,
+        since: args.since.as_deref().map(parse_time).transpose()?,
+        until: args.until.as_deref().map(parse_time).transpose()?,
+        limit: Some(args.limit),
+    };
+    let events = reader.events(filter).await?;
+
+    match args.format {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&events).context("Failed to serialize events")?
+        ),
+        OutputFormat::Table => print!(
+            "{}",
+            format_table(
+                [
+                    "TIME", "HOSTNAME", "TYPE", "PROVIDER", "OLD", "NEW", "RESULT", "CLIENT"
+                ],
+                events
+                    .into_iter()
+                    .map(|it| {
+                        [
+                            it.timestamp.strftime("%Y-%m-%d %H:%M:%S UTC").to_string(),
+                            it.hostname,
+                            it.record_type.to_string(),
+                            it.provider.unwrap_or_else(|| "-".to_string()),
+                            it.old_content.unwrap_or_else(|| "-".to_string()),
+                            it.new_content.unwrap_or_else(|| "-".to_string()),
+                            it.result.to_string(),
+                            it.client.map_or("-".to_string(), |it| it.to_string()),
+                        ]
+                    })
+                    .collect(),
+            )
+        ),
+    }
+    Ok(())
+}
+
+async fn run_list_records(args: ListRecordsArgs) -> Result<(), Report> {
+    let dns = get_dns_config()?;
+    // The zone comes from the global `--origin` like for every other command
+    let filter = RecordFilter {
+        origin: None,
+        name: args.name,
+    };
+    let records = list_records(&dns, &filter).await?;
+
+    match args.format {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&records).context("Failed to serialize records")?
+        ),
+        OutputFormat::Table => print!(
+            "{}",
+            format_table(
+                ["PROVIDER", "TYPE", "NAME", "CONTENT", "TTL", "ID"],
+                records
+                    .into_iter()
+                    .map(|it| {
+                        [
+                            it.provider.to_string(),
+                            it.record_type.to_string(),
+                            it.name,
+                            it.content,
+                            it.ttl.map_or("-".to_string(), |it| it.to_string()),
+                            it.id.to_string(),
+                        ]
+                    })
+                    .collect(),
+            )
+        ),
+    }
+    Ok(())
+}
+
+/// The records side of the server configuration, for the commands writing records directly.
+fn get_dns_config() -> Result<DnsConfig, Report> {
+    let mut problems = ConfigProblems::default();
+    let builder = configure_dns(&mut problems, DynDnsServer::builder());
+    problems.finish()?;
+    builder.build_dns_config()
+}
+
+/// Reads the file in `CONFIG_FILE`, if set.
+fn get_config_file() -> Result<ConfigFile, Report> {
+    match settings::var("CONFIG_FILE") {
+        Ok(path) if !path.is_empty() => ConfigFile::load(Path::new(&path)),
+        _ => Ok(ConfigFile::default()),
+    }
+}
+
+fn hash_password_from_stdin() -> Result<String, Report> {
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .context("Failed to read password from stdin")?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        bail!("Password must not be empty");
+    }
+
+    hash_password(password)
+}
+
+fn sign_url_from_stdin(args: SignUrlArgs) -> Result<String, Report> {
+    let mut secret = String::new();
+    std::io::stdin()
+        .read_line(&mut secret)
+        .context("Failed to read signing secret from stdin")?;
+    let secret = secret.trim_end_matches(['\r', '\n']);
+    if secret.is_empty() {
+        bail!("Signing secret must not be empty");
+    }
+
+    let ts = args
+        .ts
+        .unwrap_or_else(|| Timestamp::now().as_second())
+        .to_string();
+    let signature = sign(secret, &args.hostname, &args.myip, &ts);
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("hostname", &args.hostname)
+        .append_pair("myip", &args.myip)
+        .append_pair("ts", &ts)
+        .append_pair(SIGNATURE_PARAM, &signature)
+        .finish();
+    Ok(format!(
+        "{}/nic/update?{query}",
+        args.url.trim_end_matches('/')
+    ))
+}
+
+async fn push_from_stdin(args: PushArgs) -> Result<(), Report> {
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .context("Failed to read password from stdin")?;
+    let password = password.trim_end_matches(['\r', '\n']);
+
+    let client = DyndnsClient::new(
+        &args.url,
+        &args.username,
+        password,
+        Duration::from_secs(args.timeout),
+    )?;
+    let hostnames = args
+        .hostnames
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let results = client.update(&hostnames, &args.myip).await?;
+    for result in &results {
+        println!("{result}");
+    }
+    if let Some(failed) = results.iter().find(|it| !it.is_success()) {
+        bail!("Update failed: {failed}");
+    }
+    Ok(())
+}
+
+async fn run_healthcheck(args: HealthcheckArgs) -> Result<(), Report> {
+    let url = args.url.unwrap_or_else(healthcheck::default_url);
+
+    // Keep the output to one line, it ends up in `docker inspect`
+    match healthcheck::check(&url, Duration::from_secs(args.timeout)).await {
+        Ok(()) => {
+            println!("healthy: {url}");
+            Ok(())
+        }
+        Err(e) => {
+            let reason = e
+                .iter_reports()
+                .map(|it| it.format_current_context().to_string())
+                .collect::<Vec<_>>()
+                .join(": ");
+            println!("unhealthy: {reason}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The fully parsed server configuration.
+struct ServerConfig {
+    server: DynDnsServer,
+    listen_addr: String,
+    startup_validation: StartupValidation,
+    validation_retry: ValidationRetry,
+    credentials_file: Option<PathBuf>,
+    reuse_port: bool,
+    history: Option<HistoryConfig>,
+    #[cfg(feature = "acme")]
+    acme: Option<AcmeConfig>,
+}
+
+/// Reads the server configuration from the environment, reporting all problems at once.
+fn load_server_config() -> Result<ServerConfig, Report> {
+    let mut problems = ConfigProblems::default();
+
+    let dns = configure_dns(&mut problems, DynDnsServer::builder());
+    let interface = settings::var("INTERFACE").unwrap_or("0.0.0.0".to_string());
+    let port: String = settings::var("PORT").unwrap_or("3000".to_string());
+    let auth_mode = problems.check(env_or_default("AUTH_MODE", AuthMode::default()));
+    let signed_only = auth_mode == Some(AuthMode::Signed);
+    let client_passwords = problems.check(get_client_passwords(signed_only));
+    let lockout = problems.check(get_lockout_config());
+    let allow_query_auth = problems.check(get_allow_query_auth());
+    let allow_insecure_query_auth =
+        problems.check(env_or_default("ALLOW_INSECURE_QUERY_AUTH", false));
+    let require_https = problems.check(env_or_default("REQUIRE_HTTPS", false));
+    let digest_auth = problems.check(get_digest_auth());
+    let api_tokens = problems.check(
+        parse_api_tokens(&settings::var("API_TOKENS").unwrap_or_default())
+            .context("Invalid API_TOKENS environment variable")
+            .map_err(Report::into_dynamic),
+    );
+    let signing_secrets = problems.check(
+        parse_signing_secrets(&settings::var("SIGNING_SECRETS").unwrap_or_default())
+            .context("Invalid SIGNING_SECRETS environment variable")
+            .map_err(Report::into_dynamic),
+    );
+    let signature_max_skew = problems.check(env_or_default(
+        "SIGNATURE_MAX_SKEW_SECS",
+        DEFAULT_MAX_SKEW.as_secs(),
+    ));
+    let trusted_proxies = problems.check(get_trusted_proxies());
+    let startup_validation = problems.check(env_or_default(
+        "STARTUP_VALIDATION",
+        StartupValidation::default(),
+    ));
+    let validation_retry = problems.check(get_validation_retry());
+    let request_limits = problems.check(get_request_limits());
+    let propagation_check = problems.check(get_propagation_check());
+    let retry_queue = problems.check(get_retry_queue());
+    let history = problems.check(get_history());
+    #[cfg(feature = "acme")]
+    let acme = {
+        // An invalid ORIGIN was already reported by configure_dns
+        let origin = settings::var("ORIGIN")
+            .ok()
+            .and_then(|it| Origin::parse(&it).ok());
+        problems.check(get_acme_config(origin.as_ref()))
+    };
+    #[cfg(not(feature = "acme"))]
+    problems.check(get_acme_config());
+    let dashboard = problems.check(env_or_default("DASHBOARD", true));
+    let debug_errors = problems.check(env_or_default("DEBUG_ERRORS", false));
+    let hash_metric_hostnames = problems.check(env_or_default("METRICS_HASH_HOSTNAMES", false));
+    let reuse_port = problems.check(get_reuse_port());
+    problems.finish()?;
+
+    // All values are present, otherwise finish would have returned the problems
+    let mut builder = dns
+        .lockout(lockout.unwrap_or_default())
+        .allow_query_auth(allow_query_auth.unwrap_or_default())
+        .allow_insecure_query_auth(allow_insecure_query_auth.unwrap_or_default())
+        .require_https(require_https.unwrap_or_default())
+        .digest_auth(digest_auth.unwrap_or_default())
+        .auth_mode(auth_mode.unwrap_or_default())
+        .signature_max_skew(SignedDuration::from_secs(
+            signature_max_skew.unwrap_or(DEFAULT_MAX_SKEW.as_secs()),
+        ))
+        .hash_metric_hostnames(hash_metric_hostnames.unwrap_or_default())
+        .request_limits(request_limits.unwrap_or_default())
+        .dashboard(dashboard.unwrap_or(true))
+        .debug_errors(debug_errors.unwrap_or_default());
+    if let Some(check) = propagation_check.flatten() {
+        builder = builder.propagation_check(check);
+    }
+    if let Some(queue) = retry_queue.flatten() {
+        builder = builder.retry_queue(queue);
+    }
+    if let Some(token) = settings::var("ADMIN_TOKEN")
+        .ok()
+        .filter(|it| !it.is_empty())
+    {
+        builder = builder.admin_token(token.trim());
+    }
+    if let Some(username) = settings::var("DYNDNS_USERNAME")
+        .ok()
+        .filter(|it| !it.is_empty())
+    {
+        builder = builder.username(username);
+    }
+    if let Some(base_path) = settings::var("BASE_PATH").ok().filter(|it| !it.is_empty()) {
+        builder = builder.base_path(base_path);
+    }
+    for password in client_passwords.unwrap_or_default() {
+        builder = builder.password(password);
+    }
+    for token in api_tokens.unwrap_or_default() {
+        builder = builder.api_token(token);
+    }
+    for secret in signing_secrets.unwrap_or_default() {
+        builder = builder.signing_secret(secret);
+    }
+    for proxy in trusted_proxies.unwrap_or_default() {
+        builder = builder.trusted_proxy(proxy);
+    }
+
+    Ok(ServerConfig {
+        server: builder.build()?,
+        listen_addr: format!("{}:{}", interface, port),
+        startup_validation: startup_validation.unwrap_or_default(),
+        validation_retry: validation_retry.unwrap_or_default(),
+        credentials_file: settings::var_os("CREDENTIALS_FILE").map(PathBuf::from),
+        reuse_port: reuse_port.unwrap_or_default(),
+        history: history.flatten(),
+        #[cfg(feature = "acme")]
+        acme: acme.flatten(),
+    })
+}
+
+/// Reads the origin, the providers and the settings of the records into `builder`, shared by
+/// the server and the commands writing records directly.
+fn configure_dns(
+    problems: &mut ConfigProblems,
+    mut builder: DynDnsServerBuilder,
+) -> DynDnsServerBuilder {
+    problems.check(ensure_env_vars(&["ORIGIN", "PROVIDERS"]));
+    let origin = settings::var("ORIGIN").ok().and_then(|it| {
+        problems.check(
+            Origin::parse(&it)
+                .context("Invalid ORIGIN environment variable")
+                .map_err(Report::into_dynamic),
+        )
+    });
+    let providers = match settings::var("PROVIDERS") {
+        Ok(enabled_providers) => problems.check(get_providers(enabled_providers)),
+        Err(_) => None,
+    };
+    let provider_origin_mappings = problems.check(get_provider_origin_mappings());
+    let require_managed_records = problems.check(env_or_default("REQUIRE_MANAGED_RECORDS", false));
+    let config_file = problems.check(get_config_file());
+    if let (Some(config_file), Some(origin)) = (&config_file, &origin) {
+        problems.check(config_file.validate(origin));
+    }
+    let aliases = problems.check(
+        parse_aliases(&settings::var("ALIASES").unwrap_or_default())
+            .context("Invalid ALIASES environment variable")
+            .map_err(Report::into_dynamic),
+    );
+    let negative_cache = problems.check(get_negative_cache());
+    let ownership = problems.check(get_ownership());
+    let dedupe_records = problems.check(env_or_default("DEDUPE_RECORDS", false));
+    let prefix_fan_out = problems.check(env_or_default("PREFIX_FAN_OUT", false));
+    let prefix_rewrite = problems.check(env_or_default("PREFIX_REWRITE", false));
+
+    builder = builder
+        .require_managed_records(require_managed_records.unwrap_or_default())
+        .dedupe_records(dedupe_records.unwrap_or_default())
+        .prefix_fan_out(prefix_fan_out.unwrap_or_default())
+        .prefix_rewrite(prefix_rewrite.unwrap_or_default());
+    if let Some(origin) = origin {
+        builder = builder.origin(origin);
+    }
+    if let Some(cache) = negative_cache {
+        builder = builder.negative_cache(cache);
+    }
+    if let Some(ownership) = ownership.flatten() {
+        builder = builder.ownership(ownership);
+    }
+    for hostname in settings::var("MANAGED_HOSTNAMES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|it| !it.is_empty())
+    {
+        builder = builder.managed_hostname(hostname);
+    }
+    let config_file = config_file.unwrap_or_default();
+    for (hostname, config) in config_file.hostnames {
+        builder = builder.hostname(hostname, config);
+    }
+    for (hostname, aliases) in config_file
+        .aliases
+        .into_iter()
+        .chain(aliases.unwrap_or_default())
+    {
+        for alias in aliases {
+            builder = builder.alias(hostname.clone(), alias);
+        }
+    }
+    for provider in providers.unwrap_or_default() {
+        builder = builder.provider(provider);
+    }
+    for (provider, mappings) in provider_origin_mappings.unwrap_or_default() {
+        builder = builder.provider_origin_mapping(provider, mappings);
+    }
+    builder
+}
+
+async fn run_check_config(args: CheckConfigArgs) -> Result<(), Report> {
+    let config = load_server_config()?;
+    let dns = &config.server.state().dns;
+    validate_providers(dns).await?;
+
+    let mut problems = ConfigProblems::default();
+    let mut rows = Vec::new();
+    for provider in &dns.dns_providers {
+        let origin = dns.origin_for(provider.as_ref());
+        let records = problems.check(
+            provider
+                .list_records(&origin)
+                .await
+                .context("Failed to list records")
+                .attach(format!("Provider: {}", provider.name()))
+                .map_err(Report::into_dynamic),
+        );
+        for record in records.unwrap_or_default() {
+            rows.push([
+                provider.name().to_string(),
+                origin.to_string(),
+                record.typ.to_string(),
+                record.name,
+                record.content,
+            ]);
+        }
+    }
+    problems.finish()?;
+
+    if !args.quiet {
+        print!(
+            "{}",
+            format_table(["PROVIDER", "ORIGIN", "TYPE", "NAME", "CONTENT"], rows)
+        );
+        println!("\nConfiguration is valid");
+    }
+
+    Ok(())
+}
+
+async fn run_server() -> Result<(), Report> {
+    let ServerConfig {
+        server,
+        listen_addr,
+        startup_validation,
+        validation_retry,
+        credentials_file,
+        reuse_port,
+        history,
+        #[cfg(feature = "acme")]
+        acme,
+    } = load_server_config()?;
+    if let Some(path) = credentials_file {
+        credentials_file::watch(path, server.state().auth.clone())?;
+    }
+    let server = match history {
+        Some(history) => server.with_history(
+            history
+                .open()
+                .context("Invalid DATABASE_PATH environment variable")?,
+        ),
+        None => server,
+    };
+    server.restore_status().await?;
+    let version = server.version_info();
+    info!(
+        version = version.version,
+        commit = version.commit,
+        build_timestamp = ?version.build_timestamp,
+        features = ?version.features,
+        providers = ?version.providers,
+        "Starting server"
+    );
+    // Before binding, clients would otherwise fail the handshake until the certificate is there
+    #[cfg(feature = "acme")]
+    let acme = match acme {
+        Some(config) => {
+            let acme = Arc::new(AcmeManager::new(config, server.state().status.clone()));
+            acme.init(&server.state().dns).await?;
+            acme.clone().spawn_renewal(server.state().dns.clone());
+            Some(acme)
+        }
+        None => None,
+    };
+    #[cfg(feature = "acme")]
+    let scheme = if acme.is_some() { "https" } else { "http" };
+    #[cfg(not(feature = "acme"))]
+    let scheme = "http";
+
+    // Bind before validating, so clients get a 911 instead of a refused connection meanwhile
+    let listener = bind(&listen_addr, reuse_port)
+        .await
+        .context("Failed to bind to listen address")?;
+
+    let local_addr = listener.local_addr().context("Getting local address")?;
+    info!("Listening on {local_addr}");
+    info!(
+        "Updates are accepted at {scheme}://{local_addr}{}/nic/update",
+        server.base_path()
+    );
+    if reuse_port {
+        info!("Port sharing is active, other processes may listen on the same port");
+    }
+
+    let shutdown = async { graceful_shutdown().await }.instrument(Span::current());
+    #[cfg(feature = "acme")]
+    let mut serve = match &acme {
+        Some(acme) => tokio::spawn(server.serve_tls(listener, acme.tls_acceptor(), shutdown)),
+        None => tokio::spawn(server.serve(listener, shutdown)),
+    };
+    #[cfg(not(feature = "acme"))]
+    let mut serve = tokio::spawn(server.serve(listener, shutdown));
+
+    // Stop validating if the server shuts down in the meantime
+    let validation = server.startup_validation(startup_validation, validation_retry);
+    let served = select! {
+        result = validation => {
+            result?;
+            serve.await
+        }
+        result = &mut serve => result,
+    };
+    if let Some(history) = server.state().updates.history() {
+        history.flush().await;
+    }
+    served
+        .context("Server task failed")?
+        .context("Server error")?;
+
+    Ok(())
+}
+
+fn get_reuse_port() -> Result<bool, Report> {
+    let reuse_port = env_or_default("REUSE_PORT", false)?;
+    if reuse_port && !REUSE_PORT_SUPPORTED {
+        bail!("REUSE_PORT is not supported on this platform, as it lacks SO_REUSEPORT");
+    }
+    Ok(reuse_port)
+}
+
+fn get_providers(
+    enabled_providers: String,
+) -> Result<Vec<Arc<dyn DnsProvider + Send + Sync>>, Report> {
+    let mut dns_providers: Vec<Arc<dyn DnsProvider + Send + Sync>> = Vec::new();
+    for provider in enabled_providers.split(",").filter(|s| !s.is_empty()) {
+        let provider = provider.trim().to_ascii_lowercase();
+        dns_providers.push(Arc::from(build_provider(&provider)?));
+    }
+
+    if dns_providers.is_empty() {
+        return Err(report!("No valid providers found")
+            .attach(format!("env PROVIDERS={enabled_providers}")));
+    }
+
+    Ok(dns_providers)
+}
+
+/// Creates the provider of an entry in `PROVIDERS`, which may wrap others with `chaos:` or
+/// `failover:`.
+fn build_provider(provider: &str) -> Result<Box<dyn DnsProvider + Send + Sync>, Report> {
+    if let Some(providers) = provider.strip_prefix("failover:") {
+        let providers = providers
+            .split('+')
+            .map(|it| build_provider(it.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if providers.len() < 2 {
+            bail!("Failover in PROVIDERS needs at least two providers joined by '+'");
+        }
+        let check_interval = env_or_default(
+            "FAILOVER_CHECK_INTERVAL_SECS",
+            DEFAULT_CHECK_INTERVAL.as_secs(),
+        )?;
+        return Ok(Box::new(FailoverProvider::new(
+            providers,
+            Duration::from_secs(check_interval),
+        )));
+    }
+    if let Some(inner) = provider.strip_prefix("chaos:") {
+        let inner = build_provider(inner)?;
+        warn!(
+            provider = inner.name(),
+            "Injecting faults into the calls of this provider"
+        );
+        let config = ChaosConfig::from_env()?;
+        return Ok(Box::new(ChaosProvider::new(inner, config)));
+    }
+
+    provider_from_env(provider)
+}
+
+fn get_provider_origin_mappings() -> Result<HashMap<String, Vec<(Origin, Origin)>>, Report> {
+    const PREFIX: &str = "PROVIDER_ORIGIN_MAPPING_";
+
+    let mut provider_mappings = HashMap::new();
+    for (name, val) in std::env::vars().filter(|(name, _)| name.starts_with(PREFIX)) {
+        let provider_name = name
+            .strip_prefix(PREFIX)
+            .expect("prefix checked")
+            .to_ascii_lowercase();
+
+        let mappings = val
+            .split(",")
+            .map(|it| {
+                let (from, to) = it
+                    .split_once("=")
+                    .context("provider mapping entry is malformed")
+                    .attach(format!("mapping: '{it}'"))?;
+                let parse = |origin: &str| {
+                    Origin::parse(origin)
+                        .context("provider mapping entry is invalid")
+                        .attach(format!("mapping: '{it}'"))
+                };
+                Ok((parse(from)?, parse(to)?))
+            })
+            .collect::<Result<Vec<_>, Report>>()?;
+
+        provider_mappings.insert(provider_name, mappings);
+    }
+
+    Ok(provider_mappings)
+}
+
+/// Reads the client passwords. They are optional if `signed_only` updates are accepted.
+fn get_client_passwords(signed_only: bool) -> Result<Vec<ClientPassword>, Report> {
+    let passwords = match (
+        settings::var("PASSWORD"),
+        settings::var("PASSWORDS"),
+        settings::var_os("CREDENTIALS_FILE"),
+    ) {
+        (Ok(password), Err(_), None) => parse_client_passwords(vec![password])?,
+        (Err(_), Ok(passwords), None) => {
+            let passwords = split_passwords(&passwords);
+            if passwords.is_empty() {
+                bail!("PASSWORDS does not contain any password");
+            }
+            parse_client_passwords(passwords)?
+        }
+        (Err(_), Err(_), Some(path)) => credentials_file::load(Path::new(&path))?,
+        (Err(_), Err(_), None) if signed_only => Vec::new(),
+        (Err(_), Err(_), None) => {
+            return Err(report!("Missing required environment variable")
+                .attach("'PASSWORD', 'PASSWORDS' or 'CREDENTIALS_FILE' is not set"));
+        }
+        _ => bail!("Only one of PASSWORD, PASSWORDS and CREDENTIALS_FILE may be set"),
+    };
+
+    let hashed = passwords
+        .iter()
+        .filter(|it| !matches!(it, ClientPassword::Plain(_)))
+        .count();
+    if hashed > 0 {
+        info!("Using {hashed} hashed client password(s)");
+    }
+    if passwords.len() > 1 {
+        warn!(
+            "{} client passwords are configured. Remember to remove old ones once all clients use the new one",
+            passwords.len()
+        );
+    }
+
+    Ok(passwords)
+}
+
+fn get_trusted_proxies() -> Result<Vec<IpNet>, Report> {
+    let Ok(proxies) = settings::var("TRUSTED_PROXIES") else {
+        return Ok(Vec::new());
+    };
+
+    proxies
+        .split(",")
+        .map(str::trim)
+        .filter(|it| !it.is_empty())
+        .map(|it| {
+            Ok(it
+                .parse::<IpNet>()
+                .or_else(|_| it.parse::<IpAddr>().map(IpNet::from))
+                .context("trusted proxy is neither an IP address nor a CIDR range")
+                .attach(format!("entry: '{it}'"))?)
+        })
+        .collect()
+}
+
+fn get_digest_auth() -> Result<bool, Report> {
+    let enabled = env_or_default("DIGEST_AUTH", false)?;
+    if enabled {
+        info!("Digest authentication is enabled");
+    }
+    Ok(enabled)
+}
+
+fn get_allow_query_auth() -> Result<bool, Report> {
+    if !env_or_default("ALLOW_QUERY_AUTH", false)? {
+        return Ok(false);
+    }
+    warn!("!!! ALLOW_QUERY_AUTH is enabled !!!");
+    warn!("API tokens are accepted in the query string, where proxies and routers might log them");
+    Ok(true)
+}
+
+fn get_validation_retry() -> Result<ValidationRetry, Report> {
+    let default = ValidationRetry::default();
+    let attempts = env_or_default("STARTUP_VALIDATION_ATTEMPTS", default.attempts)?;
+    if attempts == 0 {
+        bail!("STARTUP_VALIDATION_ATTEMPTS must be at least 1");
+    }
+
+    Ok(ValidationRetry {
+        attempts,
+        max_delay: Duration::from_secs(env_or_default(
+            "STARTUP_VALIDATION_MAX_DELAY_SECS",
+            default.max_delay.as_secs(),
+        )?),
+        ..default
+    })
+}
+
+fn get_lockout_config() -> Result<LockoutConfig, Report> {
+    Ok(LockoutConfig {
+        threshold: env_or_default("LOCKOUT_THRESHOLD", 0)?,
+        window: SignedDuration::from_secs(
+            env_or_default::<u32>("LOCKOUT_WINDOW_SECS", 600)?.into(),
+        ),
+        duration: SignedDuration::from_secs(
+            env_or_default::<u32>("LOCKOUT_DURATION_SECS", 900)?.into(),
+        ),
+    })
+}
+
+fn get_request_limits() -> Result<RequestLimits, Report> {
+    let defaults = RequestLimits::default();
+    Ok(RequestLimits {
+        max_uri_length: env_or_default("MAX_URI_LENGTH", defaults.max_uri_length)?,
+        max_body_bytes: env_or_default("MAX_BODY_BYTES", defaults.max_body_bytes)?,
+        max_head_bytes: env_or_default("MAX_HEADER_BYTES", defaults.max_head_bytes)?,
+        header_read_timeout: Duration::from_secs(env_or_default(
+            "HEADER_READ_TIMEOUT_SECS",
+            defaults.header_read_timeout.as_secs(),
+        )?),
+    })
+}
+
+fn get_propagation_check() -> Result<Option<PropagationCheck>, Report> {
+    if !env_or_default("PROPAGATION_CHECK", false)? {
+        return Ok(None);
+    }
+    let resolver = env_or_default("PROPAGATION_RESOLVER", DEFAULT_RESOLVER.to_string())?;
+    let attempts = env_or_default("PROPAGATION_ATTEMPTS", DEFAULT_ATTEMPTS)?;
+    Ok(Some(PropagationCheck::new(resolver, attempts)))
+}
+
+fn get_negative_cache() -> Result<NegativeCache, Report> {
+    let ttl = env_or_default("NEGATIVE_CACHE_TTL_SECS", DEFAULT_TTL.as_secs())?;
+    let max_entries = env_or_default("NEGATIVE_CACHE_MAX_ENTRIES", DEFAULT_MAX_ENTRIES)?;
+    Ok(NegativeCache::new(
+        SignedDuration::from_secs(ttl),
+        max_entries,
+    ))
+}
+
+/// The ownership from `OWNERSHIP_ID`, if set.
+fn get_ownership() -> Result<Option<Ownership>, Report> {
+    settings::var("OWNERSHIP_ID")
+        .ok()
+        .map(|it| it.trim().to_string())
+        .filter(|it| !it.is_empty())
+        .map(Ownership::new)
+        .transpose()
+        .context("Invalid OWNERSHIP_ID environment variable")
+        .map_err(Report::into_dynamic)
+}
+
+fn get_retry_queue() -> Result<Option<RetryQueue>, Report> {
+    if !env_or_default("RETRY_QUEUE", false)? {
+        return Ok(None);
+    }
+    let attempts = env_or_default("RETRY_ATTEMPTS", DEFAULT_MAX_ATTEMPTS)?;
+    if attempts < 2 {
+        bail!("RETRY_ATTEMPTS must be at least 2, the first attempt is the update itself");
+    }
+    let file = settings::var_os("RETRY_QUEUE_FILE").map(PathBuf::from);
+    Ok(Some(RetryQueue::new(attempts, file)?))
+}
+
+#[cfg(feature = "acme")]
+fn get_acme_config(origin: Option<&Origin>) -> Result<Option<AcmeConfig>, Report> {
+    if !env_or_default("ACME", false)? {
+        return Ok(None);
+    }
+    Ok(Some(AcmeConfig::from_settings(
+        |name| settings::var(name).ok(),
+        origin,
+    )?))
+}
+
+#[cfg(not(feature = "acme"))]
+fn get_acme_config() -> Result<(), Report> {
+    if env_or_default("ACME", false)? {
+        return Err(report!("ACME certificates need the `acme` feature")
+            .attach("hint: rebuild with --features acme")
+            .into_dynamic());
+    }
+    Ok(())
+}
+
+/// Reads where the history is kept. The database is only opened when the server starts, so e.g.
+/// `check-config` neither creates it nor starts a writer.
+fn get_history() -> Result<Option<HistoryConfig>, Report> {
+    let Some(path) = settings::var_os("DATABASE_PATH").filter(|it| !it.is_empty()) else {
+        return Ok(None);
+    };
+    let path = PathBuf::from(path);
+    if let Some(parent) = path.parent().filter(|it| !it.as_os_str().is_empty())
+        && !parent.is_dir()
+    {
+        return Err(report!("Invalid DATABASE_PATH environment variable")
+            .attach(format!("directory does not exist: {}", parent.display()))
+            .into_dynamic());
+    }
+    let retention_days = env_or_default("HISTORY_RETENTION_DAYS", DEFAULT_RETENTION_DAYS)?;
+    let retention =
+        (retention_days > 0).then(|| SignedDuration::from_hours(i64::from(retention_days) * 24));
+    Ok(Some(HistoryConfig { path, retention }))
+}
+
+async fn graceful_shutdown() {
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    let interrupt = tokio::signal::ctrl_c();
+    select! {
+        _ = sigterm.recv() => warn!("Received SIGTERM"),
+        _ = interrupt => warn!("Received SIGINT")
+    }
+}
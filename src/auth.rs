@@ -0,0 +1,976 @@
+use crate::auth::digest::{DigestAuth, DigestResponse};
+use crate::auth::signed::{SignatureError, SignedAuth, SignedRequest};
+use crate::dyndns::{DyndnsResponse, Outcome};
+use crate::limits::TlsConnection;
+use crate::lockout::LockoutTracker;
+use crate::types::AppState;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier as _};
+use axum::extract::{ConnectInfo, OriginalUri, Request, State};
+use axum::http::{HeaderValue, StatusCode, Uri, header};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum_extra::headers::authorization::Bearer;
+use axum_extra::headers::{Authorization, HeaderMapExt};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use derive_more::{Display, FromStr};
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
+use jiff::{SignedDuration, Timestamp};
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail, report};
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, RwLock};
+use subtle::ConstantTimeEq;
+use tracing::{debug, info, instrument, warn};
+
+pub mod credentials_file;
+pub mod digest;
+pub mod signed;
+
+/// How long a successful hash verification is remembered for the identical password.
+const VERIFICATION_CACHE_DURATION: SignedDuration = SignedDuration::from_secs(60);
+
+/// Which credentials clients may authenticate updates with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Display, FromStr)]
+pub enum AuthMode {
+    /// Passwords, Digest auth and API tokens.
+    #[default]
+    #[display("password")]
+    Password,
+    /// Only updates signed with a signing secret, see
+    /// [`DynDnsServerBuilder::signing_secret`](crate::DynDnsServerBuilder::signing_secret).
+    #[display("signed")]
+    Signed,
+    /// Signed updates and everything accepted in [`Password`](Self::Password) mode.
+    #[display("both")]
+    Both,
+}
+
+/// Everything needed to authenticate clients.
+#[derive(Debug)]
+pub struct AuthConfig {
+    pub username: Option<String>,
+    pub passwords: PasswordChecker,
+    pub api_tokens: Vec<ApiToken>,
+    pub trusted_proxies: Vec<IpNet>,
+    pub lockouts: LockoutTracker,
+    /// Whether API tokens may also be passed as a `key` or `password` query parameter.
+    pub allow_query_auth: bool,
+    /// Whether requests must have been forwarded via HTTPS by a trusted proxy.
+    pub require_https: bool,
+    /// Digest authentication, if enabled. It is offered alongside Basic auth.
+    pub digest: Option<DigestAuth>,
+    pub mode: AuthMode,
+    /// The verification of signed updates. Only set in the modes accepting them.
+    pub signed: Option<SignedAuth>,
+    /// The bearer token guarding the `/admin` endpoints. They are not served without one.
+    pub admin_token: Option<String>,
+}
+
+impl AuthConfig {
+    /// Replaces the client passwords, unless they do not fit the other settings.
+    pub fn replace_passwords(&self, passwords: Vec<ClientPassword>) -> Result<(), Report> {
+        check_passwords(
+            &passwords,
+            !self.api_tokens.is_empty() || self.signed.is_some(),
+            self.digest.is_some(),
+            self.admin_token.as_deref(),
+        )?;
+        self.passwords.replace(passwords);
+        Ok(())
+    }
+}
+
+/// Query parameters that may carry credentials when query authentication is enabled.
+const QUERY_AUTH_PARAMS: [&str; 2] = ["key", "password"];
+
+/// Splits `query` into its raw `name=value` pairs, each with its decoded value if it is a
+/// credential parameter. Names are compared percent-decoded, like the query extractors see them,
+/// so `%6Bey` is a credential as well.
+pub(crate) fn query_credentials(query: &str) -> impl Iterator<Item = (&str, Option<String>)> {
+    query.split('&').filter(|it| !it.is_empty()).map(|pair| {
+        let credential = form_urlencoded::parse(pair.as_bytes())
+            .next()
+            .filter(|(name, _)| QUERY_AUTH_PARAMS.contains(&name.as_ref()))
+            .map(|(_, value)| value.into_owned());
+        (pair, credential)
+    })
+}
+
+#[instrument(name = "auth", skip_all)]
+pub async fn ensure_auth(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut req: Request,
+    next: Next,
+) -> impl IntoResponse {
+    let client_ip = client_ip(&req, addr.ip(), &state.auth.trusted_proxies);
+    let now = Timestamp::now();
+
+    if state.auth.require_https && !is_https(&req, addr.ip(), &state.auth.trusted_proxies) {
+        debug!(%client_ip, "Rejecting request not made via HTTPS");
+        return (StatusCode::FORBIDDEN, "https required").into_response();
+    }
+
+    // Guessing the password of one user from many addresses is locked out as well
+    let lockout_keys = [
+        Some(format!("ip:{client_ip}")),
+        claimed_username(&req).map(|it| format!("user:{it}")),
+    ];
+    let lockout_keys = lockout_keys.iter().flatten();
+    if let Some(until) = lockout_keys
+        .clone()
+        .filter_map(|key| state.auth.lockouts.locked_until(key, now))
+        .max()
+    {
+        debug!(%client_ip, %until, "Rejecting request from locked out client");
+        return DyndnsResponse::new(None, Outcome::Abuse).into_response();
+    }
+
+    // Nesting under a base path strips it from the URI, but clients digest the full one
+    let uri = req
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(req.uri(), |it| &it.0);
+    let request_uri = uri
+        .path_and_query()
+        .map_or_else(|| uri.path().to_string(), |it| it.to_string());
+    let query_token = if state.auth.allow_query_auth {
+        strip_query_credentials(&mut req)
+    } else {
+        None
+    };
+
+    let digest_header = digest_header(&req);
+    let signed_request = state
+        .auth
+        .signed
+        .as_ref()
+        .and_then(|_| req.uri().query().and_then(SignedRequest::parse));
+
+    let authenticated = if let Some(request) = signed_request {
+        verify_signed_request(&state, &mut req, request, client_ip, now)
+    } else if state.auth.mode == AuthMode::Signed {
+        debug!("Unsigned request from ip {client_ip}, but only signed requests are accepted");
+        false
+    } else if let Some(digest_header) = digest_header {
+        verify_digest_auth(&state, digest_header, &req, &request_uri, client_ip, now)
+    } else if let Some(basic) = parse_basic_auth(&req) {
+        match basic {
+            Ok(credentials) => verify_basic_auth(&state, &credentials, client_ip).await,
+            Err(reason) => {
+                // The header value is never logged, it might still contain the password
+                debug!(%client_ip, reason, "Malformed Basic auth header");
+                false
+            }
+        }
+    } else if let Some(bearer) = req.headers().typed_get::<Authorization<Bearer>>() {
+        verify_api_token(&state, &mut req, bearer.token(), client_ip)
+    } else if let Some(token) = query_token {
+        verify_api_token(&state, &mut req, &token, client_ip)
+    } else {
+        debug!("Request from ip {client_ip} without usable credentials");
+        false
+    };
+
+    if !authenticated {
+        for key in lockout_keys {
+            state.auth.lockouts.record_failure(key, now);
+        }
+        let mut response = DyndnsResponse::new(None, Outcome::BadAuth).into_response();
+        if state.auth.mode == AuthMode::Signed {
+            return response;
+        }
+        let headers = response.headers_mut();
+        if let Some(digest) = &state.auth.digest {
+            for challenge in digest.challenges(now) {
+                headers.append(header::WWW_AUTHENTICATE, challenge);
+            }
+        }
+        // Lets browsers prompt for credentials, e.g. for the status page
+        headers.append(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static(r#"Basic realm="dyndns", charset="UTF-8""#),
+        );
+        return response;
+    }
+
+    for key in lockout_keys {
+        state.auth.lockouts.record_success(key);
+    }
+    next.run(req).await
+}
+
+/// Only lets requests through that carry the admin token as bearer token. The client
+/// credentials accepted by [`ensure_auth`] are never valid here.
+#[instrument(name = "admin_auth", skip_all)]
+pub async fn ensure_admin(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> impl IntoResponse {
+    let client_ip = client_ip(&req, addr.ip(), &state.auth.trusted_proxies);
+    let lockout_key = format!("ip:{client_ip}");
+    let now = Timestamp::now();
+
+    if state.auth.require_https && !is_https(&req, addr.ip(), &state.auth.trusted_proxies) {
+        debug!(%client_ip, "Rejecting request not made via HTTPS");
+        return (StatusCode::FORBIDDEN, "https required").into_response();
+    }
+    if let Some(until) = state.auth.lockouts.locked_until(&lockout_key, now) {
+        debug!(%client_ip, %until, "Rejecting request from locked out client");
+        return DyndnsResponse::new(None, Outcome::Abuse).into_response();
+    }
+
+    let authenticated = match (
+        &state.auth.admin_token,
+        req.headers().typed_get::<Authorization<Bearer>>(),
+    ) {
+        (Some(expected), Some(bearer)) => verify_password(bearer.token(), expected),
+        _ => false,
+    };
+    if !authenticated {
+        debug!("Request from ip {client_ip} without valid admin token");
+        state.auth.lockouts.record_failure(&lockout_key, now);
+        return DyndnsResponse::new(None, Outcome::BadAuth).into_response();
+    }
+
+    info!("Client authenticated with admin token");
+    state.auth.lockouts.record_success(&lockout_key);
+    next.run(req).await
+}
+
+fn verify_digest_auth(
+    state: &AppState,
+    header: &str,
+    req: &Request,
+    request_uri: &str,
+    client_ip: IpAddr,
+    now: Timestamp,
+) -> bool {
+    let Some(digest) = &state.auth.digest else {
+        debug!("Digest authentication attempt from ip {client_ip}, but digest is disabled");
+        return false;
+    };
+    let response = match DigestResponse::parse(header) {
+        Ok(response) => response,
+        Err(e) => {
+            debug!(error = %e, "Invalid digest header from ip {client_ip}");
+            return false;
+        }
+    };
+
+    let username_matches = state
+        .auth
+        .username
+        .as_ref()
+        .is_none_or(|expected| verify_password(&response.username, expected));
+    let passwords = state.auth.passwords.plaintext();
+    let passwords = passwords
+        .iter()
+        .map(|(index, password)| (*index, password.as_str()))
+        .collect::<Vec<_>>();
+    let verified = digest.verify(
+        &response,
+        req.method().as_str(),
+        request_uri,
+        &passwords,
+        now,
+    );
+    match (verified, username_matches) {
+        (Ok(password_index), true) => {
+            if state.auth.passwords.count() > 1 {
+                info!(password_index, "Client authenticated via digest");
+            }
+            true
+        }
+        (verified, _) => {
+            debug!(
+                username_wrong = !username_matches,
+                error = ?verified.err(),
+                "Invalid digest login attempt for user {} from ip {client_ip}",
+                response.username
+            );
+            false
+        }
+    }
+}
+
+fn verify_api_token(state: &AppState, req: &mut Request, token: &str, client_ip: IpAddr) -> bool {
+    let Some(index) = verify_token(token, &state.auth.api_tokens) else {
+        debug!("Invalid API token from ip {client_ip}");
+        return false;
+    };
+    info!(token_index = index, "Client authenticated with API token");
+    if let Some(hostnames) = &state.auth.api_tokens[index].allowed_hostnames {
+        req.extensions_mut()
+            .insert(AllowedHostnames(hostnames.clone()));
+    }
+    true
+}
+
+fn verify_signed_request(
+    state: &AppState,
+    req: &mut Request,
+    request: Result<SignedRequest, SignatureError>,
+    client_ip: IpAddr,
+    now: Timestamp,
+) -> bool {
+    let Some(signed) = &state.auth.signed else {
+        return false;
+    };
+    let verified = request.and_then(|request| {
+        let index = signed.verify(&request, now)?;
+        Ok((index, request))
+    });
+    let (index, request) = match verified {
+        Ok(verified) => verified,
+        Err(e) => {
+            debug!(error = %e, "Invalid signed request from ip {client_ip}");
+            return false;
+        }
+    };
+    info!(
+        secret_index = index,
+        hostname = request.hostname(),
+        "Client authenticated with signed request"
+    );
+    if let Some(hostnames) = &signed.secrets()[index].allowed_hostnames {
+        req.extensions_mut()
+            .insert(AllowedHostnames(hostnames.clone()));
+    }
+    true
+}
+
+/// Removes all credential parameters from the query string of `req`, so they never reach the
+/// handler or any log, and returns the first one found.
+fn strip_query_credentials(req: &mut Request) -> Option<String> {
+    let query = req.uri().query()?;
+
+    let mut token = None;
+    let mut remaining = Vec::new();
+    for (pair, credential) in query_credentials(query) {
+        match credential {
+            Some(credential) => {
+                token.get_or_insert(credential);
+            }
+            None => remaining.push(pair),
+        }
+    }
+    token.as_ref()?;
+
+    let remaining = remaining.join("&");
+    let path_and_query = if remaining.is_empty() {
+        req.uri().path().to_string()
+    } else {
+        format!("{}?{remaining}", req.uri().path())
+    };
+
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    match Uri::from_parts(parts) {
+        Ok(uri) => *req.uri_mut() = uri,
+        Err(e) => {
+            warn!(error = %e, "Could not strip credentials from request URI");
+            return None;
+        }
+    }
+
+    token
+}
+
+/// Whether the request was made via HTTPS, either to this server or to a trusted proxy that
+/// forwarded it.
+fn is_https(req: &Request, peer: IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    if req.extensions().get::<TlsConnection>().is_some() {
+        return true;
+    }
+    trusted_proxies.iter().any(|net| net.contains(&peer))
+        && req
+            .headers()
+            .get("X-Forwarded-Proto")
+            .and_then(|it| it.to_str().ok())
+            .is_some_and(|it| it.trim().eq_ignore_ascii_case("https"))
+}
+
+/// The username and password of a Basic `Authorization` header.
+struct BasicCredentials {
+    username: String,
+    password: String,
+}
+
+/// The `Authorization` header of `req`, if it carries Digest credentials.
+fn digest_header(req: &Request) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|it| it.to_str().ok())
+        .filter(|it| it.trim_start().to_ascii_lowercase().starts_with("digest "))
+}
+
+/// The username `req` claims in its Basic or Digest credentials, whether they are valid or not.
+fn claimed_username(req: &Request) -> Option<String> {
+    if let Some(header) = digest_header(req) {
+        return DigestResponse::parse(header).ok().map(|it| it.username);
+    }
+    parse_basic_auth(req)?.ok().map(|it| it.username)
+}
+
+/// Parses the Basic `Authorization` header of `req`. `None` if there is none, and the reason if
+/// it is malformed, which counts as a failed login instead of a bad request. Clients would take
+/// a `400` for a server problem and retry forever. An empty header is treated as malformed.
+fn parse_basic_auth(req: &Request) -> Option<Result<BasicCredentials, &'static str>> {
+    let value = req
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .as_bytes()
+        .trim_ascii();
+    if value.is_empty() {
+        return Some(Err("empty header value"));
+    }
+    let scheme = value.get(..5)?;
+    if !scheme.eq_ignore_ascii_case(b"basic") {
+        return None;
+    }
+
+    Some(decode_basic_auth(&value[5..]))
+}
+
+fn decode_basic_auth(value: &[u8]) -> Result<BasicCredentials, &'static str> {
+    let encoded = value
+        .strip_prefix(b" ")
+        .ok_or("no space after the scheme, the header might be URL-encoded")?;
+    let decoded = STANDARD
+        .decode(encoded.trim_ascii())
+        .map_err(|_| "invalid base64")?;
+    let decoded = String::from_utf8(decoded).map_err(|_| "not UTF-8 after decoding")?;
+    let (username, password) = decoded
+        .split_once(':')
+        .ok_or("missing colon between username and password")?;
+    Ok(BasicCredentials {
+        username: username.to_string(),
+        password: password.to_string(),
+    })
+}
+
+async fn verify_basic_auth(state: &AppState, header: &BasicCredentials, client_ip: IpAddr) -> bool {
+    // Always check both fields, so timing does not reveal which one was wrong
+    let password_index = state.auth.passwords.verify(&header.password).await;
+    let username_matches = state
+        .auth
+        .username
+        .as_ref()
+        .is_none_or(|expected| verify_password(&header.username, expected));
+    let (Some(password_index), true) = (password_index, username_matches) else {
+        debug!(
+            username_wrong = !username_matches,
+            password_wrong = password_index.is_none(),
+            "Invalid login attempt for user {} from ip {client_ip}",
+            header.username
+        );
+        return false;
+    };
+    if state.auth.passwords.count() > 1 {
+        info!(password_index, "Client authenticated");
+    }
+    true
+}
+
+/// Determines the IP of the client. `X-Forwarded-For` is only honored if the direct peer is a
+/// trusted proxy, in which case the rightmost untrusted entry is the client.
+pub(crate) fn client_ip(req: &Request, peer: IpAddr, trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return peer;
+    }
+
+    let forwarded = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|it| it.to_str().ok())
+        .flat_map(|it| it.split(","))
+        .map(|it| it.trim().parse::<IpAddr>())
+        .collect::<Vec<_>>();
+
+    let mut client = peer;
+    for ip in forwarded.into_iter().rev() {
+        let Ok(ip) = ip else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// Checks whether `candidate` matches the `expected` password.
+///
+/// The comparison is constant-time with respect to the content of both values. Passwords of
+/// differing length never match; the length check itself is not hidden, so the length of the
+/// expected password may leak through timing, its content does not.
+pub fn verify_password(candidate: &str, expected: &str) -> bool {
+    candidate.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+/// A configured client password, either in plaintext or as a PHC-format hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientPassword {
+    Plain(String),
+    Argon2(String),
+    Bcrypt(String),
+}
+
+impl ClientPassword {
+    /// Detects the format of `value`. Anything that does not look like an argon2 or bcrypt PHC
+    /// string is treated as a plaintext password.
+    pub fn parse(value: String) -> Result<Self, Report> {
+        if value.starts_with("$argon2") {
+            PasswordHash::new(&value)
+                .map_err(|e| report!("Invalid argon2 password hash").attach(e.to_string()))?;
+            return Ok(Self::Argon2(value));
+        }
+        if ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| value.starts_with(prefix))
+        {
+            value
+                .parse::<bcrypt::HashParts>()
+                .context("Invalid bcrypt password hash")?;
+            return Ok(Self::Bcrypt(value));
+        }
+        Ok(Self::Plain(value))
+    }
+
+    fn verify_blocking(&self, candidate: &str) -> bool {
+        match self {
+            Self::Plain(expected) => verify_password(candidate, expected),
+            Self::Argon2(hash) => match PasswordHash::new(hash) {
+                Ok(hash) => Argon2::default()
+                    .verify_password(candidate.as_bytes(), &hash)
+                    .is_ok(),
+                Err(e) => {
+                    warn!(error = %e, "Could not parse argon2 password hash");
+                    false
+                }
+            },
+            Self::Bcrypt(hash) => bcrypt::verify(candidate, hash).unwrap_or_else(|e| {
+                warn!(error = %e, "Could not verify bcrypt password hash");
+                false
+            }),
+        }
+    }
+}
+
+/// Verifies candidate passwords against a list of [`ClientPassword`]s.
+///
+/// Hash verification is CPU-heavy, so it runs on the blocking thread pool and a successful
+/// verification is cached for a short time. The cache only holds an HMAC of the password, keyed
+/// with a random per-process key.
+///
+/// The passwords can be replaced at runtime, see [`credentials_file`].
+#[derive(Debug)]
+pub struct PasswordChecker {
+    passwords: RwLock<Arc<Vec<ClientPassword>>>,
+    cache_key: [u8; 32],
+    last_success: Mutex<Option<([u8; 32], usize, Timestamp)>>,
+}
+
+impl PasswordChecker {
+    pub fn new(passwords: Vec<ClientPassword>) -> Self {
+        Self {
+            passwords: RwLock::new(Arc::new(passwords)),
+            cache_key: rand::random(),
+            last_success: Mutex::new(None),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.current().len()
+    }
+
+    /// Returns all plaintext passwords with their index.
+    pub fn plaintext(&self) -> Vec<(usize, String)> {
+        self.current()
+            .iter()
+            .enumerate()
+            .filter_map(|(index, it)| match it {
+                ClientPassword::Plain(password) => Some((index, password.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn fingerprint(&self, candidate: &str) -> [u8; 32] {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.cache_key).expect("HMAC accepts keys of any size");
+        mac.update(candidate.as_bytes());
+        mac.finalize().into_bytes().into()
+    }
+
+    fn current(&self) -> Arc<Vec<ClientPassword>> {
+        self.passwords.read().expect("lock poisoned").clone()
+    }
+
+    /// Swaps in new passwords. Requests being verified right now still use the old ones.
+    fn replace(&self, passwords: Vec<ClientPassword>) {
+        *self.passwords.write().expect("lock poisoned") = Arc::new(passwords);
+        *self.last_success.lock().expect("mutex poisoned") = None;
+    }
+
+    /// Returns the index of the configured password matching `candidate`, if any.
+    pub async fn verify(&self, candidate: &str) -> Option<usize> {
+        let passwords = self.current();
+        // Compare against all plaintext passwords, so timing does not reveal which one matched
+        let plain_match = passwords
+            .iter()
+            .enumerate()
+            .filter_map(|(index, password)| match password {
+                ClientPassword::Plain(expected) => Some((index, expected)),
+                _ => None,
+            })
+            .fold(None, |found, (index, expected)| {
+                found.or(verify_password(candidate, expected).then_some(index))
+            });
+        if plain_match.is_some() {
+            return plain_match;
+        }
+        if passwords
+            .iter()
+            .all(|it| matches!(it, ClientPassword::Plain(_)))
+        {
+            return None;
+        }
+
+        let fingerprint = self.fingerprint(candidate);
+        if let Some((cached, index, verified_at)) =
+            &*self.last_success.lock().expect("mutex poisoned")
+            && *verified_at + VERIFICATION_CACHE_DURATION > Timestamp::now()
+            && bool::from(fingerprint.ct_eq(cached))
+        {
+            debug!("Using cached password verification");
+            return Some(*index);
+        }
+
+        let owned_candidate = candidate.to_string();
+        let matched = tokio::task::spawn_blocking(move || {
+            passwords
+                .iter()
+                .position(|it| it.verify_blocking(&owned_candidate))
+        })
+        .await
+        .unwrap_or_else(|e| {
+            warn!(error = %e, "Password verification task failed");
+            None
+        });
+
+        if let Some(index) = matched {
+            *self.last_success.lock().expect("mutex poisoned") =
+                Some((fingerprint, index, Timestamp::now()));
+        }
+
+        matched
+    }
+}
+
+/// Parses the passwords split off by [`split_passwords`].
+pub fn parse_client_passwords(passwords: Vec<String>) -> Result<Vec<ClientPassword>, Report> {
+    passwords
+        .into_iter()
+        .enumerate()
+        .map(|(index, it)| {
+            ClientPassword::parse(it)
+                .context("Invalid client password")
+                .attach(format!("index: {index}"))
+                .map_err(Report::into_dynamic)
+        })
+        .collect()
+}
+
+/// Checks that `passwords` fit the other auth settings, at startup and when they are reloaded.
+pub(crate) fn check_passwords(
+    passwords: &[ClientPassword],
+    has_other_credentials: bool,
+    digest_auth: bool,
+    admin_token: Option<&str>,
+) -> Result<(), Report> {
+    if passwords.is_empty() && !has_other_credentials {
+        bail!("Neither a password, an API token nor a signing secret is configured");
+    }
+    let is_admin_token = |it: &ClientPassword| matches!((it, admin_token), (ClientPassword::Plain(p), Some(token)) if p == token);
+    if passwords.iter().any(is_admin_token) {
+        bail!("The admin token must differ from all client passwords and API tokens");
+    }
+    let has_plaintext_password = passwords
+        .iter()
+        .any(|it| matches!(it, ClientPassword::Plain(_)));
+    if digest_auth && !has_plaintext_password {
+        bail!("Digest authentication requires at least one plaintext password");
+    }
+    Ok(())
+}
+
+/// Splits a list of passwords separated by newlines or commas.
+///
+/// Lines containing a password hash are taken as a whole, as PHC strings contain commas
+/// themselves.
+pub fn split_passwords(value: &str) -> Vec<String> {
+    value
+        .lines()
+        .map(str::trim)
+        .flat_map(|line| {
+            if line.starts_with('$') {
+                vec![line]
+            } else {
+                line.split(',').map(str::trim).collect()
+            }
+        })
+        .filter(|it| !it.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Hashes `password` with argon2id and the default parameters, producing a PHC string.
+pub fn hash_password(password: &str) -> Result<String, Report> {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+        .map_err(|e| report!("Could not encode salt").attach(e.to_string()))?;
+
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| report!("Could not hash password").attach(e.to_string()))?
+        .to_string())
+}
+
+pub(crate) const MIN_TOKEN_LENGTH: usize = 16;
+
+/// The hostnames an authenticated client may update. Inserted as a request extension by the auth
+/// middleware for restricted API tokens.
+#[derive(Debug, Clone)]
+pub struct AllowedHostnames(pub HashSet<String>);
+
+/// A bearer token or signing secret accepted by the server, optionally restricted to a set of
+/// hostnames.
+#[derive(Debug, Clone)]
+pub struct ApiToken {
+    pub token: String,
+    pub allowed_hostnames: Option<HashSet<String>>,
+}
+
+/// Parses a comma-separated list of tokens. Each token may be followed by `=` and a
+/// `|`-separated list of hostnames it is allowed to update, e.g. `token1,token2=a.foo.de|b.foo.de`.
+pub fn parse_api_tokens(value: &str) -> Result<Vec<ApiToken>, Report> {
+    parse_restricted_secrets(value, "API token")
+}
+
+/// Parses a list of signing secrets for signed updates, in the format of [`parse_api_tokens`].
+pub fn parse_signing_secrets(value: &str) -> Result<Vec<ApiToken>, Report> {
+    parse_restricted_secrets(value, "Signing secret")
+}
+
+fn parse_restricted_secrets(value: &str, kind: &str) -> Result<Vec<ApiToken>, Report> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|it| !it.is_empty())
+        .enumerate()
+        .map(|(index, entry)| {
+            let (token, hostnames) = match entry.split_once('=') {
+                Some((token, hostnames)) => (token.trim(), Some(hostnames)),
+                None => (entry, None),
+            };
+            if token.len() < MIN_TOKEN_LENGTH {
+                return Err(report!("{kind} is too short")
+                    .attach(format!("index: {index}"))
+                    .attach(format!("minimum length: {MIN_TOKEN_LENGTH}")));
+            }
+            let allowed_hostnames = hostnames.map(|it| {
+                it.split('|')
+                    .map(str::trim)
+                    .filter(|it| !it.is_empty())
+                    .map(str::to_string)
+                    .collect::<HashSet<_>>()
+            });
+            if allowed_hostnames.as_ref().is_some_and(HashSet::is_empty) {
+                return Err(
+                    report!("{kind} has an empty hostname list").attach(format!("index: {index}"))
+                );
+            }
+
+            Ok(ApiToken {
+                token: token.to_string(),
+                allowed_hostnames,
+            })
+        })
+        .collect()
+}
+
+/// Returns the index of the token matching `candidate`, if any. All tokens are compared, so
+/// timing does not reveal which one matched.
+pub fn verify_token(candidate: &str, tokens: &[ApiToken]) -> Option<usize> {
+    tokens.iter().enumerate().fold(None, |found, (index, it)| {
+        found.or(verify_password(candidate, &it.token).then_some(index))
+    })
+}
+
+/// Generates a random token suitable for `API_TOKENS`.
+pub fn generate_token() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stripped(uri: &str) -> (Option<String>, String) {
+        let mut req = Request::get(uri).body(axum::body::Body::empty()).unwrap();
+        let token = strip_query_credentials(&mut req);
+        (token, req.uri().to_string())
+    }
+
+    #[test]
+    fn query_credentials_are_stripped() {
+        assert_eq!(
+            stripped("/nic/update?hostname=nas&key=secret&myip=192.0.2.1"),
+            (
+                Some("secret".to_string()),
+                "/nic/update?hostname=nas&myip=192.0.2.1".to_string()
+            )
+        );
+        assert_eq!(
+            stripped("/nic/update?password=secret&key=other"),
+            (Some("secret".to_string()), "/nic/update".to_string())
+        );
+        assert_eq!(
+            stripped("/nic/update?hostname=nas&keys=value"),
+            (None, "/nic/update?hostname=nas&keys=value".to_string())
+        );
+    }
+
+    #[test]
+    fn encoded_query_credential_names_are_stripped() {
+        assert_eq!(
+            stripped("/nic/update?hostname=nas&%6Bey=sec%72et&myip=192.0.2.1"),
+            (
+                Some("secret".to_string()),
+                "/nic/update?hostname=nas&myip=192.0.2.1".to_string()
+            )
+        );
+        assert_eq!(
+            stripped("/nic/update?pass%77ord=secret&hostname=nas&pass%77ord=again"),
+            (
+                Some("secret".to_string()),
+                "/nic/update?hostname=nas".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn verify_password_accepts_equal_passwords() {
+        assert!(verify_password("hunter2", "hunter2"));
+        assert!(verify_password("", ""));
+        assert!(verify_password("pässwörd", "pässwörd"));
+    }
+
+    #[test]
+    fn verify_password_rejects_different_content() {
+        assert!(!verify_password("hunter2", "hunter3"));
+        assert!(!verify_password("Hunter2", "hunter2"));
+    }
+
+    #[test]
+    fn verify_password_rejects_different_lengths() {
+        assert!(!verify_password("hunter", "hunter2"));
+        assert!(!verify_password("hunter22", "hunter2"));
+        assert!(!verify_password("", "hunter2"));
+        assert!(!verify_password("hunter2", ""));
+    }
+
+    #[test]
+    fn client_password_detects_the_format() {
+        let argon2 = hash_password("hunter2").unwrap();
+        let bcrypt = bcrypt::hash("hunter2", 4).unwrap();
+
+        assert_eq!(
+            ClientPassword::parse(argon2.clone()).unwrap(),
+            ClientPassword::Argon2(argon2)
+        );
+        assert_eq!(
+            ClientPassword::parse(bcrypt.clone()).unwrap(),
+            ClientPassword::Bcrypt(bcrypt)
+        );
+        assert_eq!(
+            ClientPassword::parse("hunter2".to_string()).unwrap(),
+            ClientPassword::Plain("hunter2".to_string())
+        );
+        assert!(
+            ClientPassword::parse("$argon2id$v=19$m=1,t=1,p=1$not base64!".to_string()).is_err()
+        );
+        assert!(ClientPassword::parse("$2b$nonsense".to_string()).is_err());
+    }
+
+    #[tokio::test]
+    async fn password_checker_verifies_hashes() {
+        let checker = PasswordChecker::new(vec![
+            ClientPassword::parse(hash_password("first").unwrap()).unwrap(),
+            ClientPassword::parse(bcrypt::hash("second", 4).unwrap()).unwrap(),
+        ]);
+
+        assert_eq!(checker.verify("first").await, Some(0));
+        assert_eq!(checker.verify("second").await, Some(1));
+        assert_eq!(checker.verify("third").await, None);
+    }
+
+    #[tokio::test]
+    async fn password_checker_caches_a_fingerprint() {
+        let checker = PasswordChecker::new(vec![
+            ClientPassword::parse(bcrypt::hash("hunter2", 4).unwrap()).unwrap(),
+        ]);
+        assert_eq!(checker.verify("hunter2").await, Some(0));
+
+        let (cached, index, _) = checker.last_success.lock().unwrap().unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(cached, checker.fingerprint("hunter2"));
+
+        assert_eq!(checker.verify("hunter2").await, Some(0));
+        assert_eq!(checker.verify("hunter3").await, None);
+    }
+
+    #[test]
+    fn split_passwords_accepts_commas_and_newlines() {
+        assert_eq!(
+            split_passwords("old, new\n third \n\n"),
+            vec!["old", "new", "third"]
+        );
+        let hash = "$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA";
+        assert_eq!(
+            split_passwords(&format!("plain\n{hash}")),
+            vec!["plain", hash]
+        );
+    }
+
+    #[tokio::test]
+    async fn password_checker_matches_the_second_password() {
+        let checker =
+            PasswordChecker::new(parse_client_passwords(split_passwords("old,new")).unwrap());
+
+        assert_eq!(checker.count(), 2);
+        assert_eq!(checker.verify("new").await, Some(1));
+        assert_eq!(checker.verify("old").await, Some(0));
+    }
+
+    #[tokio::test]
+    async fn password_checker_rejects_unknown_passwords() {
+        let checker =
+            PasswordChecker::new(parse_client_passwords(split_passwords("old,new")).unwrap());
+
+        assert_eq!(checker.verify("neither").await, None);
+        assert_eq!(checker.verify("").await, None);
+        assert_eq!(checker.verify("old,new").await, None);
+    }
+}
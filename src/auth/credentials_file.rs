@@ -0,0 +1,272 @@
+//! Client passwords read from `CREDENTIALS_FILE`, which is watched for changes so credentials
+//! provisioned by e.g. Ansible apply without a restart.
+
+use super::{AuthConfig, ClientPassword, parse_client_passwords, split_passwords};
+use notify::{EventKind, RecursiveMode, Watcher};
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+/// Changes are applied once the file was quiet for this long, as tools often write files in
+/// several steps.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Reads the passwords in `path`, in the format of `PASSWORDS`.
+pub fn load(path: &Path) -> Result<Vec<ClientPassword>, Report> {
+    let content = std::fs::read_to_string(path)
+        .context("Failed to read credentials file")
+        .attach(format!("path: {}", path.display()))?;
+    let passwords = split_passwords(&content);
+    if passwords.is_empty() {
+        bail!(
+            "Credentials file {} does not contain any password",
+            path.display()
+        );
+    }
+    Ok(parse_client_passwords(passwords)
+        .context("Invalid credentials file")
+        .attach(format!("path: {}", path.display()))?)
+}
+
+/// Replaces the passwords of `auth` whenever `path` changes. If the new file is invalid, the
+/// previous passwords stay active.
+///
+/// The directory of the file is watched, so files replaced by renaming another one over them are
+/// picked up as well.
+pub fn watch(path: PathBuf, auth: Arc<AuthConfig>) -> Result<(), Report> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = path.file_name().map(ToOwned::to_owned);
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+            Ok(event) => {
+                if event
+                    .paths
+                    .iter()
+                    .any(|it| it.file_name() == file_name.as_deref())
+                {
+                    let _ = tx.send(());
+                }
+            }
+            Err(e) => warn!(error = %e, "Failed to watch the credentials file"),
+        })
+        .context("Failed to watch the credentials file")?;
+    watcher
+        .watch(&directory, RecursiveMode::NonRecursive)
+        .context("Failed to watch the credentials file")
+        .attach(format!("directory: {}", directory.display()))?;
+
+    info!(path = %path.display(), "Watching the credentials file for changes");
+    tokio::spawn(async move {
+        // Stops watching when dropped
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            tokio::time::sleep(DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+            reload(&path, &auth);
+        }
+    });
+    Ok(())
+}
+
+fn reload(path: &Path, auth: &AuthConfig) {
+    let passwords = match load(path) {
+        Ok(passwords) => passwords,
+        Err(e) => {
+            error!(error = %e, "Failed to reload the credentials file, keeping the previous ones");
+            return;
+        }
+    };
+
+    let previous = auth.passwords.current();
+    if *previous == passwords {
+        debug!("Credentials file changed, but its passwords did not");
+        return;
+    }
+    let added = passwords.iter().filter(|it| !previous.contains(it)).count();
+    let removed = previous.iter().filter(|it| !passwords.contains(it)).count();
+    let count = passwords.len();
+    if let Err(e) = auth.replace_passwords(passwords) {
+        error!(error = %e, "Failed to reload the credentials file, keeping the previous ones");
+        return;
+    }
+    info!(count, added, removed, "Reloaded the credentials file");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::memory::MemoryProvider;
+    use crate::test_support::*;
+    use crate::{DynDnsServer, Origin};
+    use axum::body::Body;
+    use axum::http::{StatusCode, header};
+    use tokio::time::{Instant, sleep};
+
+    /// Well above the debounce of the watcher, so slow CI machines do not fail the tests.
+    const RELOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// A fresh directory for the credentials file of one test.
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "speedport-credentials-{}-{test}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A server with the passwords of `path`, which is watched for changes.
+    fn server(path: &Path, provider: &Arc<MemoryProvider>) -> DynDnsServer {
+        let mut builder = DynDnsServer::builder()
+            .origin(Origin::parse("foobar.de").unwrap())
+            .provider(provider.clone());
+        for password in load(path).unwrap() {
+            builder = builder.password(password);
+        }
+        let server = builder.build().unwrap();
+        watch(path.to_path_buf(), server.state().auth.clone()).unwrap();
+        server
+    }
+
+    /// Waits until `password` is accepted, or fails after [`RELOAD_TIMEOUT`].
+    async fn wait_until_accepted(server: &DynDnsServer, password: &str) {
+        let deadline = Instant::now() + RELOAD_TIMEOUT;
+        while server
+            .state()
+            .auth
+            .passwords
+            .verify(password)
+            .await
+            .is_none()
+        {
+            assert!(
+                Instant::now() < deadline,
+                "'{password}' was not accepted in time"
+            );
+            sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    async fn update_status(server: &DynDnsServer, password: &str) -> StatusCode {
+        let request = request("/nic/update?hostname=nas.foobar.de&myip=198.51.100.7")
+            .header(header::AUTHORIZATION, basic_auth("router", password))
+            .body(Body::empty())
+            .unwrap();
+        send(&server.router(), request).await.0
+    }
+
+    #[tokio::test]
+    async fn changed_passwords_apply_without_restart() {
+        let dir = temp_dir("changed");
+        let path = dir.join("credentials");
+        std::fs::write(&path, "old-password\n").unwrap();
+        let provider = Arc::new(MemoryProvider::new(nas_records()));
+        let server = server(&path, &provider);
+        assert_eq!(
+            update_status(&server, "new-password").await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        std::fs::write(&path, "new-password\n").unwrap();
+        wait_until_accepted(&server, "new-password").await;
+
+        assert_eq!(update_status(&server, "new-password").await, StatusCode::OK);
+        assert_eq!(
+            update_status(&server, "old-password").await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(server.state().auth.passwords.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn files_renamed_over_the_old_one_are_picked_up() {
+        let dir = temp_dir("renamed");
+        let path = dir.join("credentials");
+        std::fs::write(&path, "old-password").unwrap();
+        let provider = Arc::new(MemoryProvider::new(nas_records()));
+        let server = server(&path, &provider);
+
+        // How Ansible and most editors replace files
+        let staged = dir.join("credentials.tmp");
+        std::fs::write(&staged, "old-password, new-password").unwrap();
+        std::fs::rename(&staged, &path).unwrap();
+
+        wait_until_accepted(&server, "new-password").await;
+        assert_eq!(server.state().auth.passwords.count(), 2);
+    }
+
+    #[tokio::test]
+    async fn invalid_files_keep_the_previous_passwords() {
+        let dir = temp_dir("invalid");
+        let path = dir.join("credentials");
+        std::fs::write(&path, "old-password").unwrap();
+        let provider = Arc::new(MemoryProvider::new(nas_records()));
+        let server = server(&path, &provider);
+        let (logs, _guard) = capture_logs();
+
+        std::fs::write(&path, "$argon2id$v=19$m=1,t=1,p=1$not base64!").unwrap();
+        sleep(Duration::from_secs(2)).await;
+        std::fs::write(&path, "").unwrap();
+        sleep(Duration::from_secs(2)).await;
+
+        assert!(
+            server
+                .state()
+                .auth
+                .passwords
+                .verify("old-password")
+                .await
+                .is_some()
+        );
+        assert_eq!(update_status(&server, "old-password").await, StatusCode::OK);
+
+        // A valid file afterwards applies again
+        std::fs::write(&path, "new-password").unwrap();
+        wait_until_accepted(&server, "new-password").await;
+
+        let logs = logs.contents();
+        assert!(logs.contains("keeping the previous ones"), "{logs}");
+        assert!(logs.contains("Reloaded the credentials file"), "{logs}");
+        assert!(!logs.contains("old-password"), "{logs}");
+        assert!(!logs.contains("new-password"), "{logs}");
+    }
+
+    #[test]
+    fn load_reads_the_format_of_passwords() {
+        let dir = temp_dir("load");
+        let path = dir.join("credentials");
+        std::fs::write(&path, "first, second\n\n third \n").unwrap();
+
+        assert_eq!(
+            load(&path).unwrap(),
+            vec![
+                ClientPassword::Plain("first".to_string()),
+                ClientPassword::Plain("second".to_string()),
+                ClientPassword::Plain("third".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn load_rejects_empty_and_missing_files() {
+        let dir = temp_dir("load-errors");
+        let path = dir.join("credentials");
+
+        let error = load(&path).unwrap_err().to_string();
+        assert!(error.contains("Failed to read credentials file"), "{error}");
+
+        std::fs::write(&path, " \n,\n").unwrap();
+        let error = load(&path).unwrap_err().to_string();
+        assert!(error.contains("does not contain any password"), "{error}");
+    }
+}
@@ -0,0 +1,480 @@
+//! HTTP Digest authentication as described in RFC 7616.
+//!
+//! Only the `auth` quality of protection and the MD5 and SHA-256 algorithms are supported. Nonces
+//! are kept in a bounded in-memory store and expire after a while; each nonce count may only be
+//! used once per nonce, which prevents replaying captured requests.
+
+use axum::http::HeaderValue;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use derive_more::Display;
+use jiff::{SignedDuration, Timestamp};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use subtle::ConstantTimeEq;
+
+/// How long an issued nonce stays valid.
+const NONCE_LIFETIME: SignedDuration = SignedDuration::from_secs(300);
+/// Maximum number of nonces remembered at once. The oldest ones are evicted first.
+const MAX_NONCES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum DigestAlgorithm {
+    #[display("MD5")]
+    Md5,
+    #[display("SHA-256")]
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_uppercase().as_str() {
+            "MD5" => Some(Self::Md5),
+            "SHA-256" => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    fn hash(self, data: &str) -> String {
+        match self {
+            Self::Md5 => format!("{:x}", Md5::digest(data.as_bytes())),
+            Self::Sha256 => format!("{:x}", Sha256::digest(data.as_bytes())),
+        }
+    }
+}
+
+#[derive(Debug, Display, PartialEq, Eq)]
+pub enum DigestError {
+    #[display("malformed digest header: {_0}")]
+    Malformed(&'static str),
+    #[display("unsupported digest parameters: {_0}")]
+    Unsupported(&'static str),
+    #[display("unknown or expired nonce")]
+    UnknownNonce,
+    #[display("nonce count was already used")]
+    Replay,
+    #[display("uri does not match request")]
+    UriMismatch,
+    #[display("invalid credentials")]
+    InvalidCredentials,
+}
+
+/// The parameters of a `Digest` authorization header.
+#[derive(Debug, Clone)]
+pub struct DigestResponse {
+    pub username: String,
+    realm: String,
+    nonce: String,
+    uri: String,
+    response: String,
+    algorithm: DigestAlgorithm,
+    cnonce: String,
+    nc: u32,
+    nc_raw: String,
+    qop: String,
+}
+
+impl DigestResponse {
+    /// Parses the value of an `Authorization` header, including the leading `Digest` scheme.
+    pub fn parse(header: &str) -> Result<Self, DigestError> {
+        let (scheme, params) = header
+            .trim()
+            .split_once(' ')
+            .ok_or(DigestError::Malformed("missing parameters"))?;
+        if !scheme.eq_ignore_ascii_case("digest") {
+            return Err(DigestError::Malformed("not a digest header"));
+        }
+
+        let mut params = parse_params(params)?;
+        let mut take = |name: &'static str| {
+            params
+                .remove(name)
+                .ok_or(DigestError::Malformed("missing parameter"))
+        };
+
+        let username = take("username")?;
+        let realm = take("realm")?;
+        let nonce = take("nonce")?;
+        let uri = take("uri")?;
+        let response = take("response")?;
+        let cnonce = take("cnonce")?;
+        let nc_raw = take("nc")?;
+        let qop = take("qop")?;
+        let algorithm = match params.remove("algorithm") {
+            Some(it) => DigestAlgorithm::parse(&it).ok_or(DigestError::Unsupported("algorithm"))?,
+            None => DigestAlgorithm::Md5,
+        };
+        if qop != "auth" {
+            return Err(DigestError::Unsupported("qop"));
+        }
+        if params
+            .get("userhash")
+            .is_some_and(|it| it.eq_ignore_ascii_case("true"))
+        {
+            return Err(DigestError::Unsupported("userhash"));
+        }
+        let nc = u32::from_str_radix(&nc_raw, 16).map_err(|_| DigestError::Malformed("nc"))?;
+
+        Ok(Self {
+            username,
+            realm,
+            nonce,
+            uri,
+            response,
+            algorithm,
+            cnonce,
+            nc,
+            nc_raw,
+            qop,
+        })
+    }
+
+    /// Computes the expected `response` value for `password`.
+    fn expected_response(&self, method: &str, password: &str) -> String {
+        let hash = |data: &str| self.algorithm.hash(data);
+        let ha1 = hash(&format!("{}:{}:{}", self.username, self.realm, password));
+        let ha2 = hash(&format!("{}:{}", method, self.uri));
+        hash(&format!(
+            "{ha1}:{}:{}:{}:{}:{ha2}",
+            self.nonce, self.nc_raw, self.cnonce, self.qop
+        ))
+    }
+}
+
+/// Splits `name=value, name="quoted value"` pairs. Names are lowercased.
+fn parse_params(input: &str) -> Result<HashMap<String, String>, DigestError> {
+    let mut params = HashMap::new();
+    let mut chars = input.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        if chars.peek().is_none() {
+            return Ok(params);
+        }
+
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=') {
+            name.push(c);
+        }
+        if chars.next() != Some('=') {
+            return Err(DigestError::Malformed("parameter without value"));
+        }
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => value.push(
+                        chars
+                            .next()
+                            .ok_or(DigestError::Malformed("unterminated escape"))?,
+                    ),
+                    Some(c) => value.push(c),
+                    None => return Err(DigestError::Malformed("unterminated quoted string")),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                value.push(c);
+            }
+        }
+
+        params.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+}
+
+#[derive(Debug)]
+struct NonceState {
+    issued: Timestamp,
+    last_nc: u32,
+}
+
+/// Issues nonces and verifies digest responses against them.
+#[derive(Debug)]
+pub struct DigestAuth {
+    realm: String,
+    nonces: Mutex<HashMap<String, NonceState>>,
+}
+
+impl DigestAuth {
+    pub fn new(realm: String) -> Self {
+        Self {
+            realm,
+            nonces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Issues a fresh nonce and returns one `WWW-Authenticate` challenge per supported algorithm,
+    /// the stronger one first.
+    pub fn challenges(&self, now: Timestamp) -> Vec<HeaderValue> {
+        let nonce = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 24]>());
+
+        let mut nonces = self.nonces.lock().expect("mutex poisoned");
+        nonces.retain(|_, it| it.issued + NONCE_LIFETIME > now);
+        if nonces.len() >= MAX_NONCES
+            && let Some(oldest) = nonces
+                .iter()
+                .min_by_key(|(_, it)| it.issued)
+                .map(|(nonce, _)| nonce.clone())
+        {
+            nonces.remove(&oldest);
+        }
+        nonces.insert(
+            nonce.clone(),
+            NonceState {
+                issued: now,
+                last_nc: 0,
+            },
+        );
+        drop(nonces);
+
+        [DigestAlgorithm::Sha256, DigestAlgorithm::Md5]
+            .into_iter()
+            .filter_map(|algorithm| {
+                HeaderValue::from_str(&format!(
+                    r#"Digest realm="{}", qop="auth", algorithm={algorithm}, nonce="{nonce}""#,
+                    self.realm
+                ))
+                .ok()
+            })
+            .collect()
+    }
+
+    /// Verifies `response` against the plaintext `passwords` and returns the index of the
+    /// matching one.
+    ///
+    /// `request_uri` is the URI the request was made to and `method` its HTTP method.
+    pub fn verify(
+        &self,
+        response: &DigestResponse,
+        method: &str,
+        request_uri: &str,
+        passwords: &[(usize, &str)],
+        now: Timestamp,
+    ) -> Result<usize, DigestError> {
+        if response.realm != self.realm {
+            return Err(DigestError::InvalidCredentials);
+        }
+        if response.uri != request_uri {
+            return Err(DigestError::UriMismatch);
+        }
+
+        let matched = passwords.iter().fold(None, |found, (index, password)| {
+            let expected = response.expected_response(method, password);
+            let matches: bool = expected
+                .as_bytes()
+                .ct_eq(response.response.to_ascii_lowercase().as_bytes())
+                .into();
+            found.or(matches.then_some(*index))
+        });
+        let Some(index) = matched else {
+            return Err(DigestError::InvalidCredentials);
+        };
+
+        // Only consume the nonce count once the response is known to be authentic, so forged
+        // requests can not burn counts of legitimate clients
+        let mut nonces = self.nonces.lock().expect("mutex poisoned");
+        let state = nonces
+            .get_mut(&response.nonce)
+            .filter(|it| it.issued + NONCE_LIFETIME > now)
+            .ok_or(DigestError::UnknownNonce)?;
+        if response.nc <= state.last_nc {
+            return Err(DigestError::Replay);
+        }
+        state.last_nc = response.nc;
+
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example of RFC 7616, section 3.9.1
+    const REALM: &str = "http-auth@example.org";
+    const NONCE: &str = "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v";
+    const CNONCE: &str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+    const PASSWORD: &str = "Circle of Life";
+    const MD5_RESPONSE: &str = "8ca523f5e9506fed4657c9700eebdbec";
+    const SHA256_RESPONSE: &str =
+        "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1";
+
+    fn now() -> Timestamp {
+        Timestamp::from_second(1_700_000_000).unwrap()
+    }
+
+    fn header(algorithm: &str, response: &str, nc: &str) -> String {
+        format!(
+            r#"Digest username="Mufasa", realm="{REALM}", uri="/dir/index.html", algorithm={algorithm}, nonce="{NONCE}", nc={nc}, cnonce="{CNONCE}", qop=auth, response="{response}", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#
+        )
+    }
+
+    fn digest_auth() -> DigestAuth {
+        let auth = DigestAuth::new(REALM.to_string());
+        auth.nonces.lock().unwrap().insert(
+            NONCE.to_string(),
+            NonceState {
+                issued: now(),
+                last_nc: 0,
+            },
+        );
+        auth
+    }
+
+    fn verify(auth: &DigestAuth, header: &str, now: Timestamp) -> Result<usize, DigestError> {
+        let response = DigestResponse::parse(header)?;
+        auth.verify(
+            &response,
+            "GET",
+            "/dir/index.html",
+            &[(0, "wrong"), (1, PASSWORD)],
+            now,
+        )
+    }
+
+    #[test]
+    fn rfc_7616_md5_vector() {
+        let response = DigestResponse::parse(&header("MD5", MD5_RESPONSE, "00000001")).unwrap();
+        assert_eq!(response.algorithm, DigestAlgorithm::Md5);
+        assert_eq!(response.expected_response("GET", PASSWORD), MD5_RESPONSE);
+
+        let auth = digest_auth();
+        assert_eq!(
+            verify(&auth, &header("MD5", MD5_RESPONSE, "00000001"), now()),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn rfc_7616_sha256_vector() {
+        let response =
+            DigestResponse::parse(&header("SHA-256", SHA256_RESPONSE, "00000001")).unwrap();
+        assert_eq!(response.algorithm, DigestAlgorithm::Sha256);
+        assert_eq!(response.expected_response("GET", PASSWORD), SHA256_RESPONSE);
+
+        let auth = digest_auth();
+        assert_eq!(
+            verify(
+                &auth,
+                &header("SHA-256", SHA256_RESPONSE, "00000001"),
+                now()
+            ),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn missing_algorithm_means_md5() {
+        let header = header("MD5", MD5_RESPONSE, "00000001").replace("algorithm=MD5, ", "");
+        let response = DigestResponse::parse(&header).unwrap();
+        assert_eq!(response.algorithm, DigestAlgorithm::Md5);
+        assert_eq!(response.expected_response("GET", PASSWORD), MD5_RESPONSE);
+    }
+
+    #[test]
+    fn responses_without_qop_auth_are_rejected() {
+        for algorithm in ["MD5", "SHA-256"] {
+            let header = header(algorithm, MD5_RESPONSE, "00000001");
+            let without_qop = header.replace("qop=auth, ", "");
+            assert!(matches!(
+                DigestResponse::parse(&without_qop),
+                Err(DigestError::Malformed(_))
+            ));
+            let auth_int = header.replace("qop=auth", "qop=auth-int");
+            assert!(matches!(
+                DigestResponse::parse(&auth_int),
+                Err(DigestError::Unsupported("qop"))
+            ));
+        }
+    }
+
+    #[test]
+    fn wrong_password_is_rejected() {
+        let auth = digest_auth();
+        let response =
+            DigestResponse::parse(&header("SHA-256", SHA256_RESPONSE, "00000001")).unwrap();
+        assert_eq!(
+            auth.verify(&response, "GET", "/dir/index.html", &[(0, "wrong")], now()),
+            Err(DigestError::InvalidCredentials)
+        );
+        assert_eq!(
+            auth.verify(
+                &response,
+                "POST",
+                "/dir/index.html",
+                &[(0, PASSWORD)],
+                now()
+            ),
+            Err(DigestError::InvalidCredentials)
+        );
+    }
+
+    #[test]
+    fn uri_must_match_the_request() {
+        let auth = digest_auth();
+        let response = DigestResponse::parse(&header("MD5", MD5_RESPONSE, "00000001")).unwrap();
+        assert_eq!(
+            auth.verify(&response, "GET", "/other", &[(0, PASSWORD)], now()),
+            Err(DigestError::UriMismatch)
+        );
+    }
+
+    #[test]
+    fn nonce_counts_can_not_be_replayed() {
+        let auth = digest_auth();
+        let first = header("MD5", MD5_RESPONSE, "00000001");
+        assert_eq!(verify(&auth, &first, now()), Ok(1));
+        assert_eq!(verify(&auth, &first, now()), Err(DigestError::Replay));
+
+        let response = DigestResponse::parse(&header("MD5", "", "00000002")).unwrap();
+        let second = header(
+            "MD5",
+            &response.expected_response("GET", PASSWORD),
+            "00000002",
+        );
+        assert_eq!(verify(&auth, &second, now()), Ok(1));
+        assert_eq!(verify(&auth, &first, now()), Err(DigestError::Replay));
+    }
+
+    #[test]
+    fn unknown_and_expired_nonces_are_rejected() {
+        let auth = DigestAuth::new(REALM.to_string());
+        let header = header("MD5", MD5_RESPONSE, "00000001");
+        assert_eq!(
+            verify(&auth, &header, now()),
+            Err(DigestError::UnknownNonce)
+        );
+
+        let auth = digest_auth();
+        assert_eq!(
+            verify(&auth, &header, now() + NONCE_LIFETIME),
+            Err(DigestError::UnknownNonce)
+        );
+    }
+
+    #[test]
+    fn challenges_offer_both_algorithms() {
+        let auth = DigestAuth::new("dyndns".to_string());
+        let challenges = auth.challenges(now());
+
+        assert_eq!(challenges.len(), 2);
+        let sha256 = challenges[0].to_str().unwrap();
+        let md5 = challenges[1].to_str().unwrap();
+        assert!(sha256.starts_with(r#"Digest realm="dyndns", qop="auth", algorithm=SHA-256"#));
+        assert!(md5.starts_with(r#"Digest realm="dyndns", qop="auth", algorithm=MD5"#));
+        assert_eq!(auth.nonces.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn quoted_values_are_unescaped() {
+        let params = parse_params(r#"username="Mu\"fasa", nc=00000001 , realm="a, b""#).unwrap();
+        assert_eq!(params["username"], r#"Mu"fasa"#);
+        assert_eq!(params["nc"], "00000001");
+        assert_eq!(params["realm"], "a, b");
+        assert!(parse_params(r#"username="unterminated"#).is_err());
+    }
+}
@@ -0,0 +1,487 @@
+//! Updates authenticated by an HMAC over their parameters, for clients that should not hold a
+//! reusable password.
+//!
+//! A signed update carries exactly the query parameters `hostname`, `myip`, `ts` and `sig`:
+//!
+//! - `ts` is the current Unix time in seconds, as decimal digits.
+//! - `sig` is the HMAC-SHA256 of the [canonical string](canonical_string), keyed with the UTF-8
+//!   bytes of the secret and encoded as hex (either case).
+//!
+//! Other parameters are rejected, as they would not be covered by the signature. Requests whose
+//! timestamp is further than the allowed skew from the server time are rejected, and so is a
+//! pair of timestamp and signature that was already used.
+
+use crate::auth::ApiToken;
+use derive_more::Display;
+use hmac::{Hmac, Mac};
+use jiff::{SignedDuration, Timestamp};
+use sha2::Sha256;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::Mutex;
+use subtle::ConstantTimeEq;
+
+/// How far the timestamp of a signed request may be from the server time by default.
+pub const DEFAULT_MAX_SKEW: SignedDuration = SignedDuration::from_secs(300);
+/// Maximum number of used signatures remembered at once. The oldest ones are evicted first.
+const MAX_SEEN: usize = 4096;
+
+/// The query parameter carrying the signature. Requests without it are not signed.
+pub const SIGNATURE_PARAM: &str = "sig";
+
+#[derive(Debug, Display, PartialEq, Eq)]
+pub enum SignatureError {
+    #[display("malformed signed request: {_0}")]
+    Malformed(&'static str),
+    #[display("parameter '{_0}' is not covered by the signature")]
+    UnsignedParameter(String),
+    #[display("timestamp is outside of the allowed skew")]
+    Stale,
+    #[display("signature was already used")]
+    Replay,
+    #[display("invalid signature")]
+    InvalidSignature,
+}
+
+/// The parameters of a signed update.
+#[derive(Debug, Clone)]
+pub struct SignedRequest {
+    hostname: String,
+    myip: String,
+    /// The timestamp as sent, which is what is signed.
+    ts_raw: String,
+    ts: Timestamp,
+    signature: String,
+}
+
+impl SignedRequest {
+    /// Parses the signed parameters of `query`, or returns `None` if it has no signature.
+    pub fn parse(query: &str) -> Option<Result<Self, SignatureError>> {
+        let params = form_urlencoded::parse(query.as_bytes()).collect::<Vec<_>>();
+        if !params.iter().any(|(name, _)| name == SIGNATURE_PARAM) {
+            return None;
+        }
+        Some(Self::from_params(params))
+    }
+
+    fn from_params(params: Vec<(Cow<'_, str>, Cow<'_, str>)>) -> Result<Self, SignatureError> {
+        let mut values = HashMap::new();
+        for (name, value) in params {
+            if !["hostname", "myip", "ts", SIGNATURE_PARAM].contains(&name.as_ref()) {
+                return Err(SignatureError::UnsignedParameter(name.into_owned()));
+            }
+            if values.insert(name, value).is_some() {
+                return Err(SignatureError::Malformed("duplicate parameter"));
+            }
+        }
+        let mut take = |name: &'static str| {
+            values
+                .remove(name)
+                .map(|it| it.into_owned())
+                .ok_or(SignatureError::Malformed("missing parameter"))
+        };
+        let hostname = take("hostname")?;
+        let myip = take("myip").unwrap_or_default();
+        let ts_raw = take("ts")?;
+        let signature = take(SIGNATURE_PARAM)?;
+
+        if ts_raw.is_empty() || !ts_raw.bytes().all(|it| it.is_ascii_digit()) {
+            return Err(SignatureError::Malformed("ts is not a Unix timestamp"));
+        }
+        let ts = ts_raw
+            .parse::<i64>()
+            .ok()
+            .and_then(|it| Timestamp::from_second(it).ok())
+            .ok_or(SignatureError::Malformed("ts is out of range"))?;
+
+        Ok(Self {
+            hostname,
+            myip,
+            ts_raw,
+            ts,
+            signature: signature.to_ascii_lowercase(),
+        })
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+}
+
+/// The string a signed update signs: the percent-decoded values of `hostname`, `myip` and `ts`
+/// exactly as sent, joined by `|`. Nothing is trimmed or case folded, and a missing `myip` is the
+/// empty string.
+///
+/// E.g. `nas.example.com|203.0.113.7|1767225600` for
+/// `?hostname=nas.example.com&myip=203.0.113.7&ts=1767225600`.
+pub fn canonical_string(hostname: &str, myip: &str, ts: &str) -> String {
+    format!("{hostname}|{myip}|{ts}")
+}
+
+/// Computes the lowercase hex signature of an update, as a client would.
+pub fn sign(secret: &str, hostname: &str, myip: &str, ts: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(canonical_string(hostname, myip, ts).as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+/// Verifies signed updates and remembers the used signatures.
+#[derive(Debug)]
+pub struct SignedAuth {
+    secrets: Vec<ApiToken>,
+    max_skew: SignedDuration,
+    /// Used timestamps and signatures. They can be forgotten once the timestamp is stale.
+    seen: Mutex<HashSet<(Timestamp, String)>>,
+}
+
+impl SignedAuth {
+    pub fn new(secrets: Vec<ApiToken>, max_skew: SignedDuration) -> Self {
+        Self {
+            secrets,
+            max_skew,
+            seen: Mutex::default(),
+        }
+    }
+
+    pub fn secrets(&self) -> &[ApiToken] {
+        &self.secrets
+    }
+
+    /// Verifies `request` and returns the index of the secret it was signed with.
+    pub fn verify(&self, request: &SignedRequest, now: Timestamp) -> Result<usize, SignatureError> {
+        if now.duration_since(request.ts).abs() > self.max_skew {
+            return Err(SignatureError::Stale);
+        }
+
+        // All secrets are compared, so timing does not reveal which one matched
+        let matched = self
+            .secrets
+            .iter()
+            .enumerate()
+            .fold(None, |found, (index, secret)| {
+                let expected = sign(
+                    &secret.token,
+                    &request.hostname,
+                    &request.myip,
+                    &request.ts_raw,
+                );
+                let matches: bool = expected
+                    .as_bytes()
+                    .ct_eq(request.signature.as_bytes())
+                    .into();
+                found.or(matches.then_some(index))
+            });
+        let Some(index) = matched else {
+            return Err(SignatureError::InvalidSignature);
+        };
+
+        // Only remember the signature once it is known to be authentic, so forged requests can
+        // not fill the store
+        let mut seen = self.seen.lock().expect("mutex poisoned");
+        seen.retain(|(ts, _)| now.duration_since(*ts).abs() <= self.max_skew);
+        let key = (request.ts, request.signature.clone());
+        if seen.contains(&key) {
+            return Err(SignatureError::Replay);
+        }
+        if seen.len() >= MAX_SEEN
+            && let Some(oldest) = seen.iter().min().cloned()
+        {
+            seen.remove(&oldest);
+        }
+        seen.insert(key);
+
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "abcdefghijklmnopqrstuvwx";
+    /// The example of the README.
+    const EXAMPLE_SIG: &str = "c55919af8b27a5f532ae4173ff17b7a1b510f40ba243a04b654f2b056697bbc5";
+    const TS: i64 = 1767225600;
+
+    fn auth() -> SignedAuth {
+        SignedAuth::new(
+            vec![ApiToken {
+                token: SECRET.to_string(),
+                allowed_hostnames: None,
+            }],
+            DEFAULT_MAX_SKEW,
+        )
+    }
+
+    fn at(seconds: i64) -> Timestamp {
+        Timestamp::from_second(seconds).unwrap()
+    }
+
+    fn parse(query: &str) -> Result<SignedRequest, SignatureError> {
+        SignedRequest::parse(query).expect("query is signed")
+    }
+
+    fn example() -> SignedRequest {
+        parse(&format!(
+            "hostname=nas.foobar.de&myip=203.0.113.7&ts={TS}&sig={EXAMPLE_SIG}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn canonical_string_joins_the_values() {
+        assert_eq!(
+            canonical_string("nas.foobar.de", "203.0.113.7", "1767225600"),
+            "nas.foobar.de|203.0.113.7|1767225600"
+        );
+        assert_eq!(
+            canonical_string("nas.foobar.de", "", "1767225600"),
+            "nas.foobar.de||1767225600"
+        );
+    }
+
+    #[test]
+    fn sign_matches_fixed_vectors() {
+        let vectors = [
+            (
+                SECRET,
+                "nas.foobar.de",
+                "203.0.113.7",
+                "1767225600",
+                EXAMPLE_SIG,
+            ),
+            (
+                SECRET,
+                "nas.foobar.de",
+                "",
+                "1767225600",
+                "5ee10dd52b87ba01db826198c3cd025dc9cd96dd8b35b8779bdaf7ab50ea2348",
+            ),
+            (
+                SECRET,
+                "nas.foobar.de",
+                "203.0.113.7,2001:db8::1",
+                "1767225600",
+                "4c42a63b2100dd13eed17e1ba0e35c0f23b87d4942d85f3fbc0afb5e8d6ff2a3",
+            ),
+            (
+                SECRET,
+                "NAS.foobar.de",
+                "203.0.113.7",
+                "1767225600",
+                "bd1cc7ad8a4c8a715997f40c2eab640c038204836b4f28c90fd8bd54c50913bc",
+            ),
+            (
+                "schlüssel-0123456789abcdef",
+                "bücher.foobar.de",
+                "203.0.113.7",
+                "0",
+                "cd57a4e5e1a8d7459c4ae10c96b634626803a12aac252daf5c606da097047bcb",
+            ),
+        ];
+        for (secret, hostname, myip, ts, expected) in vectors {
+            assert_eq!(
+                sign(secret, hostname, myip, ts),
+                expected,
+                "{hostname}|{myip}|{ts}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_ignores_unsigned_queries() {
+        assert!(SignedRequest::parse("hostname=nas.foobar.de&myip=203.0.113.7").is_none());
+        assert!(SignedRequest::parse("").is_none());
+    }
+
+    #[test]
+    fn parse_percent_decodes_the_signed_values() {
+        let request = parse(
+            "hostname=nas.foobar.de&myip=203.0.113.7%2C2001%3Adb8%3A%3A1&ts=1767225600\
+             &sig=4c42a63b2100dd13eed17e1ba0e35c0f23b87d4942d85f3fbc0afb5e8d6ff2a3",
+        )
+        .unwrap();
+
+        assert_eq!(request.myip, "203.0.113.7,2001:db8::1");
+        assert_eq!(auth().verify(&request, at(TS)), Ok(0));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_requests() {
+        let cases = [
+            (
+                "hostname=a&myip=b&ts=1&sig=00&offline=YES",
+                SignatureError::UnsignedParameter("offline".to_string()),
+            ),
+            (
+                "hostname=a&hostname=b&ts=1&sig=00",
+                SignatureError::Malformed("duplicate parameter"),
+            ),
+            (
+                "myip=b&ts=1&sig=00",
+                SignatureError::Malformed("missing parameter"),
+            ),
+            (
+                "hostname=a&sig=00",
+                SignatureError::Malformed("missing parameter"),
+            ),
+            (
+                "hostname=a&ts=&sig=00",
+                SignatureError::Malformed("ts is not a Unix timestamp"),
+            ),
+            (
+                "hostname=a&ts=-1&sig=00",
+                SignatureError::Malformed("ts is not a Unix timestamp"),
+            ),
+            (
+                "hostname=a&ts=1.5&sig=00",
+                SignatureError::Malformed("ts is not a Unix timestamp"),
+            ),
+            (
+                "hostname=a&ts=99999999999999999999&sig=00",
+                SignatureError::Malformed("ts is out of range"),
+            ),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(parse(query).unwrap_err(), expected, "{query}");
+        }
+    }
+
+    #[test]
+    fn verify_accepts_the_example() {
+        assert_eq!(auth().verify(&example(), at(TS)), Ok(0));
+    }
+
+    #[test]
+    fn verify_accepts_uppercase_hex() {
+        let request = parse(&format!(
+            "hostname=nas.foobar.de&myip=203.0.113.7&ts={TS}&sig={}",
+            EXAMPLE_SIG.to_ascii_uppercase()
+        ))
+        .unwrap();
+
+        assert_eq!(auth().verify(&request, at(TS)), Ok(0));
+    }
+
+    #[test]
+    fn verify_returns_the_index_of_the_matching_secret() {
+        let auth = SignedAuth::new(
+            vec![
+                ApiToken {
+                    token: "another-secret-0123456789".to_string(),
+                    allowed_hostnames: None,
+                },
+                ApiToken {
+                    token: SECRET.to_string(),
+                    allowed_hostnames: None,
+                },
+            ],
+            DEFAULT_MAX_SKEW,
+        );
+
+        assert_eq!(auth.verify(&example(), at(TS)), Ok(1));
+    }
+
+    #[test]
+    fn verify_rejects_changed_values() {
+        let queries = [
+            format!("hostname=nas.foobar.de&myip=203.0.113.8&ts={TS}&sig={EXAMPLE_SIG}"),
+            format!("hostname=NAS.foobar.de&myip=203.0.113.7&ts={TS}&sig={EXAMPLE_SIG}"),
+            format!("hostname=nas.foobar.de&ts={TS}&sig={EXAMPLE_SIG}"),
+            format!("hostname=nas.foobar.de&myip=203.0.113.7&ts=0{TS}&sig={EXAMPLE_SIG}"),
+            format!(
+                "hostname=nas.foobar.de&myip=203.0.113.7&ts={TS}&sig={}",
+                &EXAMPLE_SIG[1..]
+            ),
+        ];
+        for query in queries {
+            assert_eq!(
+                auth().verify(&parse(&query).unwrap(), at(TS)),
+                Err(SignatureError::InvalidSignature),
+                "{query}"
+            );
+        }
+    }
+
+    #[test]
+    fn verify_rejects_timestamps_outside_the_skew() {
+        let skew = DEFAULT_MAX_SKEW.as_secs();
+
+        assert_eq!(auth().verify(&example(), at(TS + skew)), Ok(0));
+        assert_eq!(auth().verify(&example(), at(TS - skew)), Ok(0));
+        assert_eq!(
+            auth().verify(&example(), at(TS + skew + 1)),
+            Err(SignatureError::Stale)
+        );
+        assert_eq!(
+            auth().verify(&example(), at(TS - skew - 1)),
+            Err(SignatureError::Stale)
+        );
+    }
+
+    #[test]
+    fn verify_rejects_stale_timestamps_before_the_signature() {
+        let request = parse(&format!(
+            "hostname=nas.foobar.de&myip=203.0.113.7&ts={TS}&sig=00"
+        ))
+        .unwrap();
+
+        assert_eq!(
+            auth().verify(&request, at(TS + 3600)),
+            Err(SignatureError::Stale)
+        );
+    }
+
+    #[test]
+    fn verify_rejects_replays() {
+        let auth = auth();
+
+        assert_eq!(auth.verify(&example(), at(TS)), Ok(0));
+        assert_eq!(
+            auth.verify(&example(), at(TS + 1)),
+            Err(SignatureError::Replay)
+        );
+    }
+
+    #[test]
+    fn verify_does_not_remember_invalid_signatures() {
+        let auth = auth();
+        let forged = parse(&format!(
+            "hostname=nas.foobar.de&myip=203.0.113.7&ts={TS}&sig={}",
+            "0".repeat(64)
+        ))
+        .unwrap();
+
+        assert_eq!(
+            auth.verify(&forged, at(TS)),
+            Err(SignatureError::InvalidSignature)
+        );
+        assert!(auth.seen.lock().unwrap().is_empty());
+        assert_eq!(auth.verify(&example(), at(TS)), Ok(0));
+    }
+
+    #[test]
+    fn verify_forgets_signatures_once_stale() {
+        let auth = auth();
+        let skew = DEFAULT_MAX_SKEW.as_secs();
+        auth.verify(&example(), at(TS)).unwrap();
+
+        let next = TS + skew + 1;
+        let later = parse(&format!(
+            "hostname=nas.foobar.de&myip=203.0.113.7&ts={next}&sig={}",
+            sign(SECRET, "nas.foobar.de", "203.0.113.7", &next.to_string())
+        ))
+        .unwrap();
+        assert_eq!(auth.verify(&later, at(next)), Ok(0));
+
+        assert_eq!(auth.seen.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,856 @@
+use crate::watch::Ipv6Source;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(
+    version = crate::version::LONG_VERSION,
+    about = "A DynDNS v2 server forwarding updates to your DNS provider",
+    after_help = "Settings are taken from the first of: command line flag, environment variable, \
+        config file (per-hostname settings only), built-in default. Secrets can also be read \
+        from the file named in the matching _FILE flag or variable, or from Vault via _VAULT_PATH."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub settings: Settings,
+}
+
+/// The settings of the server, each also read from the environment variable named in its help.
+/// Only strings are accepted here, they are validated when the configuration is loaded. See
+/// [`crate::settings`] for how flags and variables are combined.
+#[derive(Debug, Default, Args)]
+pub struct Settings {
+    /// The interface to listen on [default: 0.0.0.0]
+    #[arg(long, global = true, env = "INTERFACE", help_heading = "Server")]
+    pub interface: Option<String>,
+    /// The port to listen on [default: 3000]
+    #[arg(long, global = true, env = "PORT", help_heading = "Server")]
+    pub port: Option<String>,
+    /// Set SO_REUSEPORT, so a new version can listen on the port before the old one stopped
+    #[arg(long, global = true, env = "REUSE_PORT", help_heading = "Server", num_args = 0..=1, default_missing_value = "true")]
+    pub reuse_port: Option<String>,
+    /// Serve all endpoints below this path, e.g. /dyndns for /dyndns/nic/update
+    #[arg(long, global = true, env = "BASE_PATH", help_heading = "Server")]
+    pub base_path: Option<String>,
+    /// Serve the status page at / [default: true]
+    #[arg(long, global = true, env = "DASHBOARD", help_heading = "Server", num_args = 0..=1, default_missing_value = "true")]
+    pub dashboard: Option<String>,
+    /// Send the full error report (with secrets redacted) to clients, only meant for the setup
+    #[arg(long, global = true, env = "DEBUG_ERRORS", help_heading = "Server", num_args = 0..=1, default_missing_value = "true")]
+    pub debug_errors: Option<String>,
+    /// Path of an optional TOML file with per-hostname settings
+    #[arg(long, global = true, env = "CONFIG_FILE", help_heading = "Server")]
+    pub config_file: Option<String>,
+    /// `full`, `pretty` (multi-line), `compact` or `json` [default: full]
+    #[arg(long, global = true, env = "LOG_FORMAT", help_heading = "Server")]
+    pub log_format: Option<String>,
+    /// The zone all updated hostnames must be part of
+    #[arg(long, global = true, env = "ORIGIN", help_heading = "DNS")]
+    pub origin: Option<String>,
+    /// Comma-separated DNS providers compiled into this binary, see `--version`
+    #[arg(long, global = true, env = "PROVIDERS", help_heading = "DNS")]
+    pub providers: Option<String>,
+    /// Cloudflare API token. Prefer the _FILE variant or the variable, flags show up in the process list
+    #[arg(
+        long,
+        global = true,
+        env = "CLOUDFLARE_API_TOKEN",
+        help_heading = "DNS",
+        hide_env_values = true
+    )]
+    pub cloudflare_api_token: Option<String>,
+    /// File containing the Cloudflare API token
+    #[arg(
+        long,
+        global = true,
+        env = "CLOUDFLARE_API_TOKEN_FILE",
+        help_heading = "DNS"
+    )]
+    pub cloudflare_api_token_file: Option<String>,
+    /// TTL written to updated Cloudflare records. Unset keeps the current one
+    #[arg(long, global = true, env = "CLOUDFLARE_TTL", help_heading = "DNS")]
+    pub cloudflare_ttl: Option<String>,
+    /// Whether updated Cloudflare records are proxied. Unset keeps the current setting
+    #[arg(long, global = true, env = "CLOUDFLARE_PROXIED", help_heading = "DNS")]
+    pub cloudflare_proxied: Option<String>,
+    /// Root of the Cloudflare API, for API gateways or a mock server
+    #[arg(long, global = true, env = "CLOUDFLARE_API_BASE", help_heading = "DNS")]
+    pub cloudflare_api_base: Option<String>,
+    /// Warn once the API calls of the last 5 minutes exceed this fraction of the limit [default: 0.8]
+    #[arg(
+        long,
+        global = true,
+        env = "CLOUDFLARE_RATE_LIMIT_WARNING",
+        help_heading = "DNS"
+    )]
+    pub cloudflare_rate_limit_warning: Option<String>,
+    /// Only list and write Cloudflare records carrying this tag
+    #[arg(
+        long,
+        global = true,
+        env = "CLOUDFLARE_MANAGED_TAG",
+        help_heading = "DNS"
+    )]
+    pub cloudflare_managed_tag: Option<String>,
+    /// Only list and write Cloudflare records whose comment contains this marker
+    #[arg(
+        long,
+        global = true,
+        env = "CLOUDFLARE_MANAGED_COMMENT",
+        help_heading = "DNS"
+    )]
+    pub cloudflare_managed_comment: Option<String>,
+    /// The probability of a call to a provider wrapped with `chaos:` failing, between 0 and 1
+    #[arg(long, global = true, env = "CHAOS_FAILURE_RATE", help_heading = "DNS")]
+    pub chaos_failure_rate: Option<String>,
+    /// Latency added to calls of providers wrapped with `chaos:`, in milliseconds, e.g. 50-500
+    #[arg(long, global = true, env = "CHAOS_LATENCY_MS", help_heading = "DNS")]
+    pub chaos_latency_ms: Option<String>,
+    /// Comma-separated faults injected at random: rate_limited, auth_failed, timeout
+    #[arg(long, global = true, env = "CHAOS_ERRORS", help_heading = "DNS")]
+    pub chaos_errors: Option<String>,
+    /// How long an injected timeout hangs before failing
+    #[arg(long, global = true, env = "CHAOS_TIMEOUT_SECS", help_heading = "DNS")]
+    pub chaos_timeout_secs: Option<String>,
+    /// How often the primary of a `failover:` provider is checked while failed over
+    #[arg(
+        long,
+        global = true,
+        env = "FAILOVER_CHECK_INTERVAL_SECS",
+        help_heading = "DNS"
+    )]
+    pub failover_check_interval_secs: Option<String>,
+    /// netcup customer number
+    #[arg(
+        long,
+        global = true,
+        env = "NETCUP_CUSTOMER_NUMBER",
+        help_heading = "DNS"
+    )]
+    pub netcup_customer_number: Option<String>,
+    /// netcup API key. Prefer the _FILE variant or the variable, flags show up in the process list
+    #[arg(
+        long,
+        global = true,
+        env = "NETCUP_API_KEY",
+        help_heading = "DNS",
+        hide_env_values = true
+    )]
+    pub netcup_api_key: Option<String>,
+    /// File containing the netcup API key
+    #[arg(long, global = true, env = "NETCUP_API_KEY_FILE", help_heading = "DNS")]
+    pub netcup_api_key_file: Option<String>,
+    /// netcup API password. Prefer the _FILE variant or the variable, flags show up in the process list
+    #[arg(
+        long,
+        global = true,
+        env = "NETCUP_API_PASSWORD",
+        help_heading = "DNS",
+        hide_env_values = true
+    )]
+    pub netcup_api_password: Option<String>,
+    /// File containing the netcup API password
+    #[arg(
+        long,
+        global = true,
+        env = "NETCUP_API_PASSWORD_FILE",
+        help_heading = "DNS"
+    )]
+    pub netcup_api_password_file: Option<String>,
+    /// `strict`, `warn` or `off` [default: strict]
+    #[arg(long, global = true, env = "STARTUP_VALIDATION", help_heading = "DNS")]
+    pub startup_validation: Option<String>,
+    /// Attempts of strict startup validation before giving up [default: 5]
+    #[arg(
+        long,
+        global = true,
+        env = "STARTUP_VALIDATION_ATTEMPTS",
+        help_heading = "DNS"
+    )]
+    pub startup_validation_attempts: Option<String>,
+    /// The longest delay between two attempts of strict startup validation [default: 30]
+    #[arg(
+        long,
+        global = true,
+        env = "STARTUP_VALIDATION_MAX_DELAY_SECS",
+        help_heading = "DNS"
+    )]
+    pub startup_validation_max_delay_secs: Option<String>,
+    /// Hostnames following the updates of another one, e.g. home.example.com=vpn.example.com,mail.example.com
+    #[arg(long, global = true, env = "ALIASES", help_heading = "DNS")]
+    pub aliases: Option<String>,
+    /// Comma-separated hostnames whose records are listed (and checked) at startup
+    #[arg(long, global = true, env = "MANAGED_HOSTNAMES", help_heading = "DNS")]
+    pub managed_hostnames: Option<String>,
+    /// Fail validation if a managed hostname has neither an A nor an AAAA record
+    #[arg(long, global = true, env = "REQUIRE_MANAGED_RECORDS", help_heading = "DNS", num_args = 0..=1, default_missing_value = "true")]
+    pub require_managed_records: Option<String>,
+    /// Delete all but the first record when several exist for the same hostname and type
+    #[arg(long, global = true, env = "DEDUPE_RECORDS", help_heading = "DNS", num_args = 0..=1, default_missing_value = "true")]
+    pub dedupe_records: Option<String>,
+    /// Only write records of hostnames whose ownership TXT record contains this ID
+    #[arg(long, global = true, env = "OWNERSHIP_ID", help_heading = "DNS")]
+    pub ownership_id: Option<String>,
+    /// Update all hostnames with a suffix when an update carries a delegated IPv6 prefix
+    #[arg(long, global = true, env = "PREFIX_FAN_OUT", help_heading = "DNS", num_args = 0..=1, default_missing_value = "true")]
+    pub prefix_fan_out: Option<String>,
+    /// Move all AAAA records in the old delegated prefix to the new one on a prefix change
+    #[arg(long, global = true, env = "PREFIX_REWRITE", help_heading = "DNS", num_args = 0..=1, default_missing_value = "true")]
+    pub prefix_rewrite: Option<String>,
+    /// Check via DNS-over-HTTPS that updated records become visible
+    #[arg(long, global = true, env = "PROPAGATION_CHECK", help_heading = "DNS", num_args = 0..=1, default_missing_value = "true")]
+    pub propagation_check: Option<String>,
+    /// DoH endpoint speaking the JSON API for the propagation check
+    #[arg(
+        long,
+        global = true,
+        env = "PROPAGATION_RESOLVER",
+        help_heading = "DNS"
+    )]
+    pub propagation_resolver: Option<String>,
+    /// Minimum queries of the propagation check, which also waits out the old TTL [default: 5]
+    #[arg(
+        long,
+        global = true,
+        env = "PROPAGATION_ATTEMPTS",
+        help_heading = "DNS"
+    )]
+    pub propagation_attempts: Option<String>,
+    /// Retry updates that failed at a provider in the background
+    #[arg(long, global = true, env = "RETRY_QUEUE", help_heading = "DNS", num_args = 0..=1, default_missing_value = "true")]
+    pub retry_queue: Option<String>,
+    /// Failed attempts, including the update itself, after which a retry is given up [default: 8]
+    #[arg(long, global = true, env = "RETRY_ATTEMPTS", help_heading = "DNS")]
+    pub retry_attempts: Option<String>,
+    /// JSON file persisting pending retries across restarts
+    #[arg(long, global = true, env = "RETRY_QUEUE_FILE", help_heading = "DNS")]
+    pub retry_queue_file: Option<String>,
+    /// SQLite database storing the hostname state and the update history
+    #[arg(long, global = true, env = "DATABASE_PATH", help_heading = "DNS")]
+    pub database_path: Option<String>,
+    /// Days after which update events are deleted from the database, 0 keeps them [default: 365]
+    #[arg(
+        long,
+        global = true,
+        env = "HISTORY_RETENTION_DAYS",
+        help_heading = "DNS"
+    )]
+    pub history_retention_days: Option<String>,
+    /// The client password. Prefer the _FILE variant or the variable, flags show up in the process list
+    #[arg(
+        long,
+        global = true,
+        env = "PASSWORD",
+        help_heading = "Authentication",
+        hide_env_values = true
+    )]
+    pub password: Option<String>,
+    /// File containing the client password
+    #[arg(
+        long,
+        global = true,
+        env = "PASSWORD_FILE",
+        help_heading = "Authentication"
+    )]
+    pub password_file: Option<String>,
+    /// Several client passwords, separated by commas or newlines. Prefer the _FILE variant or the variable, flags show up in the process list
+    #[arg(
+        long,
+        global = true,
+        env = "PASSWORDS",
+        help_heading = "Authentication",
+        hide_env_values = true
+    )]
+    pub passwords: Option<String>,
+    /// File containing several client passwords
+    #[arg(
+        long,
+        global = true,
+        env = "PASSWORDS_FILE",
+        help_heading = "Authentication"
+    )]
+    pub passwords_file: Option<String>,
+    /// File with the passwords in the format of PASSWORDS, reloaded when it changes
+    #[arg(
+        long,
+        global = true,
+        env = "CREDENTIALS_FILE",
+        help_heading = "Authentication"
+    )]
+    pub credentials_file: Option<String>,
+    /// If set, the username sent by the client must match it as well
+    #[arg(
+        long,
+        global = true,
+        env = "DYNDNS_USERNAME",
+        help_heading = "Authentication"
+    )]
+    pub dyndns_username: Option<String>,
+    /// Comma-separated bearer tokens. Prefer the _FILE variant or the variable, flags show up in the process list
+    #[arg(
+        long,
+        global = true,
+        env = "API_TOKENS",
+        help_heading = "Authentication",
+        hide_env_values = true
+    )]
+    pub api_tokens: Option<String>,
+    /// File containing the bearer tokens
+    #[arg(
+        long,
+        global = true,
+        env = "API_TOKENS_FILE",
+        help_heading = "Authentication"
+    )]
+    pub api_tokens_file: Option<String>,
+    /// Which credentials updates may be authenticated with: password, signed or both [default: password]
+    #[arg(
+        long,
+        global = true,
+        env = "AUTH_MODE",
+        help_heading = "Authentication"
+    )]
+    pub auth_mode: Option<String>,
+    /// Comma-separated secrets for signed updates. Prefer the _FILE variant or the variable, flags show up in the process list
+    #[arg(
+        long,
+        global = true,
+        env = "SIGNING_SECRETS",
+        help_heading = "Authentication",
+        hide_env_values = true
+    )]
+    pub signing_secrets: Option<String>,
+    /// File containing the signing secrets
+    #[arg(
+        long,
+        global = true,
+        env = "SIGNING_SECRETS_FILE",
+        help_heading = "Authentication"
+    )]
+    pub signing_secrets_file: Option<String>,
+    /// How far the timestamp of signed updates may be from the server time [default: 300]
+    #[arg(
+        long,
+        global = true,
+        env = "SIGNATURE_MAX_SKEW_SECS",
+        help_heading = "Authentication"
+    )]
+    pub signature_max_skew_secs: Option<String>,
+    /// Bearer token for the /admin endpoints. They are not served if unset. Prefer the _FILE variant or the variable, flags show up in the process list
+    #[arg(
+        long,
+        global = true,
+        env = "ADMIN_TOKEN",
+        help_heading = "Authentication",
+        hide_env_values = true
+    )]
+    pub admin_token: Option<String>,
+    /// File containing the admin token
+    #[arg(
+        long,
+        global = true,
+        env = "ADMIN_TOKEN_FILE",
+        help_heading = "Authentication"
+    )]
+    pub admin_token_file: Option<String>,
+    /// Also accept API tokens as ?key=<token> or ?password=<token>
+    #[arg(long, global = true, env = "ALLOW_QUERY_AUTH", help_heading = "Authentication", num_args = 0..=1, default_missing_value = "true")]
+    pub allow_query_auth: Option<String>,
+    /// Allow ALLOW_QUERY_AUTH without REQUIRE_HTTPS
+    #[arg(long, global = true, env = "ALLOW_INSECURE_QUERY_AUTH", help_heading = "Authentication", num_args = 0..=1, default_missing_value = "true")]
+    pub allow_insecure_query_auth: Option<String>,
+    /// Only accept requests forwarded by a trusted proxy with X-Forwarded-Proto: https
+    #[arg(long, global = true, env = "REQUIRE_HTTPS", help_heading = "Authentication", num_args = 0..=1, default_missing_value = "true")]
+    pub require_https: Option<String>,
+    /// Offer HTTP Digest auth next to Basic auth. Needs a plaintext password
+    #[arg(long, global = true, env = "DIGEST_AUTH", help_heading = "Authentication", num_args = 0..=1, default_missing_value = "true")]
+    pub digest_auth: Option<String>,
+    /// Comma-separated IPs/CIDRs of reverse proxies whose X-Forwarded-For header is trusted
+    #[arg(
+        long,
+        global = true,
+        env = "TRUSTED_PROXIES",
+        help_heading = "Authentication"
+    )]
+    pub trusted_proxies: Option<String>,
+    /// Failed password attempts from one client IP that trigger a lockout, 0 disables it [default: 0]
+    #[arg(
+        long,
+        global = true,
+        env = "LOCKOUT_THRESHOLD",
+        help_heading = "Authentication"
+    )]
+    pub lockout_threshold: Option<String>,
+    /// The window in which failed attempts are counted [default: 600]
+    #[arg(
+        long,
+        global = true,
+        env = "LOCKOUT_WINDOW_SECS",
+        help_heading = "Authentication"
+    )]
+    pub lockout_window_secs: Option<String>,
+    /// How long a client is locked out [default: 900]
+    #[arg(
+        long,
+        global = true,
+        env = "LOCKOUT_DURATION_SECS",
+        help_heading = "Authentication"
+    )]
+    pub lockout_duration_secs: Option<String>,
+    /// Longest accepted path and query in bytes [default: 2048]
+    #[arg(
+        long,
+        global = true,
+        env = "MAX_URI_LENGTH",
+        help_heading = "Limits and metrics"
+    )]
+    pub max_uri_length: Option<String>,
+    /// Largest accepted request line and headers in bytes [default: 16384]
+    #[arg(
+        long,
+        global = true,
+        env = "MAX_HEADER_BYTES",
+        help_heading = "Limits and metrics"
+    )]
+    pub max_header_bytes: Option<String>,
+    /// Largest accepted request body in bytes [default: 8192]
+    #[arg(
+        long,
+        global = true,
+        env = "MAX_BODY_BYTES",
+        help_heading = "Limits and metrics"
+    )]
+    pub max_body_bytes: Option<String>,
+    /// Close connections not sending their request headers within this time [default: 10]
+    #[arg(
+        long,
+        global = true,
+        env = "HEADER_READ_TIMEOUT_SECS",
+        help_heading = "Limits and metrics"
+    )]
+    pub header_read_timeout_secs: Option<String>,
+    /// Seconds a missing record is remembered, 0 disables it [default: 60]
+    #[arg(
+        long,
+        global = true,
+        env = "NEGATIVE_CACHE_TTL_SECS",
+        help_heading = "Limits and metrics"
+    )]
+    pub negative_cache_ttl_secs: Option<String>,
+    /// The most missing records remembered at once [default: 1024]
+    #[arg(
+        long,
+        global = true,
+        env = "NEGATIVE_CACHE_MAX_ENTRIES",
+        help_heading = "Limits and metrics"
+    )]
+    pub negative_cache_max_entries: Option<String>,
+    /// Replace hostnames in the /metrics labels by a hash of them
+    #[arg(long, global = true, env = "METRICS_HASH_HOSTNAMES", help_heading = "Limits and metrics", num_args = 0..=1, default_missing_value = "true")]
+    pub metrics_hash_hostnames: Option<String>,
+    /// Address of the Vault (or OpenBao) server secrets are read from, e.g. https://vault:8200
+    #[arg(long, global = true, env = "VAULT_ADDR", help_heading = "Vault")]
+    pub vault_addr: Option<String>,
+    /// Vault token. Prefer the _FILE variant or the variable, flags show up in the process list
+    #[arg(
+        long,
+        global = true,
+        env = "VAULT_TOKEN",
+        help_heading = "Vault",
+        hide_env_values = true
+    )]
+    pub vault_token: Option<String>,
+    /// File containing the Vault token
+    #[arg(long, global = true, env = "VAULT_TOKEN_FILE", help_heading = "Vault")]
+    pub vault_token_file: Option<String>,
+    /// Role to log in with via the Kubernetes or JWT auth method, instead of a token
+    #[arg(long, global = true, env = "VAULT_ROLE", help_heading = "Vault")]
+    pub vault_role: Option<String>,
+    /// Mount of the auth method used with VAULT_ROLE [default: kubernetes]
+    #[arg(long, global = true, env = "VAULT_AUTH_MOUNT", help_heading = "Vault")]
+    pub vault_auth_mount: Option<String>,
+    /// JWT used with VAULT_ROLE [default: the Kubernetes service account token]
+    #[arg(long, global = true, env = "VAULT_JWT_FILE", help_heading = "Vault")]
+    pub vault_jwt_file: Option<String>,
+    /// Vault KV v2 path and key of the client password, e.g. kv/data/dyndns#password
+    #[arg(
+        long,
+        global = true,
+        env = "PASSWORD_VAULT_PATH",
+        help_heading = "Vault"
+    )]
+    pub password_vault_path: Option<String>,
+    /// Vault KV v2 path and key of the client passwords, e.g. kv/data/dyndns#passwords
+    #[arg(
+        long,
+        global = true,
+        env = "PASSWORDS_VAULT_PATH",
+        help_heading = "Vault"
+    )]
+    pub passwords_vault_path: Option<String>,
+    /// Vault KV v2 path and key of the bearer tokens, e.g. kv/data/dyndns#api_tokens
+    #[arg(
+        long,
+        global = true,
+        env = "API_TOKENS_VAULT_PATH",
+        help_heading = "Vault"
+    )]
+    pub api_tokens_vault_path: Option<String>,
+    /// Vault KV v2 path and key of the signing secrets, e.g. kv/data/dyndns#signing_secrets
+    #[arg(
+        long,
+        global = true,
+        env = "SIGNING_SECRETS_VAULT_PATH",
+        help_heading = "Vault"
+    )]
+    pub signing_secrets_vault_path: Option<String>,
+    /// Vault KV v2 path and key of the admin token, e.g. kv/data/dyndns#admin_token
+    #[arg(
+        long,
+        global = true,
+        env = "ADMIN_TOKEN_VAULT_PATH",
+        help_heading = "Vault"
+    )]
+    pub admin_token_vault_path: Option<String>,
+    /// Serve HTTPS with a certificate obtained via ACME. Needs the `acme` feature
+    #[arg(long, global = true, env = "ACME", help_heading = "ACME", num_args = 0..=1, default_missing_value = "true")]
+    pub acme: Option<String>,
+    /// Domain the certificate is issued for. Must be inside the ORIGIN
+    #[arg(long, global = true, env = "ACME_DOMAIN", help_heading = "ACME")]
+    pub acme_domain: Option<String>,
+    /// Contact email registered with the ACME account
+    #[arg(long, global = true, env = "ACME_CONTACT_EMAIL", help_heading = "ACME")]
+    pub acme_contact_email: Option<String>,
+    /// ACME directory URL [default: Let's Encrypt production]
+    #[arg(long, global = true, env = "ACME_DIRECTORY", help_heading = "ACME")]
+    pub acme_directory: Option<String>,
+    /// Directory the account and certificate are cached in [default: acme-cache]
+    #[arg(long, global = true, env = "ACME_CACHE_DIR", help_heading = "ACME")]
+    pub acme_cache_dir: Option<String>,
+    /// Vault KV v2 path and key of the Cloudflare API token, e.g. kv/data/dyndns#cloudflare_api_token
+    #[arg(
+        long,
+        global = true,
+        env = "CLOUDFLARE_API_TOKEN_VAULT_PATH",
+        help_heading = "Vault"
+    )]
+    pub cloudflare_api_token_vault_path: Option<String>,
+    /// Vault KV v2 path and key of the netcup API key, e.g. kv/data/dyndns#netcup_api_key
+    #[arg(
+        long,
+        global = true,
+        env = "NETCUP_API_KEY_VAULT_PATH",
+        help_heading = "Vault"
+    )]
+    pub netcup_api_key_vault_path: Option<String>,
+    /// Vault KV v2 path and key of the netcup API password, e.g. kv/data/dyndns#netcup_api_password
+    #[arg(
+        long,
+        global = true,
+        env = "NETCUP_API_PASSWORD_VAULT_PATH",
+        help_heading = "Vault"
+    )]
+    pub netcup_api_password_vault_path: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the dyndns server. This is the default if no command is given
+    Serve,
+    /// Update the records of a hostname directly, without going through the HTTP server
+    Update(UpdateArgs),
+    /// Periodically look up the public addresses of this host and update hostnames when they change
+    Watch(WatchArgs),
+    /// Check the configuration and provider access, then list the records of each origin
+    CheckConfig(CheckConfigArgs),
+    /// List the records of the configured providers
+    ListRecords(ListRecordsArgs),
+    /// List the update history stored in DATABASE_PATH, newest first
+    History(HistoryArgs),
+    /// Probe the health endpoint of a running server, e.g. as a Docker HEALTHCHECK
+    Healthcheck(HealthcheckArgs),
+    /// Read a password from stdin and print its argon2 hash for use in PASSWORD
+    HashPassword,
+    /// Print a random token for use in API_TOKENS or SIGNING_SECRETS
+    GenerateToken,
+    /// Read a signing secret from stdin and print a signed update URL, e.g. to test with curl
+    SignUrl(SignUrlArgs),
+    /// Read a password from stdin and send an update to a dyndns2 server, e.g. to test this one
+    Push(PushArgs),
+}
+
+impl Command {
+    /// Whether the command prints its result to stdout, so logs have to go to stderr.
+    pub fn prints_output(&self) -> bool {
+        !matches!(self, Self::Serve | Self::Update(_) | Self::Watch(_))
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct UpdateArgs {
+    /// The fully qualified hostname to update
+    #[arg(long)]
+    pub hostname: String,
+    /// The new address of the A record
+    #[arg(long)]
+    pub ipv4: Option<Ipv4Addr>,
+    /// The new address of the AAAA record
+    #[arg(long)]
+    pub ipv6: Option<Ipv6Addr>,
+    /// Only print what would be updated
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct WatchArgs {
+    /// A fully qualified hostname to update. Can be given several times
+    #[arg(long = "hostname", required = true)]
+    pub hostnames: Vec<String>,
+    /// Seconds between two address lookups
+    #[arg(long, default_value_t = 300)]
+    pub interval: u64,
+    /// A URL answering with the address of the caller in plain text. Can be given several times,
+    /// the majority of the answers then wins. Defaults to a few public services
+    #[arg(long = "ip-url")]
+    pub ip_urls: Vec<String>,
+    /// Timeout of a single address lookup in seconds
+    #[arg(long, default_value_t = 10)]
+    pub timeout: u64,
+    /// Where the last pushed addresses are stored, so restarts do not update again
+    #[arg(long, default_value = "dyndns-watch-state.json")]
+    pub state_file: PathBuf,
+    /// Where the IPv6 address comes from: `http` (the --ip-url lookups) or `interface:<name>`,
+    /// the stable global address of a network interface
+    #[arg(long, default_value = "http")]
+    pub source: Ipv6Source,
+    /// With `--source interface:<name>`, use the address ending in this interface ID
+    #[arg(long)]
+    pub interface_id: Option<Ipv6Addr>,
+    /// Do not look up and update the IPv4 address
+    #[arg(long)]
+    pub no_ipv4: bool,
+    /// Do not look up and update the IPv6 address
+    #[arg(long)]
+    pub no_ipv6: bool,
+    /// Only log what would be updated
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Serve Prometheus metrics at /metrics on this address, e.g. 127.0.0.1:9100
+    #[arg(long)]
+    pub metrics_listen: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckConfigArgs {
+    /// Do not print the record table, only signal the result via the exit code
+    #[arg(long, short)]
+    pub quiet: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ListRecordsArgs {
+    /// Only list records with this fully qualified name
+    #[arg(long)]
+    pub name: Option<String>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct HistoryArgs {
+    /// Only list events of this fully qualified hostname
+    #[arg(long)]
+    pub hostname: Option<String>,
+    /// Only list events of the request with this `X-Request-Id`
+    #[arg(long)]
+    pub request_id: Option<String>,
+    /// Only list events at or after this time, e.g. 2026-03-01 or 2026-03-01T12:00:00Z
+    #[arg(long)]
+    pub since: Option<String>,
+    /// Only list events before this time
+    #[arg(long)]
+    pub until: Option<String>,
+    /// The maximum number of events
+    #[arg(long, default_value_t = crate::history::DEFAULT_LIMIT)]
+    pub limit: usize,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// An aligned table
+    Table,
+    /// A JSON array, in the format of `GET /admin/records` or the history of `GET /status`
+    Json,
+}
+
+#[derive(Debug, Args)]
+pub struct HealthcheckArgs {
+    /// The URL to probe. Defaults to /healthz on the INTERFACE and PORT the server listens on
+    #[arg(long)]
+    pub url: Option<String>,
+    /// Timeout of the request in seconds
+    #[arg(long, default_value_t = 3)]
+    pub timeout: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct SignUrlArgs {
+    /// The base URL of the server, including the base path if one is set
+    #[arg(long, default_value = "http://localhost:3000")]
+    pub url: String,
+    /// The fully qualified hostname to update
+    #[arg(long)]
+    pub hostname: String,
+    /// The new addresses, as in the myip parameter of an update
+    #[arg(long)]
+    pub myip: String,
+    /// The Unix timestamp to sign. Defaults to now
+    #[arg(long)]
+    pub ts: Option<i64>,
+}
+
+#[derive(Debug, Args)]
+pub struct PushArgs {
+    /// The base URL of the server, including the base path if one is set
+    #[arg(long, default_value = "http://localhost:3000")]
+    pub url: String,
+    /// The username sent with the password
+    #[arg(long, default_value = "dyndns")]
+    pub username: String,
+    /// A fully qualified hostname to update. Can be given several times
+    #[arg(long = "hostname", required = true)]
+    pub hostnames: Vec<String>,
+    /// The new addresses, as in the myip parameter of an update
+    #[arg(long)]
+    pub myip: String,
+    /// Timeout of the request in seconds
+    #[arg(long, default_value_t = 10)]
+    pub timeout: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("speedport-custom-dyndns").chain(args.iter().copied()))
+            .unwrap()
+    }
+
+    #[test]
+    fn cli_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn serve_is_optional() {
+        assert!(parse(&[]).command.is_none());
+        assert!(matches!(parse(&["serve"]).command, Some(Command::Serve)));
+    }
+
+    #[test]
+    fn settings_are_accepted_before_and_after_the_command() {
+        let before = parse(&["--port", "80", "serve"]);
+        assert_eq!(before.settings.port.as_deref(), Some("80"));
+
+        let after = parse(&["serve", "--port", "80", "--origin", "foobar.de"]);
+        assert!(matches!(after.command, Some(Command::Serve)));
+        assert_eq!(after.settings.port.as_deref(), Some("80"));
+        assert_eq!(after.settings.origin.as_deref(), Some("foobar.de"));
+
+        let update = parse(&[
+            "update",
+            "--hostname",
+            "nas.foobar.de",
+            "--providers",
+            "netcup",
+        ]);
+        assert_eq!(update.settings.providers.as_deref(), Some("netcup"));
+    }
+
+    #[test]
+    fn push_takes_several_hostnames() {
+        let cli = parse(&[
+            "push",
+            "--hostname",
+            "nas.foobar.de",
+            "--hostname",
+            "vpn.foobar.de",
+            "--myip",
+            "192.0.2.1",
+        ]);
+        let Some(Command::Push(args)) = cli.command else {
+            panic!("not a push: {:?}", cli.command);
+        };
+        assert_eq!(args.hostnames, ["nas.foobar.de", "vpn.foobar.de"]);
+        assert_eq!(args.url, "http://localhost:3000");
+    }
+
+    #[test]
+    fn secrets_and_their_files_are_flags() {
+        let cli = parse(&[
+            "--password",
+            "hunter2",
+            "--cloudflare-api-token-file",
+            "/run/secrets/cloudflare",
+        ]);
+        assert_eq!(cli.settings.password.as_deref(), Some("hunter2"));
+        assert_eq!(
+            cli.settings.cloudflare_api_token_file.as_deref(),
+            Some("/run/secrets/cloudflare")
+        );
+    }
+
+    #[test]
+    fn boolean_flags_need_no_value() {
+        let cli = parse(&["--reuse-port", "--prefix-rewrite=false"]);
+        assert_eq!(cli.settings.reuse_port.as_deref(), Some("true"));
+        assert_eq!(cli.settings.prefix_rewrite.as_deref(), Some("false"));
+    }
+
+    #[test]
+    fn username_flag_is_not_the_os_variable() {
+        let cli = parse(&["--dyndns-username", "router"]);
+        assert_eq!(cli.settings.dyndns_username.as_deref(), Some("router"));
+        let arg = Cli::command()
+            .get_arguments()
+            .find(|it| it.get_id() == "dyndns_username")
+            .and_then(|it| it.get_env().map(ToOwned::to_owned));
+        assert_eq!(arg.as_deref(), Some("DYNDNS_USERNAME".as_ref()));
+    }
+
+    #[test]
+    fn subcommand_arguments_are_parsed() {
+        let cli = parse(&["list-records", "--origin", "foobar.de", "--format", "json"]);
+        assert_eq!(cli.settings.origin.as_deref(), Some("foobar.de"));
+        let Some(Command::ListRecords(args)) = cli.command else {
+            panic!("expected list-records");
+        };
+        assert_eq!(args.format, OutputFormat::Json);
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        assert!(Cli::try_parse_from(["speedport-custom-dyndns", "--no-such-flag"]).is_err());
+        assert!(
+            Cli::try_parse_from(["speedport-custom-dyndns", "serve", "--hostname", "x"]).is_err()
+        );
+    }
+
+    #[test]
+    fn commands_printing_a_result_are_known() {
+        let prints = |args: &[&str]| parse(args).command.unwrap().prints_output();
+        assert!(!prints(&["serve"]));
+        assert!(!prints(&["watch", "--hostname", "nas.foobar.de"]));
+        assert!(prints(&["list-records"]));
+        assert!(prints(&["history"]));
+        assert!(prints(&["generate-token"]));
+    }
+}
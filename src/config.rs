@@ -0,0 +1,153 @@
+//! The optional TOML configuration file, read from the path in `CONFIG_FILE`.
+//!
+//! ```toml
+//! [hostnames."vpn.example.com"]
+//! ttl = 60
+//! proxied = false
+//! suffix = "::1234"
+//!
+//! [aliases]
+//! "home.example.com" = ["vpn.example.com", "mail.example.com"]
+//! ```
+
+use crate::provider::{DnsRecordType, Origin, RecordId, RecordOptions};
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, report};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Settings per fully qualified hostname.
+    #[serde(default)]
+    pub hostnames: HashMap<String, HostnameConfig>,
+    /// Hostnames that follow every update of the key hostname.
+    #[serde(default)]
+    pub aliases: HashMap<String, Vec<String>>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self, Report> {
+        let content = std::fs::read_to_string(path)
+            .context("Failed to read config file")
+            .attach(format!("path: {}", path.display()))?;
+        Ok(toml::from_str(&content)
+            .context("Invalid config file")
+            .attach(format!("path: {}", path.display()))?)
+    }
+
+    /// Rejects hostnames that are not part of `origin`, as no update could ever reach them, and
+    /// aliases that would be updated more than once.
+    pub fn validate(&self, origin: &Origin) -> Result<(), Report> {
+        let mut outside = self
+            .hostnames
+            .keys()
+            .chain(self.aliases.keys())
+            .chain(self.aliases.values().flatten())
+            .filter(|it| !origin.is_subdomain(it))
+            .collect::<Vec<_>>();
+        if outside.is_empty() {
+            return self.validate_aliases();
+        }
+
+        outside.sort();
+        outside.dedup();
+        let mut report = report!("Configured hostnames or aliases are outside the origin")
+            .attach(format!("origin: '{origin}'"));
+        for hostname in outside {
+            report = report.attach(format!("hostname: '{hostname}'"));
+        }
+        Err(report.into_dynamic())
+    }
+
+    /// Rejects aliases of themselves, aliases having aliases themselves and aliases listed more
+    /// than once, so an update expands to every name at most once.
+    fn validate_aliases(&self) -> Result<(), Report> {
+        let mut keys = self.aliases.keys().collect::<Vec<_>>();
+        keys.sort();
+        let mut seen = HashMap::<&str, &str>::new();
+        for hostname in keys {
+            for alias in &self.aliases[hostname] {
+                if alias == hostname {
+                    return Err(
+                        report!("Hostname '{hostname}' is an alias of itself").into_dynamic()
+                    );
+                }
+                if self.aliases.contains_key(alias) {
+                    return Err(
+                        report!("Alias '{alias}' of '{hostname}' has aliases itself")
+                            .attach("hint: list all of them as aliases of the same hostname")
+                            .into_dynamic(),
+                    );
+                }
+                if let Some(other) = seen.insert(alias, hostname) {
+                    return Err(report!("Alias '{alias}' is listed more than once")
+                        .attach(format!("hostnames: '{other}', '{hostname}'"))
+                        .into_dynamic());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses `ALIASES`: entries separated by semicolons, each a hostname followed by `=` and its
+/// comma-separated aliases, e.g. `home.example.com=vpn.example.com,mail.example.com`.
+pub fn parse_aliases(value: &str) -> Result<HashMap<String, Vec<String>>, Report> {
+    let mut aliases = HashMap::<String, Vec<String>>::new();
+    for entry in value.split(';').map(str::trim).filter(|it| !it.is_empty()) {
+        let Some((hostname, names)) = entry.split_once('=') else {
+            return Err(
+                report!("Alias entry '{entry}' is not of the form <hostname>=<aliases>")
+                    .into_dynamic(),
+            );
+        };
+        let names = names
+            .split(',')
+            .map(str::trim)
+            .filter(|it| !it.is_empty())
+            .map(str::to_string);
+        aliases
+            .entry(hostname.trim().to_string())
+            .or_default()
+            .extend(names);
+    }
+    Ok(aliases)
+}
+
+/// Settings for a single hostname. Unset values fall back to the global settings of the provider
+/// and then to whatever the existing record has.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostnameConfig {
+    pub ttl: Option<u32>,
+    pub proxied: Option<bool>,
+    /// Replaces the interface identifier (the lower 64 bits) of the IPv6 address sent by the
+    /// client, e.g. to point the record at a host behind the router. If the client sends a
+    /// delegated prefix, all bits outside of it are taken from the suffix instead.
+    pub suffix: Option<Ipv6Addr>,
+    /// The ID of the A record. Pinned records are updated without listing the zone first.
+    pub a_record_id: Option<String>,
+    /// The ID of the AAAA record, see [`HostnameConfig::a_record_id`].
+    pub aaaa_record_id: Option<String>,
+}
+
+impl HostnameConfig {
+    /// The pinned ID of the record of type `record_type`, if any.
+    pub fn pinned_record(&self, record_type: &DnsRecordType) -> Option<RecordId> {
+        match record_type {
+            DnsRecordType::A => self.a_record_id.clone().map(RecordId),
+            DnsRecordType::AAAA => self.aaaa_record_id.clone().map(RecordId),
+        }
+    }
+
+    pub fn record_options(&self) -> RecordOptions {
+        RecordOptions {
+            ttl: self.ttl,
+            proxied: self.proxied,
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! A small HTML status page at `/` and its JSON variant at `/status`, rendered from the
+//! [`StatusTracker`](crate::status) and the [`history`](crate::history) only, so viewing them
+//! never causes provider calls.
+
+use crate::auth::AllowedHostnames;
+use crate::history::{self, HistoryFilter, UpdateEvent};
+use crate::propagation::Propagation;
+use crate::provider::DnsRecordType;
+use crate::status::RecordStatus;
+use crate::types::AppState;
+use crate::version;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use axum::{Extension, Json};
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use tracing::warn;
+
+const REFRESH_SECS: u32 = 60;
+/// The number of events in the recent changes of the status page.
+const RECENT_CHANGES: usize = 20;
+
+/// The records of one hostname, merged for display.
+#[derive(Default)]
+struct HostnameRow<'a> {
+    records: Vec<(&'a DnsRecordType, &'a RecordStatus)>,
+}
+
+impl HostnameRow<'_> {
+    fn address(&self, typ: &DnsRecordType) -> &str {
+        self.records
+            .iter()
+            .find(|(it, _)| *it == typ)
+            .and_then(|(_, status)| status.address.as_deref())
+            .unwrap_or("-")
+    }
+
+    fn last_success(&self) -> Option<Timestamp> {
+        self.records
+            .iter()
+            .filter_map(|(_, it)| it.last_success)
+            .max()
+    }
+
+    fn last_client(&self) -> String {
+        self.records
+            .iter()
+            .filter(|(_, it)| it.last_attempt.is_some())
+            .max_by_key(|(_, it)| it.last_attempt)
+            .and_then(|(_, it)| it.last_client)
+            .map_or("-".to_string(), |it| it.to_string())
+    }
+
+    /// Whether the addresses are visible, if the propagation check is enabled.
+    fn propagation(&self) -> &'static str {
+        let states = self
+            .records
+            .iter()
+            .filter_map(|(_, it)| it.propagation)
+            .collect::<Vec<_>>();
+        if states.contains(&Propagation::NotVisible) {
+            "not visible"
+        } else if states.contains(&Propagation::Pending) {
+            "pending"
+        } else if states.is_empty() {
+            "-"
+        } else {
+            "visible"
+        }
+    }
+
+    /// Whether the latest attempt for every record succeeded.
+    fn is_fresh(&self) -> bool {
+        self.records
+            .iter()
+            .all(|(_, it)| it.last_success.is_some() && it.consecutive_failures == 0)
+    }
+}
+
+pub(crate) async fn render(
+    State(state): State<AppState>,
+    allowed_hostnames: Option<Extension<AllowedHostnames>>,
+) -> Html<String> {
+    let snapshot = state.status.snapshot();
+    let mut hostnames: BTreeMap<&str, HostnameRow<'_>> = BTreeMap::new();
+    for ((hostname, typ), status) in &snapshot {
+        if !is_allowed(&allowed_hostnames, hostname) {
+            continue;
+        }
+        hostnames
+            .entry(hostname)
+            .or_default()
+            .records
+            .push((typ, status));
+    }
+
+    let now = Timestamp::now();
+    let mut rows = String::new();
+    for (hostname, row) in &hostnames {
+        let (class, label) = if row.is_fresh() {
+            ("fresh", "current")
+        } else {
+            ("stale", "failing")
+        };
+        let _ = writeln!(
+            rows,
+            r#"<tr><td><span class="{class}">●</span> {label}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+            escape(hostname),
+            escape(row.address(&DnsRecordType::A)),
+            escape(row.address(&DnsRecordType::AAAA)),
+            row.last_success()
+                .map_or("never".to_string(), |it| format_time(it, now)),
+            row.propagation(),
+            escape(&row.last_client()),
+        );
+    }
+    if hostnames.is_empty() {
+        rows.push_str(r#"<tr><td colspan="7">No updates since startup</td></tr>"#);
+    }
+
+    let mut usage = String::new();
+    for it in state.dns.api_usage() {
+        let headers = it
+            .headers
+            .iter()
+            .map(|(name, value)| escape(&format!("{name}: {value}")))
+            .collect::<Vec<_>>()
+            .join("<br>");
+        let _ = writeln!(
+            usage,
+            "<tr><td>{}</td><td>{} / {}</td><td>{}</td><td>{}</td></tr>",
+            it.provider,
+            it.calls,
+            it.limit,
+            if headers.is_empty() {
+                "-".to_string()
+            } else {
+                headers
+            },
+            it.headers_at
+                .map_or("-".to_string(), |it| format_time(it, now)),
+        );
+    }
+    if !usage.is_empty() {
+        usage = format!(
+            "<h2>API usage</h2>\n<div style=\"overflow-x: auto\">\n<table>\n<tr><th>Provider</th><th>Calls (5 min)</th><th>Rate limit headers</th><th>Received</th></tr>\n{usage}</table>\n</div>\n"
+        );
+    }
+
+    let mut retries = String::new();
+    let pending = state
+        .updates
+        .retry_queue()
+        .map(|it| it.snapshot())
+        .unwrap_or_default();
+    for retry in &pending {
+        if !is_allowed(&allowed_hostnames, &retry.hostname) {
+            continue;
+        }
+        let _ = writeln!(
+            retries,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} (in {})</td></tr>",
+            escape(&retry.hostname),
+            retry.record_type,
+            escape(&retry.content),
+            retry.attempts,
+            retry.next_attempt.strftime("%Y-%m-%d %H:%M:%S UTC"),
+            format_duration(
+                retry
+                    .next_attempt
+                    .duration_since(now)
+                    .max(SignedDuration::ZERO)
+            ),
+        );
+    }
+    if !retries.is_empty() {
+        retries = format!(
+            "<h2>Pending retries</h2>\n<div style=\"overflow-x: auto\">\n<table>\n<tr><th>Hostname</th><th>Type</th><th>Address</th><th>Failed attempts</th><th>Next attempt</th></tr>\n{retries}</table>\n</div>\n"
+        );
+    }
+
+    let mut changes = String::new();
+    let recent = match state.updates.history() {
+        Some(history) => history
+            .reader()
+            .events(HistoryFilter {
+                limit: Some(RECENT_CHANGES),
+                ..HistoryFilter::default()
+            })
+            .await
+            .inspect_err(|e| warn!(error = %e, "Failed to read the history"))
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+    for event in &recent {
+        if !is_allowed(&allowed_hostnames, &event.hostname) {
+            continue;
+        }
+        let _ = writeln!(
+            changes,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{} → {}</td><td>{}</td><td>{}</td></tr>",
+            format_time(event.timestamp, now),
+            escape(&event.hostname),
+            event.record_type,
+            escape(event.old_content.as_deref().unwrap_or("-")),
+            escape(event.new_content.as_deref().unwrap_or("-")),
+            event.result,
+            escape(event.error.as_deref().unwrap_or("-")),
+        );
+    }
+    if !changes.is_empty() {
+        changes = format!(
+            "<h2>Recent changes</h2>\n<div style=\"overflow-x: auto\">\n<table>\n<tr><th>Time</th><th>Hostname</th><th>Type</th><th>Address</th><th>Result</th><th>Error</th></tr>\n{changes}</table>\n</div>\n"
+        );
+    }
+
+    let uptime = now.duration_since(state.status.started());
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta http-equiv="refresh" content="{REFRESH_SECS}">
+<title>DynDNS status</title>
+<style>
+body {{ font-family: system-ui, sans-serif; margin: 1em; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #ddd; }}
+.fresh {{ color: #2a2; }}
+.stale {{ color: #c22; }}
+footer {{ margin-top: 1em; color: #666; font-size: 0.9em; }}
+</style>
+</head>
+<body>
+<h1>DynDNS status</h1>
+<div style="overflow-x: auto">
+<table>
+<tr><th>State</th><th>Hostname</th><th>IPv4</th><th>IPv6</th><th>Last update</th><th>Propagation</th><th>Last client</th></tr>
+{rows}</table>
+</div>
+{changes}{retries}{usage}<footer>Version {} ({}), up for {}</footer>
+</body>
+</html>
+"#,
+        version::VERSION,
+        version::GIT_COMMIT,
+        format_duration(uptime),
+    ))
+}
+
+/// Tokens limited to some hostnames only get to see those.
+fn is_allowed(allowed_hostnames: &Option<Extension<AllowedHostnames>>, hostname: &str) -> bool {
+    match allowed_hostnames {
+        Some(Extension(AllowedHostnames(allowed))) => allowed.contains(hostname),
+        None => true,
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct StatusQuery {
+    /// Includes the update events with `1` or `true`, which needs `DATABASE_PATH`.
+    history: Option<String>,
+    hostname: Option<String>,
+    request_id: Option<String>,
+    /// An RFC 3339 timestamp or a date, see [`history::parse_time`].
+    since: Option<String>,
+    until: Option<String>,
+    limit: Option<usize>,
+}
+
+/// The state of one record in `GET /status`.
+#[derive(Debug, Serialize)]
+struct StatusRecord<'a> {
+    hostname: &'a str,
+    record_type: &'a DnsRecordType,
+    address: Option<&'a str>,
+    last_attempt: Option<Timestamp>,
+    last_success: Option<Timestamp>,
+    consecutive_failures: u32,
+    last_client: Option<IpAddr>,
+}
+
+/// The state of every record as JSON and, with `history=1`, the update events matching the
+/// filters, newest first.
+pub(crate) async fn status(
+    State(state): State<AppState>,
+    allowed_hostnames: Option<Extension<AllowedHostnames>>,
+    Query(query): Query<StatusQuery>,
+) -> Response {
+    let snapshot = state.status.snapshot();
+    let records = snapshot
+        .iter()
+        .filter(|((hostname, _), _)| is_allowed(&allowed_hostnames, hostname))
+        .filter(|((hostname, _), _)| query.hostname.as_ref().is_none_or(|it| it == hostname))
+        .map(|((hostname, record_type), status)| StatusRecord {
+            hostname,
+            record_type,
+            address: status.address.as_deref(),
+            last_attempt: status.last_attempt,
+            last_success: status.last_success,
+            consecutive_failures: status.consecutive_failures,
+            last_client: status.last_client,
+        })
+        .collect::<Vec<_>>();
+
+    if !matches!(query.history.as_deref(), Some("1" | "true")) {
+        return Json(json!({ "records": records })).into_response();
+    }
+    let Some(history) = state.updates.history() else {
+        return error(
+            StatusCode::NOT_FOUND,
+            "no history, DATABASE_PATH is not set",
+        );
+    };
+    let parse = |value: &Option<String>| value.as_deref().map(history::parse_time).transpose();
+    let (since, until) = match (parse(&query.since), parse(&query.until)) {
+        (Ok(since), Ok(until)) => (since, until),
+        (Err(e), _) | (_, Err(e)) => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                &e.format_current_context().to_string(),
+            );
+        }
+    };
+    let filter = HistoryFilter {
+        hostname: query.hostname.clone(),
+        request_id: query.request_id.clone(),
+        since,
+        until,
+        limit: query.limit,
+    };
+    let events = match history.reader().events(filter).await {
+        Ok(events) => events,
+        Err(e) => {
+            warn!(error = %e, "Failed to read the history");
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to read the history",
+            );
+        }
+    };
+    let events = events
+        .into_iter()
+        .filter(|it| is_allowed(&allowed_hostnames, &it.hostname))
+        .collect::<Vec<UpdateEvent>>();
+    Json(json!({ "records": records, "history": events })).into_response()
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+fn format_time(time: Timestamp, now: Timestamp) -> String {
+    let ago = now.duration_since(time);
+    format!(
+        "{} ({} ago)",
+        time.strftime("%Y-%m-%d %H:%M:%S UTC"),
+        format_duration(ago)
+    )
+}
+
+/// Formats `duration` in whole seconds, e.g. `1h 2m 3s`.
+fn format_duration(duration: SignedDuration) -> String {
+    format!("{:#}", SignedDuration::from_secs(duration.as_secs()))
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
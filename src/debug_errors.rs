@@ -0,0 +1,366 @@
+//! Error details for the client, enabled by `DEBUG_ERRORS` while setting up the server. The
+//! protocol keyword stays the first line of the body, the full report chain is sent as JSON in
+//! the [`HEADER`] header.
+//!
+//! Everything sent is passed through a [`Redactor`], as reports may contain request and response
+//! bodies of the providers.
+
+use crate::types::AppState;
+use crate::update::UpdateError;
+use axum::http::HeaderValue;
+use serde::Serialize;
+use std::fmt::Write;
+
+pub const HEADER: &str = "x-debug-error";
+/// Sent in the [`HEADER`] header if the details cannot be encoded, so a missing header still
+/// means there was no error.
+const UNENCODABLE: &str = r#"[{"message":"the error details could not be encoded"}]"#;
+
+/// What secrets are replaced with.
+const REDACTED: &str = "[redacted]";
+/// Keys whose values are redacted wherever they appear as `key: value`, `key=value` or
+/// `"key": "value"`, e.g. in the request data attached by the netcup provider.
+const SECRET_KEYS: &[&str] = &[
+    "apikey",
+    "apipassword",
+    "apisessionid",
+    "api_token",
+    "authorization",
+    "password",
+    "secret",
+    "token",
+];
+/// Secrets shorter than this are only redacted by key, as replacing them everywhere would
+/// mangle the output more than it protects.
+const MIN_SECRET_LENGTH: usize = 4;
+
+/// Scrubs known secrets and the values of [`SECRET_KEYS`] from text.
+pub struct Redactor {
+    secrets: Vec<String>,
+}
+
+impl Redactor {
+    pub fn new(secrets: impl IntoIterator<Item = String>) -> Self {
+        let mut secrets = secrets
+            .into_iter()
+            .filter(|it| it.len() >= MIN_SECRET_LENGTH)
+            .collect::<Vec<_>>();
+        // Longest first, so a secret containing another one is replaced as a whole
+        secrets.sort_by_key(|it| std::cmp::Reverse(it.len()));
+        secrets.dedup();
+        Self { secrets }
+    }
+
+    /// The credentials of the clients and providers of `state`.
+    pub fn for_state(state: &AppState) -> Self {
+        let auth = &state.auth;
+        let providers = state.dns.dns_providers.iter().flat_map(|it| it.secrets());
+        Self::new(
+            auth.passwords
+                .plaintext()
+                .into_iter()
+                .map(|(_, password)| password)
+                .chain(auth.api_tokens.iter().map(|it| it.token.clone()))
+                .chain(
+                    auth.signed
+                        .iter()
+                        .flat_map(|it| it.secrets().iter().map(|it| it.token.clone())),
+                )
+                .chain(auth.admin_token.clone())
+                .chain(providers),
+        )
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for secret in &self.secrets {
+            text = text.replace(secret.as_str(), REDACTED);
+        }
+        redact_keys(&text)
+    }
+}
+
+/// Replaces the values following any of the [`SECRET_KEYS`].
+fn redact_keys(text: &str) -> String {
+    // ASCII lowercasing keeps byte offsets, so positions in `lower` are valid in `text`
+    let lower = text.to_ascii_lowercase();
+    let mut values = Vec::new();
+    for key in SECRET_KEYS {
+        let mut search = 0;
+        while let Some(found) = lower[search..].find(key) {
+            let start = search + found;
+            search = start + key.len();
+            if let Some(value) = value_after(&lower, start, key.len()) {
+                values.push(value);
+            }
+        }
+    }
+    values.sort_by_key(|it| it.0);
+
+    let mut redacted = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (start, end) in values {
+        // Overlaps, e.g. `token` within `api_token`
+        if start < copied {
+            continue;
+        }
+        redacted.push_str(&text[copied..start]);
+        redacted.push_str(REDACTED);
+        copied = end;
+    }
+    redacted.push_str(&text[copied..]);
+    redacted
+}
+
+/// The byte range of the value assigned to the key at `start`, if it is one.
+fn value_after(lower: &str, start: usize, key_len: usize) -> Option<(usize, usize)> {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    // Only whole keys, `tokens_used` or `x-token-count` are no secrets
+    if lower[..start].ends_with(is_word) {
+        return None;
+    }
+    let rest = &lower[start + key_len..];
+    let rest_start = rest.trim_start_matches(['"', '\'']);
+    let rest_start = rest_start.trim_start();
+    let separator = rest_start.strip_prefix([':', '='])?;
+    let value = separator.trim_start().trim_start_matches(['"', '\'']);
+    // `Authorization: Bearer <token>` keeps the scheme
+    let value = match value.strip_prefix("bearer ") {
+        Some(token) => token.trim_start(),
+        None => value,
+    };
+    let value_start = lower.len() - value.len();
+    let value_len = value
+        .find(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | ',' | '&' | '}' | ';'))
+        .unwrap_or(value.len());
+    (value_len > 0).then_some((value_start, value_start + value_len))
+}
+
+/// The JSON of `errors` for the [`HEADER`] header. Header values have to be visible ASCII, so
+/// other characters, e.g. of internationalized domains in messages, are escaped.
+pub fn header_value(errors: &[DebugError]) -> HeaderValue {
+    let Ok(json) = serde_json::to_string(errors) else {
+        return HeaderValue::from_static(UNENCODABLE);
+    };
+    // Outside of strings, JSON only consists of visible ASCII, so this escapes within strings
+    let mut escaped = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() && c != '\x7f' {
+            escaped.push(c);
+            continue;
+        }
+        for unit in c.encode_utf16(&mut [0; 2]) {
+            let _ = write!(escaped, "\\u{unit:04x}");
+        }
+    }
+    HeaderValue::from_str(&escaped).unwrap_or(HeaderValue::from_static(UNENCODABLE))
+}
+
+/// The details of a failed update, sent as JSON in the [`HEADER`] header.
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugError {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<&'static str>,
+    pub message: String,
+    /// The report and its causes, outermost first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub chain: Vec<DebugReport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugReport {
+    pub context: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<String>,
+}
+
+impl DebugError {
+    pub fn new(error: &UpdateError, redactor: &Redactor) -> Self {
+        let message = redactor.redact(&error.to_string());
+        let UpdateError::Provider { provider, report } = error else {
+            return Self {
+                provider: None,
+                message,
+                chain: Vec::new(),
+            };
+        };
+        let chain = report
+            .iter_reports()
+            .map(|it| DebugReport {
+                context: redactor.redact(&it.format_current_context().to_string()),
+                attachments: it
+                    .attachments()
+                    .iter()
+                    .map(|attachment| redactor.redact(&attachment.format_inner().to_string()))
+                    .collect(),
+            })
+            .collect();
+        Self {
+            provider: Some(provider),
+            message,
+            chain,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rootcause::report;
+
+    fn redactor(secrets: &[&str]) -> Redactor {
+        Redactor::new(secrets.iter().map(|it| it.to_string()))
+    }
+
+    #[test]
+    fn known_secrets_are_replaced_everywhere() {
+        let redactor = redactor(&["hunter2", "cloudflare-token"]);
+        assert_eq!(
+            redactor.redact("login with hunter2 failed, hunter2 is wrong"),
+            "login with [redacted] failed, [redacted] is wrong"
+        );
+        assert_eq!(
+            redactor.redact("body: {\"errors\":[\"cloudflare-token invalid\"]}"),
+            "body: {\"errors\":[\"[redacted] invalid\"]}"
+        );
+    }
+
+    #[test]
+    fn longer_secrets_are_replaced_first() {
+        let redactor = redactor(&["pass", "password123"]);
+        assert_eq!(redactor.redact("got password123"), "got [redacted]");
+        assert_eq!(redactor.redact("got pass"), "got [redacted]");
+    }
+
+    #[test]
+    fn short_secrets_are_only_redacted_by_key() {
+        let redactor = redactor(&["abc"]);
+        assert_eq!(
+            redactor.redact("abc is the alphabet"),
+            "abc is the alphabet"
+        );
+        assert_eq!(redactor.redact("password=abc"), "password=[redacted]");
+    }
+
+    #[test]
+    fn values_of_secret_keys_are_replaced() {
+        assert_eq!(redact_keys("apikey: 12345"), "apikey: [redacted]");
+        assert_eq!(
+            redact_keys("password=hunter2&x=1"),
+            "password=[redacted]&x=1"
+        );
+        assert_eq!(
+            redact_keys(r#"{"apipassword": "s3cret", "domainname": "foobar.de"}"#),
+            r#"{"apipassword": "[redacted]", "domainname": "foobar.de"}"#
+        );
+        assert_eq!(
+            redact_keys("'secret' = 'x'; next"),
+            "'secret' = '[redacted]'; next"
+        );
+        assert_eq!(
+            redact_keys("APISessionID: abc,def"),
+            "APISessionID: [redacted],def"
+        );
+    }
+
+    #[test]
+    fn bearer_scheme_is_kept() {
+        assert_eq!(
+            redact_keys("Authorization: Bearer abc.def"),
+            "Authorization: Bearer [redacted]"
+        );
+        assert_eq!(
+            redact_keys("authorization: Basic cm91dGVy"),
+            "authorization: [redacted] cm91dGVy"
+        );
+    }
+
+    #[test]
+    fn keys_inside_other_words_are_not_secrets() {
+        assert_eq!(redact_keys("tokens_used: 5"), "tokens_used: 5");
+        assert_eq!(redact_keys("mytoken: 5"), "mytoken: 5");
+        assert_eq!(redact_keys("x-token-count: 5"), "x-token-count: 5");
+        assert_eq!(redact_keys("the token expired"), "the token expired");
+        assert_eq!(redact_keys("token:"), "token:");
+    }
+
+    #[test]
+    fn overlapping_keys_are_replaced_once() {
+        assert_eq!(redact_keys("api_token=abc def"), "api_token=[redacted] def");
+        assert_eq!(
+            redact_keys("token=a, secret=b, token=c"),
+            "token=[redacted], secret=[redacted], token=[redacted]"
+        );
+    }
+
+    #[test]
+    fn value_ranges_point_into_the_original_text() {
+        let text = "x password = \"hunter2\" y";
+        let start = text.find("password").unwrap();
+        let (value_start, value_end) = value_after(text, start, "password".len()).unwrap();
+        assert_eq!(&text[value_start..value_end], "hunter2");
+
+        assert_eq!(value_after("password hunter2", 0, "password".len()), None);
+        assert_eq!(value_after("password=", 0, "password".len()), None);
+    }
+
+    #[test]
+    fn provider_reports_are_redacted() {
+        let error = UpdateError::Provider {
+            provider: "cloudflare",
+            report: report!("Request failed")
+                .attach("token=cloudflare-token")
+                .attach("zone: bücher.de")
+                .into_dynamic(),
+        };
+
+        let debug = DebugError::new(&error, &redactor(&["cloudflare-token"]));
+
+        assert_eq!(debug.provider, Some("cloudflare"));
+        assert_eq!(debug.chain[0].context, "Request failed");
+        let attachments = &debug.chain[0].attachments;
+        assert!(
+            attachments.contains(&"token=[redacted]".to_string()),
+            "{attachments:?}"
+        );
+        assert!(
+            attachments.contains(&"zone: bücher.de".to_string()),
+            "{attachments:?}"
+        );
+        assert!(
+            !debug.message.contains("cloudflare-token"),
+            "{}",
+            debug.message
+        );
+    }
+
+    #[test]
+    fn header_escapes_non_ascii() {
+        let errors = vec![DebugError {
+            provider: None,
+            message: "zone bücher.de not found 🦀\x7f".to_string(),
+            chain: Vec::new(),
+        }];
+
+        let header = header_value(&errors);
+
+        let text = header.to_str().unwrap();
+        assert!(text.contains(r"b\u00fccher.de"), "{text}");
+        assert!(text.contains(r"\ud83e\udd80\u007f"), "{text}");
+        let parsed: serde_json::Value = serde_json::from_str(text).unwrap();
+        assert_eq!(parsed[0]["message"], errors[0].message);
+    }
+
+    #[test]
+    fn ascii_header_is_plain_json() {
+        let errors = vec![DebugError {
+            provider: Some("memory"),
+            message: "failed".to_string(),
+            chain: Vec::new(),
+        }];
+        assert_eq!(
+            header_value(&errors),
+            r#"[{"provider":"memory","message":"failed"}]"#
+        );
+    }
+}
@@ -1,168 +1,405 @@
 use axum::{
+    Extension,
     extract::{Query, State},
     http::StatusCode,
     response::{IntoResponse, Response},
 };
-use rootcause::{Report, bail, prelude::ResultExt};
-use serde::Deserialize;
-use std::collections::HashSet;
-use std::{
-    net::{Ipv4Addr, Ipv6Addr},
-    str::FromStr,
-};
-use tracing::{info, warn};
+use ipnet::Ipv6Net;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use tracing::{debug, info, instrument, warn};
 
-use crate::provider::{DnsProvider, Origin};
-use crate::{provider::DnsRecordType, types::AppState};
+use crate::access_log::{ClientIp, RequestId};
+use crate::auth::AllowedHostnames;
+use crate::debug_errors::{self, DebugError, Redactor};
+use crate::ip_update::ParsedIpUpdate;
+use crate::provider::{DnsRecordType, canonical_hostname};
+use crate::types::AppState;
+use crate::update::{PrefixRewrite, UpdateError, UpdateOutcome, UpdateRequest};
 
+#[instrument(name = "dyndns_update", skip_all)]
 pub(crate) async fn handle_dyndns_request(
     State(state): State<AppState>,
-    Query(query): Query<UpdateQuery>,
-) -> Result<String, Response> {
+    Query(mut query): Query<UpdateQuery>,
+    allowed_hostnames: Option<Extension<AllowedHostnames>>,
+    client_ip: Option<Extension<ClientIp>>,
+    request_id: Option<Extension<RequestId>>,
+) -> DyndnsResponse {
     info!(query = ?query, "handling update");
+    query.hostname = match canonical_hostname(&query.hostname) {
+        Ok(hostname) => hostname,
+        Err(e) => {
+            let detail = Some(e.format_current_context().to_string());
+            return DyndnsResponse::new(Some(query.hostname), Outcome::NoHost { detail });
+        }
+    };
+    let hostname = Some(query.hostname.clone());
 
-    let ip = ParsedIpUpdate::from_str(&query.myip).map_err(|e| {
-        (
-            StatusCode::BAD_REQUEST,
-            format!("invalid 'myip' parameter: {}", e),
-        )
-            .into_response()
-    })?;
+    if let Some(Extension(AllowedHostnames(allowed))) = &allowed_hostnames
+        && !allowed.contains(&query.hostname)
+    {
+        warn!(query = %query.hostname, "hostname is not allowed for this API token");
+        return DyndnsResponse::new(hostname, Outcome::NoHost { detail: None });
+    }
 
-    info!(ip = ?ip, domain=?query.hostname, "parsed IP update");
-    let mut all_ips = HashSet::new();
-
-    for provider in &state.dns_providers {
-        let expected_origin =
-            state.map_origin(state.origin_for(provider.as_ref()), provider.as_ref());
-        let actual_origin = state.map_origin(Origin(query.hostname.clone()), provider.as_ref());
-        if !expected_origin.is_subdomain(&actual_origin.0) {
-            warn!(
-                query = %query.hostname,
-                mapped = %actual_origin,
-                expected = %expected_origin,
-                "requested domain is not a subdomain of the configured origin"
-            );
-            return Err((
-                StatusCode::BAD_REQUEST,
-                format!(
-                    "domain '{}' (=> '{}') is not a subdomain of '{}'",
-                    query.hostname,
-                    actual_origin,
-                    state.origin_for(provider.as_ref())
-                ),
-            )
-                .into_response());
+    let mut ip = match ParsedIpUpdate::from_str(&query.myip) {
+        Ok(ip) => ip,
+        Err(e) => {
+            let detail = format!("invalid 'myip' parameter: {}", e);
+            return DyndnsResponse::new(hostname, Outcome::BadRequest { detail });
         }
-
-        let ips = match update_record(&state, provider.as_ref(), &actual_origin.0, &ip).await {
+    };
+    if let Some(prefix) = &query.ip6lanprefix {
+        match prefix.trim().parse::<Ipv6Net>() {
+            Ok(prefix) => ip = ip.with_ipv6_prefix(prefix),
             Err(e) => {
-                warn!(
-                    error = %e,
-                    query = %query.hostname,
-                    mapped = %actual_origin,
-                    ip = ?ip,
-                    "failed to update DNS record"
-                );
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("failed to update DNS record: {}", e),
-                )
-                    .into_response());
+                let detail = format!("invalid 'ip6lanprefix' parameter: {}", e);
+                return DyndnsResponse::new(hostname, Outcome::BadRequest { detail });
             }
-            Ok(ips) => ips,
-        };
-        info!(
-            query = %query.hostname,
-            mapped = %actual_origin,
-            ips = ?ips,
-            provider = %provider.name(),
-            "successfully updated DNS record"
-        );
-        all_ips.extend(ips)
+        }
+    }
+
+    info!(ip = ?ip, domain=?query.hostname, "parsed IP update");
+
+    let request = UpdateRequest {
+        hostname: query.hostname,
+        ip,
+        client: client_ip.map(|Extension(ClientIp(ip))| ip),
+        request_id: request_id.map(|Extension(RequestId(id))| id),
+        alias_of: None,
+        dry_run: false,
+    };
+    // Before the update, which replaces the address the old prefix is derived from
+    let allowed = allowed_hostnames
+        .as_ref()
+        .map(|Extension(AllowedHostnames(allowed))| allowed);
+    let rewrite = match state.updates.rewrite_prefix(&request, allowed).await {
+        Err(e @ UpdateError::NotReady) => return respond(&state, request.hostname, Err(e)),
+        rewrite => rewrite,
+    };
+    let outcome = state.updates.apply(&request).await;
+    let failed = outcome.is_err();
+    let not_ready = matches!(outcome, Err(UpdateError::NotReady));
+    let mut response = respond(&state, request.hostname.clone(), outcome);
+    if not_ready {
+        return response;
     }
 
-    Ok(all_ips
-        .into_iter()
-        .map(|it| format!("good {}", it))
-        .collect::<Vec<_>>()
-        .join("\n"))
+    // After the line of the hostname itself, whose update does not depend on the other records
+    let rewrite = respond_rewrite(&state, &request.hostname, rewrite);
+    response.results.extend(rewrite.results);
+    response.debug.extend(rewrite.debug);
+
+    // Aliases are independent of the hostname, so they are updated even if it failed
+    for alias in state.updates.aliases(&request) {
+        let hostname = &alias.hostname;
+        if let Some(Extension(AllowedHostnames(allowed))) = &allowed_hostnames
+            && !allowed.contains(hostname)
+        {
+            warn!(%hostname, "Not updating alias not allowed for this API token");
+            continue;
+        }
+        info!(%hostname, alias_of = %request.hostname, "Updating alias");
+        let outcome = state.updates.apply(&alias).await;
+        let alias = respond(&state, alias.hostname, outcome);
+        response.results.extend(alias.results);
+        response.debug.extend(alias.debug);
+    }
+    if failed {
+        return response;
+    }
+
+    // Other hosts behind the router, whose addresses are derived from the delegated prefix
+
+    for fan_out in state.updates.fan_out(&request) {
+        let hostname = &fan_out.hostname;
+        if let Some(Extension(AllowedHostnames(allowed))) = &allowed_hostnames
+            && !allowed.contains(hostname)
+        {
+            debug!(%hostname, "Not fanning out to hostname not allowed for this API token");
+            continue;
+        }
+        info!(%hostname, "Fanning out IPv6 prefix update");
+        let outcome = state.updates.apply(&fan_out).await;
+        let fan_out = respond(&state, fan_out.hostname, outcome);
+        response.results.extend(fan_out.results);
+        response.debug.extend(fan_out.debug);
+    }
+
+    // E.g. a prefix alone for a hostname without suffix, or addresses of types without a record
+    if response.results.is_empty() {
+        warn!(hostname = %request.hostname, "Update matched no record");
+        let detail = Some(format!(
+            "no record of '{}' matches the given addresses",
+            request.hostname
+        ));
+        return DyndnsResponse::new(Some(request.hostname), Outcome::NoHost { detail });
+    }
+    response
 }
 
-async fn update_record(
+/// A line per record moved to the new prefix, or failing to be moved. Failing to read the zone
+/// is reported for `hostname`.
+fn respond_rewrite(
     state: &AppState,
-    provider: &(dyn DnsProvider + Send + Sync),
-    domain: &str,
-    ip: &ParsedIpUpdate,
-) -> Result<Vec<String>, Report> {
-    let mut updated_ips = Vec::new();
-
-    let records = provider
-        .list_records(&state.origin_for(provider))
-        .await?
-        .into_iter()
-        .filter(|r| r.name == domain)
-        .collect::<Vec<_>>();
-
-    for (record_type, new_ip) in &ip.record_update {
-        let Some(record) = records.iter().find(|it| &it.typ == record_type) else {
-            info!(
-                domain = %domain,
-                record_type= ?record_type,
-                "No existing record found, skipping update"
+    hostname: &str,
+    rewrite: Result<Vec<PrefixRewrite>, UpdateError>,
+) -> DyndnsResponse {
+    let rewrites = match rewrite {
+        Ok(rewrites) => rewrites,
+        Err(e) => return respond(state, hostname.to_string(), Err(e)),
+    };
+    let mut response = DyndnsResponse {
+        results: Vec::new(),
+        status: None,
+        debug: Vec::new(),
+    };
+    for rewrite in rewrites {
+        response
+            .results
+            .extend(
+                rewrite
+                    .rewritten
+                    .into_iter()
+                    .map(|(name, address)| RecordResult {
+                        hostname: Some(name),
+                        record_type: Some(DnsRecordType::AAAA),
+                        outcome: Outcome::Good {
+                            address: address.to_string(),
+                        },
+                    }),
             );
-            continue;
-        };
-        provider
-            .update_record(&state.origin_for(provider), &record.id, new_ip)
-            .await
-            .attach(format!("For domain '{domain}'"))
-            .attach(format!("For {:?} record", record_type))?;
+        for (name, report) in rewrite.failed {
+            let error = UpdateError::Provider {
+                provider: rewrite.provider,
+                report,
+            };
+            let failed = respond(state, name, Err(error));
+            response.results.extend(failed.results);
+            response.debug.extend(failed.debug);
+        }
+    }
+    response
+}
 
-        updated_ips.push(new_ip.clone());
+/// [`DyndnsResponse::from_outcome`], with the details of a failure if `DEBUG_ERRORS` is set.
+fn respond(state: &AppState, hostname: String, outcome: UpdateOutcome) -> DyndnsResponse {
+    let debug = outcome
+        .as_ref()
+        .err()
+        .filter(|_| state.debug_errors)
+        .map(|e| {
+            let redactor = Redactor::for_state(state);
+            (DebugError::new(e, &redactor), redactor)
+        });
+    let response = DyndnsResponse::from_outcome(hostname, outcome);
+    match debug {
+        Some((error, redactor)) => response.with_debug(error, &redactor),
+        None => response,
     }
+}
+
+/// The answer to an update request in the dyndns2 protocol, with one [`RecordResult`] per
+/// updated record, or a single one if the request failed as a whole.
+///
+/// As a response, every result becomes a line starting with its protocol keyword, followed by
+/// the lines of its detail if there is one. Repeated results for a hostname, e.g. the same
+/// address written to several providers, are only sent once.
+#[derive(Debug, Clone, Serialize)]
+pub struct DyndnsResponse {
+    pub results: Vec<RecordResult>,
+    /// Overrides the status derived from the outcomes.
+    #[serde(skip)]
+    status: Option<StatusCode>,
+    /// Sent in the [`debug_errors::HEADER`] header, if any.
+    #[serde(skip)]
+    debug: Vec<DebugError>,
+}
 
-    Ok(updated_ips)
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record_type: Option<DnsRecordType>,
+    #[serde(flatten)]
+    pub outcome: Outcome,
 }
 
-#[derive(Debug, Clone)]
-struct ParsedIpUpdate {
-    record_update: Vec<(DnsRecordType, String)>,
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "code", rename_all = "lowercase")]
+pub enum Outcome {
+    Good {
+        address: String,
+    },
+    #[serde(rename = "nochg")]
+    NoChg {
+        address: String,
+    },
+    NoHost {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        detail: Option<String>,
+    },
+    BadAuth,
+    Abuse,
+    #[serde(rename = "911")]
+    ServerError {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        detail: Option<String>,
+    },
+    /// The request itself is invalid. Not part of the dyndns2 protocol, so only the detail is
+    /// sent.
+    BadRequest {
+        detail: String,
+    },
 }
 
-impl FromStr for ParsedIpUpdate {
-    type Err = Report;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut record_update = Vec::new();
-
-        for part in s.split(',') {
-            if part.contains('.') {
-                record_update.push((
-                    DnsRecordType::A,
-                    part.to_string()
-                        .parse::<Ipv4Addr>()
-                        .context("Could not parse IPv4")?
-                        .to_string(),
-                ));
-            } else if part.contains(':') {
-                record_update.push((
-                    DnsRecordType::AAAA,
-                    part.to_string()
-                        .parse::<Ipv6Addr>()
-                        .context("Could not parse IPv6")?
-                        .to_string(),
-                ));
-            } else {
-                bail!("IP part '{}' is neither IPv4 nor IPv6", part);
+impl DyndnsResponse {
+    /// A response with a single result.
+    pub fn new(hostname: Option<String>, outcome: Outcome) -> Self {
+        Self {
+            results: vec![RecordResult {
+                hostname,
+                record_type: None,
+                outcome,
+            }],
+            status: None,
+            debug: Vec::new(),
+        }
+    }
+
+    /// The response to an update of `hostname`.
+    pub fn from_outcome(hostname: String, outcome: UpdateOutcome) -> Self {
+        let hostname = Some(hostname);
+        match outcome {
+            Ok(updated) => Self {
+                results: updated
+                    .into_iter()
+                    .map(|it| RecordResult {
+                        hostname: hostname.clone(),
+                        record_type: Some(it.record_type),
+                        outcome: if it.changed {
+                            Outcome::Good {
+                                address: it.content,
+                            }
+                        } else {
+                            Outcome::NoChg {
+                                address: it.content,
+                            }
+                        },
+                    })
+                    .collect(),
+                status: None,
+                debug: Vec::new(),
+            },
+            Err(e @ (UpdateError::NotInOrigin { .. } | UpdateError::NotOwned { .. })) => {
+                let detail = Some(e.to_string());
+                Self::new(hostname, Outcome::NoHost { detail })
+            }
+            Err(UpdateError::NotReady) => {
+                Self::new(hostname, Outcome::ServerError { detail: None })
+                    .with_status(StatusCode::SERVICE_UNAVAILABLE)
+            }
+            Err(e @ UpdateError::Provider { .. }) => {
+                let detail = Some(format!("failed to update DNS record: {}", e));
+                Self::new(hostname, Outcome::ServerError { detail })
             }
         }
+    }
 
-        if record_update.is_empty() {
-            bail!("No IP addresses found in '{s}'");
+    /// Adds the details of a failure. The details of the results are redacted, and a failure
+    /// without one gets the error message as detail.
+    pub fn with_debug(mut self, error: DebugError, redactor: &Redactor) -> Self {
+        for result in &mut self.results {
+            if let Outcome::NoHost { detail } | Outcome::ServerError { detail } =
+                &mut result.outcome
+            {
+                *detail = Some(match detail.take() {
+                    Some(detail) => redactor.redact(&detail),
+                    None => error.message.clone(),
+                });
+            }
         }
+        self.debug.push(error);
+        self
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = Some(status);
+        self
+    }
 
-        Ok(Self { record_update })
+    /// The explicit status, or the first one that is not `200 OK`.
+    pub fn status(&self) -> StatusCode {
+        self.status.unwrap_or_else(|| {
+            self.results
+                .iter()
+                .map(|it| it.outcome.status())
+                .find(|it| *it != StatusCode::OK)
+                .unwrap_or(StatusCode::OK)
+        })
+    }
+
+    /// The classic plain text body.
+    pub fn to_text(&self) -> String {
+        let mut blocks = Vec::<(Option<&str>, String)>::new();
+        for result in &self.results {
+            let block = (
+                result.hostname.as_deref(),
+                result.outcome.lines().join("\n"),
+            );
+            if !blocks.contains(&block) {
+                blocks.push(block);
+            }
+        }
+        blocks
+            .into_iter()
+            .map(|(_, block)| block)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl IntoResponse for DyndnsResponse {
+    fn into_response(self) -> Response {
+        let mut response = (self.status(), self.to_text()).into_response();
+        if self.debug.is_empty() {
+            return response;
+        }
+        response.headers_mut().insert(
+            debug_errors::HEADER,
+            debug_errors::header_value(&self.debug),
+        );
+        response
+    }
+}
+
+impl Outcome {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Good { .. } | Self::NoChg { .. } => StatusCode::OK,
+            Self::NoHost { .. } => StatusCode::FORBIDDEN,
+            Self::BadAuth => StatusCode::UNAUTHORIZED,
+            Self::Abuse => StatusCode::TOO_MANY_REQUESTS,
+            Self::ServerError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::BadRequest { .. } => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let (keyword, detail) = match self {
+            Self::Good { address } => return vec![format!("good {address}")],
+            Self::NoChg { address } => return vec![format!("nochg {address}")],
+            Self::NoHost { detail } => ("nohost", detail.as_deref()),
+            Self::BadAuth => ("badauth", None),
+            Self::Abuse => ("abuse", None),
+            Self::ServerError { detail } => ("911", detail.as_deref()),
+            Self::BadRequest { detail } => return vec![detail.clone()],
+        };
+        std::iter::once(keyword)
+            .chain(detail.into_iter().flat_map(str::lines))
+            .map(str::to_string)
+            .collect()
     }
 }
 
@@ -170,4 +407,251 @@ impl FromStr for ParsedIpUpdate {
 pub struct UpdateQuery {
     pub myip: String,
     pub hostname: String,
+    /// The delegated IPv6 prefix, as sent by e.g. the Speedport or FRITZ!Box.
+    pub ip6lanprefix: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::Origin;
+    use crate::update::UpdatedRecord;
+    use rootcause::report;
+    use serde_json::json;
+
+    fn result(
+        hostname: &str,
+        record_type: Option<DnsRecordType>,
+        outcome: Outcome,
+    ) -> RecordResult {
+        RecordResult {
+            hostname: Some(hostname.to_string()),
+            record_type,
+            outcome,
+        }
+    }
+
+    fn response(results: Vec<RecordResult>) -> DyndnsResponse {
+        DyndnsResponse {
+            results,
+            status: None,
+            debug: Vec::new(),
+        }
+    }
+
+    fn updated(record_type: DnsRecordType, content: &str, changed: bool) -> UpdatedRecord {
+        UpdatedRecord {
+            provider: "memory",
+            record_type,
+            old_content: None,
+            content: content.to_string(),
+            changed,
+            name: "nas.foobar.de".to_string(),
+            old_ttl: None,
+        }
+    }
+
+    /// The text body, status and JSON form of each outcome.
+    #[test]
+    fn outcome_wire_representation() {
+        let detail = Some("first\nsecond".to_string());
+        let cases = [
+            (
+                Outcome::Good {
+                    address: "192.0.2.1".to_string(),
+                },
+                "good 192.0.2.1",
+                StatusCode::OK,
+                json!({ "code": "good", "address": "192.0.2.1" }),
+            ),
+            (
+                Outcome::NoChg {
+                    address: "192.0.2.1".to_string(),
+                },
+                "nochg 192.0.2.1",
+                StatusCode::OK,
+                json!({ "code": "nochg", "address": "192.0.2.1" }),
+            ),
+            (
+                Outcome::NoHost { detail: None },
+                "nohost",
+                StatusCode::FORBIDDEN,
+                json!({ "code": "nohost" }),
+            ),
+            (
+                Outcome::NoHost {
+                    detail: detail.clone(),
+                },
+                "nohost\nfirst\nsecond",
+                StatusCode::FORBIDDEN,
+                json!({ "code": "nohost", "detail": "first\nsecond" }),
+            ),
+            (
+                Outcome::BadAuth,
+                "badauth",
+                StatusCode::UNAUTHORIZED,
+                json!({ "code": "badauth" }),
+            ),
+            (
+                Outcome::Abuse,
+                "abuse",
+                StatusCode::TOO_MANY_REQUESTS,
+                json!({ "code": "abuse" }),
+            ),
+            (
+                Outcome::ServerError { detail: None },
+                "911",
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "code": "911" }),
+            ),
+            (
+                Outcome::ServerError { detail },
+                "911\nfirst\nsecond",
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "code": "911", "detail": "first\nsecond" }),
+            ),
+            (
+                Outcome::BadRequest {
+                    detail: "invalid 'myip' parameter".to_string(),
+                },
+                "invalid 'myip' parameter",
+                StatusCode::BAD_REQUEST,
+                json!({ "code": "badrequest", "detail": "invalid 'myip' parameter" }),
+            ),
+        ];
+
+        for (outcome, text, status, value) in cases {
+            let response = DyndnsResponse::new(None, outcome.clone());
+            assert_eq!(response.to_text(), text, "{outcome:?}");
+            assert_eq!(response.status(), status, "{outcome:?}");
+            assert_eq!(
+                serde_json::to_value(&response).unwrap(),
+                json!({ "results": [value] }),
+                "{outcome:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn json_names_hostname_and_record_type() {
+        let response = response(vec![result(
+            "nas.foobar.de",
+            Some(DnsRecordType::AAAA),
+            Outcome::Good {
+                address: "2001:db8::1".to_string(),
+            },
+        )]);
+
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({
+                "results": [{
+                    "hostname": "nas.foobar.de",
+                    "record_type": "AAAA",
+                    "code": "good",
+                    "address": "2001:db8::1",
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn repeated_results_are_sent_once() {
+        let good = || Outcome::Good {
+            address: "192.0.2.1".to_string(),
+        };
+        let response = response(vec![
+            result("nas.foobar.de", Some(DnsRecordType::A), good()),
+            result("nas.foobar.de", Some(DnsRecordType::A), good()),
+            result("tv.foobar.de", Some(DnsRecordType::A), good()),
+        ]);
+
+        assert_eq!(response.to_text(), "good 192.0.2.1\ngood 192.0.2.1");
+    }
+
+    #[test]
+    fn status_is_the_first_failure() {
+        let response = response(vec![
+            result(
+                "nas.foobar.de",
+                None,
+                Outcome::NoChg {
+                    address: "192.0.2.1".to_string(),
+                },
+            ),
+            result("tv.foobar.de", None, Outcome::NoHost { detail: None }),
+            result("pc.foobar.de", None, Outcome::ServerError { detail: None }),
+        ]);
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            response
+                .with_status(StatusCode::SERVICE_UNAVAILABLE)
+                .status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[test]
+    fn updated_records_are_good_or_nochg() {
+        let response = DyndnsResponse::from_outcome(
+            "nas.foobar.de".to_string(),
+            Ok(vec![
+                updated(DnsRecordType::A, "192.0.2.1", true),
+                updated(DnsRecordType::AAAA, "2001:db8::1", false),
+            ]),
+        );
+
+        assert_eq!(response.to_text(), "good 192.0.2.1\nnochg 2001:db8::1");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn update_errors_map_to_protocol_codes() {
+        let hostname = || "nas.foobar.de".to_string();
+
+        let response = DyndnsResponse::from_outcome(
+            hostname(),
+            Err(UpdateError::NotInOrigin {
+                hostname: hostname(),
+                mapped: hostname(),
+                origin: Origin::parse("example.org").unwrap(),
+            }),
+        );
+        assert_eq!(
+            response.to_text(),
+            "nohost\ndomain 'nas.foobar.de' (=> 'nas.foobar.de') is not a subdomain of \
+             'example.org'"
+        );
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = DyndnsResponse::from_outcome(
+            hostname(),
+            Err(UpdateError::NotOwned {
+                provider: "memory",
+                mapped: hostname(),
+            }),
+        );
+        assert!(response.to_text().starts_with("nohost\n"));
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = DyndnsResponse::from_outcome(hostname(), Err(UpdateError::NotReady));
+        assert_eq!(response.to_text(), "911");
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let response = DyndnsResponse::from_outcome(
+            hostname(),
+            Err(UpdateError::Provider {
+                provider: "memory",
+                report: report!("connection refused").into_dynamic(),
+            }),
+        );
+        let text = response.to_text();
+        assert!(
+            text.starts_with("911\nfailed to update DNS record:"),
+            "{text}"
+        );
+        assert!(text.contains("connection refused"), "{text}");
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
 }
@@ -0,0 +1,379 @@
+//! The client side of the dyndns2 protocol, for pushing updates to this server or any other
+//! dyndns2 endpoint.
+
+pub use crate::auth::signed::{SIGNATURE_PARAM, sign};
+use derive_more::Display;
+use reqwest::StatusCode;
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// At most this many characters of an unrecognized response are attached to errors.
+const MAX_BODY_EXCERPT: usize = 200;
+
+/// Sends updates to the `/nic/update` endpoint below a base URL with Basic auth.
+pub struct DyndnsClient {
+    base_url: String,
+    username: String,
+    password: String,
+    http: reqwest::Client,
+}
+
+impl DyndnsClient {
+    pub fn new(
+        base_url: impl Into<String>,
+        username: impl Into<String>,
+        password: impl Into<String>,
+        timeout: Duration,
+    ) -> Result<Self, Report> {
+        let http = reqwest::Client::builder()
+            .timeout(timeout)
+            .user_agent(concat!(
+                "speedport-custom-dyndns/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .build()
+            .context("Failed to build HTTP client")?;
+        Ok(Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            username: username.into(),
+            password: password.into(),
+            http,
+        })
+    }
+
+    /// Updates `hostnames` to `myip`, a comma separated list of addresses. Servers answer with
+    /// one or more lines per hostname, so the results are returned in the order of the lines.
+    pub async fn update(
+        &self,
+        hostnames: &[&str],
+        myip: &str,
+    ) -> Result<Vec<UpdateResult>, Report> {
+        let url = format!("{}/nic/update", self.base_url);
+        let hostname = hostnames.join(",");
+        let response = self
+            .http
+            .get(&url)
+            .basic_auth(&self.username, Some(&self.password))
+            .query(&[("hostname", hostname.as_str()), ("myip", myip)])
+            .send()
+            .await
+            .context("Update request failed")
+            .attach(format!("URL: {url}"))?;
+
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .context("Failed to read update response")?;
+        parse_response(status, &body)
+    }
+}
+
+/// The result of one response line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateResult {
+    /// The record was changed. Some servers do not repeat the address.
+    Good { address: Option<IpAddr> },
+    /// The record already had the address.
+    NoChg { address: Option<IpAddr> },
+    /// Any other return code. The detail is taken from the lines following the code, as sent
+    /// by this server.
+    Failed {
+        code: ErrorCode,
+        detail: Option<String>,
+    },
+}
+
+/// The error return codes of the dyndns2 protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum ErrorCode {
+    #[display("badauth")]
+    BadAuth,
+    #[display("!donator")]
+    NotDonator,
+    #[display("notfqdn")]
+    NotFqdn,
+    #[display("nohost")]
+    NoHost,
+    #[display("numhost")]
+    NumHost,
+    #[display("abuse")]
+    Abuse,
+    #[display("badagent")]
+    BadAgent,
+    #[display("dnserr")]
+    DnsErr,
+    #[display("911")]
+    ServerError,
+}
+
+impl ErrorCode {
+    fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "badauth" => Self::BadAuth,
+            "!donator" => Self::NotDonator,
+            "notfqdn" => Self::NotFqdn,
+            "nohost" => Self::NoHost,
+            "numhost" => Self::NumHost,
+            "abuse" => Self::Abuse,
+            "badagent" => Self::BadAgent,
+            "dnserr" => Self::DnsErr,
+            "911" => Self::ServerError,
+            _ => return None,
+        })
+    }
+}
+
+impl UpdateResult {
+    /// Whether the record has the requested address now.
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Good { .. } | Self::NoChg { .. })
+    }
+
+    /// Parses a line starting with a return code, or `None` if it does not.
+    fn parse(line: &str) -> Option<Self> {
+        let (code, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let address = rest
+            .split_whitespace()
+            .next()
+            .and_then(|it| it.parse().ok());
+        Some(match code {
+            "good" => Self::Good { address },
+            "nochg" => Self::NoChg { address },
+            _ => Self::Failed {
+                code: ErrorCode::parse(code)?,
+                detail: Some(rest.trim())
+                    .filter(|it| !it.is_empty())
+                    .map(str::to_string),
+            },
+        })
+    }
+}
+
+/// Formats the result as the response line it was parsed from.
+impl std::fmt::Display for UpdateResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Good {
+                address: Some(address),
+            } => write!(f, "good {address}"),
+            Self::Good { address: None } => f.write_str("good"),
+            Self::NoChg {
+                address: Some(address),
+            } => write!(f, "nochg {address}"),
+            Self::NoChg { address: None } => f.write_str("nochg"),
+            Self::Failed {
+                code,
+                detail: Some(detail),
+            } => write!(f, "{code} {detail}"),
+            Self::Failed { code, detail: None } => write!(f, "{code}"),
+        }
+    }
+}
+
+/// Parses a response body. Lines without a return code are appended to the detail of the
+/// preceding error. A body without any return code, such as the HTML error page of a proxy, is
+/// an error, unless the status alone is conclusive or the body is empty.
+pub fn parse_response(status: StatusCode, body: &str) -> Result<Vec<UpdateResult>, Report> {
+    let mut results = Vec::<UpdateResult>::new();
+    for line in body.lines().map(str::trim).filter(|it| !it.is_empty()) {
+        if let Some(result) = UpdateResult::parse(line) {
+            results.push(result);
+            continue;
+        }
+        let Some(UpdateResult::Failed { detail, .. }) = results.last_mut() else {
+            bail!("{}", unrecognized(status, body));
+        };
+        match detail {
+            Some(detail) => {
+                detail.push('\n');
+                detail.push_str(line);
+            }
+            None => *detail = Some(line.to_string()),
+        }
+    }
+
+    if results.is_empty() {
+        let code = match status {
+            StatusCode::UNAUTHORIZED => ErrorCode::BadAuth,
+            StatusCode::TOO_MANY_REQUESTS => ErrorCode::Abuse,
+            // Sent by this server if the hostname has no records to update
+            _ if status.is_success() && body.trim().is_empty() => return Ok(results),
+            _ => bail!("{}", unrecognized(status, body)),
+        };
+        results.push(UpdateResult::Failed { code, detail: None });
+    }
+    Ok(results)
+}
+
+fn unrecognized(status: StatusCode, body: &str) -> String {
+    let excerpt = body
+        .trim()
+        .chars()
+        .take(MAX_BODY_EXCERPT)
+        .collect::<String>();
+    format!("Unrecognized update response with status {status}: {excerpt:?}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_ok(body: &str) -> Vec<UpdateResult> {
+        parse_response(StatusCode::OK, body).unwrap()
+    }
+
+    fn good(address: &str) -> UpdateResult {
+        UpdateResult::Good {
+            address: Some(address.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn one_line_per_hostname() {
+        assert_eq!(
+            parse_ok("good 192.0.2.1\nnochg 2001:db8::1\n"),
+            vec![
+                good("192.0.2.1"),
+                UpdateResult::NoChg {
+                    address: Some("2001:db8::1".parse().unwrap())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn trailing_whitespace_and_crlf_are_ignored() {
+        assert_eq!(
+            parse_ok("good 192.0.2.1   \r\n\r\n  "),
+            vec![good("192.0.2.1")]
+        );
+    }
+
+    #[test]
+    fn address_is_optional() {
+        assert_eq!(
+            parse_ok("nochg\ngood"),
+            vec![
+                UpdateResult::NoChg { address: None },
+                UpdateResult::Good { address: None },
+            ]
+        );
+        assert_eq!(
+            parse_ok("good not-an-address"),
+            vec![UpdateResult::Good { address: None }]
+        );
+    }
+
+    #[test]
+    fn error_codes_keep_their_detail() {
+        assert_eq!(
+            parse_ok("nohost\n911 provider unreachable\nretry later\n!donator"),
+            vec![
+                UpdateResult::Failed {
+                    code: ErrorCode::NoHost,
+                    detail: None,
+                },
+                UpdateResult::Failed {
+                    code: ErrorCode::ServerError,
+                    detail: Some("provider unreachable\nretry later".to_string()),
+                },
+                UpdateResult::Failed {
+                    code: ErrorCode::NotDonator,
+                    detail: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn detail_lines_after_an_error_without_detail() {
+        assert_eq!(
+            parse_ok("dnserr\nzone is locked"),
+            vec![UpdateResult::Failed {
+                code: ErrorCode::DnsErr,
+                detail: Some("zone is locked".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn html_error_page_is_an_error() {
+        let body = "<html><head><title>502 Bad Gateway</title></head>\n<body>nginx</body></html>";
+        let error = parse_response(StatusCode::BAD_GATEWAY, body).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("502 Bad Gateway"), "{message}");
+        assert!(message.contains("<title>"), "{message}");
+    }
+
+    #[test]
+    fn text_before_the_first_code_is_an_error() {
+        assert!(parse_response(StatusCode::OK, "hello\ngood 192.0.2.1").is_err());
+    }
+
+    #[test]
+    fn long_bodies_are_cut_in_errors() {
+        let body = "x".repeat(1000);
+        let message = parse_response(StatusCode::OK, &body)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains(&"x".repeat(MAX_BODY_EXCERPT)), "{message}");
+        assert!(
+            !message.contains(&"x".repeat(MAX_BODY_EXCERPT + 1)),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn empty_bodies_fall_back_to_the_status() {
+        assert_eq!(parse_ok(""), vec![]);
+        assert_eq!(
+            parse_response(StatusCode::UNAUTHORIZED, "").unwrap(),
+            vec![UpdateResult::Failed {
+                code: ErrorCode::BadAuth,
+                detail: None,
+            }]
+        );
+        assert_eq!(
+            parse_response(StatusCode::TOO_MANY_REQUESTS, "\n").unwrap(),
+            vec![UpdateResult::Failed {
+                code: ErrorCode::Abuse,
+                detail: None,
+            }]
+        );
+        assert!(parse_response(StatusCode::INTERNAL_SERVER_ERROR, "").is_err());
+    }
+
+    #[test]
+    fn display_matches_the_response_line() {
+        for line in [
+            "good 192.0.2.1",
+            "good",
+            "nochg 2001:db8::1",
+            "nochg",
+            "badauth",
+            "911 provider unreachable",
+            "abuse",
+            "badagent",
+            "notfqdn",
+            "numhost",
+        ] {
+            assert_eq!(parse_ok(line)[0].to_string(), line);
+        }
+    }
+
+    #[test]
+    fn only_good_and_nochg_are_successes() {
+        assert!(good("192.0.2.1").is_success());
+        assert!(UpdateResult::NoChg { address: None }.is_success());
+        assert!(
+            !UpdateResult::Failed {
+                code: ErrorCode::NoHost,
+                detail: None,
+            }
+            .is_success()
+        );
+    }
+}
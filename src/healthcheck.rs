@@ -0,0 +1,107 @@
+//! A minimal HTTP client probing the `/healthz` endpoint, so container images do not need curl.
+
+use crate::settings;
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Derives the health check URL from the `INTERFACE`, `PORT` and `BASE_PATH` the server
+/// listens on.
+///
+/// Wildcard interfaces are replaced by the matching loopback address.
+pub fn default_url() -> String {
+    let interface = settings::var("INTERFACE").unwrap_or("0.0.0.0".to_string());
+    let port = settings::var("PORT").unwrap_or("3000".to_string());
+    let base_path = settings::var("BASE_PATH").unwrap_or_default();
+
+    match interface.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) if ip.is_unspecified() => {
+            format!("http://127.0.0.1:{port}{base_path}/healthz")
+        }
+        Ok(IpAddr::V6(ip)) if ip.is_unspecified() => {
+            format!("http://[::1]:{port}{base_path}/healthz")
+        }
+        Ok(IpAddr::V6(ip)) => format!("http://[{ip}]:{port}{base_path}/healthz"),
+        _ => format!("http://{interface}:{port}{base_path}/healthz"),
+    }
+}
+
+/// Requests `url` and fails unless the server answers with a success status within `timeout`.
+pub async fn check(url: &str, timeout: Duration) -> Result<(), Report> {
+    let client = reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .context("Failed to build HTTP client")?;
+
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .context("Health check request failed")
+        .attach(format!("URL: {url}"))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        bail!("Health check returned status {status}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::memory::MemoryProvider;
+    use crate::test_support::*;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    const TIMEOUT: Duration = Duration::from_secs(3);
+
+    #[tokio::test]
+    async fn running_server_is_healthy() {
+        let provider = Arc::new(MemoryProvider::new(nas_records()));
+        let server = builder(&provider).build().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let serve = tokio::spawn(server.serve(listener, async {
+            stopped.await.ok();
+        }));
+
+        let url = format!("http://{addr}/healthz");
+        check(&url, TIMEOUT).await.unwrap();
+
+        check(&format!("http://{addr}/readyz"), TIMEOUT)
+            .await
+            .unwrap();
+
+        let error = check(&format!("http://{addr}/missing"), TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("404"), "{error}");
+
+        stop.send(()).unwrap();
+        serve.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn unreachable_server_is_unhealthy() {
+        // Bind and drop, so the port is very likely closed
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let error = check(&format!("http://{addr}/healthz"), TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("Health check request failed"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,891 @@
+//! Update history and hostname state in SQLite, enabled by `DATABASE_PATH`, so both survive
+//! restarts and past changes can be queried.
+//!
+//! Events are written by a background thread, so updates never wait for the disk. If the thread
+//! falls behind, events are dropped with a warning instead. Without a database, the state only
+//! lives in the [`StatusTracker`](crate::status::StatusTracker) and there is no history.
+
+use crate::provider::DnsRecordType;
+use crate::status::{RecordStatus, StatusEntry};
+use derive_more::Display;
+use jiff::civil::Date;
+use jiff::{SignedDuration, Timestamp, tz::TimeZone};
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail, report};
+use rusqlite::{Connection, OpenFlags, Row, params};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+pub const DEFAULT_RETENTION_DAYS: u32 = 365;
+/// Events waiting for the writer. Further events are dropped.
+const QUEUE_SIZE: usize = 1024;
+/// How often events older than the retention are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// The number of events returned by a query without a limit.
+pub const DEFAULT_LIMIT: usize = 100;
+
+/// The schema, one migration per version. `PRAGMA user_version` stores how many were applied.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE hostname_state (
+        hostname TEXT NOT NULL,
+        record_type TEXT NOT NULL,
+        address TEXT,
+        last_attempt INTEGER,
+        last_success INTEGER,
+        consecutive_failures INTEGER NOT NULL DEFAULT 0,
+        last_client TEXT,
+        PRIMARY KEY (hostname, record_type)
+    );
+    CREATE TABLE update_events (
+        id INTEGER PRIMARY KEY,
+        timestamp INTEGER NOT NULL,
+        hostname TEXT NOT NULL,
+        record_type TEXT NOT NULL,
+        provider TEXT,
+        old_content TEXT,
+        new_content TEXT,
+        result TEXT NOT NULL,
+        client TEXT,
+        error TEXT
+    );
+    CREATE INDEX update_events_timestamp ON update_events (timestamp);
+    CREATE INDEX update_events_hostname ON update_events (hostname, timestamp);
+",
+    "
+    ALTER TABLE update_events ADD COLUMN request_id TEXT;
+    CREATE INDEX update_events_request_id ON update_events (request_id);
+",
+    "
+    ALTER TABLE update_events ADD COLUMN detail TEXT;
+",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventResult {
+    /// The record was changed.
+    #[display("good")]
+    Good,
+    /// The record already had the address.
+    #[display("nochg")]
+    Nochg,
+    #[display("failed")]
+    Failed,
+    /// A duplicate record was deleted, see `DEDUPE_RECORDS`.
+    #[display("deleted")]
+    Deleted,
+    /// The AAAA records of the zone were moved to a new delegated prefix, see `PREFIX_REWRITE`.
+    #[display("rewritten")]
+    Rewritten,
+    /// The resolver of the `PROPAGATION_CHECK` returned the new address.
+    #[display("visible")]
+    Visible,
+    /// The resolver still returned other addresses when the check gave up.
+    #[display("not_visible")]
+    NotVisible,
+}
+
+impl EventResult {
+    /// Whether the event is about the update of the record itself, which the state follows.
+    fn is_update(self) -> bool {
+        matches!(self, Self::Good | Self::Nochg | Self::Failed)
+    }
+}
+
+impl FromStr for EventResult {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "good" => Self::Good,
+            "nochg" => Self::Nochg,
+            "failed" => Self::Failed,
+            "deleted" => Self::Deleted,
+            "rewritten" => Self::Rewritten,
+            "visible" => Self::Visible,
+            "not_visible" => Self::NotVisible,
+            other => bail!("Unknown event result '{other}'"),
+        })
+    }
+}
+
+/// The outcome of an update of one record at one provider.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateEvent {
+    pub timestamp: Timestamp,
+    pub hostname: String,
+    pub record_type: DnsRecordType,
+    /// The provider, unknown for failures before any provider was asked.
+    pub provider: Option<String>,
+    /// The content before the update, if it was read.
+    pub old_content: Option<String>,
+    pub new_content: Option<String>,
+    pub result: EventResult,
+    pub client: Option<IpAddr>,
+    /// Why the update failed.
+    pub error: Option<String>,
+    /// The `X-Request-Id` of the request, to find its lines in the access log.
+    pub request_id: Option<String>,
+    /// More about the event, e.g. the ID of a deleted record.
+    pub detail: Option<String>,
+}
+
+/// Selects events, newest first.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    pub hostname: Option<String>,
+    pub request_id: Option<String>,
+    pub since: Option<Timestamp>,
+    pub until: Option<Timestamp>,
+    /// At most this many events, [`DEFAULT_LIMIT`] if unset.
+    pub limit: Option<usize>,
+}
+
+/// Parses a filter bound: an RFC 3339 timestamp, or a date meaning its start in UTC.
+pub fn parse_time(value: &str) -> Result<Timestamp, Report> {
+    let value = value.trim();
+    if let Ok(timestamp) = value.parse::<Timestamp>() {
+        return Ok(timestamp);
+    }
+    let date = value
+        .parse::<Date>()
+        .context("Expected a timestamp like 2026-03-01T12:00:00Z or a date like 2026-03-01")
+        .attach(format!("value: '{value}'"))?;
+    Ok(date
+        .to_zoned(TimeZone::UTC)
+        .context("Date is out of range")?
+        .timestamp())
+}
+
+/// Where the history is kept. Reading it from the environment does not touch the database, that
+/// is left to [`open`](Self::open) when the server starts.
+#[derive(Debug, Clone)]
+pub struct HistoryConfig {
+    pub path: PathBuf,
+    /// Events older than this are deleted, none if unset.
+    pub retention: Option<SignedDuration>,
+}
+
+impl HistoryConfig {
+    /// Opens the database and starts the writer, see [`HistoryStore::open`].
+    pub fn open(&self) -> Result<HistoryStore, Report> {
+        HistoryStore::open(&self.path, self.retention)
+    }
+}
+
+enum Message {
+    Event(UpdateEvent),
+    /// Writes all events sent before, then answers.
+    Flush(SyncSender<()>),
+}
+
+/// Writes events in the background and answers queries.
+pub struct HistoryStore {
+    sender: SyncSender<Message>,
+    reader: HistoryReader,
+}
+
+impl HistoryStore {
+    /// Opens (or creates) the database at `path`, applies pending migrations and starts the
+    /// writer. Events older than `retention` are deleted regularly, unless it is `None`.
+    pub fn open(path: &Path, retention: Option<SignedDuration>) -> Result<Self, Report> {
+        let mut connection = Connection::open(path)
+            .context("Failed to open the database")
+            .attach(format!("path: {}", path.display()))?;
+        // Lets queries run while the writer writes
+        connection
+            .pragma_update(None, "journal_mode", "WAL")
+            .context("Failed to enable the write-ahead log")?;
+        migrate(&mut connection).attach(format!("path: {}", path.display()))?;
+
+        let (sender, receiver) = sync_channel(QUEUE_SIZE);
+        let path_buf = path.to_path_buf();
+        std::thread::Builder::new()
+            .name("history-writer".to_string())
+            .spawn(move || write_events(connection, receiver, retention, path_buf))
+            .context("Failed to start the history writer")?;
+
+        Ok(Self {
+            sender,
+            reader: HistoryReader::open(path)?,
+        })
+    }
+
+    /// Queues `event` for writing. Never blocks, the event is dropped if the writer is behind.
+    pub fn record(&self, event: UpdateEvent) {
+        match self.sender.try_send(Message::Event(event)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                warn!("History writer is behind, dropping update event");
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                error!("History writer stopped, dropping update event");
+            }
+        }
+    }
+
+    /// Waits until all events recorded so far are written.
+    pub async fn flush(&self) {
+        let sender = self.sender.clone();
+        // Sending waits while the queue is full, which must not stall the runtime
+        let _ = tokio::task::spawn_blocking(move || {
+            let (done, wait) = sync_channel(1);
+            if sender.send(Message::Flush(done)).is_ok() {
+                let _ = wait.recv();
+            }
+        })
+        .await;
+    }
+
+    pub fn reader(&self) -> &HistoryReader {
+        &self.reader
+    }
+}
+
+/// Reads the history, also from other processes like the `history` command.
+#[derive(Clone)]
+pub struct HistoryReader {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl HistoryReader {
+    /// Opens the existing database at `path` read-only.
+    pub fn open(path: &Path) -> Result<Self, Report> {
+        let connection = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .context("Failed to open the database")
+        .attach(format!("path: {}", path.display()))?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// The events matching `filter`, newest first.
+    pub async fn events(&self, filter: HistoryFilter) -> Result<Vec<UpdateEvent>, Report> {
+        self.blocking(move |connection| query_events(connection, &filter))
+            .await
+    }
+
+    /// The last known state of every record, to restore the status after a restart.
+    pub async fn states(&self) -> Result<Vec<StatusEntry>, Report> {
+        self.blocking(query_states).await
+    }
+
+    /// Runs `query` on a blocking thread, so the disk access does not stall the runtime.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        query: impl FnOnce(&Connection) -> Result<T, Report> + Send + 'static,
+    ) -> Result<T, Report> {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || query(&connection.lock().expect("mutex poisoned")))
+            .await
+            .context("History query panicked")?
+    }
+}
+
+/// Applies the migrations the database has not seen yet, in one transaction.
+fn migrate(connection: &mut Connection) -> Result<(), Report> {
+    let version = connection
+        .pragma_query_value(None, "user_version", |row| row.get::<_, usize>(0))
+        .context("Failed to read the schema version")?;
+    if version > MIGRATIONS.len() {
+        bail!(
+            "The database has schema version {version}, this version only knows up to {}",
+            MIGRATIONS.len()
+        );
+    }
+    if version == MIGRATIONS.len() {
+        return Ok(());
+    }
+    let transaction = connection
+        .transaction()
+        .context("Failed to start the migration")?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        transaction
+            .execute_batch(migration)
+            .context("Failed to migrate the database")
+            .attach(format!("to version: {}", index + 1))?;
+    }
+    transaction
+        .pragma_update(None, "user_version", MIGRATIONS.len())
+        .context("Failed to store the schema version")?;
+    transaction
+        .commit()
+        .context("Failed to commit the migration")?;
+    info!(
+        from = version,
+        to = MIGRATIONS.len(),
+        "Migrated the database"
+    );
+    Ok(())
+}
+
+/// The loop of the writer thread. Events arriving together are written in one transaction.
+fn write_events(
+    mut connection: Connection,
+    receiver: Receiver<Message>,
+    retention: Option<SignedDuration>,
+    path: PathBuf,
+) {
+    let mut last_prune = None;
+    loop {
+        if let Some(retention) = retention
+            && last_prune.is_none_or(|it: Timestamp| {
+                Timestamp::now().duration_since(it).unsigned_abs() >= PRUNE_INTERVAL
+            })
+        {
+            if let Err(e) = prune(&connection, Timestamp::now() - retention) {
+                warn!(error = %e, path = %path.display(), "Failed to prune the history");
+            }
+            last_prune = Some(Timestamp::now());
+        }
+
+        let first = match receiver.recv_timeout(PRUNE_INTERVAL) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        let mut events = Vec::new();
+        let mut flushes = Vec::new();
+        for message in std::iter::once(first).chain(receiver.try_iter()) {
+            match message {
+                Message::Event(event) => events.push(event),
+                Message::Flush(done) => flushes.push(done),
+            }
+        }
+        if !events.is_empty()
+            && let Err(e) = insert_events(&mut connection, &events)
+        {
+            error!(
+                error = %e,
+                path = %path.display(),
+                count = events.len(),
+                "Failed to write update events"
+            );
+        }
+        for done in flushes {
+            let _ = done.send(());
+        }
+    }
+}
+
+fn insert_events(connection: &mut Connection, events: &[UpdateEvent]) -> Result<(), Report> {
+    let transaction = connection.transaction()?;
+    for event in events {
+        let record_type = event.record_type.to_string();
+        let timestamp = event.timestamp.as_millisecond();
+        let client = event.client.map(|it| it.to_string());
+        transaction.execute(
+            "INSERT INTO update_events
+                (timestamp, hostname, record_type, provider, old_content, new_content, result,
+                 client, error, request_id, detail)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                timestamp,
+                event.hostname,
+                record_type,
+                event.provider,
+                event.old_content,
+                event.new_content,
+                event.result.to_string(),
+                client,
+                event.error,
+                event.request_id,
+                event.detail,
+            ],
+        )?;
+        // E.g. deleting a duplicate leaves the state of the kept record alone
+        if !event.result.is_update() {
+            continue;
+        }
+        if event.result == EventResult::Failed {
+            transaction.execute(
+                "INSERT INTO hostname_state
+                    (hostname, record_type, last_attempt, consecutive_failures, last_client)
+                 VALUES (?1, ?2, ?3, 1, ?4)
+                 ON CONFLICT (hostname, record_type) DO UPDATE SET
+                    last_attempt = excluded.last_attempt,
+                    consecutive_failures = consecutive_failures + 1,
+                    last_client = excluded.last_client",
+                params![event.hostname, record_type, timestamp, client],
+            )?;
+        } else {
+            transaction.execute(
+                "INSERT INTO hostname_state
+                    (hostname, record_type, address, last_attempt, last_success,
+                     consecutive_failures, last_client)
+                 VALUES (?1, ?2, ?3, ?4, ?4, 0, ?5)
+                 ON CONFLICT (hostname, record_type) DO UPDATE SET
+                    address = excluded.address,
+                    last_attempt = excluded.last_attempt,
+                    last_success = excluded.last_success,
+                    consecutive_failures = 0,
+                    last_client = excluded.last_client",
+                params![
+                    event.hostname,
+                    record_type,
+                    event.new_content,
+                    timestamp,
+                    client
+                ],
+            )?;
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+fn prune(connection: &Connection, before: Timestamp) -> Result<(), Report> {
+    let deleted = connection.execute(
+        "DELETE FROM update_events WHERE timestamp < ?1",
+        params![before.as_millisecond()],
+    )?;
+    if deleted > 0 {
+        info!(deleted, %before, "Pruned old update events");
+    } else {
+        debug!(%before, "No update events to prune");
+    }
+    Ok(())
+}
+
+fn query_events(
+    connection: &Connection,
+    filter: &HistoryFilter,
+) -> Result<Vec<UpdateEvent>, Report> {
+    let mut statement = connection
+        .prepare_cached(
+            "SELECT timestamp, hostname, record_type, provider, old_content, new_content, result,
+                    client, error, request_id, detail
+             FROM update_events
+             WHERE (?1 IS NULL OR hostname = ?1)
+               AND (?2 IS NULL OR timestamp >= ?2)
+               AND (?3 IS NULL OR timestamp < ?3)
+               AND (?5 IS NULL OR request_id = ?5)
+             ORDER BY timestamp DESC, id DESC
+             LIMIT ?4",
+        )
+        .context("Failed to query the history")?;
+    let limit = filter.limit.unwrap_or(DEFAULT_LIMIT);
+    let rows = statement
+        .query_map(
+            params![
+                filter.hostname,
+                filter.since.map(|it| it.as_millisecond()),
+                filter.until.map(|it| it.as_millisecond()),
+                i64::try_from(limit).unwrap_or(i64::MAX),
+                filter.request_id,
+            ],
+            |row| Ok(read_event(row)),
+        )
+        .context("Failed to query the history")?;
+    let mut events = Vec::new();
+    for row in rows {
+        match row.context("Failed to read an update event")? {
+            Ok(event) => events.push(event),
+            Err(e) => warn!(error = %e, "Skipping unreadable update event"),
+        }
+    }
+    Ok(events)
+}
+
+fn read_event(row: &Row<'_>) -> Result<UpdateEvent, Report> {
+    Ok(UpdateEvent {
+        timestamp: read_timestamp(row.get(0)?)?,
+        hostname: row.get(1)?,
+        record_type: read_record_type(row.get(2)?)?,
+        provider: row.get(3)?,
+        old_content: row.get(4)?,
+        new_content: row.get(5)?,
+        result: row.get::<_, String>(6)?.parse()?,
+        client: row
+            .get::<_, Option<String>>(7)?
+            .and_then(|it| it.parse().ok()),
+        error: row.get(8)?,
+        request_id: row.get(9)?,
+        detail: row.get(10)?,
+    })
+}
+
+fn query_states(connection: &Connection) -> Result<Vec<StatusEntry>, Report> {
+    let mut statement = connection
+        .prepare(
+            "SELECT hostname, record_type, address, last_attempt, last_success,
+                    consecutive_failures, last_client
+             FROM hostname_state
+             ORDER BY hostname, record_type",
+        )
+        .context("Failed to query the hostname state")?;
+    let mut rows = statement
+        .query([])
+        .context("Failed to query the hostname state")?;
+    let mut states = Vec::new();
+    while let Some(row) = rows.next().context("Failed to read the hostname state")? {
+        let hostname = row.get::<_, String>(0)?;
+        let record_type = read_record_type(row.get(1)?)?;
+        let status = RecordStatus {
+            address: row.get(2)?,
+            last_attempt: row
+                .get::<_, Option<i64>>(3)?
+                .map(read_timestamp)
+                .transpose()?,
+            last_success: row
+                .get::<_, Option<i64>>(4)?
+                .map(read_timestamp)
+                .transpose()?,
+            consecutive_failures: row.get(5)?,
+            last_client: row
+                .get::<_, Option<String>>(6)?
+                .and_then(|it| it.parse().ok()),
+            propagation: None,
+        };
+        states.push(((hostname, record_type), status));
+    }
+    Ok(states)
+}
+
+fn read_timestamp(millis: i64) -> Result<Timestamp, Report> {
+    Timestamp::from_millisecond(millis)
+        .context("Invalid timestamp in the database")
+        .attach(format!("value: {millis}"))
+        .map_err(Report::into_dynamic)
+}
+
+fn read_record_type(value: String) -> Result<DnsRecordType, Report> {
+    DnsRecordType::try_from(value.clone())
+        .map_err(|()| {
+            report!("Unknown record type in the database").attach(format!("value: '{value}'"))
+        })
+        .map_err(Report::into_dynamic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::propagation::PropagationCheck;
+    use crate::provider::memory::MemoryProvider;
+    use crate::test_support::*;
+    use axum::body::Body;
+    use axum::http::header;
+    use serde_json::json;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// The database of one test, in a fresh directory.
+    fn database(test: &str) -> HistoryConfig {
+        let dir =
+            std::env::temp_dir().join(format!("speedport-history-{}-{test}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        HistoryConfig {
+            path: dir.join("state.db"),
+            retention: None,
+        }
+    }
+
+    #[test]
+    fn database_is_only_created_when_opened() {
+        let config = database("lazy");
+
+        assert!(!config.path.exists());
+        let _store = config.open().unwrap();
+        assert!(config.path.exists());
+    }
+
+    #[tokio::test]
+    async fn updates_are_recorded_after_a_flush() {
+        let config = database("recorded");
+        let provider = Arc::new(MemoryProvider::new(nas_records()));
+        let server = builder(&provider)
+            .build()
+            .unwrap()
+            .with_history(config.open().unwrap());
+        let router = server.router();
+
+        send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+        let history = server.state().updates.history().unwrap();
+        history.flush().await;
+
+        let events = history
+            .reader()
+            .events(HistoryFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.hostname, "nas.foobar.de");
+        assert_eq!(event.record_type, DnsRecordType::A);
+        assert_eq!(event.old_content.as_deref(), Some("192.0.2.1"));
+        assert_eq!(event.new_content.as_deref(), Some("198.51.100.7"));
+        assert_eq!(event.result, EventResult::Good);
+    }
+
+    #[tokio::test]
+    async fn events_carry_the_request_id() {
+        let config = database("request-id");
+        let provider = Arc::new(MemoryProvider::new(nas_records()));
+        let server = builder(&provider)
+            .build()
+            .unwrap()
+            .with_history(config.open().unwrap());
+
+        let request = request("/nic/update?hostname=nas.foobar.de&myip=198.51.100.7")
+            .header(header::AUTHORIZATION, basic_auth("router", PASSWORD))
+            .header("X-Request-Id", "router-42")
+            .body(Body::empty())
+            .unwrap();
+        send(&server.router(), request).await;
+        send(
+            &server.router(),
+            update("hostname=nas.foobar.de&myip=198.51.100.8"),
+        )
+        .await;
+        let history = server.state().updates.history().unwrap();
+        history.flush().await;
+
+        let filter = HistoryFilter {
+            request_id: Some("router-42".to_string()),
+            ..HistoryFilter::default()
+        };
+        let events = history.reader().events(filter).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].new_content.as_deref(), Some("198.51.100.7"));
+        assert_eq!(events[0].request_id.as_deref(), Some("router-42"));
+    }
+
+    #[tokio::test]
+    async fn deleted_duplicates_are_recorded() {
+        let config = database("deleted");
+        let provider = Arc::new(MemoryProvider::new(vec![
+            record("a-1", DnsRecordType::A, "nas.foobar.de", "192.0.2.1"),
+            record("a-2", DnsRecordType::A, "nas.foobar.de", "192.0.2.2"),
+        ]));
+        let server = builder(&provider)
+            .dedupe_records(true)
+            .build()
+            .unwrap()
+            .with_history(config.open().unwrap());
+
+        send(
+            &server.router(),
+            update("hostname=nas.foobar.de&myip=198.51.100.7"),
+        )
+        .await;
+        let history = server.state().updates.history().unwrap();
+        history.flush().await;
+
+        let events = history
+            .reader()
+            .events(HistoryFilter::default())
+            .await
+            .unwrap();
+        let deleted = events
+            .iter()
+            .find(|it| it.result == EventResult::Deleted)
+            .unwrap();
+        assert_eq!(deleted.old_content.as_deref(), Some("192.0.2.2"));
+        assert_eq!(deleted.detail.as_deref(), Some("record ID a-2"));
+        // The state follows the kept record
+        let states = history.reader().states().await.unwrap();
+        assert_eq!(states[0].1.address.as_deref(), Some("198.51.100.7"));
+    }
+
+    #[tokio::test]
+    async fn alias_updates_name_the_requested_hostname() {
+        let config = database("alias");
+        let mut records = nas_records();
+        records.push(record(
+            "files",
+            DnsRecordType::A,
+            "files.foobar.de",
+            "192.0.2.1",
+        ));
+        let provider = Arc::new(MemoryProvider::new(records));
+        let server = builder(&provider)
+            .alias("nas.foobar.de", "files.foobar.de")
+            .build()
+            .unwrap()
+            .with_history(config.open().unwrap());
+
+        send(
+            &server.router(),
+            update("hostname=nas.foobar.de&myip=198.51.100.7"),
+        )
+        .await;
+        let history = server.state().updates.history().unwrap();
+        history.flush().await;
+
+        let events = history
+            .reader()
+            .events(HistoryFilter::default())
+            .await
+            .unwrap();
+        let detail = |hostname: &str| {
+            let event = events.iter().find(|it| it.hostname == hostname).unwrap();
+            event.detail.clone()
+        };
+        assert_eq!(detail("nas.foobar.de"), None);
+        assert_eq!(
+            detail("files.foobar.de").as_deref(),
+            Some("alias of nas.foobar.de")
+        );
+    }
+
+    #[tokio::test]
+    async fn prefix_rewrite_is_a_single_event() {
+        let config = database("rewrite");
+        let provider = Arc::new(MemoryProvider::new(vec![
+            record(
+                "router",
+                DnsRecordType::AAAA,
+                "router.foobar.de",
+                "2001:db8:1200::1",
+            ),
+            record(
+                "nas",
+                DnsRecordType::AAAA,
+                "nas.foobar.de",
+                "2001:db8:1200:1::7",
+            ),
+            record(
+                "pc",
+                DnsRecordType::AAAA,
+                "pc.foobar.de",
+                "2001:db8:1200:2::8",
+            ),
+        ]));
+        let server = builder(&provider)
+            .prefix_rewrite(true)
+            .build()
+            .unwrap()
+            .with_history(config.open().unwrap());
+
+        send(
+            &server.router(),
+            update(
+                "hostname=router.foobar.de&myip=2001:db8:3400::1&ip6lanprefix=2001:db8:3400::/56",
+            ),
+        )
+        .await;
+        let history = server.state().updates.history().unwrap();
+        history.flush().await;
+
+        let events = history
+            .reader()
+            .events(HistoryFilter::default())
+            .await
+            .unwrap();
+        let rewritten = events
+            .iter()
+            .filter(|it| it.result == EventResult::Rewritten)
+            .collect::<Vec<_>>();
+        assert_eq!(rewritten.len(), 1);
+        assert_eq!(rewritten[0].hostname, "router.foobar.de");
+        assert_eq!(
+            rewritten[0].old_content.as_deref(),
+            Some("2001:db8:1200::/56")
+        );
+        assert_eq!(
+            rewritten[0].new_content.as_deref(),
+            Some("2001:db8:3400::/56")
+        );
+        assert_eq!(
+            rewritten[0].detail.as_deref(),
+            Some("nas.foobar.de, pc.foobar.de")
+        );
+        // Only the requested hostname has a state
+        let states = history.reader().states().await.unwrap();
+        assert_eq!(states.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn propagation_results_are_recorded() {
+        let resolver = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "Status": 0,
+                "Answer": [{ "name": "nas.foobar.de", "type": 1, "TTL": 300, "data": "198.51.100.7" }],
+            })))
+            .mount(&resolver)
+            .await;
+        let config = database("propagation");
+        let provider = Arc::new(MemoryProvider::new(nas_records()));
+        let server = builder(&provider)
+            .propagation_check(PropagationCheck::new(resolver.uri(), 1))
+            .build()
+            .unwrap()
+            .with_history(config.open().unwrap());
+
+        send(
+            &server.router(),
+            update("hostname=nas.foobar.de&myip=198.51.100.7"),
+        )
+        .await;
+        let history = server.state().updates.history().unwrap();
+        let mut visible = None;
+        for _ in 0..60 {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            history.flush().await;
+            let events = history
+                .reader()
+                .events(HistoryFilter::default())
+                .await
+                .unwrap();
+            visible = events
+                .into_iter()
+                .find(|it| it.result == EventResult::Visible);
+            if visible.is_some() {
+                break;
+            }
+        }
+
+        let visible = visible.expect("no propagation event");
+        assert_eq!(visible.hostname, "nas.foobar.de");
+        assert_eq!(visible.new_content.as_deref(), Some("198.51.100.7"));
+        // The state still follows the update itself
+        let states = history.reader().states().await.unwrap();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].1.consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn status_is_restored_from_the_database() {
+        let config = database("restored");
+        let provider = Arc::new(MemoryProvider::new(nas_records()));
+        {
+            let server = builder(&provider)
+                .build()
+                .unwrap()
+                .with_history(config.open().unwrap());
+            send(
+                &server.router(),
+                update("hostname=nas.foobar.de&myip=198.51.100.7"),
+            )
+            .await;
+            server.state().updates.history().unwrap().flush().await;
+        }
+
+        let server = builder(&provider)
+            .build()
+            .unwrap()
+            .with_history(config.open().unwrap());
+        server.restore_status().await.unwrap();
+
+        let snapshot = server.state().status.snapshot();
+        let ((hostname, typ), status) = snapshot
+            .iter()
+            .find(|((_, typ), _)| *typ == DnsRecordType::A)
+            .unwrap();
+        assert_eq!(hostname, "nas.foobar.de");
+        assert_eq!(*typ, DnsRecordType::A);
+        assert_eq!(status.address.as_deref(), Some("198.51.100.7"));
+    }
+}
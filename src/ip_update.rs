@@ -0,0 +1,338 @@
+//! Parsing of the `myip` parameter of an update.
+//!
+//! The parameter is a comma-separated list of addresses:
+//!
+//! ```text
+//! myip    = segment *( "," segment )
+//! segment = *WSP ( ipv4 / ipv6 / ipv6 "/" prefix-length ) *WSP
+//! ```
+//!
+//! A segment containing a `.` is parsed as an IPv4 address and updates the A record, one
+//! containing a `:` as an IPv6 address updating the AAAA record. IPv4-mapped IPv6 addresses
+//! therefore count as (invalid) IPv4. Addresses are stored in their canonical form, so
+//! `2001:DB8:0::1` becomes `2001:db8::1`. The order of the segments does not matter.
+//!
+//! A segment in prefix notation, like `2001:db8:1200::/56`, is the delegated IPv6 prefix instead
+//! (also accepted as `ip6lanprefix` parameter). It updates no record by itself, but is combined
+//! with the configured suffix of a hostname, see [`ParsedIpUpdate::with_ipv6_suffix`].
+
+use crate::provider::DnsRecordType;
+use ipnet::Ipv6Net;
+use rootcause::{Report, bail, prelude::ResultExt};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+#[derive(Debug, Clone)]
+pub struct ParsedIpUpdate {
+    record_update: Vec<(DnsRecordType, String)>,
+    ipv6_prefix: Option<Ipv6Net>,
+}
+
+impl ParsedIpUpdate {
+    /// Creates an update from explicit addresses. Returns `None` if both are missing.
+    pub fn new(ipv4: Option<Ipv4Addr>, ipv6: Option<Ipv6Addr>) -> Option<Self> {
+        let record_update = ipv4
+            .map(|it| (DnsRecordType::A, it.to_string()))
+            .into_iter()
+            .chain(ipv6.map(|it| (DnsRecordType::AAAA, it.to_string())))
+            .collect::<Vec<_>>();
+        (!record_update.is_empty()).then_some(Self {
+            record_update,
+            ipv6_prefix: None,
+        })
+    }
+
+    /// An update only carrying a delegated prefix. It updates nothing without a suffix.
+    pub fn from_ipv6_prefix(prefix: Ipv6Net) -> Self {
+        Self {
+            record_update: Vec::new(),
+            ipv6_prefix: Some(prefix.trunc()),
+        }
+    }
+
+    pub fn with_ipv6_prefix(mut self, prefix: Ipv6Net) -> Self {
+        self.ipv6_prefix = Some(prefix.trunc());
+        self
+    }
+
+    /// The delegated IPv6 prefix, if the update carries one.
+    pub fn ipv6_prefix(&self) -> Option<Ipv6Net> {
+        self.ipv6_prefix
+    }
+
+    /// Points the AAAA record at the host with the interface identifier `suffix`.
+    ///
+    /// With a delegated prefix, the address is the prefix followed by the bits of `suffix` outside
+    /// of it. Otherwise the lower 64 bits of the IPv6 address are replaced by those of `suffix`.
+    pub fn with_ipv6_suffix(&self, suffix: Ipv6Addr) -> Self {
+        let host_mask = match self.ipv6_prefix {
+            Some(prefix) => prefix.hostmask().to_bits(),
+            None => u64::MAX as u128,
+        };
+        let combine = |ip: Ipv6Addr| {
+            Ipv6Addr::from_bits((ip.to_bits() & !host_mask) | (suffix.to_bits() & host_mask))
+                .to_string()
+        };
+
+        let mut record_update = self
+            .record_update
+            .iter()
+            .filter(|(record_type, _)| {
+                self.ipv6_prefix.is_none() || *record_type != DnsRecordType::AAAA
+            })
+            .map(|(record_type, content)| match content.parse::<Ipv6Addr>() {
+                Ok(ip) if *record_type == DnsRecordType::AAAA => (record_type.clone(), combine(ip)),
+                _ => (record_type.clone(), content.clone()),
+            })
+            .collect::<Vec<_>>();
+        if let Some(prefix) = self.ipv6_prefix {
+            record_update.push((DnsRecordType::AAAA, combine(prefix.network())));
+        }
+        Self {
+            record_update,
+            ipv6_prefix: self.ipv6_prefix,
+        }
+    }
+
+    /// The record types to update and their new content.
+    pub fn records(&self) -> &[(DnsRecordType, String)] {
+        &self.record_update
+    }
+}
+
+impl FromStr for ParsedIpUpdate {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut record_update = Vec::new();
+        let mut ipv6_prefix = None;
+
+        for part in s.split(',').map(str::trim) {
+            if part.contains('/') {
+                if ipv6_prefix.is_some() {
+                    bail!("Only one IPv6 prefix may be given, found a second one: '{part}'");
+                }
+                ipv6_prefix = Some(
+                    part.parse::<Ipv6Net>()
+                        .context("Could not parse IPv6 prefix")
+                        .attach(format!("segment: '{part}'"))?
+                        .trunc(),
+                );
+            } else if part.contains('.') {
+                record_update.push((
+                    DnsRecordType::A,
+                    part.parse::<Ipv4Addr>()
+                        .context("Could not parse IPv4")
+                        .attach(format!("segment: '{part}'"))?
+                        .to_string(),
+                ));
+            } else if part.contains(':') {
+                record_update.push((
+                    DnsRecordType::AAAA,
+                    part.parse::<Ipv6Addr>()
+                        .context("Could not parse IPv6")
+                        .attach(format!("segment: '{part}'"))?
+                        .to_string(),
+                ));
+            } else {
+                bail!("IP part '{}' is neither IPv4 nor IPv6", part);
+            }
+        }
+
+        if record_update.is_empty() && ipv6_prefix.is_none() {
+            bail!("No IP addresses found in '{s}'");
+        }
+
+        Ok(Self {
+            record_update,
+            ipv6_prefix,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::net::IpAddr;
+
+    fn parse(s: &str) -> Result<Vec<(DnsRecordType, String)>, String> {
+        s.parse::<ParsedIpUpdate>()
+            .map(|it| it.records().to_vec())
+            .map_err(|e| e.to_string())
+    }
+
+    fn record(typ: DnsRecordType, content: &str) -> (DnsRecordType, String) {
+        (typ, content.to_string())
+    }
+
+    /// Addresses that are valid segments. IPv4-mapped IPv6 addresses are written with dots and
+    /// therefore rejected as IPv4.
+    fn address() -> impl Strategy<Value = IpAddr> {
+        prop_oneof![
+            any::<Ipv4Addr>().prop_map(IpAddr::V4),
+            any::<Ipv6Addr>()
+                .prop_filter("IPv4-mapped", |it| it.to_ipv4_mapped().is_none())
+                .prop_map(IpAddr::V6),
+        ]
+    }
+
+    fn whitespace() -> impl Strategy<Value = String> {
+        "[ \t]{0,3}"
+    }
+
+    fn expected(addresses: &[IpAddr]) -> Vec<(DnsRecordType, String)> {
+        addresses
+            .iter()
+            .map(|it| match it {
+                IpAddr::V4(ip) => (DnsRecordType::A, ip.to_string()),
+                IpAddr::V6(ip) => (DnsRecordType::AAAA, ip.to_string()),
+            })
+            .collect()
+    }
+
+    fn sorted(mut records: Vec<(DnsRecordType, String)>) -> Vec<(DnsRecordType, String)> {
+        records
+            .sort_by(|a, b| (a.0 == DnsRecordType::A, &a.1).cmp(&(b.0 == DnsRecordType::A, &b.1)));
+        records
+    }
+
+    proptest! {
+        #[test]
+        fn addresses_round_trip(
+            segments in prop::collection::vec((address(), whitespace(), whitespace()), 1..5)
+        ) {
+            let input = segments
+                .iter()
+                .map(|(ip, before, after)| format!("{before}{ip}{after}"))
+                .collect::<Vec<_>>()
+                .join(",");
+            let addresses = segments.iter().map(|(ip, _, _)| *ip).collect::<Vec<_>>();
+
+            prop_assert_eq!(parse(&input), Ok(expected(&addresses)));
+        }
+
+        #[test]
+        fn non_canonical_addresses_are_canonicalized(ip in any::<Ipv6Addr>()) {
+            prop_assume!(ip.to_ipv4_mapped().is_none());
+            let segments = ip.segments();
+            let long_form = segments
+                .iter()
+                .map(|it| format!("{it:04X}"))
+                .collect::<Vec<_>>()
+                .join(":");
+
+            prop_assert_eq!(parse(&long_form), Ok(vec![(DnsRecordType::AAAA, ip.to_string())]));
+        }
+
+        #[test]
+        fn segment_order_does_not_matter(
+            addresses in prop::collection::vec(address(), 1..5)
+                .prop_flat_map(|it| (Just(it.clone()), Just(it).prop_shuffle()))
+        ) {
+            let (addresses, shuffled) = addresses;
+            let join = |addresses: &[IpAddr]| {
+                addresses.iter().map(ToString::to_string).collect::<Vec<_>>().join(",")
+            };
+
+            let records = parse(&join(&addresses)).map(sorted);
+            prop_assert_eq!(records, parse(&join(&shuffled)).map(sorted));
+        }
+
+        #[test]
+        fn garbage_never_panics_and_names_the_segment(input in ".{0,40}") {
+            if let Err(e) = parse(&input) {
+                let mentioned = input
+                    .split(',')
+                    .map(str::trim)
+                    .any(|segment| e.contains(&format!("'{segment}'")));
+                prop_assert!(mentioned, "{} does not name a segment of {:?}", e, input);
+            }
+        }
+    }
+
+    #[test]
+    fn single_addresses() {
+        assert_eq!(
+            parse("192.0.2.1"),
+            Ok(vec![record(DnsRecordType::A, "192.0.2.1")])
+        );
+        assert_eq!(
+            parse("2001:db8::1"),
+            Ok(vec![record(DnsRecordType::AAAA, "2001:db8::1")])
+        );
+    }
+
+    #[test]
+    fn whitespace_around_segments_is_ignored() {
+        assert_eq!(
+            parse(" 192.0.2.1 ,\t2001:DB8:0::1 "),
+            Ok(vec![
+                record(DnsRecordType::A, "192.0.2.1"),
+                record(DnsRecordType::AAAA, "2001:db8::1"),
+            ])
+        );
+    }
+
+    #[test]
+    fn ipv4_mapped_ipv6_is_rejected_as_ipv4() {
+        let error = parse("::ffff:192.0.2.1").unwrap_err();
+        assert!(error.contains("Could not parse IPv4"), "{error}");
+        assert!(error.contains("'::ffff:192.0.2.1'"), "{error}");
+    }
+
+    #[test]
+    fn empty_input_is_rejected() {
+        assert!(parse("").unwrap_err().contains("neither IPv4 nor IPv6"));
+        assert!(parse("192.0.2.1,").unwrap_err().contains("''"));
+    }
+
+    #[test]
+    fn invalid_segment_is_named() {
+        let error = parse("192.0.2.1,192.0.2.300").unwrap_err();
+        assert!(error.contains("'192.0.2.300'"), "{error}");
+    }
+
+    #[test]
+    fn prefix_is_truncated_and_updates_nothing() {
+        let update = "2001:db8:1200::1/56".parse::<ParsedIpUpdate>().unwrap();
+        assert!(update.records().is_empty());
+        assert_eq!(
+            update.ipv6_prefix(),
+            Some("2001:db8:1200::/56".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn second_prefix_is_rejected() {
+        let error = parse("2001:db8:1200::/56,2001:db8:1300::/56").unwrap_err();
+        assert!(error.contains("'2001:db8:1300::/56'"), "{error}");
+    }
+
+    #[test]
+    fn suffix_is_combined_with_the_prefix() {
+        let update = "192.0.2.1,2001:db8::1,2001:db8:1200::/56"
+            .parse::<ParsedIpUpdate>()
+            .unwrap()
+            .with_ipv6_suffix("::12:34ff:fe56:7890".parse().unwrap());
+        assert_eq!(
+            update.records(),
+            [
+                record(DnsRecordType::A, "192.0.2.1"),
+                record(DnsRecordType::AAAA, "2001:db8:1200:0:12:34ff:fe56:7890"),
+            ]
+        );
+    }
+
+    #[test]
+    fn suffix_replaces_the_interface_identifier() {
+        let update = "2001:db8::1"
+            .parse::<ParsedIpUpdate>()
+            .unwrap()
+            .with_ipv6_suffix("::12:34ff:fe56:7890".parse().unwrap());
+        assert_eq!(
+            update.records(),
+            [record(DnsRecordType::AAAA, "2001:db8::12:34ff:fe56:7890")]
+        );
+    }
+}
@@ -0,0 +1,56 @@
+//! A DynDNS v2 server forwarding updates to DNS providers.
+//!
+//! The [`DynDnsServer`] builder assembles the axum [`Router`](axum::Router) serving the update
+//! endpoint, so it can also be embedded into other applications. [`dyndns_client`] implements
+//! the client side of the protocol.
+
+mod access_log;
+#[cfg(feature = "acme")]
+mod acme;
+mod admin;
+mod app;
+mod auth;
+mod cli;
+mod config;
+mod dashboard;
+mod debug_errors;
+mod dyndns;
+pub mod dyndns_client;
+mod healthcheck;
+mod history;
+mod ip_update;
+mod limits;
+mod lockout;
+mod logging;
+mod metrics;
+mod negative_cache;
+mod ownership;
+mod propagation;
+pub mod provider;
+mod retry;
+mod server;
+mod settings;
+mod status;
+#[cfg(test)]
+mod test_support;
+mod types;
+mod update;
+mod version;
+mod watch;
+
+#[cfg(feature = "acme")]
+pub use acme::{AcmeConfig, AcmeManager};
+pub use app::run_cli;
+pub use auth::{ApiToken, AuthMode, ClientPassword};
+pub use config::HostnameConfig;
+pub use history::{HistoryConfig, HistoryStore};
+pub use limits::RequestLimits;
+pub use lockout::LockoutConfig;
+pub use negative_cache::NegativeCache;
+pub use ownership::Ownership;
+pub use propagation::PropagationCheck;
+pub use provider::{DnsEntry, DnsProvider, DnsRecordType, Origin, RecordId, RecordRef};
+pub use retry::RetryQueue;
+pub use server::{DynDnsServer, DynDnsServerBuilder, StartupValidation, ValidationRetry};
+pub use status::ValidationState;
+pub use version::VersionInfo;
@@ -0,0 +1,318 @@
+//! Limits protecting the server against oversized, malformed and slow requests.
+//!
+//! The time to receive the request head is enforced by hyper in [`serve`], which also bounds the
+//! buffer the head is read into. Everything else is checked by the [`enforce`] middleware before
+//! any handler runs. Violations are answered with a bare status, never echoing the offending
+//! input, and counted in the [`StatusTracker`].
+
+use crate::status::StatusTracker;
+use axum::Router;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use derive_more::Display;
+use hyper::body::Incoming;
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::{GracefulShutdown, Watcher};
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::select;
+#[cfg(feature = "acme")]
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{Instrument, Span, debug, warn};
+
+/// Bodies are only accepted as form data, the way dyndns clients POST their updates.
+const ACCEPTED_CONTENT_TYPES: &[&str] = &["application/x-www-form-urlencoded"];
+
+#[derive(Debug, Clone, Copy)]
+pub struct RequestLimits {
+    /// The longest accepted path and query, in bytes.
+    pub max_uri_length: usize,
+    /// The largest accepted request body, in bytes.
+    pub max_body_bytes: usize,
+    /// The largest accepted request head (request line and headers), in bytes. hyper requires at
+    /// least [`MIN_HEAD_BYTES`](Self::MIN_HEAD_BYTES).
+    pub max_head_bytes: usize,
+    /// Connections that do not send a complete request head within this time are closed. Must not
+    /// be zero.
+    pub header_read_timeout: Duration,
+}
+
+impl RequestLimits {
+    pub const MIN_HEAD_BYTES: usize = 8192;
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_uri_length: 2048,
+            max_body_bytes: 8192,
+            max_head_bytes: 16384,
+            header_read_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Why a request or connection was rejected. Used as the `reason` metric label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Display)]
+pub enum Rejection {
+    #[display("uri_too_long")]
+    UriTooLong,
+    #[display("body_too_large")]
+    BodyTooLarge,
+    #[display("unsupported_content_type")]
+    UnsupportedContentType,
+    #[display("head_too_large")]
+    HeadTooLarge,
+    #[display("header_read_timeout")]
+    HeaderReadTimeout,
+    #[display("malformed")]
+    Malformed,
+}
+
+impl Rejection {
+    fn of_request(req: &Request, limits: &RequestLimits) -> Option<Self> {
+        let uri_length = req.uri().path_and_query().map_or(0, |it| it.as_str().len());
+        if uri_length > limits.max_uri_length {
+            return Some(Self::UriTooLong);
+        }
+
+        let headers = req.headers();
+        // hyper's buffer limit is not exact, as it reads in chunks
+        let head_length = uri_length
+            + headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum::<usize>();
+        if head_length > limits.max_head_bytes {
+            return Some(Self::HeadTooLarge);
+        }
+
+        // hyper already rejects requests with an invalid Content-Length
+        let content_length = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|it| it.to_str().ok())
+            .and_then(|it| it.parse::<usize>().ok())
+            .unwrap_or(0);
+        if content_length > limits.max_body_bytes {
+            return Some(Self::BodyTooLarge);
+        }
+
+        let has_body = content_length > 0 || headers.contains_key(header::TRANSFER_ENCODING);
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|it| it.to_str().ok())
+            .and_then(|it| it.split(';').next())
+            .map(|it| it.trim().to_ascii_lowercase());
+        let accepted = content_type.is_some_and(|it| ACCEPTED_CONTENT_TYPES.contains(&it.as_str()));
+        if has_body && !accepted {
+            return Some(Self::UnsupportedContentType);
+        }
+
+        None
+    }
+
+    fn status(self) -> StatusCode {
+        match self {
+            Self::UriTooLong => StatusCode::URI_TOO_LONG,
+            Self::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::HeadTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            Self::HeaderReadTimeout => StatusCode::REQUEST_TIMEOUT,
+            Self::Malformed => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// Rejects requests exceeding the URI, head or body limits, or sending a body of an unexpected
+/// type.
+pub async fn enforce(
+    State((limits, status)): State<(RequestLimits, Arc<StatusTracker>)>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(rejection) = Rejection::of_request(&req, &limits) else {
+        return next.run(req).await;
+    };
+    debug!(reason = %rejection, "Rejecting request");
+    status.record_rejection(rejection);
+    let code = rejection.status();
+    (code, code.canonical_reason().unwrap_or_default()).into_response()
+}
+
+/// Serves `router` on `listener` until `shutdown` completes, then waits for open connections.
+///
+/// This mirrors `axum::serve`, which does not allow limiting the size of the request head or the
+/// time to receive it. The router gets the client address as [`ConnectInfo`]`<SocketAddr>`.
+pub async fn serve(
+    listener: TcpListener,
+    router: Router,
+    limits: RequestLimits,
+    status: Arc<StatusTracker>,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    serve_transport(listener, Transport::Plain, router, limits, status, shutdown).await
+}
+
+/// Like [`serve`], but speaks HTTPS using `acceptor`. The TLS handshake has to finish within the
+/// header read timeout, and requests carry a [`TlsConnection`] extension.
+#[cfg(feature = "acme")]
+pub async fn serve_tls(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    router: Router,
+    limits: RequestLimits,
+    status: Arc<StatusTracker>,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let transport = Transport::Tls(acceptor);
+    serve_transport(listener, transport, router, limits, status, shutdown).await
+}
+
+/// Marks requests received over a TLS connection terminated by this server.
+#[derive(Debug, Clone, Copy)]
+pub struct TlsConnection;
+
+/// How HTTP is spoken on accepted connections.
+#[derive(Clone)]
+enum Transport {
+    Plain,
+    #[cfg(feature = "acme")]
+    Tls(TlsAcceptor),
+}
+
+async fn serve_transport(
+    listener: TcpListener,
+    transport: Transport,
+    router: Router,
+    limits: RequestLimits,
+    status: Arc<StatusTracker>,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let graceful = GracefulShutdown::new();
+    let mut shutdown = pin!(shutdown);
+
+    loop {
+        let (stream, remote_addr) = select! {
+            accepted = listener.accept() => match accepted {
+                Ok(it) => it,
+                Err(e) => {
+                    if !is_connection_error(&e) {
+                        warn!(error = %e, "Failed to accept connection");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let router = router.clone();
+        let transport = transport.clone();
+        let watcher = graceful.watcher();
+        let status = status.clone();
+        tokio::spawn(
+            async move {
+                let served = match transport {
+                    Transport::Plain => {
+                        serve_connection(stream, remote_addr, router, limits, watcher, false).await
+                    }
+                    #[cfg(feature = "acme")]
+                    Transport::Tls(acceptor) => {
+                        let handshake = acceptor.accept(stream);
+                        match tokio::time::timeout(limits.header_read_timeout, handshake).await {
+                            Ok(Ok(stream)) => {
+                                serve_connection(stream, remote_addr, router, limits, watcher, true)
+                                    .await
+                            }
+                            Ok(Err(e)) => {
+                                debug!(error = %e, client = %remote_addr, "TLS handshake failed");
+                                return;
+                            }
+                            Err(_) => {
+                                let rejection = Rejection::HeaderReadTimeout;
+                                debug!(reason = %rejection, client = %remote_addr, "Rejected connection");
+                                status.record_rejection(rejection);
+                                return;
+                            }
+                        }
+                    }
+                };
+                let Err(e) = served else {
+                    return;
+                };
+                let Some(e) = e.downcast_ref::<hyper::Error>() else {
+                    debug!(error = %e, client = %remote_addr, "Connection failed");
+                    return;
+                };
+                let rejection = if e.is_timeout() {
+                    Rejection::HeaderReadTimeout
+                } else if e.is_parse_too_large() {
+                    Rejection::HeadTooLarge
+                } else if e.is_parse() {
+                    Rejection::Malformed
+                } else {
+                    debug!(error = %e, client = %remote_addr, "Connection failed");
+                    return;
+                };
+                debug!(reason = %rejection, client = %remote_addr, "Rejected connection");
+                status.record_rejection(rejection);
+            }
+            .instrument(Span::current()),
+        );
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
+
+/// Speaks HTTP/1 or HTTP/2, whichever the client chooses, on `stream` until the client or a
+/// graceful shutdown closes it.
+async fn serve_connection(
+    stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    remote_addr: SocketAddr,
+    router: Router,
+    limits: RequestLimits,
+    watcher: Watcher,
+    tls: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let service = service_fn(move |mut req: hyper::Request<Incoming>| {
+        req.extensions_mut().insert(ConnectInfo(remote_addr));
+        if tls {
+            req.extensions_mut().insert(TlsConnection);
+        }
+        router.clone().oneshot(req)
+    });
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(limits.header_read_timeout)
+        .max_buf_size(limits.max_head_bytes);
+    builder
+        .http2()
+        .timer(TokioTimer::new())
+        .max_header_list_size(u32::try_from(limits.max_head_bytes).unwrap_or(u32::MAX));
+    let connection = builder.serve_connection(TokioIo::new(stream), service);
+    watcher.watch(connection).await
+}
+
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
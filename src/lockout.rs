@@ -0,0 +1,219 @@
+use jiff::{SignedDuration, Timestamp};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::warn;
+
+#[derive(Debug, Clone)]
+pub struct LockoutConfig {
+    /// Number of failed attempts within `window` that trigger a lockout. `0` disables lockouts.
+    pub threshold: usize,
+    pub window: SignedDuration,
+    pub duration: SignedDuration,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0,
+            window: SignedDuration::from_secs(600),
+            duration: SignedDuration::from_secs(900),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LockoutEntry {
+    failures: Vec<Timestamp>,
+    locked_until: Option<Timestamp>,
+}
+
+impl LockoutEntry {
+    fn is_stale(&self, now: Timestamp, window: SignedDuration) -> bool {
+        let locked = self.locked_until.is_some_and(|until| until > now);
+        let recent_failures = self.failures.iter().any(|it| *it + window > now);
+        !locked && !recent_failures
+    }
+}
+
+/// Tracks failed authentication attempts per key (e.g. client IP or username) and locks keys out
+/// after too many failures within a window.
+///
+/// All methods take the current time explicitly, so callers decide what "now" is.
+#[derive(Debug)]
+pub struct LockoutTracker {
+    config: LockoutConfig,
+    entries: Mutex<HashMap<String, LockoutEntry>>,
+}
+
+impl LockoutTracker {
+    pub fn new(config: LockoutConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.config.threshold > 0
+    }
+
+    /// Returns the end of the lockout if `key` is currently locked out.
+    pub fn locked_until(&self, key: &str, now: Timestamp) -> Option<Timestamp> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let entries = self.entries.lock().expect("mutex poisoned");
+        entries
+            .get(key)
+            .and_then(|it| it.locked_until)
+            .filter(|until| *until > now)
+    }
+
+    /// Records a failed attempt and returns the end of the lockout if this failure triggered one.
+    pub fn record_failure(&self, key: &str, now: Timestamp) -> Option<Timestamp> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let mut entries = self.entries.lock().expect("mutex poisoned");
+        let window = self.config.window;
+        entries.retain(|_, it| !it.is_stale(now, window));
+
+        let entry = entries.entry(key.to_string()).or_default();
+        entry.failures.retain(|it| *it + window > now);
+        entry.failures.push(now);
+
+        if entry.failures.len() < self.config.threshold {
+            return None;
+        }
+
+        let until = now + self.config.duration;
+        entry.failures.clear();
+        entry.locked_until = Some(until);
+
+        let active = entries
+            .values()
+            .filter(|it| it.locked_until.is_some_and(|until| until > now))
+            .count();
+        warn!(
+            key = %key,
+            until = %until,
+            active_lockouts = active,
+            "Too many failed authentication attempts, locking out"
+        );
+
+        Some(until)
+    }
+
+    /// The keys locked out at `now` and the end of their lockout, sorted by key.
+    pub fn active(&self, now: Timestamp) -> Vec<(String, Timestamp)> {
+        let entries = self.entries.lock().expect("mutex poisoned");
+        let mut active = entries
+            .iter()
+            .filter_map(|(key, it)| Some((key.clone(), it.locked_until.filter(|it| *it > now)?)))
+            .collect::<Vec<_>>();
+        active.sort();
+        active
+    }
+
+    /// Forgets all failures recorded for `key`.
+    pub fn record_success(&self, key: &str) {
+        self.entries.lock().expect("mutex poisoned").remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(threshold: usize) -> LockoutTracker {
+        LockoutTracker::new(LockoutConfig {
+            threshold,
+            window: SignedDuration::from_secs(60),
+            duration: SignedDuration::from_secs(300),
+        })
+    }
+
+    fn at(secs: i64) -> Timestamp {
+        Timestamp::from_second(1_700_000_000 + secs).unwrap()
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let tracker = LockoutTracker::new(LockoutConfig::default());
+        for i in 0..100 {
+            assert_eq!(tracker.record_failure("ip:1.2.3.4", at(i)), None);
+        }
+        assert_eq!(tracker.locked_until("ip:1.2.3.4", at(100)), None);
+    }
+
+    #[test]
+    fn locks_out_after_threshold() {
+        let tracker = tracker(3);
+        assert_eq!(tracker.record_failure("ip:1.2.3.4", at(0)), None);
+        assert_eq!(tracker.record_failure("ip:1.2.3.4", at(1)), None);
+        assert_eq!(tracker.record_failure("ip:1.2.3.4", at(2)), Some(at(302)));
+
+        assert_eq!(tracker.locked_until("ip:1.2.3.4", at(3)), Some(at(302)));
+        assert_eq!(tracker.locked_until("ip:5.6.7.8", at(3)), None);
+    }
+
+    #[test]
+    fn lockout_expires() {
+        let tracker = tracker(1);
+        tracker.record_failure("ip:1.2.3.4", at(0));
+
+        assert_eq!(tracker.locked_until("ip:1.2.3.4", at(299)), Some(at(300)));
+        assert_eq!(tracker.locked_until("ip:1.2.3.4", at(300)), None);
+    }
+
+    #[test]
+    fn failures_outside_the_window_do_not_count() {
+        let tracker = tracker(3);
+        tracker.record_failure("ip:1.2.3.4", at(0));
+        tracker.record_failure("ip:1.2.3.4", at(30));
+
+        assert_eq!(tracker.record_failure("ip:1.2.3.4", at(61)), None);
+        assert_eq!(tracker.locked_until("ip:1.2.3.4", at(61)), None);
+        assert_eq!(tracker.record_failure("ip:1.2.3.4", at(62)), Some(at(362)));
+    }
+
+    #[test]
+    fn success_resets_the_counter() {
+        let tracker = tracker(2);
+        tracker.record_failure("ip:1.2.3.4", at(0));
+        tracker.record_success("ip:1.2.3.4");
+
+        assert_eq!(tracker.record_failure("ip:1.2.3.4", at(1)), None);
+        assert_eq!(tracker.locked_until("ip:1.2.3.4", at(1)), None);
+    }
+
+    #[test]
+    fn only_current_lockouts_are_active() {
+        let tracker = tracker(1);
+        tracker.record_failure("user:router", at(0));
+        tracker.record_failure("ip:1.2.3.4", at(100));
+
+        assert_eq!(
+            tracker.active(at(200)),
+            [
+                ("ip:1.2.3.4".to_string(), at(400)),
+                ("user:router".to_string(), at(300))
+            ]
+        );
+        assert_eq!(
+            tracker.active(at(300)),
+            [("ip:1.2.3.4".to_string(), at(400))]
+        );
+    }
+
+    #[test]
+    fn stale_entries_are_evicted() {
+        let tracker = tracker(3);
+        tracker.record_failure("ip:1.2.3.4", at(0));
+        tracker.record_failure("ip:5.6.7.8", at(120));
+
+        let entries = tracker.entries.lock().unwrap();
+        assert!(!entries.contains_key("ip:1.2.3.4"));
+        assert!(entries.contains_key("ip:5.6.7.8"));
+    }
+}
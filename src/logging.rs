@@ -0,0 +1,213 @@
+#[cfg(feature = "otel")]
+mod otel;
+
+use crate::settings;
+use derive_more::FromStr;
+use tracing::warn;
+use tracing_subscriber::fmt::format::{DefaultFields, Format};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::{Layer, Registry, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// The output format of log lines, configured via `LOG_FORMAT`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromStr)]
+pub enum LogFormat {
+    /// The default human-readable format, one line per event.
+    #[default]
+    Full,
+    /// Multi-line human-readable output with the source location of every event.
+    Pretty,
+    /// Like [`LogFormat::Full`], but terser.
+    Compact,
+    /// One JSON object per line with the event and span fields flattened into it, for log
+    /// aggregators.
+    Json,
+}
+
+/// Where log lines are written to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogOutput {
+    #[default]
+    Stdout,
+    /// For commands printing their result, so stdout only carries that.
+    Stderr,
+}
+
+impl LogFormat {
+    fn layer(self, writer: BoxMakeWriter) -> Box<dyn Layer<Registry> + Send + Sync> {
+        match self {
+            Self::Full => fmt_layer(writer).boxed(),
+            Self::Pretty => fmt_layer(writer).pretty().boxed(),
+            Self::Compact => fmt_layer(writer).compact().boxed(),
+            Self::Json => fmt_layer(writer)
+                .json()
+                .flatten_event(true)
+                .with_current_span(true)
+                .with_span_list(true)
+                .boxed(),
+        }
+    }
+}
+
+impl LogOutput {
+    fn writer(self) -> BoxMakeWriter {
+        match self {
+            Self::Stdout => BoxMakeWriter::new(std::io::stdout),
+            Self::Stderr => BoxMakeWriter::new(std::io::stderr),
+        }
+    }
+}
+
+fn fmt_layer<S>(
+    writer: BoxMakeWriter,
+) -> tracing_subscriber::fmt::Layer<S, DefaultFields, Format, BoxMakeWriter> {
+    tracing_subscriber::fmt::layer().with_writer(writer)
+}
+
+/// Keeps the trace exporter alive. Call [`LoggingGuard::shutdown`] before exiting to flush it.
+#[must_use]
+pub struct LoggingGuard {
+    #[cfg(feature = "otel")]
+    tracer_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl LoggingGuard {
+    /// Flushes all pending spans.
+    pub fn shutdown(self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.tracer_provider
+            && let Err(e) = provider.shutdown()
+        {
+            eprintln!("Failed to flush traces: {e}");
+        }
+    }
+}
+
+/// Installs the global tracing subscriber writing to `output`. The filter is read from `RUST_LOG`
+/// and defaults to `info`, the format is read from `LOG_FORMAT` and defaults to
+/// [`LogFormat::Full`].
+///
+/// With the `otel` feature, spans are also exported via OTLP if `OTEL_EXPORTER_OTLP_ENDPOINT` is
+/// set.
+pub fn init(output: LogOutput) -> LoggingGuard {
+    let raw_format = settings::var("LOG_FORMAT").unwrap_or_default();
+    let format = match raw_format.as_str() {
+        "" => Ok(LogFormat::default()),
+        it => it.parse::<LogFormat>(),
+    };
+
+    let registry = tracing_subscriber::registry()
+        .with(format.unwrap_or_default().layer(output.writer()))
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
+        );
+
+    #[cfg(feature = "otel")]
+    let (registry, tracer_provider) = {
+        let tracer_provider = otel::tracer_provider();
+        let layer = tracer_provider
+            .as_ref()
+            .ok()
+            .and_then(Option::as_ref)
+            .map(otel::layer);
+        (registry.with(layer), tracer_provider)
+    };
+
+    registry.init();
+
+    if format.is_err() {
+        warn!(
+            value = %raw_format,
+            "Unknown LOG_FORMAT, expected one of full, pretty, compact or json. Using full"
+        );
+    }
+
+    LoggingGuard {
+        #[cfg(feature = "otel")]
+        tracer_provider: match tracer_provider {
+            Ok(provider) => provider,
+            Err(e) => {
+                warn!(error = %e, "Could not set up trace export");
+                None
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing::{error, info, info_span};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Logs an update inside a request span, like the server does, and returns the output.
+    fn log_with(format: LogFormat) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let layer = format.layer(BoxMakeWriter::new(move || writer.clone()));
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!("request", request_id = "abc-123");
+            let _guard = span.enter();
+            info!(query = ?("nas.foobar.de", "192.0.2.1"), "handling update");
+            error!(
+                error = "Update failed\n ├ Provider: memory",
+                "Application error"
+            );
+        });
+
+        String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap()
+    }
+
+    #[test]
+    fn parses_case_insensitively() {
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("Pretty".parse::<LogFormat>().unwrap(), LogFormat::Pretty);
+        assert_eq!("full".parse::<LogFormat>().unwrap(), LogFormat::Full);
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn json_prints_one_object_per_event() {
+        let output = log_with(LogFormat::Json);
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{output}");
+
+        let update: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(update["level"], "INFO");
+        assert_eq!(update["message"], "handling update");
+        assert_eq!(update["query"], r#"("nas.foobar.de", "192.0.2.1")"#);
+        assert_eq!(update["span"]["request_id"], "abc-123");
+        assert_eq!(update["spans"][0]["name"], "request");
+
+        let error: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(error["error"], "Update failed\n ├ Provider: memory");
+    }
+
+    #[test]
+    fn pretty_spans_several_lines() {
+        let full = log_with(LogFormat::Full);
+        let pretty = log_with(LogFormat::Pretty);
+
+        assert_eq!(full.lines().count(), 2, "{full}");
+        assert!(full.contains("handling update"));
+        assert!(pretty.lines().count() > 4, "{pretty}");
+        assert!(pretty.contains("handling update"));
+        assert!(pretty.contains("src/logging.rs"), "{pretty}");
+    }
+}
@@ -0,0 +1,65 @@
+//! OpenTelemetry trace export via OTLP, enabled by setting `OTEL_EXPORTER_OTLP_ENDPOINT`.
+//!
+//! The exporter is configured with the standard `OTEL_*` environment variables. The protocol is
+//! chosen with `OTEL_EXPORTER_OTLP_PROTOCOL` (`grpc` or `http/protobuf`, the default).
+
+use crate::version;
+use opentelemetry::KeyValue;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, report};
+use tracing::Subscriber;
+use tracing_subscriber::Layer;
+use tracing_subscriber::registry::LookupSpan;
+
+const DEFAULT_SERVICE_NAME: &str = "speedport-custom-dyndns";
+
+/// Creates the tracer provider, if an OTLP endpoint is configured.
+pub fn tracer_provider() -> Result<Option<SdkTracerProvider>, Report> {
+    // The exporters read the endpoint themselves
+    if std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+        .unwrap_or_default()
+        .is_empty()
+    {
+        return Ok(None);
+    }
+
+    let protocol = std::env::var("OTEL_EXPORTER_OTLP_PROTOCOL").unwrap_or_default();
+    let exporter = match protocol.as_str() {
+        "grpc" => SpanExporter::builder().with_tonic().build(),
+        "" | "http/protobuf" => SpanExporter::builder().with_http().build(),
+        _ => {
+            return Err(report!("Unsupported OTEL_EXPORTER_OTLP_PROTOCOL")
+                .attach(format!("protocol: {protocol}"))
+                .attach("expected: grpc or http/protobuf")
+                .into_dynamic());
+        }
+    }
+    .context("Failed to create OTLP span exporter")?;
+
+    let mut resource = Resource::builder().with_attributes([
+        KeyValue::new("service.version", version::VERSION),
+        KeyValue::new("vcs.ref.head.revision", version::GIT_COMMIT),
+    ]);
+    if std::env::var("OTEL_SERVICE_NAME").is_err() {
+        resource = resource.with_service_name(DEFAULT_SERVICE_NAME);
+    }
+
+    Ok(Some(
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource.build())
+            .build(),
+    ))
+}
+
+/// A layer forwarding spans to `provider`.
+pub fn layer<S>(provider: &SdkTracerProvider) -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(DEFAULT_SERVICE_NAME))
+}
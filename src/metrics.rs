@@ -0,0 +1,240 @@
+//! Prometheus metrics in the text exposition format.
+
+use crate::propagation::Propagation;
+use crate::provider::api_usage::ApiUsageSnapshot;
+use crate::status::{StatusTracker, ValidationState};
+use crate::types::DnsConfig;
+use axum::http::header;
+use axum::response::IntoResponse;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+/// Renders the per-hostname and per-provider gauges and the counters. With `hash_hostnames`, hostnames are
+/// replaced by a prefix of their SHA-256 hash.
+pub fn render(
+    status: &StatusTracker,
+    dns: &DnsConfig,
+    usage: &[ApiUsageSnapshot],
+    hash_hostnames: bool,
+) -> impl IntoResponse + use<> {
+    let records = status.snapshot();
+    let label = |hostname: &str| {
+        if hash_hostnames {
+            format!("{:x}", Sha256::digest(hostname.as_bytes()))[..16].to_string()
+        } else {
+            escape_label(hostname)
+        }
+    };
+
+    let mut out = String::new();
+    let mut gauge = |name: &str, help: &str, values: Vec<(String, String)>| {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        for (labels, value) in values {
+            if labels.is_empty() {
+                let _ = writeln!(out, "{name} {value}");
+            } else {
+                let _ = writeln!(out, "{name}{{{labels}}} {value}");
+            }
+        }
+    };
+
+    gauge(
+        "dyndns_provider_validation_succeeded",
+        "Whether the providers have been validated successfully since startup",
+        vec![(
+            String::new(),
+            u8::from(status.validation() == ValidationState::Succeeded).to_string(),
+        )],
+    );
+    gauge(
+        "dyndns_last_attempt_timestamp_seconds",
+        "Unix time of the last update attempt",
+        records
+            .iter()
+            .filter_map(|((hostname, typ), it)| {
+                let labels = format!(r#"hostname="{}",type="{typ}""#, label(hostname));
+                Some((labels, it.last_attempt?.as_second().to_string()))
+            })
+            .collect(),
+    );
+    gauge(
+        "dyndns_last_success_timestamp_seconds",
+        "Unix time of the last successful update",
+        records
+            .iter()
+            .filter_map(|((hostname, typ), it)| {
+                let labels = format!(r#"hostname="{}",type="{typ}""#, label(hostname));
+                Some((labels, it.last_success?.as_second().to_string()))
+            })
+            .collect(),
+    );
+    gauge(
+        "dyndns_consecutive_failures",
+        "Failed update attempts since the last success",
+        records
+            .iter()
+            .map(|((hostname, typ), it)| {
+                let labels = format!(r#"hostname="{}",type="{typ}""#, label(hostname));
+                (labels, it.consecutive_failures.to_string())
+            })
+            .collect(),
+    );
+    gauge(
+        "dyndns_record_propagated",
+        "Whether the resolver returns the address a record was last set to",
+        records
+            .iter()
+            .filter_map(|((hostname, typ), it)| {
+                let labels = format!(r#"hostname="{}",type="{typ}""#, label(hostname));
+                let visible = matches!(it.propagation?, Propagation::Visible { .. });
+                Some((labels, u8::from(visible).to_string()))
+            })
+            .collect(),
+    );
+    gauge(
+        "dyndns_record_propagated_timestamp_seconds",
+        "Unix time the resolver first returned the address a record was last set to",
+        records
+            .iter()
+            .filter_map(|((hostname, typ), it)| {
+                let labels = format!(r#"hostname="{}",type="{typ}""#, label(hostname));
+                let Some(Propagation::Visible { at }) = it.propagation else {
+                    return None;
+                };
+                Some((labels, at.as_second().to_string()))
+            })
+            .collect(),
+    );
+    gauge(
+        "dyndns_provider_api_calls",
+        "API calls of a provider in the last 5 minutes",
+        usage
+            .iter()
+            .map(|it| {
+                (
+                    format!(r#"provider="{}""#, it.provider),
+                    it.calls.to_string(),
+                )
+            })
+            .collect(),
+    );
+    gauge(
+        "dyndns_provider_api_call_limit",
+        "The documented limit of API calls of a provider per 5 minutes",
+        usage
+            .iter()
+            .map(|it| {
+                (
+                    format!(r#"provider="{}""#, it.provider),
+                    it.limit.to_string(),
+                )
+            })
+            .collect(),
+    );
+    gauge(
+        "dyndns_provider_rate_limit_header",
+        "Numeric Retry-After and rate limit headers of the last provider response that had any",
+        usage
+            .iter()
+            .flat_map(|it| {
+                it.headers.iter().filter_map(|(header, value)| {
+                    let value = value.trim().parse::<f64>().ok()?;
+                    let labels = format!(
+                        r#"provider="{}",header="{}""#,
+                        it.provider,
+                        escape_label(header)
+                    );
+                    Some((labels, value.to_string()))
+                })
+            })
+            .collect(),
+    );
+
+    gauge(
+        "dyndns_negative_cache_entries",
+        "Hostnames and record types remembered as missing",
+        vec![(String::new(), dns.negative_cache.len().to_string())],
+    );
+
+    let name = "dyndns_negative_cache_hits_total";
+    let _ = writeln!(
+        out,
+        "# HELP {name} Updates of records skipped as they were recently found to be missing"
+    );
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "{name} {}", dns.negative_cache.hits());
+
+    if let Some(certificate) = status.certificate() {
+        if let Some(not_after) = certificate.not_after {
+            let name = "dyndns_certificate_not_after";
+            let _ = writeln!(
+                out,
+                "# HELP {name} Unix time the certificate obtained via ACME expires"
+            );
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name} {}", not_after.as_second());
+        }
+        let name = "dyndns_certificate_renewal_failures_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Failed attempts to obtain or renew the certificate via ACME"
+        );
+        let _ = writeln!(out, "# TYPE {name} counter");
+        let _ = writeln!(out, "{name} {}", certificate.renewal_failures);
+    }
+
+    let name = "dyndns_rejected_requests_total";
+    let _ = writeln!(
+        out,
+        "# HELP {name} Requests and connections rejected for exceeding the request limits"
+    );
+    let _ = writeln!(out, "# TYPE {name} counter");
+    for (reason, count) in status.rejections() {
+        let _ = writeln!(out, r#"{name}{{reason="{reason}"}} {count}"#);
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out)
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::provider::memory::MemoryProvider;
+    use crate::test_support::*;
+    use axum::body::Body;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn certificate_is_exported_once_tracked() {
+        let provider = Arc::new(MemoryProvider::new(nas_records()));
+        let server = builder(&provider).build().unwrap();
+        let router = server.router();
+        let scrape = || async {
+            send(&router, request("/metrics").body(Body::empty()).unwrap())
+                .await
+                .1
+        };
+
+        let before = scrape().await;
+        assert!(!before.contains("dyndns_certificate"), "{before}");
+
+        let status = &server.state().status;
+        status.record_certificate_failure();
+        let failed = scrape().await;
+        assert!(!failed.contains("dyndns_certificate_not_after"), "{failed}");
+        assert!(failed.contains("\ndyndns_certificate_renewal_failures_total 1\n"));
+
+        status.record_certificate("2126-09-21T14:27:50Z".parse().unwrap());
+        status.record_certificate_failure();
+        let renewed = scrape().await;
+        assert!(renewed.contains("\ndyndns_certificate_not_after 4945674470\n"));
+        assert!(renewed.contains("\ndyndns_certificate_renewal_failures_total 2\n"));
+    }
+}
@@ -0,0 +1,135 @@
+//! Remembers hostnames without a record of some type for a short time, so repeated updates for
+//! them are answered without listing the zone again. Clients asking for a name that does not
+//! exist would otherwise cause an API call on every attempt.
+
+use crate::provider::{DnsRecordType, Origin};
+use jiff::{SignedDuration, Timestamp};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const DEFAULT_TTL: SignedDuration = SignedDuration::from_secs(60);
+pub const DEFAULT_MAX_ENTRIES: usize = 1024;
+
+/// A provider, the origin it manages, a hostname and a record type it has no record of.
+type Key = (&'static str, Origin, String, DnsRecordType);
+
+pub struct NegativeCache {
+    /// How long a missing record is remembered. Zero (or less) disables the cache.
+    ttl: SignedDuration,
+    /// The most entries kept, so iterating random hostnames cannot fill the memory.
+    max_entries: usize,
+    entries: Mutex<HashMap<Key, Timestamp>>,
+    hits: AtomicU64,
+}
+
+impl Default for NegativeCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL, DEFAULT_MAX_ENTRIES)
+    }
+}
+
+impl NegativeCache {
+    pub fn new(ttl: SignedDuration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::default(),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether `provider` was recently found to have no `record_type` record for `hostname`.
+    pub fn is_missing(
+        &self,
+        provider: &'static str,
+        origin: &Origin,
+        hostname: &str,
+        record_type: &DnsRecordType,
+    ) -> bool {
+        let mut entries = self.entries.lock().expect("mutex poisoned");
+        let key = (
+            provider,
+            origin.clone(),
+            hostname.to_string(),
+            record_type.clone(),
+        );
+        match entries.get(&key) {
+            Some(expires) if *expires > Timestamp::now() => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Some(_) => {
+                entries.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Remembers that `provider` has no `record_type` record for `hostname`. When full, expired
+    /// entries are dropped first and then the one expiring soonest.
+    pub fn insert(
+        &self,
+        provider: &'static str,
+        origin: &Origin,
+        hostname: &str,
+        record_type: &DnsRecordType,
+    ) {
+        if !self.ttl.is_positive() || self.max_entries == 0 {
+            return;
+        }
+        let now = Timestamp::now();
+        let mut entries = self.entries.lock().expect("mutex poisoned");
+        if entries.len() >= self.max_entries {
+            entries.retain(|_, expires| *expires > now);
+        }
+        if entries.len() >= self.max_entries
+            && let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, expires)| **expires)
+                .map(|(key, _)| key.clone())
+        {
+            entries.remove(&oldest);
+        }
+        let key = (
+            provider,
+            origin.clone(),
+            hostname.to_string(),
+            record_type.clone(),
+        );
+        entries.insert(key, now + self.ttl);
+    }
+
+    /// Forgets that `provider` has no `record_type` record for `hostname`, e.g. as it was seen
+    /// in a listing.
+    pub fn remove(
+        &self,
+        provider: &'static str,
+        origin: &Origin,
+        hostname: &str,
+        record_type: &DnsRecordType,
+    ) {
+        let key = (
+            provider,
+            origin.clone(),
+            hostname.to_string(),
+            record_type.clone(),
+        );
+        self.entries.lock().expect("mutex poisoned").remove(&key);
+    }
+
+    /// How often an update was answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of entries, including expired ones not dropped yet.
+    pub fn len(&self) -> usize {
+        self.entries.lock().expect("mutex poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
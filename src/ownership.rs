@@ -0,0 +1,380 @@
+//! Ownership markers, so the server only writes address records it created or was granted, with
+//! any provider.
+//!
+//! With `OWNERSHIP_ID`, the A and AAAA records of a hostname are only written if the TXT record
+//! `_dyndns-owner.<hostname>` contains the owner ID. Other servers sharing the zone use their own
+//! ID, and records without a marker are left alone. Existing hostnames are stamped with
+//! `POST /admin/ownership`.
+
+use crate::provider::{DnsProvider, Origin};
+use derive_more::Display;
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail, report};
+use std::collections::HashSet;
+use tracing::info;
+
+/// The label prepended to a hostname to get the name of its marker.
+pub const MARKER_PREFIX: &str = "_dyndns-owner";
+
+/// The context of reports caused by writing the records of a hostname without its marker.
+#[derive(Debug, Display)]
+#[display("hostname has no ownership marker of this server")]
+pub struct NotOwned;
+
+impl NotOwned {
+    /// Whether `report` or one of its causes is a [`NotOwned`].
+    pub fn is_cause_of(report: &Report) -> bool {
+        report
+            .iter_reports()
+            .any(|it| it.downcast_current_context::<Self>().is_some())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ownership {
+    owner_id: String,
+}
+
+impl Ownership {
+    /// Fails if `owner_id` is empty or could not be stored as a TXT record verbatim.
+    pub fn new(owner_id: impl Into<String>) -> Result<Self, Report> {
+        let owner_id = owner_id.into();
+        if owner_id.is_empty() || owner_id.len() > 255 {
+            bail!("The owner ID must have between 1 and 255 characters");
+        }
+        if !owner_id
+            .chars()
+            .all(|it| it.is_ascii_graphic() && it != '"')
+        {
+            bail!("The owner ID may only contain printable ASCII characters except quotes");
+        }
+        Ok(Self { owner_id })
+    }
+
+    pub fn owner_id(&self) -> &str {
+        &self.owner_id
+    }
+
+    /// The name of the marker of `hostname`, e.g. `_dyndns-owner.nas.example.com`.
+    pub fn marker_name(hostname: &str) -> String {
+        format!("{MARKER_PREFIX}.{hostname}")
+    }
+
+    /// Whether `provider` has the marker of this owner for `hostname`.
+    pub async fn is_owned(
+        &self,
+        provider: &(dyn DnsProvider + Send + Sync),
+        origin: &Origin,
+        hostname: &str,
+    ) -> Result<bool, Report> {
+        let markers = provider
+            .list_txt_records(origin, &Self::marker_name(hostname))
+            .await
+            .context("Failed to read the ownership marker")
+            .attach(format!("For domain '{hostname}'"))?;
+        Ok(markers.iter().any(|it| it.content == self.owner_id))
+    }
+
+    /// The hostnames `provider` has the marker of this owner for, listing the markers once to
+    /// check many hostnames.
+    pub async fn owned_hostnames(
+        &self,
+        provider: &(dyn DnsProvider + Send + Sync),
+        origin: &Origin,
+    ) -> Result<HashSet<String>, Report> {
+        let markers = provider
+            .list_all_txt_records(origin)
+            .await
+            .context("Failed to read the ownership markers")?;
+        Ok(markers
+            .into_iter()
+            .filter(|it| it.content == self.owner_id)
+            .filter_map(|it| {
+                it.name
+                    .strip_prefix(MARKER_PREFIX)?
+                    .strip_prefix('.')
+                    .map(str::to_string)
+            })
+            .collect())
+    }
+
+    /// Fails with a `NotOwned` error unless `provider` has the marker of this owner for `hostname`.
+    pub async fn check(
+        &self,
+        provider: &(dyn DnsProvider + Send + Sync),
+        origin: &Origin,
+        hostname: &str,
+    ) -> Result<(), Report> {
+        if self.is_owned(provider, origin, hostname).await? {
+            return Ok(());
+        }
+        Err(report!(NotOwned)
+            .attach(format!("For domain '{hostname}'"))
+            .attach(format!("Provider: {}", provider.name()))
+            .attach(format!(
+                "hint: create a TXT record '{}' containing '{}', or stamp it with \
+                 POST /admin/ownership",
+                Self::marker_name(hostname),
+                self.owner_id
+            ))
+            .into_dynamic())
+    }
+
+    /// Creates the marker of `hostname` unless it exists. Returns whether it was created.
+    pub async fn stamp(
+        &self,
+        provider: &(dyn DnsProvider + Send + Sync),
+        origin: &Origin,
+        hostname: &str,
+    ) -> Result<bool, Report> {
+        if self.is_owned(provider, origin, hostname).await? {
+            return Ok(false);
+        }
+        provider
+            .create_txt_record(origin, &Self::marker_name(hostname), &self.owner_id)
+            .await
+            .context("Failed to create the ownership marker")
+            .attach(format!("For domain '{hostname}'"))?;
+        info!(
+            domain = %hostname,
+            provider = provider.name(),
+            owner_id = %self.owner_id,
+            "Created ownership marker"
+        );
+        Ok(true)
+    }
+
+    /// Deletes the markers of this owner for `hostname`, leaving those of other owners alone.
+    /// Returns the number of deleted markers.
+    pub async fn remove(
+        &self,
+        provider: &(dyn DnsProvider + Send + Sync),
+        origin: &Origin,
+        hostname: &str,
+    ) -> Result<usize, Report> {
+        let markers = provider
+            .list_txt_records(origin, &Self::marker_name(hostname))
+            .await
+            .context("Failed to read the ownership marker")
+            .attach(format!("For domain '{hostname}'"))?;
+        let mut removed = 0;
+        for marker in markers.iter().filter(|it| it.content == self.owner_id) {
+            provider
+                .delete_txt_record(origin, &marker.id)
+                .await
+                .context("Failed to delete the ownership marker")
+                .attach(format!("For domain '{hostname}'"))
+                .attach(format!("record_id: '{}'", marker.id))?;
+            removed += 1;
+        }
+        if removed > 0 {
+            info!(
+                domain = %hostname,
+                provider = provider.name(),
+                removed,
+                "Deleted ownership marker"
+            );
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::memory::MemoryProvider;
+
+    fn origin() -> Origin {
+        Origin::parse("foobar.de").unwrap()
+    }
+
+    fn ownership() -> Ownership {
+        Ownership::new("server-a").unwrap()
+    }
+
+    async fn marker(provider: &MemoryProvider, hostname: &str, owner_id: &str) {
+        provider
+            .create_txt_record(&origin(), &Ownership::marker_name(hostname), owner_id)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn owner_id_must_be_storable_verbatim() {
+        assert!(Ownership::new("server-a").is_ok());
+        assert!(Ownership::new("a".repeat(255)).is_ok());
+        assert!(Ownership::new("").is_err());
+        assert!(Ownership::new("a".repeat(256)).is_err());
+        assert!(Ownership::new("server a").is_err());
+        assert!(Ownership::new("server\"a").is_err());
+        assert!(Ownership::new("server-ä").is_err());
+    }
+
+    #[test]
+    fn marker_name_prepends_the_label() {
+        assert_eq!(
+            Ownership::marker_name("nas.foobar.de"),
+            "_dyndns-owner.nas.foobar.de"
+        );
+    }
+
+    #[tokio::test]
+    async fn check_refuses_hostnames_without_a_marker() {
+        let provider = MemoryProvider::default();
+
+        let error = ownership()
+            .check(&provider, &origin(), "nas.foobar.de")
+            .await
+            .unwrap_err();
+
+        assert!(NotOwned::is_cause_of(&error), "{error}");
+    }
+
+    #[tokio::test]
+    async fn check_refuses_markers_of_other_owners() {
+        let provider = MemoryProvider::default();
+        marker(&provider, "nas.foobar.de", "server-b").await;
+
+        let error = ownership()
+            .check(&provider, &origin(), "nas.foobar.de")
+            .await
+            .unwrap_err();
+
+        assert!(NotOwned::is_cause_of(&error), "{error}");
+    }
+
+    #[tokio::test]
+    async fn check_accepts_the_marker_of_this_owner() {
+        let provider = MemoryProvider::default();
+        marker(&provider, "nas.foobar.de", "server-b").await;
+        marker(&provider, "nas.foobar.de", "server-a").await;
+
+        ownership()
+            .check(&provider, &origin(), "nas.foobar.de")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_fails_without_not_owned_if_markers_can_not_be_read() {
+        let provider = MemoryProvider::default();
+        provider.fail(true);
+
+        let error = ownership()
+            .check(&provider, &origin(), "nas.foobar.de")
+            .await
+            .unwrap_err();
+
+        assert!(!NotOwned::is_cause_of(&error), "{error}");
+    }
+
+    #[tokio::test]
+    async fn stamp_creates_the_marker_once() {
+        let provider = MemoryProvider::default();
+
+        assert!(
+            ownership()
+                .stamp(&provider, &origin(), "nas.foobar.de")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !ownership()
+                .stamp(&provider, &origin(), "nas.foobar.de")
+                .await
+                .unwrap()
+        );
+
+        let markers = provider.txt_records();
+        assert_eq!(markers.len(), 1);
+        assert_eq!(markers[0].name, "_dyndns-owner.nas.foobar.de");
+        assert_eq!(markers[0].content, "server-a");
+    }
+
+    #[tokio::test]
+    async fn remove_keeps_markers_of_other_owners() {
+        let provider = MemoryProvider::default();
+        marker(&provider, "nas.foobar.de", "server-a").await;
+        marker(&provider, "nas.foobar.de", "server-b").await;
+        marker(&provider, "tv.foobar.de", "server-a").await;
+
+        let removed = ownership()
+            .remove(&provider, &origin(), "nas.foobar.de")
+            .await
+            .unwrap();
+
+        assert_eq!(removed, 1);
+        let markers = provider
+            .txt_records()
+            .into_iter()
+            .map(|it| (it.name, it.content))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            markers,
+            [
+                (
+                    "_dyndns-owner.nas.foobar.de".to_string(),
+                    "server-b".to_string()
+                ),
+                (
+                    "_dyndns-owner.tv.foobar.de".to_string(),
+                    "server-a".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            ownership()
+                .remove(&provider, &origin(), "nas.foobar.de")
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn marker_lifecycle() {
+        let provider = MemoryProvider::default();
+        let ownership = ownership();
+        let origin = origin();
+        let owned = || ownership.is_owned(&provider, &origin, "nas.foobar.de");
+
+        assert!(!owned().await.unwrap());
+        ownership
+            .stamp(&provider, &origin, "nas.foobar.de")
+            .await
+            .unwrap();
+        assert!(owned().await.unwrap());
+        ownership
+            .remove(&provider, &origin, "nas.foobar.de")
+            .await
+            .unwrap();
+        assert!(!owned().await.unwrap());
+        assert!(provider.txt_records().is_empty());
+    }
+
+    #[tokio::test]
+    async fn owned_hostnames_lists_the_markers_of_this_owner() {
+        let provider = MemoryProvider::default();
+        marker(&provider, "nas.foobar.de", "server-a").await;
+        marker(&provider, "tv.foobar.de", "server-b").await;
+        marker(&provider, "foobar.de", "server-a").await;
+        provider
+            .create_txt_record(&origin(), "_dyndns-ownerx.vps.foobar.de", "server-a")
+            .await
+            .unwrap();
+        provider
+            .create_txt_record(&origin(), "_acme-challenge.foobar.de", "server-a")
+            .await
+            .unwrap();
+
+        let owned = ownership()
+            .owned_hostnames(&provider, &origin())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            owned,
+            HashSet::from(["nas.foobar.de".to_string(), "foobar.de".to_string()])
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//! Checks whether updated records are visible to the world, by resolving them via
+//! DNS-over-HTTPS after an update.
+//!
+//! The check runs in the background and is informational only. Its result is recorded in the
+//! [`StatusTracker`](crate::status::StatusTracker) and the history by the caller. Resolvers may serve the old address until the TTL of the old record
+//! expired, so the check keeps querying at least that long.
+
+use crate::provider::DnsRecordType;
+use jiff::Timestamp;
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail};
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::{Instrument, debug, info, info_span, warn};
+
+/// Cloudflare's resolver. Google's `https://dns.google/resolve` works as well.
+pub const DEFAULT_RESOLVER: &str = "https://cloudflare-dns.com/dns-query";
+pub const DEFAULT_ATTEMPTS: u32 = 5;
+
+/// Assumed if the TTL of the old record is unknown or Cloudflare's "automatic" TTL of 1, which
+/// is 300 seconds.
+const DEFAULT_TTL: u64 = 300;
+
+/// Added to the TTL, for the provider to publish the record.
+const PUBLISH_DELAY: Duration = Duration::from_secs(60);
+
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Whether an updated record was seen by the resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Propagation {
+    Pending,
+    Visible {
+        at: Timestamp,
+    },
+    /// The resolver still returned other addresses after the last attempt.
+    NotVisible,
+}
+
+#[derive(Debug, Clone)]
+pub struct PropagationCheck {
+    /// A DoH endpoint supporting the JSON API (`application/dns-json`).
+    resolver: String,
+    /// How often the resolver is queried at least before giving up.
+    attempts: u32,
+    /// The delay before the first query. It doubles after every attempt, up to [`MAX_DELAY`].
+    initial_delay: Duration,
+    client: reqwest::Client,
+}
+
+impl PropagationCheck {
+    pub fn new(resolver: impl Into<String>, attempts: u32) -> Self {
+        Self {
+            resolver: resolver.into(),
+            attempts,
+            initial_delay: Duration::from_secs(5),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Checks in the background until `name` resolves to `address`, then passes the result to
+    /// `done`. `old_ttl` is the TTL of the record before the update, if known.
+    pub fn spawn(
+        &self,
+        hostname: &str,
+        name: String,
+        record_type: DnsRecordType,
+        address: String,
+        old_ttl: Option<u32>,
+        done: impl FnOnce(Propagation) + Send + 'static,
+    ) {
+        let check = self.clone();
+        let span = info_span!("propagation_check", %hostname, %name, %record_type);
+        tokio::spawn(
+            async move {
+                let result = check
+                    .wait_until_visible(&name, &record_type, &address, old_ttl)
+                    .await;
+                done(result);
+            }
+            .instrument(span),
+        );
+    }
+
+    /// How long resolvers may still serve the content of a record with `ttl`, plus the time the
+    /// provider needs to publish the new content.
+    fn timeout(ttl: Option<u32>) -> Duration {
+        let ttl = ttl
+            .map(u64::from)
+            .filter(|it| *it > 1)
+            .unwrap_or(DEFAULT_TTL);
+        Duration::from_secs(ttl) + PUBLISH_DELAY
+    }
+
+    /// Queries the resolver until `name` resolves to `address`. Gives up after the configured
+    /// attempts, but not before [`timeout`](Self::timeout) passed.
+    async fn wait_until_visible(
+        &self,
+        name: &str,
+        record_type: &DnsRecordType,
+        address: &str,
+        old_ttl: Option<u32>,
+    ) -> Propagation {
+        let Ok(expected) = address.parse::<IpAddr>() else {
+            return Propagation::NotVisible;
+        };
+        let timeout = Self::timeout(old_ttl);
+        let mut waited = Duration::ZERO;
+        let mut delay = self.initial_delay;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            tokio::time::sleep(delay).await;
+            waited += delay;
+            delay = (delay * 2).min(MAX_DELAY);
+            match self.resolve(name, record_type).await {
+                Ok(addresses) if addresses.contains(&expected) => {
+                    info!(attempt, %address, "Updated record is visible");
+                    return Propagation::Visible {
+                        at: Timestamp::now(),
+                    };
+                }
+                Ok(addresses) => debug!(attempt, ?addresses, "Updated record is not visible yet"),
+                Err(e) => debug!(attempt, error = %e, "Failed to query resolver"),
+            }
+            if attempt >= self.attempts && waited >= timeout {
+                break;
+            }
+        }
+        warn!(
+            %address,
+            attempts = attempt,
+            timeout = ?timeout,
+            "Updated record is still not visible, giving up"
+        );
+        Propagation::NotVisible
+    }
+
+    /// Queries the resolver until the TXT record `name` contains `value`, e.g. an ACME
+    /// challenge. Gives up after the configured attempts, but not before `PUBLISH_DELAY`
+    /// passed. Returns whether the value became visible.
+    pub async fn wait_for_txt(&self, name: &str, value: &str) -> bool {
+        let mut waited = Duration::ZERO;
+        let mut delay = self.initial_delay;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            tokio::time::sleep(delay).await;
+            waited += delay;
+            delay = (delay * 2).min(MAX_DELAY);
+            match self.query(name, "TXT").await {
+                // The JSON API returns the text quoted
+                Ok(texts) if texts.iter().any(|it| it.trim_matches('"') == value) => {
+                    info!(attempt, %name, "TXT record is visible");
+                    return true;
+                }
+                Ok(texts) => debug!(attempt, ?texts, "TXT record is not visible yet"),
+                Err(e) => debug!(attempt, error = %e, "Failed to query resolver"),
+            }
+            if attempt >= self.attempts && waited >= PUBLISH_DELAY {
+                break;
+            }
+        }
+        warn!(%name, attempts = attempt, "TXT record is still not visible, giving up");
+        false
+    }
+
+    async fn resolve(
+        &self,
+        name: &str,
+        record_type: &DnsRecordType,
+    ) -> Result<Vec<IpAddr>, Report> {
+        Ok(self
+            .query(name, &record_type.to_string())
+            .await?
+            .iter()
+            .filter_map(|it| it.parse().ok())
+            .collect())
+    }
+
+    /// The data of the answers for `name` of type `record_type`.
+    async fn query(&self, name: &str, record_type: &str) -> Result<Vec<String>, Report> {
+        let response = self
+            .client
+            .get(&self.resolver)
+            .header("Accept", "application/dns-json")
+            .query(&[("name", name), ("type", record_type)])
+            .send()
+            .await
+            .context("Querying DoH resolver")
+            .attach(format!("resolver: '{}'", self.resolver))?;
+        if !response.status().is_success() {
+            bail!("DoH resolver returned status {}", response.status());
+        }
+        let response = response
+            .json::<DohResponse>()
+            .await
+            .context("Parsing DoH response")?;
+
+        Ok(response.answer.into_iter().map(|it| it.data).collect())
+    }
+}
+
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    data: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{method, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn answer(address: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "Status": 0,
+            "Answer": [{ "name": "nas.example.net", "type": 1, "TTL": 300, "data": address }],
+        }))
+    }
+
+    /// A resolver answering with the old address `stale` times, then with the new one. Always
+    /// answers with the old address without `stale`.
+    async fn resolver(stale: Option<u64>) -> MockServer {
+        let server = MockServer::start().await;
+        let old = Mock::given(method("GET"))
+            .and(query_param("name", "nas.example.net"))
+            .and(query_param("type", "A"))
+            .respond_with(answer("192.0.2.1"));
+        let Some(times) = stale else {
+            old.mount(&server).await;
+            return server;
+        };
+        old.up_to_n_times(times).mount(&server).await;
+        Mock::given(method("GET"))
+            .and(query_param("name", "nas.example.net"))
+            .respond_with(answer("198.51.100.7"))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    async fn check(server: &MockServer, attempts: u32, old_ttl: Option<u32>) -> Propagation {
+        PropagationCheck::new(server.uri(), attempts)
+            .wait_until_visible(
+                "nas.example.net",
+                &DnsRecordType::A,
+                "198.51.100.7",
+                old_ttl,
+            )
+            .await
+    }
+
+    #[test]
+    fn timeout_covers_the_ttl() {
+        assert_eq!(PropagationCheck::timeout(None), Duration::from_secs(360));
+        assert_eq!(PropagationCheck::timeout(Some(1)), Duration::from_secs(360));
+        assert_eq!(
+            PropagationCheck::timeout(Some(3600)),
+            Duration::from_secs(3660)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn visible_once_the_resolver_returns_the_address() {
+        let server = resolver(Some(2)).await;
+
+        let result = check(&server, 5, Some(300)).await;
+
+        assert!(matches!(result, Propagation::Visible { .. }), "{result:?}");
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_querying_until_the_ttl_expired() {
+        let server = resolver(None).await;
+
+        let result = check(&server, 2, Some(600)).await;
+
+        // After 5, 15, 35, 75 and 135 seconds, then every minute until 675 seconds
+        assert_eq!(result, Propagation::NotVisible);
+        assert_eq!(server.received_requests().await.unwrap().len(), 14);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn short_ttl_gives_up_after_the_attempts() {
+        let server = resolver(None).await;
+
+        let result = check(&server, 5, Some(30)).await;
+
+        // The delays alone add up to 135 seconds, past the 90 seconds of the TTL
+        assert_eq!(result, Propagation::NotVisible);
+        assert_eq!(server.received_requests().await.unwrap().len(), 5);
+    }
+
+    /// A resolver answering with the TXT record `value` for the challenge of `nas.example.net`
+    /// after `missing` queries without it.
+    async fn txt_resolver(missing: u64, value: &str) -> MockServer {
+        let server = MockServer::start().await;
+        if missing > 0 {
+            Mock::given(method("GET"))
+                .and(query_param("name", "_acme-challenge.nas.example.net"))
+                .and(query_param("type", "TXT"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "Status": 3 })))
+                .up_to_n_times(missing)
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(query_param("name", "_acme-challenge.nas.example.net"))
+            .and(query_param("type", "TXT"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "Status": 0,
+                "Answer": [{
+                    "name": "_acme-challenge.nas.example.net",
+                    "type": 16,
+                    "TTL": 60,
+                    "data": format!("\"{value}\""),
+                }],
+            })))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn txt_record_is_found_once_published() {
+        let server = txt_resolver(2, "challenge-value").await;
+
+        let visible = PropagationCheck::new(server.uri(), 5)
+            .wait_for_txt("_acme-challenge.nas.example.net", "challenge-value")
+            .await;
+
+        assert!(visible);
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn txt_record_with_another_value_is_not_visible() {
+        let server = txt_resolver(0, "old-value").await;
+
+        let visible = PropagationCheck::new(server.uri(), 3)
+            .wait_for_txt("_acme-challenge.nas.example.net", "challenge-value")
+            .await;
+
+        // After 5, 15 and 35 seconds, then until the publish delay of 60 seconds passed
+        assert!(!visible);
+        assert_eq!(server.received_requests().await.unwrap().len(), 4);
+    }
+}
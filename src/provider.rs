@@ -1,18 +1,111 @@
+use crate::provider::api_usage::ApiUsageSnapshot;
+use crate::provider::chaos::ChaosProvider;
 use async_trait::async_trait;
 use derive_more::Display;
-use rootcause::Report;
+use rootcause::{Report, bail, report};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use tracing::debug;
 
+// Only the Cloudflare provider counts its calls so far
+#[cfg_attr(not(feature = "provider-cloudflare"), allow(dead_code))]
+pub(crate) mod api_usage;
+pub mod chaos;
+#[cfg(feature = "provider-cloudflare")]
 pub mod cloudflare;
+pub mod failover;
+pub mod memory;
+#[cfg(feature = "provider-netcup")]
 pub mod netcup;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Display)]
+/// Creates a provider from its environment variables.
+pub(crate) type FromEnv = fn() -> Result<Box<dyn DnsProvider + Send + Sync>, Report>;
+
+/// A provider that can be listed in `PROVIDERS`.
+pub(crate) struct ProviderFactory {
+    pub name: &'static str,
+    /// The cargo feature compiling the provider in, `None` if it is always compiled in.
+    pub feature: Option<&'static str>,
+    /// `None` if the provider is not compiled in.
+    pub from_env: Option<FromEnv>,
+}
+
+/// All providers that can be listed in `PROVIDERS`, whether they are compiled in or not.
+pub(crate) const FACTORIES: &[ProviderFactory] = &[
+    ProviderFactory {
+        name: "cloudflare",
+        feature: Some("provider-cloudflare"),
+        #[cfg(feature = "provider-cloudflare")]
+        from_env: Some(|| Ok(Box::new(cloudflare::CloudflareProvider::new_from_env()?))),
+        #[cfg(not(feature = "provider-cloudflare"))]
+        from_env: None,
+    },
+    ProviderFactory {
+        name: "netcup",
+        feature: Some("provider-netcup"),
+        #[cfg(feature = "provider-netcup")]
+        from_env: Some(|| Ok(Box::new(netcup::NetcupProvider::new_from_env()?))),
+        #[cfg(not(feature = "provider-netcup"))]
+        from_env: None,
+    },
+    ProviderFactory {
+        name: "memory",
+        feature: None,
+        from_env: Some(|| Ok(Box::new(memory::MemoryProvider::new_from_env()?))),
+    },
+];
+
+/// The names of the providers compiled into this binary.
+pub(crate) fn compiled_providers() -> Vec<&'static str> {
+    FACTORIES
+        .iter()
+        .filter(|it| it.from_env.is_some())
+        .map(|it| it.name)
+        .collect()
+}
+
+/// Creates the provider `name` from its environment variables.
+pub(crate) fn provider_from_env(name: &str) -> Result<Box<dyn DnsProvider + Send + Sync>, Report> {
+    let Some(factory) = FACTORIES.iter().find(|it| it.name == name) else {
+        return Err(report!(
+            "Unknown provider specified in PROVIDERS environment variable: '{name}'"
+        )
+        .attach(format!(
+            "available providers: {}",
+            compiled_providers().join(", ")
+        ))
+        .into_dynamic());
+    };
+    let Some(from_env) = factory.from_env else {
+        let feature = factory
+            .feature
+            .expect("providers without a feature are always compiled in");
+        return Err(
+            report!("The '{name}' provider is not compiled into this binary")
+                .attach(format!("hint: rebuild with --features {feature}"))
+                .into_dynamic(),
+        );
+    };
+    from_env()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Display, Serialize, Deserialize)]
 pub enum DnsRecordType {
     A,
     #[allow(clippy::upper_case_acronyms)]
     AAAA,
 }
 
+impl DnsRecordType {
+    /// Whether `content` is an address of the family of this record type.
+    pub fn accepts(&self, content: &str) -> bool {
+        match self {
+            Self::A => content.parse::<Ipv4Addr>().is_ok(),
+            Self::AAAA => content.parse::<Ipv6Addr>().is_ok(),
+        }
+    }
+}
+
 impl TryFrom<String> for DnsRecordType {
     type Error = ();
 
@@ -28,16 +121,78 @@ impl TryFrom<String> for DnsRecordType {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Display)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Display, Serialize)]
 pub struct RecordId(pub String);
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Display)]
-pub struct Origin(pub String);
+/// A zone in canonical form: lowercase ASCII (punycode) labels without a trailing dot.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Display, Serialize)]
+pub struct Origin(String);
 
 impl Origin {
+    /// Normalizes `value` and rejects it if it is not a valid domain. Surrounding whitespace, a
+    /// URL scheme and path (if someone pasted a URL) and a single trailing dot are removed.
+    pub fn parse(value: &str) -> Result<Self, Report> {
+        canonical_domain("Origin", value).map(Self)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether `domain`, which must be in canonical form as well, is below this origin.
     pub fn is_subdomain(&self, domain: &str) -> bool {
         domain.ends_with(&format!(".{}", self.0))
     }
+
+    /// Replaces `from` by `to` in this origin, for the provider origin mappings.
+    pub fn replace(&self, from: &Self, to: &Self) -> Self {
+        Self(self.0.replace(&from.0, &to.0))
+    }
+}
+
+/// Brings a hostname of a request into the canonical form of an [`Origin`], so `NAS.Foobar.de.`
+/// matches the configured `nas.foobar.de`. Fails if it is not a valid domain.
+pub(crate) fn canonical_hostname(value: &str) -> Result<String, Report> {
+    canonical_domain("Hostname", value)
+}
+
+fn canonical_domain(kind: &str, value: &str) -> Result<String, Report> {
+    let mut domain = value.trim();
+    if let Some((_, rest)) = domain.split_once("://") {
+        domain = rest;
+    }
+    if let Some((host, _path)) = domain.split_once('/') {
+        domain = host;
+    }
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    if domain.is_empty() {
+        bail!("{kind} '{value}' is empty");
+    }
+
+    let domain = idna::domain_to_ascii(domain)
+        .map_err(|e| report!("{kind} '{value}' is not a valid domain").attach(e.to_string()))?;
+    if domain.len() > 253 {
+        bail!("{kind} '{value}' is longer than 253 characters");
+    }
+    for label in domain.split('.') {
+        if label.is_empty() {
+            bail!("{kind} '{value}' has an empty label");
+        }
+        if label.len() > 63 {
+            bail!("{kind} '{value}' has label '{label}' longer than 63 characters");
+        }
+        if let Some(invalid) = label
+            .chars()
+            .find(|it| !it.is_ascii_alphanumeric() && *it != '-')
+        {
+            bail!("{kind} '{value}' has label '{label}' with invalid character '{invalid}'");
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            bail!("{kind} '{value}' has label '{label}' starting or ending with a hyphen");
+        }
+    }
+
+    Ok(domain)
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -46,6 +201,145 @@ pub struct DnsEntry {
     pub id: RecordId,
     pub name: String,
     pub content: String,
+    /// The TTL in seconds, if the provider has one per record. Cloudflare uses `1` for "auto".
+    pub ttl: Option<u32>,
+    /// Whether the provider proxies the record, if it supports that.
+    pub proxied: Option<bool>,
+}
+
+impl DnsEntry {
+    /// Whether the record has `content` and the `options` that are set, so writing it would
+    /// change nothing.
+    pub fn is_up_to_date(&self, content: &str, options: &RecordOptions) -> bool {
+        same_content(&self.content, content)
+            && options.ttl.is_none_or(|ttl| self.ttl == Some(ttl))
+            && options
+                .proxied
+                .is_none_or(|proxied| self.proxied == Some(proxied))
+    }
+
+    pub fn to_ref(&self) -> RecordRef {
+        RecordRef {
+            typ: self.typ.clone(),
+            id: self.id.clone(),
+            name: self.name.clone(),
+        }
+    }
+}
+
+/// Identifies the record to write. Providers without record IDs can use the type and name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RecordRef {
+    pub typ: DnsRecordType,
+    pub id: RecordId,
+    pub name: String,
+}
+
+impl RecordRef {
+    /// Fails if `content` cannot be written to a record of this type, e.g. an IPv6 address to
+    /// an A record.
+    pub fn check_content(&self, content: &str) -> Result<(), Report> {
+        if !self.typ.accepts(content) {
+            return Err(report!(ContentTypeMismatch)
+                .attach(format!("record: {} {} ({})", self.typ, self.name, self.id))
+                .attach(format!("content: '{content}'"))
+                .into_dynamic());
+        }
+        Ok(())
+    }
+}
+
+/// The context of reports caused by content not matching the record type.
+#[derive(Debug, Display)]
+#[display("content does not match the record type")]
+pub struct ContentTypeMismatch;
+
+/// The context of reports caused by a record no longer having the content it was listed with,
+/// as someone else changed it in the meantime.
+#[derive(Debug, Display)]
+#[display("record was changed concurrently, expected '{expected}' but found '{actual}'")]
+pub struct RecordConflict {
+    pub expected: String,
+    pub actual: String,
+}
+
+impl RecordConflict {
+    /// Fails with a [`RecordConflict`] unless `actual` is the `expected` content, if any.
+    pub fn check(expected: Option<&str>, actual: &str) -> Result<(), Report> {
+        match expected {
+            Some(expected) if !same_content(actual, expected) => Err(report!(Self {
+                expected: expected.to_string(),
+                actual: actual.to_string(),
+            })
+            .into_dynamic()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The conflict `report` or one of its causes is about.
+    pub fn find(report: &Report) -> Option<&Self> {
+        report
+            .iter_reports()
+            .find_map(|it| it.downcast_current_context::<Self>())
+    }
+}
+
+/// Whether a record with `content` has the `other` content. Addresses are compared parsed, as
+/// providers do not necessarily return them in canonical form.
+pub fn same_content(content: &str, other: &str) -> bool {
+    match (content.parse::<IpAddr>(), other.parse::<IpAddr>()) {
+        (Ok(content), Ok(other)) => content == other,
+        _ => content == other,
+    }
+}
+
+/// The context of reports caused by a record ID that does not exist (anymore).
+#[derive(Debug, Display)]
+#[display("record not found")]
+pub struct RecordNotFound;
+
+impl ContentTypeMismatch {
+    /// Whether `report` or one of its causes is a [`ContentTypeMismatch`].
+    pub fn is_cause_of(report: &Report) -> bool {
+        report
+            .iter_reports()
+            .any(|it| it.downcast_current_context::<Self>().is_some())
+    }
+}
+
+impl RecordNotFound {
+    /// Whether `report` or one of its causes is a [`RecordNotFound`].
+    pub fn is_cause_of(report: &Report) -> bool {
+        report
+            .iter_reports()
+            .any(|it| it.downcast_current_context::<Self>().is_some())
+    }
+}
+
+/// A TXT record, which the server only reads and writes for [ownership markers](crate::Ownership).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TxtRecord {
+    pub id: RecordId,
+    pub name: String,
+    /// The text without surrounding quotes.
+    pub content: String,
+}
+
+/// Settings applied when writing a record. `None` keeps the provider default or existing value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordOptions {
+    pub ttl: Option<u32>,
+    pub proxied: Option<bool>,
+}
+
+impl RecordOptions {
+    /// Fills unset values from `defaults`.
+    pub fn or(self, defaults: Self) -> Self {
+        Self {
+            ttl: self.ttl.or(defaults.ttl),
+            proxied: self.proxied.or(defaults.proxied),
+        }
+    }
 }
 
 #[async_trait]
@@ -53,12 +347,300 @@ pub trait DnsProvider {
     fn name(&self) -> &'static str;
 
     async fn list_records(&self, origin: &Origin) -> Result<Vec<DnsEntry>, Report>;
+    /// Sets the content of `record`. With `expected`, providers that
+    /// [support compare-and-set](Self::supports_compare_and_set) only write the record if it
+    /// still has that content, and fail with a [`RecordConflict`] otherwise.
+    ///
+    /// Implementations call [`RecordRef::check_content`] before anything else, so content not
+    /// matching the record type fails with a [`ContentTypeMismatch`] without touching the record.
     async fn update_record(
         &self,
         origin: &Origin,
-        record_id: &RecordId,
+        record: &RecordRef,
+        expected: Option<&str>,
         new_content: &str,
+        options: &RecordOptions,
     ) -> Result<(), Report>;
+    async fn delete_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report>;
 
     async fn validate(&self, origin: &Origin) -> Result<(), Report>;
+
+    /// Whether [`update_record`](Self::update_record) writes conditionally on its expected
+    /// content. Reading the record before writing it does not count, as that costs a request
+    /// and still races, so such providers keep last-write-wins.
+    fn supports_compare_and_set(&self) -> bool {
+        false
+    }
+
+    /// The options [`update_record`](Self::update_record) applies if they are unset, e.g. from
+    /// `CLOUDFLARE_TTL`.
+    fn default_options(&self) -> RecordOptions {
+        RecordOptions::default()
+    }
+
+    /// The credentials of this provider, redacted from error reports sent to clients.
+    fn secrets(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Recent API calls and rate limit information, for providers tracking them.
+    fn api_usage(&self) -> Option<ApiUsageSnapshot> {
+        None
+    }
+
+    /// The fault injection of this provider, if it is wrapped in a [`ChaosProvider`].
+    fn chaos(&self) -> Option<&ChaosProvider> {
+        None
+    }
+
+    /// All TXT records of `origin`. Providers without TXT support fail, so ownership markers
+    /// can not be used with them.
+    async fn list_all_txt_records(&self, _origin: &Origin) -> Result<Vec<TxtRecord>, Report> {
+        bail!("The {} provider does not support TXT records", self.name())
+    }
+
+    /// The TXT records named `name`. Listing them costs as much as
+    /// [listing all](Self::list_all_txt_records), so checks of many names list all once.
+    async fn list_txt_records(
+        &self,
+        origin: &Origin,
+        name: &str,
+    ) -> Result<Vec<TxtRecord>, Report> {
+        Ok(self
+            .list_all_txt_records(origin)
+            .await?
+            .into_iter()
+            .filter(|it| it.name == name)
+            .collect())
+    }
+
+    async fn create_txt_record(
+        &self,
+        _origin: &Origin,
+        _name: &str,
+        _content: &str,
+    ) -> Result<(), Report> {
+        bail!("The {} provider does not support TXT records", self.name())
+    }
+
+    async fn delete_txt_record(
+        &self,
+        _origin: &Origin,
+        _record_id: &RecordId,
+    ) -> Result<(), Report> {
+        bail!("The {} provider does not support TXT records", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(content: &str, ttl: Option<u32>, proxied: Option<bool>) -> DnsEntry {
+        DnsEntry {
+            typ: DnsRecordType::AAAA,
+            id: RecordId("aaaa".to_string()),
+            name: "nas.foobar.de".to_string(),
+            content: content.to_string(),
+            ttl,
+            proxied,
+        }
+    }
+
+    fn origin(value: &str) -> String {
+        Origin::parse(value).unwrap().as_str().to_string()
+    }
+
+    fn origin_error(value: &str) -> String {
+        Origin::parse(value).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn origin_is_normalized() {
+        assert_eq!(origin("foobar.de"), "foobar.de");
+        assert_eq!(origin("  foobar.de\n"), "foobar.de");
+        assert_eq!(origin("foobar.de."), "foobar.de");
+        assert_eq!(origin("FooBar.DE"), "foobar.de");
+        assert_eq!(origin("https://foobar.de/"), "foobar.de");
+        assert_eq!(origin("http://Foobar.de/nic/update"), "foobar.de");
+        assert_eq!(origin("foobar.de/"), "foobar.de");
+        assert_eq!(origin("bücher.example"), "xn--bcher-kva.example");
+        assert_eq!(origin("xn--bcher-kva.example"), "xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn origin_rejects_invalid_domains() {
+        assert!(origin_error("").contains("is empty"));
+        assert!(origin_error(" . ").contains("is empty"));
+        assert!(origin_error("https://").contains("is empty"));
+        assert!(origin_error("foobar.de..").contains("foobar.de.."));
+        assert!(origin_error("foo..de").contains("foo..de"));
+        assert!(origin_error(".foobar.de").contains(".foobar.de"));
+        assert!(origin_error("foo_bar.de").contains("'_'"));
+        assert!(origin_error("-foo.de").contains("hyphen"));
+        assert!(origin_error("foo-.de").contains("hyphen"));
+
+        let long_label = "a".repeat(64);
+        assert!(origin_error(&format!("{long_label}.de")).contains("longer than 63"));
+        let long_domain = vec!["a".repeat(60); 5].join(".");
+        assert!(origin_error(&long_domain).contains("longer than 253"));
+    }
+
+    #[test]
+    fn hostnames_are_normalized_like_origins() {
+        assert_eq!(
+            canonical_hostname(" NAS.Foobar.de. ").unwrap(),
+            "nas.foobar.de"
+        );
+        let error = canonical_hostname("nas..foobar.de")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("Hostname 'nas..foobar.de' has an empty label"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn subdomains_need_a_label_in_front() {
+        let origin = Origin::parse("foobar.de").unwrap();
+        assert!(origin.is_subdomain("nas.foobar.de"));
+        assert!(origin.is_subdomain("a.nas.foobar.de"));
+        assert!(!origin.is_subdomain("foobar.de"));
+        assert!(!origin.is_subdomain("notfoobar.de"));
+    }
+
+    #[test]
+    fn addresses_are_compared_parsed() {
+        let record = entry("2001:DB8:0::1", None, None);
+        assert!(record.is_up_to_date("2001:db8::1", &RecordOptions::default()));
+        assert!(!record.is_up_to_date("2001:db8::2", &RecordOptions::default()));
+    }
+
+    #[test]
+    fn unset_options_are_not_compared() {
+        let record = entry("2001:db8::1", Some(300), Some(true));
+        assert!(record.is_up_to_date("2001:db8::1", &RecordOptions::default()));
+    }
+
+    #[test]
+    fn set_options_have_to_match() {
+        let record = entry("2001:db8::1", Some(300), Some(false));
+        let ttl = |ttl| RecordOptions {
+            ttl: Some(ttl),
+            proxied: None,
+        };
+        let proxied = |proxied| RecordOptions {
+            ttl: None,
+            proxied: Some(proxied),
+        };
+
+        assert!(record.is_up_to_date("2001:db8::1", &ttl(300)));
+        assert!(!record.is_up_to_date("2001:db8::1", &ttl(60)));
+        assert!(record.is_up_to_date("2001:db8::1", &proxied(false)));
+        assert!(!record.is_up_to_date("2001:db8::1", &proxied(true)));
+        // Unknown values differ from every wanted one
+        assert!(!entry("2001:db8::1", None, None).is_up_to_date("2001:db8::1", &ttl(300)));
+    }
+
+    #[test]
+    fn content_has_to_match_the_record_type() {
+        let aaaa = entry("2001:db8::1", None, None).to_ref();
+        assert!(aaaa.check_content("2001:db8::2").is_ok());
+        let error = aaaa.check_content("192.0.2.1").unwrap_err();
+        assert!(ContentTypeMismatch::is_cause_of(&error), "{error}");
+        assert!(aaaa.check_content("nas.foobar.de").is_err());
+
+        let a = RecordRef {
+            typ: DnsRecordType::A,
+            ..aaaa
+        };
+        assert!(a.check_content("192.0.2.1").is_ok());
+        assert!(a.check_content("2001:db8::1").is_err());
+        // Mapped addresses are IPv6 addresses
+        assert!(a.check_content("::ffff:192.0.2.1").is_err());
+    }
+
+    #[tokio::test]
+    async fn mismatched_content_is_not_written() {
+        let record = entry("2001:db8::1", None, None);
+        let provider = memory::MemoryProvider::new(vec![record.clone()]);
+        let origin = Origin::parse("foobar.de").unwrap();
+
+        let error = provider
+            .update_record(
+                &origin,
+                &record.to_ref(),
+                None,
+                "192.0.2.1",
+                &RecordOptions::default(),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(ContentTypeMismatch::is_cause_of(&error), "{error}");
+        assert!(error.to_string().contains("192.0.2.1"), "{error}");
+        assert_eq!(provider.records(), vec![record]);
+    }
+
+    fn from_env_error(name: &str) -> String {
+        match provider_from_env(name) {
+            Ok(_) => panic!("provider '{name}' was created"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn memory_provider_is_always_compiled_in() {
+        assert!(compiled_providers().contains(&"memory"));
+
+        let provider = provider_from_env("memory").unwrap();
+        assert_eq!(provider.name(), "memory");
+    }
+
+    #[test]
+    fn compiled_providers_follow_the_features() {
+        let compiled = compiled_providers();
+
+        assert_eq!(
+            compiled.contains(&"cloudflare"),
+            cfg!(feature = "provider-cloudflare")
+        );
+        assert_eq!(
+            compiled.contains(&"netcup"),
+            cfg!(feature = "provider-netcup")
+        );
+    }
+
+    #[test]
+    fn every_compiled_out_provider_names_its_feature() {
+        for factory in FACTORIES.iter().filter(|it| it.from_env.is_none()) {
+            let feature = factory.feature.unwrap();
+
+            let error = from_env_error(factory.name);
+
+            assert!(
+                error.contains("is not compiled into this binary"),
+                "{error}"
+            );
+            assert!(
+                error.contains(&format!("rebuild with --features {feature}")),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_provider_lists_the_compiled_in_ones() {
+        let error = from_env_error("route53");
+
+        assert!(error.contains("Unknown provider"), "{error}");
+        assert!(
+            error.contains(&format!(
+                "available providers: {}",
+                compiled_providers().join(", ")
+            )),
+            "{error}"
+        );
+    }
 }
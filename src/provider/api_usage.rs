@@ -0,0 +1,113 @@
+//! Counts the API calls a provider made recently and keeps the rate limit headers the API sent,
+//! to tell when updates are about to be rate limited, and why.
+
+use jiff::{SignedDuration, Timestamp};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// The window calls are counted in.
+pub const WINDOW: SignedDuration = SignedDuration::from_mins(5);
+
+#[derive(Debug)]
+pub struct ApiUsage {
+    provider: &'static str,
+    /// The documented number of calls allowed per [`WINDOW`].
+    limit: u32,
+    /// The fraction of `limit` above which a warning is logged.
+    warn_fraction: f64,
+    state: Mutex<UsageState>,
+}
+
+#[derive(Debug, Default)]
+struct UsageState {
+    calls: VecDeque<Timestamp>,
+    headers: BTreeMap<String, String>,
+    headers_at: Option<Timestamp>,
+    warned: bool,
+}
+
+/// The usage of a provider at one point in time.
+#[derive(Debug, Clone)]
+pub struct ApiUsageSnapshot {
+    pub provider: &'static str,
+    /// Calls in the last [`WINDOW`].
+    pub calls: usize,
+    pub limit: u32,
+    /// The `Retry-After` and rate limit headers of the last response that had any, by lowercase
+    /// name.
+    pub headers: BTreeMap<String, String>,
+    /// When `headers` were received.
+    pub headers_at: Option<Timestamp>,
+}
+
+impl ApiUsage {
+    pub fn new(provider: &'static str, limit: u32, warn_fraction: f64) -> Self {
+        Self {
+            provider,
+            limit,
+            warn_fraction,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Counts a call answered by `response` and keeps its rate limit headers.
+    pub fn record(&self, response: &reqwest::Response) {
+        let now = Timestamp::now();
+        let mut state = self.state.lock().expect("mutex poisoned");
+        state.calls.push_back(now);
+        prune(&mut state.calls, now);
+
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| is_rate_limit_header(name.as_str()))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect::<BTreeMap<_, _>>();
+        if !headers.is_empty() {
+            state.headers = headers;
+            state.headers_at = Some(now);
+        }
+
+        let threshold = f64::from(self.limit) * self.warn_fraction;
+        let above = state.calls.len() as f64 >= threshold;
+        if above && !state.warned {
+            warn!(
+                provider = self.provider,
+                calls = state.calls.len(),
+                limit = self.limit,
+                window = %WINDOW,
+                "API calls are approaching the rate limit"
+            );
+        }
+        state.warned = above;
+    }
+
+    pub fn snapshot(&self) -> ApiUsageSnapshot {
+        let mut state = self.state.lock().expect("mutex poisoned");
+        prune(&mut state.calls, Timestamp::now());
+        ApiUsageSnapshot {
+            provider: self.provider,
+            calls: state.calls.len(),
+            limit: self.limit,
+            headers: state.headers.clone(),
+            headers_at: state.headers_at,
+        }
+    }
+}
+
+/// Drops the calls that left the window.
+fn prune(calls: &mut VecDeque<Timestamp>, now: Timestamp) {
+    while calls
+        .front()
+        .is_some_and(|it| now.duration_since(*it) > WINDOW)
+    {
+        calls.pop_front();
+    }
+}
+
+/// `Retry-After` and the `RateLimit-*` headers, both as drafted by the IETF and in their
+/// `X-RateLimit-*` form.
+fn is_rate_limit_header(name: &str) -> bool {
+    name == "retry-after" || name.starts_with("ratelimit") || name.starts_with("x-ratelimit")
+}
@@ -0,0 +1,368 @@
+//! A provider wrapper injecting faults and latency, to see how the retry queue and friends cope
+//! with a misbehaving provider before relying on them.
+//!
+//! Enabled by prefixing a provider in `PROVIDERS` with `chaos:`, e.g. `chaos:cloudflare`. Calls
+//! fail randomly with the configured probability, and `POST /admin/chaos` scripts faults, e.g.
+//! "fail the next 3 updates". Every injected fault is logged with a sequence number.
+
+use super::api_usage::ApiUsageSnapshot;
+use super::{DnsEntry, DnsProvider, Origin, RecordId, RecordOptions, RecordRef, TxtRecord};
+use crate::settings;
+use async_trait::async_trait;
+use derive_more::Display;
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail, report};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::warn;
+
+/// How long a call hangs before an injected [`ChaosError::Timeout`] is returned.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The kind of fault injected into a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChaosError {
+    #[display("rate_limited")]
+    RateLimited,
+    #[display("auth_failed")]
+    AuthFailed,
+    /// Hangs for the configured timeout before failing.
+    #[display("timeout")]
+    Timeout,
+}
+
+impl FromStr for ChaosError {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim() {
+            "rate_limited" => Self::RateLimited,
+            "auth_failed" => Self::AuthFailed,
+            "timeout" => Self::Timeout,
+            other => bail!(
+                "Unknown chaos error '{other}', expected rate_limited, auth_failed or timeout"
+            ),
+        })
+    }
+}
+
+/// The provider calls faults can be injected into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    #[display("list")]
+    List,
+    #[display("update")]
+    Update,
+    #[display("delete")]
+    Delete,
+    #[display("validate")]
+    Validate,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChaosConfig {
+    /// The probability of a call failing, between 0 and 1.
+    pub failure_rate: f64,
+    /// The bounds of the latency added to every call.
+    pub latency: Option<(Duration, Duration)>,
+    /// The faults random failures pick from.
+    pub errors: Vec<ChaosError>,
+    pub timeout: Duration,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            failure_rate: 0.0,
+            latency: None,
+            errors: vec![
+                ChaosError::RateLimited,
+                ChaosError::AuthFailed,
+                ChaosError::Timeout,
+            ],
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl ChaosConfig {
+    /// Reads `CHAOS_FAILURE_RATE`, `CHAOS_LATENCY_MS` (e.g. `50-500`), `CHAOS_ERRORS` and
+    /// `CHAOS_TIMEOUT_SECS`.
+    pub fn from_env() -> Result<Self, Report> {
+        let mut config = Self::default();
+        if let Ok(rate) = settings::var("CHAOS_FAILURE_RATE") {
+            config.failure_rate = rate
+                .trim()
+                .parse()
+                .context("Invalid CHAOS_FAILURE_RATE environment variable")?;
+            if !(0.0..=1.0).contains(&config.failure_rate) {
+                bail!("CHAOS_FAILURE_RATE must be between 0 and 1");
+            }
+        }
+        if let Ok(latency) = settings::var("CHAOS_LATENCY_MS") {
+            let (min, max) = latency.split_once('-').unwrap_or((&latency, &latency));
+            let min = min.trim().parse::<u64>();
+            let max = max.trim().parse::<u64>();
+            let (Ok(min), Ok(max)) = (min, max) else {
+                bail!("Invalid CHAOS_LATENCY_MS environment variable, expected e.g. 50-500");
+            };
+            if min > max {
+                bail!("CHAOS_LATENCY_MS must not end below its start");
+            }
+            config.latency = Some((Duration::from_millis(min), Duration::from_millis(max)));
+        }
+        if let Ok(errors) = settings::var("CHAOS_ERRORS") {
+            config.errors = errors
+                .split(',')
+                .filter(|it| !it.trim().is_empty())
+                .map(ChaosError::from_str)
+                .collect::<Result<_, _>>()
+                .context("Invalid CHAOS_ERRORS environment variable")?;
+            if config.errors.is_empty() {
+                bail!("CHAOS_ERRORS must name at least one error");
+            }
+        }
+        if let Ok(timeout) = settings::var("CHAOS_TIMEOUT_SECS") {
+            let timeout = timeout
+                .trim()
+                .parse()
+                .context("Invalid CHAOS_TIMEOUT_SECS environment variable")?;
+            config.timeout = Duration::from_secs(timeout);
+        }
+        Ok(config)
+    }
+}
+
+/// Faults injected into the next calls, regardless of the failure rate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptedFault {
+    /// The calls affected, all of them if unset.
+    pub operation: Option<Operation>,
+    /// How many further calls fail.
+    pub remaining: u32,
+    pub error: ChaosError,
+}
+
+/// The state shown by `GET /admin/chaos`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChaosSnapshot {
+    pub provider: &'static str,
+    pub failure_rate: f64,
+    pub scripted: Vec<ScriptedFault>,
+    /// Faults injected since startup.
+    pub injected: u64,
+}
+
+struct ChaosState {
+    config: ChaosConfig,
+    scripted: VecDeque<ScriptedFault>,
+    injected: u64,
+}
+
+/// Wraps `inner`, injecting faults and latency into its calls. It keeps the name of the inner
+/// provider, so origin mappings and metrics are unaffected.
+pub struct ChaosProvider {
+    inner: Box<dyn DnsProvider + Send + Sync>,
+    state: Mutex<ChaosState>,
+}
+
+impl ChaosProvider {
+    pub fn new(inner: Box<dyn DnsProvider + Send + Sync>, config: ChaosConfig) -> Self {
+        Self {
+            inner,
+            state: Mutex::new(ChaosState {
+                config,
+                scripted: VecDeque::new(),
+                injected: 0,
+            }),
+        }
+    }
+
+    /// Makes the next `count` calls of `operation` (or all calls) fail with `error`. A call uses
+    /// the oldest scripted fault matching it.
+    pub fn fail_next(&self, operation: Option<Operation>, count: u32, error: ChaosError) {
+        let mut state = self.state.lock().expect("mutex poisoned");
+        state.scripted.push_back(ScriptedFault {
+            operation,
+            remaining: count,
+            error,
+        });
+    }
+
+    pub fn set_failure_rate(&self, failure_rate: f64) {
+        self.state
+            .lock()
+            .expect("mutex poisoned")
+            .config
+            .failure_rate = failure_rate;
+    }
+
+    /// Drops all scripted faults and stops random failures.
+    pub fn reset(&self) {
+        let mut state = self.state.lock().expect("mutex poisoned");
+        state.scripted.clear();
+        state.config.failure_rate = 0.0;
+    }
+
+    /// The error of a random failure, for when the request does not name one.
+    pub fn default_error(&self) -> ChaosError {
+        let state = self.state.lock().expect("mutex poisoned");
+        state.config.errors[0]
+    }
+
+    pub fn snapshot(&self) -> ChaosSnapshot {
+        let state = self.state.lock().expect("mutex poisoned");
+        ChaosSnapshot {
+            provider: self.inner.name(),
+            failure_rate: state.config.failure_rate,
+            scripted: state.scripted.iter().cloned().collect(),
+            injected: state.injected,
+        }
+    }
+
+    /// Delays the call and fails it if a fault is due.
+    async fn inject(&self, operation: Operation) -> Result<(), Report> {
+        let (latency, fault, timeout) = {
+            let mut state = self.state.lock().expect("mutex poisoned");
+            let latency = state.config.latency.map(|(min, max)| {
+                Duration::from_millis(rand::random_range(
+                    min.as_millis() as u64..=max.as_millis() as u64,
+                ))
+            });
+            let scripted = state
+                .scripted
+                .iter_mut()
+                .find(|it| it.operation.is_none_or(|it| it == operation))
+                .map(|it| {
+                    it.remaining -= 1;
+                    it.error
+                });
+            state.scripted.retain(|it| it.remaining > 0);
+            let fault = scripted.map(|it| (it, true)).or_else(|| {
+                let errors = &state.config.errors;
+                (rand::random::<f64>() < state.config.failure_rate)
+                    .then(|| (errors[rand::random_range(0..errors.len())], false))
+            });
+            if fault.is_some() {
+                state.injected += 1;
+            }
+            (
+                latency,
+                fault.map(|it| (it, state.injected)),
+                state.config.timeout,
+            )
+        };
+
+        if let Some(latency) = latency {
+            tokio::time::sleep(latency).await;
+        }
+        let Some(((error, scripted), sequence)) = fault else {
+            return Ok(());
+        };
+        warn!(
+            provider = self.inner.name(),
+            %operation,
+            %error,
+            scripted,
+            sequence,
+            "Injecting chaos fault"
+        );
+        let report = match error {
+            ChaosError::RateLimited => report!("Injected fault: rate limited")
+                .attach("status: 429 Too Many Requests")
+                .attach("Retry-After: 60"),
+            ChaosError::AuthFailed => {
+                report!("Injected fault: authentication failed").attach("status: 403 Forbidden")
+            }
+            ChaosError::Timeout => {
+                tokio::time::sleep(timeout).await;
+                report!("Injected fault: timed out").attach(format!("after: {timeout:?}"))
+            }
+        };
+        Err(report
+            .attach(format!("operation: {operation}"))
+            .attach(format!("chaos fault: #{sequence}"))
+            .into_dynamic())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for ChaosProvider {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn list_records(&self, origin: &Origin) -> Result<Vec<DnsEntry>, Report> {
+        self.inject(Operation::List).await?;
+        self.inner.list_records(origin).await
+    }
+
+    async fn update_record(
+        &self,
+        origin: &Origin,
+        record: &RecordRef,
+        expected: Option<&str>,
+        new_content: &str,
+        options: &RecordOptions,
+    ) -> Result<(), Report> {
+        self.inject(Operation::Update).await?;
+        self.inner
+            .update_record(origin, record, expected, new_content, options)
+            .await
+    }
+
+    async fn delete_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report> {
+        self.inject(Operation::Delete).await?;
+        self.inner.delete_record(origin, record_id).await
+    }
+
+    async fn validate(&self, origin: &Origin) -> Result<(), Report> {
+        self.inject(Operation::Validate).await?;
+        self.inner.validate(origin).await
+    }
+
+    fn supports_compare_and_set(&self) -> bool {
+        self.inner.supports_compare_and_set()
+    }
+
+    fn default_options(&self) -> RecordOptions {
+        self.inner.default_options()
+    }
+
+    fn secrets(&self) -> Vec<String> {
+        self.inner.secrets()
+    }
+
+    fn api_usage(&self) -> Option<ApiUsageSnapshot> {
+        self.inner.api_usage()
+    }
+
+    fn chaos(&self) -> Option<&ChaosProvider> {
+        Some(self)
+    }
+
+    async fn list_all_txt_records(&self, origin: &Origin) -> Result<Vec<TxtRecord>, Report> {
+        self.inject(Operation::List).await?;
+        self.inner.list_all_txt_records(origin).await
+    }
+
+    async fn create_txt_record(
+        &self,
+        origin: &Origin,
+        name: &str,
+        content: &str,
+    ) -> Result<(), Report> {
+        self.inject(Operation::Update).await?;
+        self.inner.create_txt_record(origin, name, content).await
+    }
+
+    async fn delete_txt_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report> {
+        self.inject(Operation::Delete).await?;
+        self.inner.delete_txt_record(origin, record_id).await
+    }
+}
@@ -1,39 +1,265 @@
-use super::{DnsEntry, DnsProvider, DnsRecordType, Origin, RecordId};
+use super::api_usage::{ApiUsage, ApiUsageSnapshot};
+use super::{
+    DnsEntry, DnsProvider, DnsRecordType, Origin, RecordConflict, RecordId, RecordNotFound,
+    RecordOptions, RecordRef, TxtRecord,
+};
+use crate::settings;
 use crate::types::ensure_env_vars;
 use async_trait::async_trait;
+use derive_more::Display;
+use reqwest::StatusCode;
 use rootcause::prelude::ResultExt;
-use rootcause::{Report, report};
+use rootcause::{Report, bail, report};
 use serde_json::json;
-use tracing::info;
+use tracing::{debug, info};
+
+/// The real Cloudflare API, used unless `CLOUDFLARE_API_BASE` is set.
+pub const DEFAULT_API_BASE: &str = "https://api.cloudflare.com/client/v4";
+
+/// The documented global limit of API calls per user and 5 minutes.
+pub const API_RATE_LIMIT: u32 = 1200;
+
+/// The default fraction of [`API_RATE_LIMIT`] above which a warning is logged.
+pub const DEFAULT_RATE_LIMIT_WARNING: f64 = 0.8;
 
 pub struct CloudflareProvider {
     api_token: String,
+    /// The API root without a trailing slash, e.g. [`DEFAULT_API_BASE`].
+    api_base: String,
     client: reqwest::Client,
+    /// Global defaults from `CLOUDFLARE_TTL` and `CLOUDFLARE_PROXIED`.
+    default_options: RecordOptions,
+    usage: ApiUsage,
+    /// Only records carrying it are listed and written, if set.
+    managed_marker: Option<ManagedMarker>,
+}
+
+/// Marks the records the provider may manage, from `CLOUDFLARE_MANAGED_TAG` or
+/// `CLOUDFLARE_MANAGED_COMMENT`. Unmarked records are invisible to the rest of the server, so no
+/// code path can touch them.
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+pub enum ManagedMarker {
+    /// A tag `<name>` or `<name>:<value>`. Tags need a paid plan.
+    #[display("tag '{_0}'")]
+    Tag(String),
+    /// A string contained in the comment of the record.
+    #[display("comment marker '{_0}'")]
+    Comment(String),
+}
+
+impl ManagedMarker {
+    fn matches(&self, record: &CloudflareDnsRecord) -> bool {
+        match self {
+            Self::Tag(name) => record
+                .tags
+                .iter()
+                .any(|tag| tag == name || tag.split_once(':').is_some_and(|(tag, _)| tag == name)),
+            Self::Comment(marker) => record
+                .comment
+                .as_deref()
+                .is_some_and(|it| it.contains(marker.as_str())),
+        }
+    }
 }
 
 impl CloudflareProvider {
+    /// Creates a provider talking to the API at `api_base`, usually [`DEFAULT_API_BASE`].
+    pub fn new(api_token: String, api_base: impl Into<String>) -> Self {
+        Self {
+            api_token,
+            api_base: api_base.into().trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            default_options: RecordOptions::default(),
+            usage: ApiUsage::new("cloudflare", API_RATE_LIMIT, DEFAULT_RATE_LIMIT_WARNING),
+            managed_marker: None,
+        }
+    }
+
+    /// Only lists and writes records carrying `marker`.
+    pub fn with_managed_marker(mut self, marker: ManagedMarker) -> Self {
+        self.managed_marker = Some(marker);
+        self
+    }
+
+    /// Warns once the calls of the last 5 minutes exceed `fraction` of [`API_RATE_LIMIT`].
+    pub fn with_rate_limit_warning(mut self, fraction: f64) -> Self {
+        self.usage = ApiUsage::new("cloudflare", API_RATE_LIMIT, fraction);
+        self
+    }
+
+    /// Sends `request`, counting it in the API usage.
+    async fn send(&self, request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+        let response = request.bearer_auth(&self.api_token).send().await?;
+        self.usage.record(&response);
+        Ok(response)
+    }
+
     pub fn new_from_env() -> Result<Self, Report> {
         ensure_env_vars(&["CLOUDFLARE_API_TOKEN"])?;
-        let api_token = std::env::var("CLOUDFLARE_API_TOKEN")
+        let api_token = settings::var("CLOUDFLARE_API_TOKEN")
             .context("CLOUDFLARE_API_TOKEN environment variable not set")?;
 
+        let ttl = settings::var("CLOUDFLARE_TTL")
+            .ok()
+            .map(|it| it.trim().parse::<u32>())
+            .transpose()
+            .context("Invalid CLOUDFLARE_TTL environment variable")?;
+        let proxied = settings::var("CLOUDFLARE_PROXIED")
+            .ok()
+            .map(|it| it.trim().parse::<bool>())
+            .transpose()
+            .context("Invalid CLOUDFLARE_PROXIED environment variable")?;
+
+        // Mostly useful for API gateways and testing against a mock server
+        let api_base = settings::var("CLOUDFLARE_API_BASE")
+            .ok()
+            .filter(|it| !it.trim().is_empty())
+            .unwrap_or(DEFAULT_API_BASE.to_string());
+
+        let rate_limit_warning = settings::var("CLOUDFLARE_RATE_LIMIT_WARNING")
+            .ok()
+            .map(|it| it.trim().parse::<f64>())
+            .transpose()
+            .context("Invalid CLOUDFLARE_RATE_LIMIT_WARNING environment variable")?
+            .unwrap_or(DEFAULT_RATE_LIMIT_WARNING);
+        if !(rate_limit_warning > 0.0 && rate_limit_warning <= 1.0) {
+            bail!("CLOUDFLARE_RATE_LIMIT_WARNING must be a fraction between 0 and 1");
+        }
+
+        let tag = settings::var("CLOUDFLARE_MANAGED_TAG")
+            .ok()
+            .map(|it| it.trim().to_string())
+            .filter(|it| !it.is_empty());
+        let comment = settings::var("CLOUDFLARE_MANAGED_COMMENT")
+            .ok()
+            .map(|it| it.trim().to_string())
+            .filter(|it| !it.is_empty());
+        let managed_marker = match (tag, comment) {
+            (Some(_), Some(_)) => {
+                bail!(
+                    "Only one of CLOUDFLARE_MANAGED_TAG and CLOUDFLARE_MANAGED_COMMENT can be set"
+                )
+            }
+            (Some(tag), None) => Some(ManagedMarker::Tag(tag)),
+            (None, Some(comment)) => Some(ManagedMarker::Comment(comment)),
+            (None, None) => None,
+        };
+
         Ok(Self {
-            api_token,
-            client: reqwest::Client::new(),
+            default_options: RecordOptions { ttl, proxied },
+            managed_marker,
+            ..Self::new(api_token, api_base.trim()).with_rate_limit_warning(rate_limit_warning)
         })
     }
 
+    async fn get_record(
+        &self,
+        origin: &Origin,
+        zone_id: &str,
+        record_id: &RecordId,
+    ) -> Result<CloudflareDnsRecord, Report> {
+        let response = self
+            .send(self.client.get(format!(
+                "{}/zones/{}/dns_records/{}",
+                self.api_base, zone_id, record_id.0
+            )))
+            .await
+            .context("Reading DNS record from Cloudflare")
+            .attach(format!("origin: '{origin}'"))
+            .attach(format!("record_id: '{record_id}'"))?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(report!(RecordNotFound)
+                .attach(format!("origin: '{origin}'"))
+                .attach(format!("record_id: '{record_id}'"))
+                .into_dynamic());
+        }
+        if !response.status().is_success() {
+            return Err(api_error(
+                response,
+                "Failed to read DNS record from Cloudflare",
+                "DNS:Read",
+                origin,
+            )
+            .await
+            .attach(format!("record_id: '{record_id}'")));
+        }
+
+        Ok(response
+            .json::<CloudflareRecordResponse>()
+            .await
+            .context("Parsing Cloudflare DNS record response")
+            .attach(format!("origin: '{origin}'"))?
+            .result)
+    }
+
+    /// Fails unless `record` carries the managed marker, if one is set.
+    fn check_managed(&self, record: &CloudflareDnsRecord) -> Result<(), Report> {
+        match &self.managed_marker {
+            Some(marker) if !marker.matches(record) => {
+                Err(report!("Refusing to touch a record without the {marker}")
+                    .attach(format!(
+                        "record: {} {} ({})",
+                        record.r#type, record.name, record.id
+                    ))
+                    .into_dynamic())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// All records of the zone, regardless of the managed marker.
+    async fn list_all_records(&self, origin: &Origin) -> Result<Vec<CloudflareDnsRecord>, Report> {
+        let zone_id = self.get_zone_id(origin).await?;
+        let response = self
+            .send(
+                self.client
+                    .get(format!("{}/zones/{}/dns_records", self.api_base, zone_id))
+                    .query(&[("per_page", "10000")]),
+            )
+            .await
+            .context("Listing DNS records from Cloudflare")
+            .attach(format!("origin: '{origin}'"))?;
+
+        if !response.status().is_success() {
+            return Err(api_error(
+                response,
+                "Failed to list DNS records from Cloudflare",
+                "DNS:Read",
+                origin,
+            )
+            .await);
+        }
+
+        Ok(response
+            .json::<CloudflareListRecordsResponse>()
+            .await
+            .context("Parsing Cloudflare DNS records response")
+            .attach(format!("origin: '{origin}'"))?
+            .result)
+    }
+
     async fn get_zone_id(&self, origin: &Origin) -> Result<String, Report> {
         let response = self
-            .client
-            .get("https://api.cloudflare.com/client/v4/zones")
-            .bearer_auth(&self.api_token)
-            .query(&[("domain", &origin.0)])
-            .send()
+            .send(
+                self.client
+                    .get(format!("{}/zones", self.api_base))
+                    .query(&[("domain", &origin.0)]),
+            )
             .await
             .context("Listing records")
             .attach(format!("origin: '{origin}'"))?;
 
+        if !response.status().is_success() {
+            return Err(api_error(
+                response,
+                "Failed to list zones from Cloudflare",
+                "Zone:Read",
+                origin,
+            )
+            .await);
+        }
+
         let response = response
             .json::<CloudflareZoneResponse>()
             .await
@@ -50,7 +276,11 @@ impl CloudflareProvider {
         }
 
         let Some(zone_result) = response.result.into_iter().find(|it| it.name == origin.0) else {
-            return Err(report!("No zone found for origin").attach(format!("origin: '{origin}'")));
+            return Err(report!("No zone found for origin")
+                .attach(format!("origin: '{origin}'"))
+                .attach(format!(
+                    "hint: the zone does not exist, or the API token lacks Zone:Read on {origin}"
+                )));
         };
 
         Ok(zone_result.id.clone())
@@ -64,64 +294,57 @@ impl DnsProvider for CloudflareProvider {
     }
 
     async fn list_records(&self, origin: &Origin) -> Result<Vec<DnsEntry>, Report> {
-        let zone_id = self.get_zone_id(origin).await?;
-        let response = self
-            .client
-            .get(format!(
-                "https://api.cloudflare.com/client/v4/zones/{}/dns_records",
-                zone_id
-            ))
-            .bearer_auth(&self.api_token)
-            .query(&[("per_page", "10000")])
-            .send()
-            .await
-            .context("Listing DNS records from Cloudflare")
-            .attach(format!("origin: '{origin}'"))?;
-
-        if !response.status().is_success() {
-            return Err(report!("Failed to list DNS records from Cloudflare")
-                .attach(format!("origin: '{origin}'"))
-                .attach(format!("status: {}", response.status()))
-                .attach(format!(
-                    "response: {:?}",
-                    response
-                        .text()
-                        .await
-                        .unwrap_or("<Response reading failed>".to_string())
-                )));
+        let records = self.list_all_records(origin).await?;
+        let total = records.len();
+        let managed = records
+            .into_iter()
+            .filter(|it| self.check_managed(it).is_ok())
+            .collect::<Vec<_>>();
+        if let Some(marker) = &self.managed_marker {
+            debug!(%marker, total, managed = managed.len(), "Skipping unmarked records");
         }
 
-        let response = response
-            .json::<CloudflareListRecordsResponse>()
-            .await
-            .context("Parsing Cloudflare DNS records response")
-            .attach(format!("origin: '{origin}'"))?;
-
-        Ok(response
-            .result
-            .into_iter()
-            .filter_map(|it| it.into())
-            .collect())
+        Ok(managed.into_iter().filter_map(|it| it.into()).collect())
     }
 
     async fn update_record(
         &self,
         origin: &Origin,
-        record_id: &RecordId,
+        record: &RecordRef,
+        expected: Option<&str>,
         new_content: &str,
+        options: &RecordOptions,
     ) -> Result<(), Report> {
+        record.check_content(new_content)?;
+        let record_id = &record.id;
         let zone_id = self.get_zone_id(origin).await?;
+        if expected.is_some() || self.managed_marker.is_some() {
+            // Cloudflare has no conditional updates, so an expected content is only checked by
+            // reading the record. The update flow does not ask for that
+            let current = self.get_record(origin, &zone_id, record_id).await?;
+            self.check_managed(&current)?;
+            RecordConflict::check(expected, &current.content)
+                .attach(format!("record_id: '{record_id}'"))?;
+        }
+        let options = options.or(self.default_options);
+        let mut body = json!({ "content": new_content });
+        // Unset values are left out, so the record keeps its current ones
+        if let Some(ttl) = options.ttl {
+            body["ttl"] = json!(ttl);
+        }
+        if let Some(proxied) = options.proxied {
+            body["proxied"] = json!(proxied);
+        }
+
         let response = self
-            .client
-            .patch(format!(
-                "https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}",
-                zone_id, record_id.0
-            ))
-            .bearer_auth(&self.api_token)
-            .json(&json!({
-                "content": new_content
-            }))
-            .send()
+            .send(
+                self.client
+                    .patch(format!(
+                        "{}/zones/{}/dns_records/{}",
+                        self.api_base, zone_id, record_id.0
+                    ))
+                    .json(&body),
+            )
             .await
             .context("Updating DNS record in Cloudflare")
             .attach(format!("origin: '{origin}'"))
@@ -129,34 +352,230 @@ impl DnsProvider for CloudflareProvider {
 
         if response.status().is_success() {
             Ok(())
+        } else if response.status() == StatusCode::NOT_FOUND {
+            Err(report!(RecordNotFound)
+                .attach(format!("origin: '{origin}'"))
+                .attach(format!("record_id: '{record_id}'"))
+                .into_dynamic())
         } else {
-            Err(report!("Failed to update DNS record in Cloudflare")
+            Err(api_error(
+                response,
+                "Failed to update DNS record in Cloudflare",
+                "DNS:Edit",
+                origin,
+            )
+            .await
+            .attach(format!("record_id: '{record_id}'")))
+        }
+    }
+
+    async fn delete_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report> {
+        let zone_id = self.get_zone_id(origin).await?;
+        if self.managed_marker.is_some() {
+            let current = self.get_record(origin, &zone_id, record_id).await?;
+            self.check_managed(&current)?;
+        }
+        let response = self
+            .send(self.client.delete(format!(
+                "{}/zones/{}/dns_records/{}",
+                self.api_base, zone_id, record_id.0
+            )))
+            .await
+            .context("Deleting DNS record in Cloudflare")
+            .attach(format!("origin: '{origin}'"))
+            .attach(format!("record_id: '{record_id}'"))?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else if response.status() == StatusCode::NOT_FOUND {
+            Err(report!(RecordNotFound)
                 .attach(format!("origin: '{origin}'"))
                 .attach(format!("record_id: '{record_id}'"))
-                .attach(format!("status: {}", response.status()))
-                .attach(format!(
-                    "response: {:?}",
-                    response
-                        .text()
-                        .await
-                        .unwrap_or("<Response reading failed>".to_string())
-                )))
+                .into_dynamic())
+        } else {
+            Err(api_error(
+                response,
+                "Failed to delete DNS record in Cloudflare",
+                "DNS:Edit",
+                origin,
+            )
+            .await
+            .attach(format!("record_id: '{record_id}'")))
         }
     }
 
+    fn default_options(&self) -> RecordOptions {
+        self.default_options
+    }
+
     async fn validate(&self, origin: &Origin) -> Result<(), Report> {
         info!("Listing all DNS records...");
         let zone_dns_records = self
-            .list_records(origin)
+            .list_all_records(origin)
             .await
             .context("Failed to list DNS records on startup")
             .attach("I think you probably want to fix that before I start...")
             .attach(format!("Origin: {}", origin.0))?;
 
         info!("Found {} DNS records", zone_dns_records.len());
+        if let Some(marker) = &self.managed_marker {
+            let managed = zone_dns_records
+                .iter()
+                .filter(|it| marker.matches(it))
+                .count();
+            info!("{managed} of them carry the {marker} and are managed by this server");
+        }
+
+        Ok(())
+    }
+
+    fn secrets(&self) -> Vec<String> {
+        vec![self.api_token.clone()]
+    }
+
+    fn api_usage(&self) -> Option<ApiUsageSnapshot> {
+        Some(self.usage.snapshot())
+    }
+
+    async fn list_all_txt_records(&self, origin: &Origin) -> Result<Vec<TxtRecord>, Report> {
+        Ok(self
+            .list_all_records(origin)
+            .await?
+            .into_iter()
+            .filter(|it| it.r#type == "TXT" && self.check_managed(it).is_ok())
+            .map(|it| TxtRecord {
+                id: RecordId(it.id),
+                name: it.name,
+                // Cloudflare returns the text quoted
+                content: it.content.trim_matches('"').to_string(),
+            })
+            .collect())
+    }
+
+    async fn create_txt_record(
+        &self,
+        origin: &Origin,
+        name: &str,
+        content: &str,
+    ) -> Result<(), Report> {
+        let zone_id = self.get_zone_id(origin).await?;
+        let mut body = json!({
+            "type": "TXT",
+            "name": name,
+            "content": format!("\"{content}\""),
+            "ttl": 1,
+        });
+        // The record has to carry the marker, or it would be invisible to the server itself
+        match &self.managed_marker {
+            Some(ManagedMarker::Tag(tag)) => body["tags"] = json!([tag]),
+            Some(ManagedMarker::Comment(comment)) => body["comment"] = json!(comment),
+            None => {}
+        }
+
+        let response = self
+            .send(
+                self.client
+                    .post(format!("{}/zones/{}/dns_records", self.api_base, zone_id))
+                    .json(&body),
+            )
+            .await
+            .context("Creating TXT record in Cloudflare")
+            .attach(format!("origin: '{origin}'"))
+            .attach(format!("name: '{name}'"))?;
 
+        if !response.status().is_success() {
+            return Err(api_error(
+                response,
+                "Failed to create TXT record in Cloudflare",
+                "DNS:Edit",
+                origin,
+            )
+            .await
+            .attach(format!("name: '{name}'")));
+        }
         Ok(())
     }
+
+    /// Deletes like [`delete_record`](Self::delete_record), which does not care about the type.
+    async fn delete_txt_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report> {
+        self.delete_record(origin, record_id).await
+    }
+}
+
+/// Hints for error codes of the Cloudflare API that are usually caused by the API token.
+/// `{permission}` and `{zone}` are replaced by the permission the request needed and the zone.
+const ERROR_HINTS: &[(u32, &str)] = &[
+    (
+        6003,
+        "the request headers are invalid, check CLOUDFLARE_API_TOKEN for stray quotes or whitespace",
+    ),
+    (
+        6111,
+        "the Authorization header is malformed, check CLOUDFLARE_API_TOKEN for stray quotes or whitespace",
+    ),
+    (
+        9109,
+        "the API token is invalid or expired, create a new one in the Cloudflare dashboard",
+    ),
+    (
+        10000,
+        "the token is valid but lacks {permission} on zone {zone}, edit the token's permissions in the Cloudflare dashboard",
+    ),
+];
+
+/// Builds the report for a failed API call, with hints for the errors in the response.
+async fn api_error(
+    response: reqwest::Response,
+    message: &'static str,
+    permission: &str,
+    origin: &Origin,
+) -> Report {
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .unwrap_or("<Response reading failed>".to_string());
+
+    let mut report = report!(message)
+        .attach(format!("origin: '{origin}'"))
+        .attach(format!("status: {status}"));
+    let errors = serde_json::from_str::<CloudflareErrorResponse>(&body)
+        .map(|it| it.errors)
+        .unwrap_or_default();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        report = report.attach(format!(
+            "hint: rate limited, see the API usage on the status page and the \
+             dyndns_provider_api_calls metric to tell whether this server used up the \
+             {API_RATE_LIMIT} calls per 5 minutes"
+        ));
+    }
+    for error in &errors {
+        report = report.attach(format!("error {}: {}", error.code, error.message));
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            continue;
+        }
+        if let Some((_, hint)) = ERROR_HINTS.iter().find(|(code, _)| *code == error.code) {
+            let hint = hint
+                .replace("{permission}", permission)
+                .replace("{zone}", &origin.0);
+            report = report.attach(format!("hint: {hint}"));
+        }
+    }
+    if errors.is_empty() {
+        report = report.attach(format!("response: {body:?}"));
+    }
+    report.into_dynamic()
+}
+
+#[derive(serde::Deserialize)]
+struct CloudflareErrorResponse {
+    errors: Vec<CloudflareError>,
+}
+
+#[derive(serde::Deserialize)]
+struct CloudflareError {
+    code: u32,
+    message: String,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -175,12 +594,22 @@ struct CloudflareListRecordsResponse {
     result: Vec<CloudflareDnsRecord>,
 }
 
+#[derive(serde::Deserialize, Clone)]
+struct CloudflareRecordResponse {
+    result: CloudflareDnsRecord,
+}
+
 #[derive(serde::Deserialize, Clone)]
 struct CloudflareDnsRecord {
     id: String,
     r#type: String,
     name: String,
     content: String,
+    ttl: Option<u32>,
+    proxied: Option<bool>,
+    #[serde(default)]
+    tags: Vec<String>,
+    comment: Option<String>,
 }
 
 impl From<CloudflareDnsRecord> for Option<DnsEntry> {
@@ -193,6 +622,80 @@ impl From<CloudflareDnsRecord> for Option<DnsEntry> {
             id: RecordId(record.id),
             name: record.name,
             content: record.content,
+            ttl: record.ttl,
+            proxied: record.proxied,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(tags: &[&str], comment: Option<&str>) -> CloudflareDnsRecord {
+        CloudflareDnsRecord {
+            id: "record-a".to_string(),
+            r#type: "A".to_string(),
+            name: "nas.foobar.de".to_string(),
+            content: "192.0.2.1".to_string(),
+            ttl: Some(300),
+            proxied: Some(false),
+            tags: tags.iter().map(|it| it.to_string()).collect(),
+            comment: comment.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn tag_matches_by_name_among_other_tags() {
+        let marker = ManagedMarker::Tag("dyndns".to_string());
+        assert!(marker.matches(&record(&["dyndns"], None)));
+        assert!(marker.matches(&record(&["team:infra", "dyndns:home"], None)));
+        assert!(marker.matches(&record(&["terraform", "dyndns", "env:prod"], None)));
+
+        assert!(!marker.matches(&record(&[], None)));
+        assert!(!marker.matches(&record(&["dyndnsx", "x-dyndns"], None)));
+        assert!(!marker.matches(&record(&["owner:dyndns"], None)));
+        // The comment does not count for a tag marker
+        assert!(!marker.matches(&record(&["terraform"], Some("dyndns"))));
+    }
+
+    #[test]
+    fn tag_with_value_matches_exactly() {
+        let marker = ManagedMarker::Tag("dyndns:home".to_string());
+        assert!(marker.matches(&record(&["other", "dyndns:home"], None)));
+        assert!(!marker.matches(&record(&["dyndns:office"], None)));
+        assert!(!marker.matches(&record(&["dyndns"], None)));
+    }
+
+    #[test]
+    fn comment_marker_is_contained_in_the_comment() {
+        let marker = ManagedMarker::Comment("[dyndns]".to_string());
+        assert!(marker.matches(&record(&[], Some("[dyndns]"))));
+        assert!(marker.matches(&record(&["dyndns"], Some("NAS [dyndns] since 2026"))));
+
+        assert!(!marker.matches(&record(&["[dyndns]"], None)));
+        assert!(!marker.matches(&record(&[], Some("dyndns"))));
+        assert!(!marker.matches(&record(&[], Some(""))));
+    }
+
+    #[test]
+    fn unmarked_records_are_refused() {
+        let provider = CloudflareProvider::new("token".to_string(), DEFAULT_API_BASE)
+            .with_managed_marker(ManagedMarker::Tag("dyndns".to_string()));
+
+        assert!(
+            provider
+                .check_managed(&record(&["x", "dyndns"], None))
+                .is_ok()
+        );
+        let error = provider
+            .check_managed(&record(&["terraform"], None))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("without the tag 'dyndns'"), "{error}");
+        assert!(error.contains("A nas.foobar.de (record-a)"), "{error}");
+
+        let unmarked = CloudflareProvider::new("token".to_string(), DEFAULT_API_BASE);
+        assert!(unmarked.check_managed(&record(&[], None)).is_ok());
+    }
+}
@@ -0,0 +1,589 @@
+//! A provider switching to a secondary provider mirroring the zone while the primary one fails.
+//!
+//! Enabled by listing providers joined by `+` in `PROVIDERS`, e.g. `failover:cloudflare+netcup`.
+//! Calls go to the first healthy provider. When it fails for other reasons than the record (e.g.
+//! a conflict), the next one takes over and a background task checks the primary every
+//! `check_interval`. Once it validates again, the updates it missed are replayed to it before
+//! switching back.
+
+use super::api_usage::ApiUsageSnapshot;
+use super::chaos::ChaosProvider;
+use super::{
+    ContentTypeMismatch, DnsEntry, DnsProvider, DnsRecordType, Origin, RecordConflict, RecordId,
+    RecordNotFound, RecordOptions, RecordRef, TxtRecord, same_content,
+};
+use async_trait::async_trait;
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, report};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+pub const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A record written to a secondary provider while failed over.
+type MissedKey = (Origin, String, DnsRecordType);
+
+pub struct FailoverProvider {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    /// The providers in order of preference, the first one is the primary.
+    providers: Vec<Box<dyn DnsProvider + Send + Sync>>,
+    /// The index of the provider calls go to.
+    active: AtomicUsize,
+    check_interval: Duration,
+    /// Whether the task switching back to the primary runs.
+    recovering: AtomicBool,
+    /// The origins calls were made for, which the primary is validated for before switching back.
+    origins: Mutex<HashSet<Origin>>,
+    /// The content and options of the records the primary missed.
+    missed: Mutex<HashMap<MissedKey, (String, RecordOptions)>>,
+}
+
+impl FailoverProvider {
+    pub fn new(
+        providers: Vec<Box<dyn DnsProvider + Send + Sync>>,
+        check_interval: Duration,
+    ) -> Self {
+        assert!(
+            !providers.is_empty(),
+            "failover needs at least one provider"
+        );
+        Self {
+            shared: Arc::new(Shared {
+                providers,
+                active: AtomicUsize::new(0),
+                check_interval,
+                recovering: AtomicBool::new(false),
+                origins: Mutex::default(),
+                missed: Mutex::default(),
+            }),
+        }
+    }
+
+    /// The provider calls currently go to.
+    pub fn active(&self) -> &(dyn DnsProvider + Send + Sync) {
+        self.shared.providers[self.shared.active()].as_ref()
+    }
+}
+
+impl Shared {
+    fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    fn remember(&self, origin: &Origin) {
+        let mut origins = self.origins.lock().expect("mutex poisoned");
+        if !origins.contains(origin) {
+            origins.insert(origin.clone());
+        }
+    }
+
+    /// Whether the error of the provider at `index` should make the next one take over.
+    fn should_fail_over(&self, index: usize, error: &Report) -> bool {
+        index + 1 < self.providers.len() && !is_record_error(error)
+    }
+
+    /// Switches from the provider at `from` to the one at `to`, unless another call did already,
+    /// and starts checking the primary.
+    fn fail_over(self: &Arc<Self>, from: usize, to: usize, error: &Report) {
+        if self
+            .active
+            .compare_exchange(from, to, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            warn!(
+                from = self.providers[from].name(),
+                to = self.providers[to].name(),
+                error = %error.format_current_context(),
+                "Provider failed, failing over to the next one"
+            );
+        }
+        if !self.recovering.swap(true, Ordering::SeqCst) {
+            tokio::spawn(self.clone().recover());
+        }
+    }
+
+    /// Checks the primary every `check_interval` until it is healthy and caught up again.
+    async fn recover(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(self.check_interval).await;
+            match self.try_switch_back().await {
+                Ok(replayed) => {
+                    info!(
+                        provider = self.providers[0].name(),
+                        replayed, "Primary provider recovered, switched back to it"
+                    );
+                    break;
+                }
+                Err(e) => {
+                    warn!(
+                        provider = self.providers[0].name(),
+                        error = %e.format_current_context(),
+                        "Primary provider is still failing"
+                    );
+                }
+            }
+        }
+        self.recovering.store(false, Ordering::SeqCst);
+    }
+
+    /// Validates the primary, replays the updates it missed and switches back to it, returning
+    /// the number of replayed updates.
+    async fn try_switch_back(&self) -> Result<usize, Report> {
+        let primary = &self.providers[0];
+        let origins = self.origins.lock().expect("mutex poisoned").clone();
+        for origin in &origins {
+            primary.validate(origin).await?;
+        }
+
+        let mut replayed = 0;
+        loop {
+            let missed = {
+                let missed = self.missed.lock().expect("mutex poisoned");
+                // Updates check the active provider under the lock, so none can be missed after
+                if missed.is_empty() {
+                    self.active.store(0, Ordering::SeqCst);
+                    return Ok(replayed);
+                }
+                missed.clone()
+            };
+            for ((origin, name, typ), (content, options)) in missed {
+                let records = primary.list_records(&origin).await?;
+                match records.iter().find(|it| it.name == name && it.typ == typ) {
+                    Some(record) if same_content(&record.content, &content) => {}
+                    Some(record) => {
+                        primary
+                            .update_record(&origin, &record.to_ref(), None, &content, &options)
+                            .await
+                            .context("Failed to replay a missed update")
+                            .attach(format!("For domain '{name}'"))
+                            .attach(format!("For {typ} record"))?;
+                        replayed += 1;
+                    }
+                    None => warn!(
+                        provider = primary.name(),
+                        domain = %name,
+                        record_type = %typ,
+                        "Primary provider has no record for a missed update, skipping it"
+                    ),
+                }
+                let key = (origin, name, typ);
+                let mut missed = self.missed.lock().expect("mutex poisoned");
+                // Newer updates are replayed in the next round
+                if missed.get(&key).is_some_and(|(it, _)| *it == content) {
+                    missed.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Updates the record of the provider at `index` with the name and type of `record`, which
+    /// may have been listed by another provider.
+    async fn update_resolved(
+        &self,
+        index: usize,
+        origin: &Origin,
+        record: &RecordRef,
+        expected: Option<&str>,
+        new_content: &str,
+        options: &RecordOptions,
+    ) -> Result<(), Report> {
+        let provider = &self.providers[index];
+        let records = provider.list_records(origin).await?;
+        let Some(resolved) = records
+            .iter()
+            .find(|it| it.name == record.name && it.typ == record.typ)
+        else {
+            return Err(report!(RecordNotFound)
+                .attach(format!("Provider: {}", provider.name()))
+                .attach(format!("record: {} {}", record.typ, record.name))
+                .into_dynamic());
+        };
+        provider
+            .update_record(origin, &resolved.to_ref(), expected, new_content, options)
+            .await
+    }
+}
+
+/// Whether `error` is about the record instead of the provider, which the next provider would
+/// fail with as well.
+fn is_record_error(error: &Report) -> bool {
+    RecordConflict::find(error).is_some()
+        || RecordNotFound::is_cause_of(error)
+        || ContentTypeMismatch::is_cause_of(error)
+}
+
+#[async_trait]
+impl DnsProvider for FailoverProvider {
+    /// The name of the primary, so origin mappings and the state kept per provider (e.g. the
+    /// missing records) stay the same while failed over.
+    fn name(&self) -> &'static str {
+        self.shared.providers[0].name()
+    }
+
+    async fn list_records(&self, origin: &Origin) -> Result<Vec<DnsEntry>, Report> {
+        let shared = &self.shared;
+        shared.remember(origin);
+        let mut index = shared.active();
+        loop {
+            match shared.providers[index].list_records(origin).await {
+                Err(e) if shared.should_fail_over(index, &e) => {
+                    shared.fail_over(index, index + 1, &e);
+                    index += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn update_record(
+        &self,
+        origin: &Origin,
+        record: &RecordRef,
+        expected: Option<&str>,
+        new_content: &str,
+        options: &RecordOptions,
+    ) -> Result<(), Report> {
+        let shared = &self.shared;
+        shared.remember(origin);
+        let key = (origin.clone(), record.name.clone(), record.typ.clone());
+        let mut index = shared.active();
+        loop {
+            // The record may have been listed by the primary, the others use different IDs
+            let result = match index {
+                0 => {
+                    shared.providers[0]
+                        .update_record(origin, record, expected, new_content, options)
+                        .await
+                }
+                _ => {
+                    shared
+                        .update_resolved(index, origin, record, expected, new_content, options)
+                        .await
+                }
+            };
+            match result {
+                Ok(()) => {
+                    let mut missed = shared.missed.lock().expect("mutex poisoned");
+                    if index == 0 {
+                        missed.remove(&key);
+                        return Ok(());
+                    }
+                    if shared.active() != 0 {
+                        missed.insert(key.clone(), (new_content.to_string(), *options));
+                        return Ok(());
+                    }
+                    // The primary was switched back to while writing, so it missed this update
+                    index = 0;
+                }
+                Err(e) if shared.should_fail_over(index, &e) => {
+                    shared.fail_over(index, index + 1, &e);
+                    index += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Deletes from the active provider only, deletions are not replayed to the primary.
+    async fn delete_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report> {
+        self.shared.remember(origin);
+        self.active().delete_record(origin, record_id).await
+    }
+
+    /// Succeeds if any provider validates, and switches to the first one that does.
+    async fn validate(&self, origin: &Origin) -> Result<(), Report> {
+        let shared = &self.shared;
+        shared.remember(origin);
+        let mut errors = Vec::new();
+        for (index, provider) in shared.providers.iter().enumerate() {
+            match provider.validate(origin).await {
+                Ok(()) => {
+                    let active = shared.active();
+                    // Switching back is left to the recovery, which replays missed updates
+                    if let Some(error) = errors.last()
+                        && index > active
+                    {
+                        shared.fail_over(active, index, error);
+                    }
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!(
+                        provider = provider.name(),
+                        error = %e.format_current_context(),
+                        "Failover provider failed validation"
+                    );
+                    errors.push(e);
+                }
+            }
+        }
+        let mut report = report!("All failover providers failed validation");
+        for error in errors {
+            report = report.attach(error.to_string());
+        }
+        Err(report.into_dynamic())
+    }
+
+    fn supports_compare_and_set(&self) -> bool {
+        self.shared
+            .providers
+            .iter()
+            .all(|it| it.supports_compare_and_set())
+    }
+
+    fn default_options(&self) -> RecordOptions {
+        self.active().default_options()
+    }
+
+    fn secrets(&self) -> Vec<String> {
+        self.shared
+            .providers
+            .iter()
+            .flat_map(|it| it.secrets())
+            .collect()
+    }
+
+    fn api_usage(&self) -> Option<ApiUsageSnapshot> {
+        self.active().api_usage()
+    }
+
+    fn chaos(&self) -> Option<&ChaosProvider> {
+        self.shared.providers.iter().find_map(|it| it.chaos())
+    }
+
+    /// TXT records only live at the active provider, they are neither failed over nor replayed.
+    async fn list_all_txt_records(&self, origin: &Origin) -> Result<Vec<TxtRecord>, Report> {
+        self.active().list_all_txt_records(origin).await
+    }
+
+    async fn create_txt_record(
+        &self,
+        origin: &Origin,
+        name: &str,
+        content: &str,
+    ) -> Result<(), Report> {
+        self.active().create_txt_record(origin, name, content).await
+    }
+
+    async fn delete_txt_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report> {
+        self.active().delete_txt_record(origin, record_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::memory::MemoryProvider;
+
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    /// A memory provider the test keeps a handle to, whose updates take `delay`.
+    struct Handle {
+        name: &'static str,
+        inner: Arc<MemoryProvider>,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl DnsProvider for Handle {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn list_records(&self, origin: &Origin) -> Result<Vec<DnsEntry>, Report> {
+            self.inner.list_records(origin).await
+        }
+
+        async fn update_record(
+            &self,
+            origin: &Origin,
+            record: &RecordRef,
+            expected: Option<&str>,
+            new_content: &str,
+            options: &RecordOptions,
+        ) -> Result<(), Report> {
+            tokio::time::sleep(self.delay).await;
+            self.inner
+                .update_record(origin, record, expected, new_content, options)
+                .await
+        }
+
+        async fn delete_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report> {
+            self.inner.delete_record(origin, record_id).await
+        }
+
+        async fn validate(&self, origin: &Origin) -> Result<(), Report> {
+            self.inner.validate(origin).await
+        }
+
+        fn supports_compare_and_set(&self) -> bool {
+            true
+        }
+    }
+
+    fn record(id: &str, content: &str) -> DnsEntry {
+        DnsEntry {
+            typ: DnsRecordType::AAAA,
+            id: RecordId(id.to_string()),
+            name: "nas.foobar.de".to_string(),
+            content: content.to_string(),
+            ttl: None,
+            proxied: None,
+        }
+    }
+
+    fn origin() -> Origin {
+        Origin::parse("foobar.de").unwrap()
+    }
+
+    fn content(provider: &MemoryProvider) -> String {
+        provider.records()[0].content.clone()
+    }
+
+    /// A failover from a primary to a secondary, whose updates take `secondary_delay`.
+    fn failover(
+        secondary_delay: Duration,
+    ) -> (FailoverProvider, Arc<MemoryProvider>, Arc<MemoryProvider>) {
+        let primary = Arc::new(MemoryProvider::new(vec![record("primary-id", "::1")]));
+        let secondary = Arc::new(MemoryProvider::new(vec![record("secondary-id", "::1")]));
+        let provider = FailoverProvider::new(
+            vec![
+                Box::new(Handle {
+                    name: "primary",
+                    inner: primary.clone(),
+                    delay: Duration::ZERO,
+                }),
+                Box::new(Handle {
+                    name: "secondary",
+                    inner: secondary.clone(),
+                    delay: secondary_delay,
+                }),
+            ],
+            INTERVAL,
+        );
+        (provider, primary, secondary)
+    }
+
+    async fn update(provider: &FailoverProvider, content: &str) -> Result<(), Report> {
+        provider
+            .update_record(
+                &origin(),
+                &record("primary-id", "::1").to_ref(),
+                None,
+                content,
+                &RecordOptions::default(),
+            )
+            .await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn updates_go_to_the_primary_while_it_works() {
+        let (provider, primary, secondary) = failover(Duration::ZERO);
+
+        update(&provider, "::2").await.unwrap();
+
+        assert_eq!(content(&primary), "::2");
+        assert_eq!(content(&secondary), "::1");
+        assert_eq!(provider.active().name(), "primary");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn updates_go_to_the_secondary_while_the_primary_fails() {
+        let (provider, primary, secondary) = failover(Duration::ZERO);
+        primary.fail(true);
+
+        update(&provider, "::2").await.unwrap();
+
+        assert_eq!(content(&primary), "::1");
+        assert_eq!(content(&secondary), "::2");
+        assert_eq!(provider.active().name(), "secondary");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn name_stays_the_primary_while_failed_over() {
+        let (provider, primary, _) = failover(Duration::ZERO);
+        assert_eq!(provider.name(), "primary");
+
+        primary.fail(true);
+        update(&provider, "::2").await.unwrap();
+
+        assert_eq!(provider.active().name(), "secondary");
+        assert_eq!(provider.name(), "primary");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn record_errors_do_not_fail_over() {
+        let (provider, primary, secondary) = failover(Duration::ZERO);
+
+        let error = provider
+            .update_record(
+                &origin(),
+                &record("primary-id", "::1").to_ref(),
+                Some("::3"),
+                "::2",
+                &RecordOptions::default(),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(RecordConflict::find(&error).is_some(), "{error}");
+        assert_eq!(content(&primary), "::1");
+        assert_eq!(content(&secondary), "::1");
+        assert_eq!(provider.active().name(), "primary");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn missed_updates_are_replayed_when_the_primary_recovers() {
+        let (provider, primary, _) = failover(Duration::ZERO);
+        primary.fail(true);
+        update(&provider, "::2").await.unwrap();
+        update(&provider, "::3").await.unwrap();
+
+        primary.fail(false);
+        tokio::time::sleep(INTERVAL + Duration::from_secs(1)).await;
+
+        assert_eq!(content(&primary), "::3");
+        assert_eq!(provider.active().name(), "primary");
+        assert!(provider.shared.missed.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stays_failed_over_while_the_primary_fails_validation() {
+        let (provider, primary, _) = failover(Duration::ZERO);
+        primary.fail(true);
+        update(&provider, "::2").await.unwrap();
+
+        tokio::time::sleep(INTERVAL * 3).await;
+
+        assert_eq!(content(&primary), "::1");
+        assert_eq!(provider.active().name(), "secondary");
+
+        primary.fail(false);
+        tokio::time::sleep(INTERVAL).await;
+
+        assert_eq!(content(&primary), "::2");
+        assert_eq!(provider.active().name(), "primary");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn updates_finishing_on_the_secondary_after_switching_back_reach_the_primary() {
+        let (provider, primary, secondary) = failover(INTERVAL * 2);
+        primary.fail(true);
+        update(&provider, "::2").await.unwrap();
+        primary.fail(false);
+
+        // Still writing to the secondary when the recovery switches back after the interval
+        update(&provider, "::3").await.unwrap();
+
+        assert_eq!(provider.active().name(), "primary");
+        assert_eq!(content(&secondary), "::3");
+        assert_eq!(content(&primary), "::3");
+        assert!(provider.shared.missed.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,271 @@
+use super::{
+    DnsEntry, DnsProvider, DnsRecordType, Origin, RecordConflict, RecordId, RecordNotFound,
+    RecordOptions, RecordRef, TxtRecord,
+};
+use crate::settings;
+use async_trait::async_trait;
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail, report};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+/// A provider keeping its records in memory, for embedding the server in tests. Listed as
+/// `memory` in `PROVIDERS`, it starts with the records of `MEMORY_RECORDS`.
+///
+/// All origins share the same records. With [`fail`](Self::fail), every call returns an error,
+/// to exercise the error paths. [`fail_times`](Self::fail_times) only fails the next few calls,
+/// to simulate transient errors, and [`fail_record`](Self::fail_record) the writes of one record.
+#[derive(Debug, Default)]
+pub struct MemoryProvider {
+    records: Mutex<Vec<DnsEntry>>,
+    txt_records: Mutex<Vec<TxtRecord>>,
+    next_txt_id: AtomicU64,
+    failing: AtomicBool,
+    remaining_failures: AtomicU32,
+    failing_records: Mutex<Vec<RecordId>>,
+}
+
+impl MemoryProvider {
+    pub fn new(records: Vec<DnsEntry>) -> Self {
+        Self {
+            records: Mutex::new(records),
+            txt_records: Mutex::default(),
+            next_txt_id: AtomicU64::new(1),
+            failing: AtomicBool::new(false),
+            remaining_failures: AtomicU32::new(0),
+            failing_records: Mutex::default(),
+        }
+    }
+
+    /// Creates a provider with the records in `MEMORY_RECORDS`, see [`parse_records`].
+    pub fn new_from_env() -> Result<Self, Report> {
+        let records = parse_records(&settings::var("MEMORY_RECORDS").unwrap_or_default())
+            .context("Invalid MEMORY_RECORDS environment variable")?;
+        Ok(Self::new(records))
+    }
+
+    /// Makes all further calls fail (or succeed again).
+    pub fn fail(&self, failing: bool) {
+        self.failing.store(failing, Ordering::Relaxed);
+    }
+
+    /// Makes the next `calls` calls fail.
+    pub fn fail_times(&self, calls: u32) {
+        self.remaining_failures.store(calls, Ordering::Relaxed);
+    }
+
+    /// Makes all further updates of the record with `id` fail.
+    pub fn fail_record(&self, id: RecordId) {
+        self.failing_records
+            .lock()
+            .expect("mutex poisoned")
+            .push(id);
+    }
+
+    /// A copy of the current records.
+    pub fn records(&self) -> Vec<DnsEntry> {
+        self.records.lock().expect("mutex poisoned").clone()
+    }
+
+    /// A copy of the current TXT records.
+    pub fn txt_records(&self) -> Vec<TxtRecord> {
+        self.txt_records.lock().expect("mutex poisoned").clone()
+    }
+
+    fn ensure_working(&self) -> Result<(), Report> {
+        if self.failing.load(Ordering::Relaxed) {
+            bail!("Memory provider is set to fail");
+        }
+        let transient_failure = self
+            .remaining_failures
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |it| it.checked_sub(1))
+            .is_ok();
+        if transient_failure {
+            bail!("Memory provider is set to fail this call");
+        }
+        Ok(())
+    }
+}
+
+/// Parses a comma-separated list of `<name>=<address>` pairs into records, whose type follows
+/// from the address family, e.g. `nas.foobar.de=192.0.2.1,nas.foobar.de=2001:db8::1`.
+pub fn parse_records(value: &str) -> Result<Vec<DnsEntry>, Report> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|it| !it.is_empty())
+        .enumerate()
+        .map(|(index, entry)| {
+            let Some((name, content)) = entry.split_once('=') else {
+                bail!("Record is not '<name>=<address>': '{entry}'");
+            };
+            let typ = match content.parse::<IpAddr>() {
+                Ok(IpAddr::V4(_)) => DnsRecordType::A,
+                Ok(IpAddr::V6(_)) => DnsRecordType::AAAA,
+                Err(_) => bail!("Record has no valid address: '{entry}'"),
+            };
+            Ok(DnsEntry {
+                typ,
+                id: RecordId(format!("memory-{}", index + 1)),
+                name: name.to_string(),
+                content: content.to_string(),
+                ttl: None,
+                proxied: None,
+            })
+        })
+        .collect()
+}
+
+#[async_trait]
+impl DnsProvider for MemoryProvider {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn list_records(&self, _origin: &Origin) -> Result<Vec<DnsEntry>, Report> {
+        self.ensure_working()?;
+        Ok(self.records())
+    }
+
+    async fn update_record(
+        &self,
+        origin: &Origin,
+        record: &RecordRef,
+        expected: Option<&str>,
+        new_content: &str,
+        options: &RecordOptions,
+    ) -> Result<(), Report> {
+        record.check_content(new_content)?;
+        self.ensure_working()?;
+        if self
+            .failing_records
+            .lock()
+            .expect("mutex poisoned")
+            .contains(&record.id)
+        {
+            bail!(
+                "Memory provider is set to fail updates of record {}",
+                record.id
+            );
+        }
+        let mut records = self.records.lock().expect("mutex poisoned");
+        let Some(existing) = records.iter_mut().find(|it| it.id == record.id) else {
+            return Err(report!(RecordNotFound)
+                .attach(format!("origin: '{origin}'"))
+                .attach(format!("record_id: '{}'", record.id))
+                .into_dynamic());
+        };
+        RecordConflict::check(expected, &existing.content)?;
+        existing.content = new_content.to_string();
+        existing.ttl = options.ttl.or(existing.ttl);
+        existing.proxied = options.proxied.or(existing.proxied);
+        Ok(())
+    }
+
+    async fn delete_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report> {
+        self.ensure_working()?;
+        let mut records = self.records.lock().expect("mutex poisoned");
+        let Some(index) = records.iter().position(|it| &it.id == record_id) else {
+            return Err(report!(RecordNotFound)
+                .attach(format!("origin: '{origin}'"))
+                .attach(format!("record_id: '{record_id}'"))
+                .into_dynamic());
+        };
+        records.remove(index);
+        Ok(())
+    }
+
+    fn supports_compare_and_set(&self) -> bool {
+        true
+    }
+
+    async fn validate(&self, _origin: &Origin) -> Result<(), Report> {
+        self.ensure_working()
+    }
+
+    async fn list_all_txt_records(&self, _origin: &Origin) -> Result<Vec<TxtRecord>, Report> {
+        self.ensure_working()?;
+        Ok(self.txt_records())
+    }
+
+    async fn create_txt_record(
+        &self,
+        _origin: &Origin,
+        name: &str,
+        content: &str,
+    ) -> Result<(), Report> {
+        self.ensure_working()?;
+        let id = RecordId(format!(
+            "txt-{}",
+            self.next_txt_id.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut records = self.txt_records.lock().expect("mutex poisoned");
+        records.push(TxtRecord {
+            id,
+            name: name.to_string(),
+            content: content.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn delete_txt_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report> {
+        self.ensure_working()?;
+        let mut records = self.txt_records.lock().expect("mutex poisoned");
+        let Some(index) = records.iter().position(|it| &it.id == record_id) else {
+            return Err(report!(RecordNotFound)
+                .attach(format!("origin: '{origin}'"))
+                .attach(format!("record_id: '{record_id}'"))
+                .into_dynamic());
+        };
+        records.remove(index);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_take_their_type_from_the_address() {
+        let records =
+            parse_records(" nas.foobar.de=192.0.2.1, nas.foobar.de=2001:db8::1 ,").unwrap();
+
+        let records = records
+            .iter()
+            .map(|it| {
+                (
+                    it.id.0.as_str(),
+                    it.typ.clone(),
+                    it.name.as_str(),
+                    it.content.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            [
+                ("memory-1", DnsRecordType::A, "nas.foobar.de", "192.0.2.1"),
+                (
+                    "memory-2",
+                    DnsRecordType::AAAA,
+                    "nas.foobar.de",
+                    "2001:db8::1"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_records_are_empty() {
+        assert!(parse_records("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        assert!(parse_records("nas.foobar.de").is_err());
+        assert!(parse_records("nas.foobar.de=nas").is_err());
+        assert!(parse_records("nas.foobar.de=192.0.2.1,tv.foobar.de=").is_err());
+    }
+}
@@ -1,4 +1,8 @@
-use super::{DnsEntry, DnsProvider, DnsRecordType, Origin, RecordId};
+use super::{
+    DnsEntry, DnsProvider, DnsRecordType, Origin, RecordConflict, RecordId, RecordNotFound,
+    RecordOptions, RecordRef, TxtRecord,
+};
+use crate::settings;
 use crate::types::ensure_env_vars;
 use async_trait::async_trait;
 use derive_more::Display;
@@ -77,11 +81,11 @@ impl NetcupProvider {
             "NETCUP_API_PASSWORD",
             "NETCUP_CUSTOMER_NUMBER",
         ])?;
-        let api_key = std::env::var("NETCUP_API_KEY")
+        let api_key = settings::var("NETCUP_API_KEY")
             .context("NETCUP_API_KEY environment variable not set")?;
-        let api_password = std::env::var("NETCUP_API_PASSWORD")
+        let api_password = settings::var("NETCUP_API_PASSWORD")
             .context("NETCUP_API_PASSWORD environment variable not set")?;
-        let customer_number = std::env::var("NETCUP_CUSTOMER_NUMBER")
+        let customer_number = settings::var("NETCUP_CUSTOMER_NUMBER")
             .context("NETCUP_CUSTOMER_NUMBER environment variable not set")?;
 
         Ok(Self {
@@ -180,6 +184,39 @@ impl NetcupProvider {
         Ok(())
     }
 
+    /// Applies `patch` to the record with `record_id` and writes it back.
+    async fn patch_record(
+        &self,
+        origin: &Origin,
+        record_id: &RecordId,
+        expected: Option<&str>,
+        patch: impl FnOnce(&mut NetcupDnsRecord),
+    ) -> Result<(), Report> {
+        self.ensure_logged_in().await?;
+        let mut patched_record = self
+            .list_records_netcup(origin)
+            .await?
+            .into_iter()
+            .find(|it| it.id == record_id.0)
+            .context(RecordNotFound)
+            .attach(format!("origin: '{origin}'"))
+            .attach(format!("record_id: '{record_id}'"))?;
+        // netcup has no conditional updates, but the record is read anyway, so an expected
+        // content is checked for free. The update flow does not ask for it
+        RecordConflict::check(expected, &patched_record.destination)?;
+        patch(&mut patched_record);
+
+        self.request(
+            NetcupAction::UpdateDnsRecords,
+            &[
+                ("domainname", origin.0.to_string().into()),
+                ("dnsrecordset", json!({ "dnsrecords": [patched_record]})),
+            ],
+        )
+        .await
+        .map(|_| ())
+    }
+
     async fn list_records_netcup(&self, origin: &Origin) -> Result<Vec<NetcupDnsRecord>, Report> {
         self.ensure_logged_in().await?;
 
@@ -222,33 +259,42 @@ impl DnsProvider for NetcupProvider {
     async fn update_record(
         &self,
         origin: &Origin,
-        record_id: &RecordId,
+        record: &RecordRef,
+        expected: Option<&str>,
         new_content: &str,
+        options: &RecordOptions,
     ) -> Result<(), Report> {
-        self.ensure_logged_in().await?;
-        let mut patched_record = self
-            .list_records_netcup(origin)
-            .await?
-            .into_iter()
-            .find(|it| it.id == record_id.0)
-            .context("record not found")
-            .attach(format!("origin: '{origin}'"))
-            .attach(format!("record_id: '{record_id}'"))?;
-        patched_record.destination = new_content.to_string();
-        patched_record.deleterecord = false;
-
-        self.request(
-            NetcupAction::UpdateDnsRecords,
-            &[
-                ("domainname", origin.0.to_string().into()),
-                ("dnsrecordset", json!({ "dnsrecords": [patched_record]})),
-            ],
-        )
+        record.check_content(new_content)?;
+        if *options != RecordOptions::default() {
+            debug!(
+                ?options,
+                "netcup does not support per-record TTL or proxying, ignoring"
+            );
+        }
+        self.patch_record(origin, &record.id, expected, |record| {
+            record.destination = new_content.to_string();
+            record.deleterecord = false;
+        })
         .await
         .context("failed to update DNS record for origin")
         .attach(format!("origin: '{origin}'"))
         .map_err(Report::into_dynamic)
-        .map(|_| ())
+    }
+
+    async fn delete_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report> {
+        self.patch_record(origin, record_id, None, |record| record.deleterecord = true)
+            .await
+            .context("failed to delete DNS record for origin")
+            .attach(format!("origin: '{origin}'"))
+            .map_err(Report::into_dynamic)
+    }
+
+    fn secrets(&self) -> Vec<String> {
+        let session = self.api_session_id.lock().expect("mutex poisoned");
+        [self.api_key.clone(), self.api_password.clone()]
+            .into_iter()
+            .chain(session.as_ref().map(|it| it.key.clone()))
+            .collect()
     }
 
     async fn validate(&self, origin: &Origin) -> Result<(), Report> {
@@ -264,6 +310,58 @@ impl DnsProvider for NetcupProvider {
 
         Ok(())
     }
+
+    async fn list_all_txt_records(&self, origin: &Origin) -> Result<Vec<TxtRecord>, Report> {
+        Ok(self
+            .list_records_netcup(origin)
+            .await?
+            .into_iter()
+            .filter(|it| it.typ == "TXT" && !it.deleterecord)
+            .map(|it| TxtRecord {
+                id: RecordId(it.id),
+                name: format!("{}.{}", it.hostname, origin.0),
+                content: it.destination,
+            })
+            .collect())
+    }
+
+    async fn create_txt_record(
+        &self,
+        origin: &Origin,
+        name: &str,
+        content: &str,
+    ) -> Result<(), Report> {
+        let Some(hostname) = name.strip_suffix(&format!(".{origin}")) else {
+            return Err(report!("TXT record is not in the origin")
+                .attach(format!("origin: '{origin}'"))
+                .attach(format!("name: '{name}'")));
+        };
+        self.ensure_logged_in().await?;
+        // Records without an ID are created
+        let record = json!({
+            "hostname": hostname,
+            "type": "TXT",
+            "destination": content,
+            "deleterecord": false,
+        });
+        self.request(
+            NetcupAction::UpdateDnsRecords,
+            &[
+                ("domainname", origin.0.to_string().into()),
+                ("dnsrecordset", json!({ "dnsrecords": [record]})),
+            ],
+        )
+        .await
+        .context("failed to create TXT record")
+        .attach(format!("origin: '{origin}'"))
+        .attach(format!("name: '{name}'"))
+        .map(|_| ())
+        .map_err(Report::into_dynamic)
+    }
+
+    async fn delete_txt_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report> {
+        self.delete_record(origin, record_id).await
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -305,6 +403,55 @@ impl NetcupDnsRecord {
             id: RecordId(self.id),
             content: self.destination,
             name: format!("{}.{}", self.hostname, origin.0),
+            // Netcup only has a TTL per zone
+            ttl: None,
+            proxied: None,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::ContentTypeMismatch;
+
+    fn provider() -> NetcupProvider {
+        NetcupProvider {
+            api_key: "key".to_string(),
+            api_password: "password".to_string(),
+            api_session_id: Arc::new(Mutex::new(None)),
+            customer_number: "12345".to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    #[test]
+    fn updates_are_not_conditional() {
+        // The record is read before writing it, which still races
+        assert!(!provider().supports_compare_and_set());
+    }
+
+    #[tokio::test]
+    async fn mismatched_content_fails_before_logging_in() {
+        let provider = provider();
+        let record = RecordRef {
+            typ: DnsRecordType::AAAA,
+            id: RecordId("1".to_string()),
+            name: "nas.foobar.de".to_string(),
+        };
+
+        let error = provider
+            .update_record(
+                &Origin::parse("foobar.de").unwrap(),
+                &record,
+                None,
+                "192.0.2.1",
+                &RecordOptions::default(),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(ContentTypeMismatch::is_cause_of(&error), "{error}");
+        assert!(provider.api_session_id.lock().unwrap().is_none());
+    }
+}
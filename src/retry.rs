@@ -0,0 +1,230 @@
+//! Retries failed updates in the background. Many routers only try again after an hour or the
+//! next reconnect, so without it, a brief provider outage leaves records outdated for long.
+//!
+//! Only the latest address per hostname and record type is kept. The client is still answered
+//! with the failure, and pending retries are shown on the status page.
+
+use crate::ip_update::ParsedIpUpdate;
+use crate::provider::DnsRecordType;
+use crate::update::{UpdateError, UpdateRequest, UpdateService};
+use jiff::{SignedDuration, Timestamp};
+use rootcause::Report;
+use rootcause::prelude::ResultExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+use tracing::Instrument;
+use tracing::{error, info, info_span, warn};
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 8;
+/// The delay before the first retry. It doubles after every failed attempt.
+const INITIAL_DELAY: SignedDuration = SignedDuration::from_secs(30);
+const MAX_DELAY: SignedDuration = SignedDuration::from_hours(1);
+
+/// An address that could not be written yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingRetry {
+    pub hostname: String,
+    pub record_type: DnsRecordType,
+    pub content: String,
+    /// Failed attempts so far, including the original update.
+    pub attempts: u32,
+    pub next_attempt: Timestamp,
+}
+
+pub struct RetryQueue {
+    pending: Mutex<BTreeMap<(String, DnsRecordType), PendingRetry>>,
+    /// Attempts (including the original update) after which an address is given up on.
+    max_attempts: u32,
+    /// Where the pending retries are persisted, so they survive restarts.
+    file: Option<PathBuf>,
+    changed: Notify,
+}
+
+impl RetryQueue {
+    /// Creates the queue, picking up the retries still pending in `file`.
+    pub fn new(max_attempts: u32, file: Option<PathBuf>) -> Result<Self, Report> {
+        let mut pending = BTreeMap::new();
+        if let Some(path) = file.as_ref().filter(|it| it.exists()) {
+            let content = std::fs::read_to_string(path)
+                .context("Failed to read retry queue")
+                .attach(format!("path: {}", path.display()))?;
+            let retries = serde_json::from_str::<Vec<PendingRetry>>(&content)
+                .context("Invalid retry queue")
+                .attach(format!("path: {}", path.display()))?;
+            if !retries.is_empty() {
+                info!(count = retries.len(), "Picked up pending retries");
+            }
+            for retry in retries {
+                pending.insert((retry.hostname.clone(), retry.record_type.clone()), retry);
+            }
+        }
+        Ok(Self {
+            pending: Mutex::new(pending),
+            max_attempts,
+            file,
+            changed: Notify::new(),
+        })
+    }
+
+    /// The pending retries, ordered by hostname and record type.
+    pub fn snapshot(&self) -> Vec<PendingRetry> {
+        self.pending
+            .lock()
+            .expect("mutex poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Schedules the next attempt for the addresses of a failed update of `hostname`, or gives
+    /// up on them. An address replacing a pending one starts over.
+    pub(crate) fn record_failure(&self, hostname: &str, records: &[(DnsRecordType, String)]) {
+        let now = Timestamp::now();
+        let mut pending = self.pending.lock().expect("mutex poisoned");
+        for (record_type, content) in records {
+            let key = (hostname.to_string(), record_type.clone());
+            let attempts = match pending.get(&key) {
+                Some(it) if it.content == *content => it.attempts + 1,
+                _ => 1,
+            };
+            if attempts >= self.max_attempts {
+                error!(
+                    %hostname,
+                    %record_type,
+                    %content,
+                    attempts,
+                    "Update still failing, giving up on retrying it"
+                );
+                pending.remove(&key);
+                continue;
+            }
+
+            let delay = INITIAL_DELAY
+                .checked_mul(1 << (attempts - 1).min(16))
+                .unwrap_or(MAX_DELAY)
+                .min(MAX_DELAY);
+            let next_attempt = now + delay;
+            info!(%hostname, %record_type, %content, attempts, %next_attempt, "Retrying update later");
+            pending.insert(
+                key,
+                PendingRetry {
+                    hostname: hostname.to_string(),
+                    record_type: record_type.clone(),
+                    content: content.clone(),
+                    attempts,
+                    next_attempt,
+                },
+            );
+        }
+        self.save(&pending);
+        self.changed.notify_one();
+    }
+
+    /// Drops the pending retries for `record_types` of `hostname`, e.g. after an update of them
+    /// succeeded.
+    pub(crate) fn remove(&self, hostname: &str, record_types: &[DnsRecordType]) {
+        let mut pending = self.pending.lock().expect("mutex poisoned");
+        let mut removed = false;
+        for record_type in record_types {
+            removed |= pending
+                .remove(&(hostname.to_string(), record_type.clone()))
+                .is_some();
+        }
+        if removed {
+            self.save(&pending);
+        }
+    }
+
+    fn save(&self, pending: &BTreeMap<(String, DnsRecordType), PendingRetry>) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        let retries = pending.values().collect::<Vec<_>>();
+        let result = serde_json::to_string_pretty(&retries)
+            .context("Failed to serialize retry queue")
+            .and_then(|content| {
+                // Replaced atomically, so a crash never leaves a truncated file behind
+                let temporary = path.with_extension("tmp");
+                std::fs::write(&temporary, content)
+                    .context("Failed to write retry queue")
+                    .attach(format!("path: {}", temporary.display()))?;
+                std::fs::rename(&temporary, path)
+                    .context("Failed to replace retry queue")
+                    .attach(format!("path: {}", path.display()))
+            });
+        if let Err(e) = result {
+            warn!(error = %e, "Failed to persist the retry queue");
+        }
+    }
+
+    /// Retries the pending updates when they are due, forever.
+    pub async fn run(&self, updates: &UpdateService) {
+        loop {
+            let next = self
+                .pending
+                .lock()
+                .expect("mutex poisoned")
+                .values()
+                .map(|it| it.next_attempt)
+                .min();
+            let Some(next) = next else {
+                self.changed.notified().await;
+                continue;
+            };
+            let delay = Timestamp::now()
+                .duration_until(next)
+                .max(SignedDuration::ZERO)
+                .unsigned_abs();
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = self.changed.notified() => continue,
+            }
+
+            let now = Timestamp::now();
+            let due = self
+                .snapshot()
+                .into_iter()
+                .filter(|it| it.next_attempt <= now)
+                .collect::<Vec<_>>();
+            for retry in due {
+                let span = info_span!("retry", hostname = %retry.hostname, record_type = %retry.record_type);
+                self.retry(updates, retry).instrument(span).await;
+            }
+        }
+    }
+
+    async fn retry(&self, updates: &UpdateService, retry: PendingRetry) {
+        info!(content = %retry.content, attempt = retry.attempts + 1, "Retrying failed update");
+        let ip = match ParsedIpUpdate::from_str(&retry.content) {
+            Ok(ip) => ip,
+            Err(e) => {
+                warn!(error = %e, "Dropping retry with invalid content");
+                self.remove(&retry.hostname, std::slice::from_ref(&retry.record_type));
+                return;
+            }
+        };
+        let request = UpdateRequest {
+            hostname: retry.hostname.clone(),
+            ip,
+            client: None,
+            request_id: None,
+            alias_of: None,
+            dry_run: false,
+        };
+        // Successes and provider failures are recorded by the update service itself
+        match updates.apply(&request).await {
+            Err(UpdateError::NotReady) => {
+                let record = (retry.record_type.clone(), retry.content.clone());
+                self.record_failure(&retry.hostname, &[record]);
+            }
+            Err(UpdateError::NotInOrigin { .. } | UpdateError::NotOwned { .. }) => {
+                self.remove(&retry.hostname, std::slice::from_ref(&retry.record_type));
+            }
+            Ok(_) | Err(UpdateError::Provider { .. }) => {}
+        }
+    }
+}
@@ -0,0 +1,1012 @@
+use crate::access_log;
+use crate::auth::digest::DigestAuth;
+use crate::auth::signed::{DEFAULT_MAX_SKEW, SignedAuth};
+use crate::auth::{
+    self, ApiToken, AuthConfig, AuthMode, ClientPassword, MIN_TOKEN_LENGTH, PasswordChecker,
+    check_passwords,
+};
+use crate::config::{ConfigFile, HostnameConfig};
+use crate::history::HistoryStore;
+use crate::limits::{self, RequestLimits};
+use crate::lockout::{LockoutConfig, LockoutTracker};
+use crate::negative_cache::NegativeCache;
+use crate::ownership::Ownership;
+use crate::propagation::PropagationCheck;
+use crate::provider::{DnsProvider, DnsRecordType, Origin};
+use crate::retry::RetryQueue;
+use crate::status::ValidationState;
+use crate::types::{AppState, ConfigProblems, DnsConfig, format_table};
+use crate::version::VersionInfo;
+use crate::{admin, dashboard, dyndns, metrics};
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router, middleware};
+use derive_more::FromStr;
+use ipnet::IpNet;
+use jiff::SignedDuration;
+use rootcause::option_ext::OptionExt;
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail, report};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tracing::{Instrument, Span, info, warn};
+
+/// How the providers are validated when the server starts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromStr)]
+pub enum StartupValidation {
+    /// Fail startup if validation still fails after retrying.
+    #[default]
+    Strict,
+    /// Log failures and keep retrying in the background. The server is not ready until
+    /// validation succeeds.
+    Warn,
+    /// Skip validation.
+    Off,
+}
+
+/// The first retry delay of background validation. It doubles up to [`MAX_VALIDATION_RETRY`].
+const INITIAL_VALIDATION_RETRY: Duration = Duration::from_secs(5);
+const MAX_VALIDATION_RETRY: Duration = Duration::from_secs(300);
+
+/// How often strict startup validation is attempted before giving up.
+#[derive(Debug, Clone, Copy)]
+pub struct ValidationRetry {
+    /// The total number of attempts, including the first one.
+    pub attempts: u32,
+    /// The delay after the first failure. It doubles with every further failure.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for ValidationRetry {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            initial_delay: Duration::from_secs(4),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A configured dyndns server. Create one with [`DynDnsServer::builder`].
+#[derive(Clone)]
+pub struct DynDnsServer {
+    state: AppState,
+    hash_metric_hostnames: bool,
+    dashboard: bool,
+    limits: RequestLimits,
+    base_path: Option<String>,
+}
+
+impl DynDnsServer {
+    pub fn builder() -> DynDnsServerBuilder {
+        DynDnsServerBuilder::default()
+    }
+
+    pub(crate) fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// How far the validation of the providers got, see [`Self::startup_validation`].
+    pub fn validation(&self) -> ValidationState {
+        self.state.status.validation()
+    }
+
+    /// Checks that every provider can access its origin.
+    pub async fn validate_providers(&self) -> Result<(), Report> {
+        validate_providers(&self.state.dns).await?;
+        self.state.status.set_validation(ValidationState::Succeeded);
+        Ok(())
+    }
+
+    /// Validates the providers according to `mode`. Updates are rejected while this runs.
+    ///
+    /// In [`StartupValidation::Strict`] mode, failed attempts are retried according to `retry`
+    /// before giving up. In [`StartupValidation::Warn`] mode this never fails, but spawns a task
+    /// retrying validation until it succeeds.
+    pub async fn startup_validation(
+        &self,
+        mode: StartupValidation,
+        retry: ValidationRetry,
+    ) -> Result<(), Report> {
+        let status = &self.state.status;
+        match mode {
+            StartupValidation::Off => {
+                info!("Skipping provider validation");
+                status.set_validation(ValidationState::NotValidated);
+                Ok(())
+            }
+            StartupValidation::Strict => {
+                status.set_validation(ValidationState::Pending);
+                let mut delay = retry.initial_delay;
+                let mut attempt = 1;
+                loop {
+                    let Err(e) = self.validate_providers().await else {
+                        return Ok(());
+                    };
+                    if attempt >= retry.attempts {
+                        status.set_validation(ValidationState::Failed);
+                        return Err(e);
+                    }
+                    warn!(
+                        error = %e,
+                        attempt,
+                        retry_in = ?delay,
+                        "Provider validation failed"
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(retry.max_delay);
+                    attempt += 1;
+                }
+            }
+            StartupValidation::Warn => {
+                status.set_validation(ValidationState::Pending);
+                if let Err(e) = self.validate_providers().await {
+                    warn!(error = %e, "Provider validation failed, retrying in the background");
+                    status.set_validation(ValidationState::Failed);
+                    let server = self.clone();
+                    tokio::spawn(
+                        async move { server.retry_validation().await }.instrument(Span::current()),
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn retry_validation(&self) {
+        let mut delay = INITIAL_VALIDATION_RETRY;
+        loop {
+            tokio::time::sleep(delay).await;
+            match self.validate_providers().await {
+                Ok(()) => {
+                    info!("Provider validation succeeded, server is ready");
+                    return;
+                }
+                Err(e) => {
+                    delay = (delay * 2).min(MAX_VALIDATION_RETRY);
+                    warn!(error = %e, retry_in = ?delay, "Provider validation failed");
+                }
+            }
+        }
+    }
+
+    /// Records the outcome of every real update in `history`, like
+    /// [`DynDnsServerBuilder::history`]. Lets the database be opened only once the server starts.
+    pub fn with_history(mut self, history: HistoryStore) -> Self {
+        self.state.updates = self.state.updates.with_history(Arc::new(history));
+        self
+    }
+
+    /// Loads the hostname state stored by the [`HistoryStore`], if any, into the status, so the
+    /// dashboard and metrics continue where the last run stopped.
+    pub async fn restore_status(&self) -> Result<(), Report> {
+        let Some(history) = self.state.updates.history() else {
+            return Ok(());
+        };
+        let states = history
+            .reader()
+            .states()
+            .await
+            .context("Failed to restore the hostname state")?;
+        info!(records = states.len(), "Restored the hostname state");
+        self.state.status.restore(states);
+        Ok(())
+    }
+
+    /// Build information, including the names of the configured providers.
+    pub fn version_info(&self) -> VersionInfo {
+        VersionInfo::new(
+            self.state
+                .dns
+                .dns_providers
+                .iter()
+                .map(|it| it.name().to_string())
+                .collect(),
+        )
+    }
+
+    /// The prefix all routes are nested under, or an empty string if they are served at `/`.
+    pub fn base_path(&self) -> &str {
+        self.base_path.as_deref().unwrap_or_default()
+    }
+
+    /// Returns the router serving the update endpoint, the status page and `/status`, guarded by
+    /// the auth middleware, the `/admin` endpoints if an admin token is set, and the
+    /// unauthenticated `/healthz`, `/readyz`, `/version` and `/metrics` endpoints. All of them are
+    /// nested under the [`Self::base_path`].
+    ///
+    /// The router relies on [`ConnectInfo`](axum::extract::ConnectInfo), so serve it using
+    /// `into_make_service_with_connect_info::<SocketAddr>()`, or use [`Self::serve`] which also
+    /// enforces the [`RequestLimits`] on the request head.
+    ///
+    /// Requests exceeding the other limits are rejected before reaching any handler.
+    pub fn router(&self) -> Router {
+        let hash_metric_hostnames = self.hash_metric_hostnames;
+        let mut authenticated =
+            Router::new().route("/nic/update", get(dyndns::handle_dyndns_request));
+        if self.dashboard {
+            authenticated = authenticated.route("/", get(dashboard::render));
+        }
+        authenticated = authenticated.route("/status", get(dashboard::status));
+        let mut admin = Router::new();
+        if self.state.auth.admin_token.is_some() {
+            admin = admin
+                .route("/admin/plan", get(admin::plan))
+                .route("/admin/records", get(admin::records))
+                .route("/admin/export", get(admin::export))
+                .route("/admin/lockouts", get(admin::lockouts))
+                .route(
+                    "/admin/chaos",
+                    get(admin::chaos)
+                        .post(admin::update_chaos)
+                        .delete(admin::reset_chaos),
+                )
+                .route(
+                    "/admin/ownership",
+                    post(admin::stamp_ownership).delete(admin::release_ownership),
+                )
+                .layer(middleware::from_fn_with_state(
+                    self.state.clone(),
+                    auth::ensure_admin,
+                ));
+        }
+
+        let mut routes = authenticated
+            .layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                auth::ensure_auth,
+            ))
+            .merge(admin)
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz))
+            .route("/version", get(Json(self.version_info())))
+            .route(
+                "/metrics",
+                get(move |State(state): State<AppState>| async move {
+                    let usage = state.dns.api_usage();
+                    metrics::render(&state.status, &state.dns, &usage, hash_metric_hostnames)
+                }),
+            );
+        if let Some(base_path) = &self.base_path {
+            routes = Router::new().nest(base_path, routes);
+        }
+
+        routes
+            .layer(DefaultBodyLimit::max(self.limits.max_body_bytes))
+            .layer(middleware::from_fn_with_state(
+                (self.limits, self.state.status.clone()),
+                limits::enforce,
+            ))
+            .layer(middleware::from_fn_with_state(
+                (self.state.clone(), self.base_path.clone()),
+                access_log::log_requests,
+            ))
+            .with_state(self.state.clone())
+    }
+
+    /// Serves the [`Self::router`] on `listener` until `shutdown` completes. The retry queue, if
+    /// any, is worked off in the background.
+    pub fn serve(
+        &self,
+        listener: TcpListener,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> impl Future<Output = io::Result<()>> + Send + 'static {
+        self.with_retry_queue(limits::serve(
+            listener,
+            self.router(),
+            self.limits,
+            self.state.status.clone(),
+            shutdown,
+        ))
+    }
+
+    /// Like [`Self::serve`], but speaks HTTPS with the certificate of `acceptor`, see
+    /// [`acme`](crate::acme).
+    #[cfg(feature = "acme")]
+    pub fn serve_tls(
+        &self,
+        listener: TcpListener,
+        acceptor: tokio_rustls::TlsAcceptor,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> impl Future<Output = io::Result<()>> + Send + 'static {
+        self.with_retry_queue(limits::serve_tls(
+            listener,
+            acceptor,
+            self.router(),
+            self.limits,
+            self.state.status.clone(),
+            shutdown,
+        ))
+    }
+
+    fn with_retry_queue(
+        &self,
+        serve: impl Future<Output = io::Result<()>> + Send + 'static,
+    ) -> impl Future<Output = io::Result<()>> + Send + 'static {
+        let updates = self.state.updates.clone();
+        async move {
+            if let Some(queue) = updates.retry_queue().cloned() {
+                tokio::spawn(async move { queue.run(&updates).await });
+            }
+            serve.await
+        }
+    }
+}
+
+/// Whether [`bind`] can share ports on this platform.
+pub const REUSE_PORT_SUPPORTED: bool = cfg!(not(any(
+    target_os = "solaris",
+    target_os = "illumos",
+    target_os = "cygwin"
+)));
+
+/// Binds the listening socket. With `reuse_port`, `SO_REUSEPORT` lets a new version start
+/// listening on the same port while the old one is still draining.
+pub async fn bind(listen_addr: &str, reuse_port: bool) -> Result<TcpListener, Report> {
+    if !reuse_port {
+        return Ok(TcpListener::bind(listen_addr).await?);
+    }
+
+    let addr = tokio::net::lookup_host(listen_addr)
+        .await
+        .context("Failed to resolve listen address")?
+        .next()
+        .ok_or_report()
+        .context("Listen address did not resolve to any address")?;
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    set_reuse_port(&socket)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+#[cfg(not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin")))]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+fn check_base_path(path: &str) -> Result<(), Report> {
+    if !path.starts_with('/') {
+        bail!("The base path '{path}' must start with a slash");
+    }
+    if path.ends_with('/') {
+        bail!("The base path '{path}' must not end with a slash");
+    }
+    if let Some(invalid) = path
+        .chars()
+        .find(|it| !it.is_ascii_alphanumeric() && !"/-._~".contains(*it))
+    {
+        bail!("The base path '{path}' contains the invalid character '{invalid}'");
+    }
+    if path.contains("//") {
+        bail!("The base path '{path}' contains an empty segment");
+    }
+    Ok(())
+}
+
+/// Signals that the server is up and accepting requests.
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Signals whether the providers have been validated, see [`StartupValidation`].
+async fn readyz(State(state): State<AppState>) -> (StatusCode, &'static str) {
+    if state.status.is_ready() {
+        (StatusCode::OK, "ready")
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "provider validation pending",
+        )
+    }
+}
+
+/// Checks that every provider in `dns` can access its origin, reporting all failures at once.
+pub async fn validate_providers(dns: &DnsConfig) -> Result<(), Report> {
+    let mut problems = ConfigProblems::default();
+    for provider in &dns.dns_providers {
+        problems.check(
+            provider
+                .validate(&dns.origin_for(provider.as_ref()))
+                .await
+                .context("Failed to validate DNS provider")
+                .attach(format!("Provider: {}", provider.name()))
+                .map_err(Report::into_dynamic),
+        );
+    }
+    problems.finish()?;
+
+    check_managed_hostnames(dns).await?;
+    check_pinned_records(dns).await
+}
+
+/// Checks that every pinned record ID resolves to a record with the expected name and type.
+async fn check_pinned_records(dns: &DnsConfig) -> Result<(), Report> {
+    let pinned = dns
+        .hostnames
+        .iter()
+        .flat_map(|(hostname, config)| {
+            [DnsRecordType::A, DnsRecordType::AAAA]
+                .into_iter()
+                .filter_map(move |typ| Some((hostname, config.pinned_record(&typ)?, typ)))
+        })
+        .collect::<Vec<_>>();
+    if pinned.is_empty() {
+        return Ok(());
+    }
+
+    let mut problems = ConfigProblems::default();
+    for provider in &dns.dns_providers {
+        let records = provider
+            .list_records(&dns.origin_for(provider.as_ref()))
+            .await
+            .context("Failed to list records")
+            .attach(format!("Provider: {}", provider.name()))?;
+
+        for (hostname, id, typ) in &pinned {
+            let mapped = dns.map_hostname(hostname, provider.as_ref());
+            let record = records.iter().find(|it| it.id == *id);
+            let problem = match record {
+                None => "does not exist",
+                Some(record) if record.name != mapped => "belongs to a different name",
+                Some(record) if record.typ != *typ => "has a different type",
+                Some(_) => continue,
+            };
+            problems.push(
+                report!("Pinned record {problem}")
+                    .attach(format!("hostname: '{hostname}'"))
+                    .attach(format!("record: {typ} {id}"))
+                    .attach(format!("Provider: {}", provider.name()))
+                    .into_dynamic(),
+            );
+        }
+    }
+    problems.finish()
+}
+
+/// Logs the A and AAAA records of every managed hostname and warns about (or, if
+/// [`DnsConfig::require_managed_records`] is set, fails on) hostnames without any.
+async fn check_managed_hostnames(dns: &DnsConfig) -> Result<(), Report> {
+    if dns.managed_hostnames.is_empty() {
+        return Ok(());
+    }
+
+    let mut rows = Vec::new();
+    let mut missing = Vec::new();
+    for provider in &dns.dns_providers {
+        let records = provider
+            .list_records(&dns.origin_for(provider.as_ref()))
+            .await
+            .context("Failed to list records")
+            .attach(format!("Provider: {}", provider.name()))?;
+
+        for hostname in &dns.managed_hostnames {
+            let mapped = dns.map_hostname(hostname, provider.as_ref());
+            let content = |typ: DnsRecordType| {
+                records
+                    .iter()
+                    .filter(|it| it.name == mapped && it.typ == typ)
+                    .map(|it| it.content.as_str())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            };
+            let (a, aaaa) = (content(DnsRecordType::A), content(DnsRecordType::AAAA));
+            if a.is_empty() && aaaa.is_empty() {
+                missing.push(format!("{hostname} at {}", provider.name()));
+            }
+            rows.push([
+                provider.name().to_string(),
+                hostname.clone(),
+                if a.is_empty() { "-".to_string() } else { a },
+                if aaaa.is_empty() {
+                    "-".to_string()
+                } else {
+                    aaaa
+                },
+            ]);
+        }
+    }
+
+    info!(
+        "Managed hostnames:\n{}",
+        format_table(["PROVIDER", "HOSTNAME", "A", "AAAA"], rows)
+    );
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+    if dns.require_managed_records {
+        let mut report = report!("Managed hostnames without A or AAAA record");
+        for it in missing {
+            report = report.attach(it);
+        }
+        return Err(report.into_dynamic());
+    }
+    for it in missing {
+        warn!(
+            hostname = %it,
+            "Managed hostname has no A or AAAA record, updates for it will do nothing"
+        );
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct DynDnsServerBuilder {
+    origin: Option<Origin>,
+    providers: Vec<Arc<dyn DnsProvider + Send + Sync>>,
+    provider_origin_mappings: HashMap<String, Vec<(Origin, Origin)>>,
+    username: Option<String>,
+    passwords: Vec<ClientPassword>,
+    api_tokens: Vec<ApiToken>,
+    trusted_proxies: Vec<IpNet>,
+    lockout: LockoutConfig,
+    allow_query_auth: bool,
+    allow_insecure_query_auth: bool,
+    require_https: bool,
+    digest_auth: bool,
+    auth_mode: AuthMode,
+    signing_secrets: Vec<ApiToken>,
+    signature_max_skew: Option<SignedDuration>,
+    hash_metric_hostnames: bool,
+    managed_hostnames: Vec<String>,
+    require_managed_records: bool,
+    disable_dashboard: bool,
+    debug_errors: bool,
+    base_path: Option<String>,
+    hostnames: HashMap<String, HostnameConfig>,
+    aliases: HashMap<String, Vec<String>>,
+    dedupe_records: bool,
+    prefix_fan_out: bool,
+    prefix_rewrite: bool,
+    limits: RequestLimits,
+    propagation_check: Option<PropagationCheck>,
+    retry_queue: Option<RetryQueue>,
+    history: Option<HistoryStore>,
+    negative_cache: Option<NegativeCache>,
+    ownership: Option<Ownership>,
+    admin_token: Option<String>,
+}
+
+impl DynDnsServerBuilder {
+    /// The zone all updated hostnames must be part of.
+    pub fn origin(mut self, origin: Origin) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    /// Adds a provider. Every update is applied to all providers.
+    pub fn provider(mut self, provider: Arc<dyn DnsProvider + Send + Sync>) -> Self {
+        self.providers.push(provider);
+        self
+    }
+
+    /// Rewrites origins for the provider with the given name, see `PROVIDER_ORIGIN_MAPPING_*`.
+    pub fn provider_origin_mapping(
+        mut self,
+        provider: impl Into<String>,
+        mappings: Vec<(Origin, Origin)>,
+    ) -> Self {
+        self.provider_origin_mappings
+            .insert(provider.into(), mappings);
+        self
+    }
+
+    /// Requires clients to send this username in addition to a valid password.
+    pub fn username(mut self, username: impl Into<String>) -> Self {
+        self.username = Some(username.into());
+        self
+    }
+
+    /// Adds an accepted client password.
+    pub fn password(mut self, password: ClientPassword) -> Self {
+        self.passwords.push(password);
+        self
+    }
+
+    /// Adds an accepted bearer token.
+    pub fn api_token(mut self, token: ApiToken) -> Self {
+        self.api_tokens.push(token);
+        self
+    }
+
+    /// Adds a reverse proxy whose `X-Forwarded-*` headers are trusted.
+    pub fn trusted_proxy(mut self, proxy: IpNet) -> Self {
+        self.trusted_proxies.push(proxy);
+        self
+    }
+
+    pub fn lockout(mut self, config: LockoutConfig) -> Self {
+        self.lockout = config;
+        self
+    }
+
+    /// Accepts API tokens in the `key` or `password` query parameter. Requires
+    /// [`Self::require_https`], unless [`Self::allow_insecure_query_auth`] is set as well.
+    pub fn allow_query_auth(mut self, allow: bool) -> Self {
+        self.allow_query_auth = allow;
+        self
+    }
+
+    /// Allows [`Self::allow_query_auth`] without [`Self::require_https`], sending the
+    /// credentials in plaintext.
+    pub fn allow_insecure_query_auth(mut self, allow: bool) -> Self {
+        self.allow_insecure_query_auth = allow;
+        self
+    }
+
+    /// Only accepts requests forwarded via HTTPS by a trusted proxy.
+    pub fn require_https(mut self, require: bool) -> Self {
+        self.require_https = require;
+        self
+    }
+
+    /// Offers HTTP Digest authentication next to Basic auth.
+    pub fn digest_auth(mut self, enabled: bool) -> Self {
+        self.digest_auth = enabled;
+        self
+    }
+
+    /// Which credentials clients may authenticate updates with. Passwords and tokens by default.
+    pub fn auth_mode(mut self, mode: AuthMode) -> Self {
+        self.auth_mode = mode;
+        self
+    }
+
+    /// Adds a secret accepted for signed updates, see [`sign`](crate::dyndns_client::sign).
+    pub fn signing_secret(mut self, secret: ApiToken) -> Self {
+        self.signing_secrets.push(secret);
+        self
+    }
+
+    /// How far the timestamp of signed updates may be from the server time, five minutes by
+    /// default.
+    pub fn signature_max_skew(mut self, max_skew: SignedDuration) -> Self {
+        self.signature_max_skew = Some(max_skew);
+        self
+    }
+
+    /// Adds a hostname whose records are checked when validating the providers.
+    pub fn managed_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.managed_hostnames.push(hostname.into());
+        self
+    }
+
+    /// Fails provider validation if a managed hostname has no A or AAAA record.
+    pub fn require_managed_records(mut self, require: bool) -> Self {
+        self.require_managed_records = require;
+        self
+    }
+
+    /// Replaces hostnames in metric labels by a hash of them.
+    pub fn hash_metric_hostnames(mut self, hash: bool) -> Self {
+        self.hash_metric_hostnames = hash;
+        self
+    }
+
+    /// Sets the settings of a single hostname, see [`HostnameConfig`].
+    pub fn hostname(mut self, hostname: impl Into<String>, config: HostnameConfig) -> Self {
+        self.hostnames.insert(hostname.into(), config);
+        self
+    }
+
+    /// Also applies every update of `hostname` to `alias`. Aliases cannot have aliases
+    /// themselves and may only follow one hostname.
+    pub fn alias(mut self, hostname: impl Into<String>, alias: impl Into<String>) -> Self {
+        self.aliases
+            .entry(hostname.into())
+            .or_default()
+            .push(alias.into());
+        self
+    }
+
+    /// Deletes all but the first record if several exist for the same name and type.
+    /// Disabled by default, duplicates are then only reported.
+    pub fn dedupe_records(mut self, dedupe: bool) -> Self {
+        self.dedupe_records = dedupe;
+        self
+    }
+
+    /// Updates the AAAA record of every hostname with a suffix when an update carries a delegated
+    /// IPv6 prefix, not only that of the requested hostname.
+    pub fn prefix_fan_out(mut self, fan_out: bool) -> Self {
+        self.prefix_fan_out = fan_out;
+        self
+    }
+
+    /// Moves every AAAA record in the old delegated prefix to the new one when an update
+    /// carries a prefix, keeping the interface identifiers. The old prefix is taken from the
+    /// current AAAA record of the updated hostname.
+    pub fn prefix_rewrite(mut self, rewrite: bool) -> Self {
+        self.prefix_rewrite = rewrite;
+        self
+    }
+
+    /// Replaces the default [`NegativeCache`], which remembers missing records for a minute.
+    pub fn negative_cache(mut self, cache: NegativeCache) -> Self {
+        self.negative_cache = Some(cache);
+        self
+    }
+
+    /// Only writes the records of hostnames with an ownership marker of this owner, see
+    /// [`Ownership`](crate::Ownership).
+    pub fn ownership(mut self, ownership: Ownership) -> Self {
+        self.ownership = Some(ownership);
+        self
+    }
+
+    /// Checks in the background whether updated records become visible via DNS-over-HTTPS.
+    pub fn propagation_check(mut self, check: PropagationCheck) -> Self {
+        self.propagation_check = Some(check);
+        self
+    }
+
+    /// Retries updates failing at a provider in the background, see [`RetryQueue`]. Only runs
+    /// while [`DynDnsServer::serve`] does.
+    pub fn retry_queue(mut self, queue: RetryQueue) -> Self {
+        self.retry_queue = Some(queue);
+        self
+    }
+
+    /// Stores the hostname state and every update in a database, see
+    /// [`HistoryStore`](crate::HistoryStore). Call [`DynDnsServer::restore_status`] to load the stored
+    /// state.
+    pub fn history(mut self, history: HistoryStore) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Serves the `/admin` endpoints, which only accept this bearer token.
+    pub fn admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = Some(token.into());
+        self
+    }
+
+    /// Limits the size of requests and the time to receive them, see [`RequestLimits`].
+    pub fn request_limits(mut self, limits: RequestLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Whether to serve the status page at `/`. Enabled by default.
+    pub fn dashboard(mut self, enabled: bool) -> Self {
+        self.disable_dashboard = !enabled;
+        self
+    }
+
+    /// Whether failed updates send the full error report to the client, with secrets redacted.
+    /// Only meant for setting up the server, the reports reveal its configuration.
+    pub fn debug_errors(mut self, enabled: bool) -> Self {
+        self.debug_errors = enabled;
+        self
+    }
+
+    /// Serves all routes below `path`, e.g. `/dyndns` for `/dyndns/nic/update`. It must start
+    /// with a slash and must not end with one.
+    pub fn base_path(mut self, path: impl Into<String>) -> Self {
+        self.base_path = Some(path.into());
+        self
+    }
+
+    pub fn build(mut self) -> Result<DynDnsServer, Report> {
+        let dns = self.take_dns_config()?;
+        if let Some(base_path) = &self.base_path {
+            check_base_path(base_path)?;
+        }
+        if self.allow_query_auth && !self.require_https && !self.allow_insecure_query_auth {
+            return Err(report!("ALLOW_QUERY_AUTH requires REQUIRE_HTTPS")
+                .attach("Credentials in the query would be sent in plaintext")
+                .attach("Set ALLOW_INSECURE_QUERY_AUTH=true if you really want this")
+                .into_dynamic());
+        }
+        if self.limits.max_head_bytes < RequestLimits::MIN_HEAD_BYTES {
+            bail!(
+                "The request head limit must be at least {} bytes",
+                RequestLimits::MIN_HEAD_BYTES
+            );
+        }
+        if self.limits.header_read_timeout.is_zero() {
+            bail!("The header read timeout must be at least one second");
+        }
+        if let Some(admin_token) = &self.admin_token {
+            if admin_token.len() < MIN_TOKEN_LENGTH {
+                bail!("The admin token must be at least {MIN_TOKEN_LENGTH} characters long");
+            }
+            if self.api_tokens.iter().any(|it| it.token == *admin_token) {
+                bail!("The admin token must differ from all client passwords and API tokens");
+            }
+            if self
+                .signing_secrets
+                .iter()
+                .any(|it| it.token == *admin_token)
+            {
+                bail!("The admin token must differ from all signing secrets");
+            }
+        }
+        let accepts_signed = self.auth_mode != AuthMode::Password;
+        if accepts_signed && self.signing_secrets.is_empty() {
+            bail!(
+                "Auth mode '{}' requires at least one signing secret",
+                self.auth_mode
+            );
+        }
+        if !accepts_signed && !self.signing_secrets.is_empty() {
+            bail!("Signing secrets are only used with the 'signed' or 'both' auth mode");
+        }
+        check_passwords(
+            &self.passwords,
+            !self.api_tokens.is_empty() || accepts_signed,
+            self.digest_auth,
+            self.admin_token.as_deref(),
+        )?;
+
+        let auth = AuthConfig {
+            username: self.username,
+            passwords: PasswordChecker::new(self.passwords),
+            api_tokens: self.api_tokens,
+            trusted_proxies: self.trusted_proxies,
+            lockouts: LockoutTracker::new(self.lockout),
+            allow_query_auth: self.allow_query_auth,
+            require_https: self.require_https,
+            digest: self
+                .digest_auth
+                .then(|| DigestAuth::new("dyndns".to_string())),
+            mode: self.auth_mode,
+            signed: accepts_signed.then(|| {
+                SignedAuth::new(
+                    self.signing_secrets,
+                    self.signature_max_skew.unwrap_or(DEFAULT_MAX_SKEW),
+                )
+            }),
+            admin_token: self.admin_token,
+        };
+
+        let mut state = AppState::new(dns, auth);
+        state.debug_errors = self.debug_errors;
+        if let Some(check) = self.propagation_check {
+            state.updates = state.updates.with_propagation_check(check);
+        }
+        if let Some(queue) = self.retry_queue {
+            state.updates = state.updates.with_retry_queue(Arc::new(queue));
+        }
+        if let Some(history) = self.history {
+            state.updates = state.updates.with_history(Arc::new(history));
+        }
+
+        Ok(DynDnsServer {
+            state,
+            hash_metric_hostnames: self.hash_metric_hostnames,
+            dashboard: !self.disable_dashboard,
+            limits: self.limits,
+            base_path: self.base_path,
+        })
+    }
+
+    /// Builds only the records side of the server, for the commands writing records without
+    /// serving requests. The authentication settings are ignored.
+    pub(crate) fn build_dns_config(mut self) -> Result<DnsConfig, Report> {
+        self.take_dns_config()
+    }
+
+    /// Checks the origin, providers and hostname settings and moves them into a [`DnsConfig`].
+    fn take_dns_config(&mut self) -> Result<DnsConfig, Report> {
+        let Some(origin) = self.origin.take() else {
+            bail!("No origin configured");
+        };
+        ConfigFile {
+            hostnames: self.hostnames.clone(),
+            aliases: self.aliases.clone(),
+        }
+        .validate(&origin)?;
+        if self.providers.is_empty() {
+            bail!("No DNS provider configured");
+        }
+        let has_pinned_records = self
+            .hostnames
+            .values()
+            .any(|it| it.a_record_id.is_some() || it.aaaa_record_id.is_some());
+        if has_pinned_records && self.providers.len() > 1 {
+            bail!("Record IDs can only be pinned with a single provider");
+        }
+
+        let mut dns = DnsConfig::new(
+            origin,
+            mem::take(&mut self.providers),
+            mem::take(&mut self.provider_origin_mappings),
+        );
+        dns.managed_hostnames = mem::take(&mut self.managed_hostnames);
+        dns.require_managed_records = self.require_managed_records;
+        dns.hostnames = mem::take(&mut self.hostnames);
+        dns.aliases = mem::take(&mut self.aliases);
+        dns.dedupe_records = self.dedupe_records;
+        dns.prefix_fan_out = self.prefix_fan_out;
+        dns.prefix_rewrite = self.prefix_rewrite;
+        if let Some(cache) = self.negative_cache.take() {
+            dns.negative_cache = cache;
+        }
+        dns.ownership = self.ownership.take();
+        Ok(dns)
+    }
+
+    /// Shorthand for building the server and returning its [`DynDnsServer::router`].
+    pub fn build_router(self) -> Result<Router, Report> {
+        Ok(self.build()?.router())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::memory::MemoryProvider;
+    use crate::test_support::nas_records;
+    use tokio::net::TcpStream;
+
+    #[cfg(not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin")))]
+    #[tokio::test]
+    async fn reuse_port_shares_the_port() {
+        let first = bind("127.0.0.1:0", true).await.unwrap();
+        let addr = first.local_addr().unwrap();
+
+        let second = bind(&addr.to_string(), true).await.unwrap();
+        assert_eq!(second.local_addr().unwrap(), addr);
+
+        // Once the old process stopped listening, the new one takes all connections
+        drop(first);
+        let (client, accepted) = tokio::join!(TcpStream::connect(addr), second.accept());
+        client.unwrap();
+        accepted.unwrap();
+    }
+
+    #[tokio::test]
+    async fn port_is_exclusive_without_reuse_port() {
+        let first = bind("127.0.0.1:0", false).await.unwrap();
+        let addr = first.local_addr().unwrap().to_string();
+
+        assert!(bind(&addr, false).await.is_err());
+        // Both processes have to opt in
+        assert!(bind(&addr, true).await.is_err());
+    }
+
+    #[test]
+    fn dns_config_keeps_the_records_settings_without_credentials() {
+        let provider = Arc::new(MemoryProvider::new(nas_records()));
+        let config = HostnameConfig {
+            ttl: Some(60),
+            ..HostnameConfig::default()
+        };
+        let dns = DynDnsServer::builder()
+            .origin(Origin::parse("foobar.de").unwrap())
+            .provider(provider)
+            .hostname("nas.foobar.de", config.clone())
+            .alias("nas.foobar.de", "files.foobar.de")
+            .managed_hostname("nas.foobar.de")
+            .dedupe_records(true)
+            .build_dns_config()
+            .unwrap();
+
+        assert_eq!(dns.hostnames["nas.foobar.de"], config);
+        assert_eq!(dns.aliases["nas.foobar.de"], ["files.foobar.de"]);
+        assert_eq!(dns.managed_hostnames, ["nas.foobar.de"]);
+        assert!(dns.dedupe_records);
+    }
+
+    #[tokio::test]
+    async fn unresolvable_address_is_an_error() {
+        let error = bind("not a host:3000", true).await.unwrap_err();
+        assert!(error.to_string().contains("listen address"), "{error}");
+    }
+}
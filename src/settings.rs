@@ -0,0 +1,144 @@
+//! Resolves the settings, which can be given as command line flags (see [`Settings`](crate::cli::Settings)) or
+//! environment variables of the same name.
+//!
+//! All settings are read via [`var`], which enforces the order: a flag wins over the variable,
+//! which wins over the default of the caller. For secrets, the file named in `<NAME>_FILE` is
+//! read if neither is set, so they do not have to show up in the process list or environment.
+//! Failing that, they are read from Vault if `<NAME>_VAULT_PATH` is set, which needs the `vault`
+//! feature.
+
+#[cfg(feature = "vault")]
+mod vault;
+
+use crate::cli::Cli;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory};
+use rootcause::Report;
+use rootcause::prelude::ResultExt;
+use std::collections::HashMap;
+use std::env::VarError;
+use std::ffi::OsString;
+use std::sync::OnceLock;
+
+/// Settings that can be read from the file named in `<NAME>_FILE`.
+pub const SECRETS: &[&str] = &[
+    "PASSWORD",
+    "PASSWORDS",
+    "API_TOKENS",
+    "SIGNING_SECRETS",
+    "ADMIN_TOKEN",
+    "CLOUDFLARE_API_TOKEN",
+    "NETCUP_API_KEY",
+    "NETCUP_API_PASSWORD",
+    "VAULT_TOKEN",
+];
+
+/// The values of flags and secret files, by variable name.
+static VALUES: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Takes the flags given in `matches`, which must be the matches of [`Cli`], and reads the
+/// secret files and Vault secrets. Without calling this, only the environment is used.
+pub async fn init(matches: &ArgMatches) -> Result<(), Report> {
+    let mut values = HashMap::new();
+    for arg in Cli::command().get_arguments() {
+        let Some(name) = arg.get_env().and_then(|it| it.to_str()) else {
+            continue;
+        };
+        let id = arg.get_id().as_str();
+        // Variables are read when they are needed, so they are never stale
+        if matches.value_source(id) != Some(ValueSource::CommandLine) {
+            continue;
+        }
+        if let Some(value) = matches.get_one::<String>(id) {
+            values.insert(name.to_string(), value.clone());
+        }
+    }
+
+    for secret in SECRETS {
+        if values.contains_key(*secret) || std::env::var_os(secret).is_some() {
+            continue;
+        }
+        let file = format!("{secret}_FILE");
+        let Some(path) = values
+            .get(&file)
+            .cloned()
+            .or_else(|| std::env::var(&file).ok())
+        else {
+            continue;
+        };
+        let content = std::fs::read_to_string(&path)
+            .context(format!("Failed to read {file}"))
+            .attach(format!("path: {path}"))?;
+        values.insert(secret.to_string(), content.trim_end().to_string());
+    }
+
+    let setting = |name: &str| {
+        values
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+            .filter(|it| !it.trim().is_empty())
+    };
+    let from_vault = SECRETS
+        .iter()
+        .filter(|it| **it != "VAULT_TOKEN" && setting(it).is_none())
+        .filter_map(|it| Some((*it, setting(&format!("{it}_VAULT_PATH"))?)))
+        .collect::<Vec<_>>();
+    if !from_vault.is_empty() {
+        let secrets = read_from_vault(&setting, &from_vault).await?;
+        values.extend(secrets);
+    }
+
+    let _ = VALUES.set(values);
+    Ok(())
+}
+
+/// Reads the `(secret, reference)` pairs in `secrets` from Vault.
+#[cfg(feature = "vault")]
+async fn read_from_vault(
+    setting: impl Fn(&str) -> Option<String>,
+    secrets: &[(&str, String)],
+) -> Result<HashMap<String, String>, Report> {
+    let vault = vault::VaultClient::connect(setting).await?;
+    let mut values = HashMap::new();
+    for (secret, reference) in secrets {
+        let value = vault
+            .read(reference)
+            .await
+            .attach(format!("setting: {secret}_VAULT_PATH"))?;
+        values.insert(secret.to_string(), value);
+    }
+    Ok(values)
+}
+
+#[cfg(not(feature = "vault"))]
+async fn read_from_vault(
+    _setting: impl Fn(&str) -> Option<String>,
+    secrets: &[(&str, String)],
+) -> Result<HashMap<String, String>, Report> {
+    let names = secrets
+        .iter()
+        .map(|(secret, _)| format!("{secret}_VAULT_PATH"))
+        .collect::<Vec<_>>();
+    Err(
+        rootcause::report!("Secrets can only be read from Vault with the `vault` feature")
+            .attach(format!("set: {}", names.join(", ")))
+            .into_dynamic(),
+    )
+}
+
+/// The value of the setting `name`, see the [module docs](self).
+pub fn var(name: &str) -> Result<String, VarError> {
+    match VALUES.get().and_then(|it| it.get(name)) {
+        Some(value) => Ok(value.clone()),
+        None => std::env::var(name),
+    }
+}
+
+/// Like [`var`], for settings that need not be unicode, such as paths.
+pub fn var_os(name: &str) -> Option<OsString> {
+    match VALUES.get().and_then(|it| it.get(name)) {
+        Some(value) => Some(value.into()),
+        None => std::env::var_os(name),
+    }
+}
@@ -0,0 +1,164 @@
+//! Reads secrets from the KV v2 secrets engine of HashiCorp Vault or OpenBao.
+//!
+//! A secret is referenced as `<path>#<key>`, e.g. `kv/data/dyndns#cf_token` for the key
+//! `cf_token` of the secret `dyndns` in the engine mounted at `kv`.
+
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail, report};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Where Kubernetes mounts the service account token used with `VAULT_ROLE` by default.
+const SERVICE_ACCOUNT_TOKEN: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+const DEFAULT_AUTH_MOUNT: &str = "kubernetes";
+
+pub struct VaultClient {
+    /// The address without a trailing slash.
+    addr: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+impl VaultClient {
+    /// Connects to the Vault configured in the `VAULT_*` settings, read with `setting`. A
+    /// `VAULT_TOKEN` is used as is, with `VAULT_ROLE` the client logs in with a JWT instead.
+    pub async fn connect(setting: impl Fn(&str) -> Option<String>) -> Result<Self, Report> {
+        let Some(addr) = setting("VAULT_ADDR") else {
+            bail!("VAULT_ADDR is not set, but secrets should be read from Vault");
+        };
+        let mut vault = Self {
+            addr: addr.trim().trim_end_matches('/').to_string(),
+            token: String::new(),
+            client: reqwest::Client::new(),
+        };
+
+        if let Some(token) = setting("VAULT_TOKEN") {
+            vault.token = token.trim().to_string();
+            return Ok(vault);
+        }
+        let Some(role) = setting("VAULT_ROLE") else {
+            bail!("Neither VAULT_TOKEN nor VAULT_ROLE is set, cannot authenticate to Vault");
+        };
+        let mount = setting("VAULT_AUTH_MOUNT").unwrap_or_else(|| DEFAULT_AUTH_MOUNT.to_string());
+        let jwt_file =
+            setting("VAULT_JWT_FILE").unwrap_or_else(|| SERVICE_ACCOUNT_TOKEN.to_string());
+        let jwt = std::fs::read_to_string(&jwt_file)
+            .context("Failed to read the JWT for the Vault login")
+            .attach(format!("path: {jwt_file}"))?;
+        vault.token = vault
+            .login(mount.trim_matches('/'), &role, jwt.trim())
+            .await?;
+        Ok(vault)
+    }
+
+    /// Logs in with the Kubernetes or JWT auth method mounted at `mount`, returning the token.
+    async fn login(&self, mount: &str, role: &str, jwt: &str) -> Result<String, Report> {
+        let url = format!("{}/v1/auth/{mount}/login", self.addr);
+        let response = self
+            .client
+            .post(&url)
+            .json(&serde_json::json!({ "role": role, "jwt": jwt }))
+            .send()
+            .await
+            .context("Failed to reach Vault")
+            .attach(format!("url: {url}"))?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(failure(status, &body)
+                .context(format!("Vault login with role '{role}' failed"))
+                .attach(format!("auth mount: {mount}"))
+                .into_dynamic());
+        }
+
+        #[derive(Deserialize)]
+        struct Login {
+            auth: LoginAuth,
+        }
+        #[derive(Deserialize)]
+        struct LoginAuth {
+            client_token: String,
+        }
+        let login = serde_json::from_str::<Login>(&body)
+            .context("Invalid Vault login response")
+            .attach(format!("auth mount: {mount}"))?;
+        Ok(login.auth.client_token)
+    }
+
+    /// Reads the secret `reference` points to, see the [module docs](self).
+    pub async fn read(&self, reference: &str) -> Result<String, Report> {
+        let Some((path, key)) = reference
+            .trim()
+            .split_once('#')
+            .filter(|(path, key)| !path.is_empty() && !key.is_empty())
+        else {
+            bail!("Vault secret '{reference}' is not of the form <path>#<key>");
+        };
+        let path = path.trim_matches('/');
+        let url = format!("{}/v1/{path}", self.addr);
+        let response = self
+            .client
+            .get(&url)
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await
+            .context("Failed to reach Vault")
+            .attach(format!("url: {url}"))?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(failure(status, &body)
+                .context(format!("Failed to read Vault secret '{path}'"))
+                .into_dynamic());
+        }
+
+        #[derive(Deserialize)]
+        struct KvResponse {
+            data: KvData,
+        }
+        #[derive(Deserialize)]
+        struct KvData {
+            data: HashMap<String, Value>,
+        }
+        let secret = serde_json::from_str::<KvResponse>(&body)
+            .context("Invalid Vault response, is the path of a KV v2 secret?")
+            .attach(format!("path: {path}"))?;
+        match secret.data.data.get(key) {
+            Some(Value::String(value)) => Ok(value.clone()),
+            Some(_) => bail!("Vault secret '{path}' has a key '{key}', but it is no string"),
+            None => {
+                let mut keys = secret.data.data.keys().cloned().collect::<Vec<_>>();
+                keys.sort();
+                Err(report!("Vault secret '{path}' has no key '{key}'")
+                    .attach(format!("keys: {}", keys.join(", ")))
+                    .into_dynamic())
+            }
+        }
+    }
+}
+
+/// Describes a failed Vault request by its status and the errors listed in its `body`.
+fn failure(status: reqwest::StatusCode, body: &str) -> Report {
+    #[derive(Deserialize)]
+    struct VaultErrors {
+        #[serde(default)]
+        errors: Vec<String>,
+    }
+    let message = match status.as_u16() {
+        400 | 401 | 403 => {
+            "Vault denied access, the credentials may be invalid or expired or lack a policy"
+        }
+        404 => "Vault has no secret at this path. KV v2 paths contain `data/`, e.g. kv/data/dyndns",
+        // Also answered by standby nodes that cannot forward requests
+        503 => "Vault is sealed or not ready",
+        _ => "Vault request failed",
+    };
+    let mut report = report!("{message}").attach(format!("status: {status}"));
+    if let Ok(errors) = serde_json::from_str::<VaultErrors>(body) {
+        for error in errors.errors {
+            report = report.attach(format!("vault: {error}"));
+        }
+    }
+    report.into_dynamic()
+}
@@ -0,0 +1,207 @@
+//! Tracks the outcome of updates per hostname and record type, and whether the providers have
+//! been validated.
+//!
+//! Only hostnames that passed validation are recorded, so the number of entries is bounded by the
+//! names actually managed and can not be inflated by arbitrary client input.
+
+use crate::limits::Rejection;
+use crate::propagation::Propagation;
+use crate::provider::DnsRecordType;
+use jiff::Timestamp;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Whether the providers were validated successfully. See
+/// [`StartupValidation`](crate::server::StartupValidation).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ValidationState {
+    /// Validation was skipped or never requested.
+    NotValidated = 0,
+    /// Startup validation is in progress. Updates are rejected until it finishes.
+    Pending = 1,
+    /// Validation failed and is retried in the background. Updates are still attempted.
+    Failed = 2,
+    Succeeded = 3,
+}
+
+/// The state of one record of a hostname.
+#[derive(Debug, Clone, Default)]
+pub struct RecordStatus {
+    pub last_attempt: Option<Timestamp>,
+    pub last_success: Option<Timestamp>,
+    pub consecutive_failures: u32,
+    /// The address the record was last successfully set to.
+    pub address: Option<String>,
+    /// The client that made the last attempt, if known.
+    pub last_client: Option<IpAddr>,
+    /// Whether `address` was seen by the resolver, if the propagation check is enabled.
+    pub propagation: Option<Propagation>,
+}
+
+/// The certificate obtained via ACME, see [`acme`](crate::acme).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CertificateStatus {
+    /// When the served certificate expires, if there is one.
+    pub not_after: Option<Timestamp>,
+    /// Failed attempts to obtain or renew the certificate since startup.
+    pub renewal_failures: u64,
+}
+
+/// A record, identified by its hostname and type, and its state.
+pub type StatusEntry = ((String, DnsRecordType), RecordStatus);
+
+#[derive(Debug)]
+pub struct StatusTracker {
+    records: Mutex<BTreeMap<(String, DnsRecordType), RecordStatus>>,
+    validation: AtomicU8,
+    started: Timestamp,
+    rejections: Mutex<BTreeMap<Rejection, u64>>,
+    /// `None` unless certificates are obtained via ACME.
+    certificate: Mutex<Option<CertificateStatus>>,
+}
+
+impl Default for StatusTracker {
+    fn default() -> Self {
+        Self {
+            records: Mutex::default(),
+            validation: AtomicU8::default(),
+            started: Timestamp::now(),
+            rejections: Mutex::default(),
+            certificate: Mutex::default(),
+        }
+    }
+}
+
+impl StatusTracker {
+    /// When the tracker, and thus the server, was created.
+    pub fn started(&self) -> Timestamp {
+        self.started
+    }
+
+    pub fn validation(&self) -> ValidationState {
+        match self.validation.load(Ordering::Relaxed) {
+            1 => ValidationState::Pending,
+            2 => ValidationState::Failed,
+            3 => ValidationState::Succeeded,
+            _ => ValidationState::NotValidated,
+        }
+    }
+
+    pub fn set_validation(&self, state: ValidationState) {
+        self.validation.store(state as u8, Ordering::Relaxed);
+    }
+
+    /// Whether the server should receive traffic, i.e. validation succeeded or was skipped.
+    pub fn is_ready(&self) -> bool {
+        matches!(
+            self.validation(),
+            ValidationState::NotValidated | ValidationState::Succeeded
+        )
+    }
+
+    /// Whether updates are accepted, i.e. startup validation is not in progress.
+    pub fn accepts_updates(&self) -> bool {
+        self.validation() != ValidationState::Pending
+    }
+
+    pub fn record_success(
+        &self,
+        hostname: &str,
+        record_type: DnsRecordType,
+        address: &str,
+        client: Option<IpAddr>,
+        now: Timestamp,
+    ) {
+        let mut records = self.records.lock().expect("mutex poisoned");
+        let status = records
+            .entry((hostname.to_string(), record_type))
+            .or_default();
+        status.last_attempt = Some(now);
+        status.last_success = Some(now);
+        status.consecutive_failures = 0;
+        status.address = Some(address.to_string());
+        status.last_client = client;
+        status.propagation = None;
+    }
+
+    pub fn record_failure(
+        &self,
+        hostname: &str,
+        record_type: DnsRecordType,
+        client: Option<IpAddr>,
+        now: Timestamp,
+    ) {
+        let mut records = self.records.lock().expect("mutex poisoned");
+        let status = records
+            .entry((hostname.to_string(), record_type))
+            .or_default();
+        status.last_attempt = Some(now);
+        status.consecutive_failures += 1;
+        status.last_client = client;
+    }
+
+    /// Records the result of a [`PropagationCheck`](crate::propagation::PropagationCheck),
+    /// unless the record was set to a different address in the meantime.
+    pub fn record_propagation(
+        &self,
+        hostname: &str,
+        record_type: &DnsRecordType,
+        address: &str,
+        propagation: Propagation,
+    ) {
+        let mut records = self.records.lock().expect("mutex poisoned");
+        if let Some(status) = records.get_mut(&(hostname.to_string(), record_type.clone()))
+            && status.address.as_deref() == Some(address)
+        {
+            status.propagation = Some(propagation);
+        }
+    }
+
+    /// Counts a request or connection rejected by the [`RequestLimits`](crate::limits::RequestLimits).
+    pub fn record_rejection(&self, rejection: Rejection) {
+        let mut rejections = self.rejections.lock().expect("mutex poisoned");
+        *rejections.entry(rejection).or_default() += 1;
+    }
+
+    /// The number of rejected requests and connections per reason.
+    pub fn rejections(&self) -> Vec<(Rejection, u64)> {
+        let rejections = self.rejections.lock().expect("mutex poisoned");
+        rejections.iter().map(|(k, v)| (*k, *v)).collect()
+    }
+
+    /// Records that the certificate expiring at `not_after` is served from now on.
+    pub fn record_certificate(&self, not_after: Timestamp) {
+        let mut certificate = self.certificate.lock().expect("mutex poisoned");
+        certificate.get_or_insert_default().not_after = Some(not_after);
+    }
+
+    /// Counts a failed attempt to obtain or renew the certificate.
+    pub fn record_certificate_failure(&self) {
+        let mut certificate = self.certificate.lock().expect("mutex poisoned");
+        certificate.get_or_insert_default().renewal_failures += 1;
+    }
+
+    /// The state of the certificate, if one was obtained or attempted to.
+    pub fn certificate(&self) -> Option<CertificateStatus> {
+        *self.certificate.lock().expect("mutex poisoned")
+    }
+
+    /// Replaces the entries with `records`, e.g. as stored by the
+    /// [`HistoryStore`](crate::history::HistoryStore) in an earlier run.
+    pub fn restore(&self, records: Vec<StatusEntry>) {
+        let mut current = self.records.lock().expect("mutex poisoned");
+        *current = records.into_iter().collect();
+    }
+
+    /// A copy of all entries, sorted by hostname and record type.
+    pub fn snapshot(&self) -> Vec<StatusEntry> {
+        let records = self.records.lock().expect("mutex poisoned");
+        records
+            .iter()
+            .map(|(key, status)| (key.clone(), status.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,110 @@
+//! Helpers for unit tests driving the full router against a [`MemoryProvider`], like
+//! `tests/common` does for the integration tests.
+
+use crate::provider::RecordId;
+use crate::provider::memory::MemoryProvider;
+use crate::{ClientPassword, DnsEntry, DnsRecordType, DynDnsServer, DynDnsServerBuilder, Origin};
+use axum::Router;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{Request, StatusCode, header};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tower::ServiceExt;
+use tracing::Level;
+use tracing::subscriber::DefaultGuard;
+
+pub(crate) const PASSWORD: &str = "hunter2";
+
+pub(crate) fn record(id: &str, typ: DnsRecordType, name: &str, content: &str) -> DnsEntry {
+    DnsEntry {
+        typ,
+        id: RecordId(id.to_string()),
+        name: name.to_string(),
+        content: content.to_string(),
+        ttl: None,
+        proxied: None,
+    }
+}
+
+/// The records of `nas.foobar.de`.
+pub(crate) fn nas_records() -> Vec<DnsEntry> {
+    vec![
+        record("a", DnsRecordType::A, "nas.foobar.de", "192.0.2.1"),
+        record("aaaa", DnsRecordType::AAAA, "nas.foobar.de", "2001:db8::1"),
+    ]
+}
+
+/// A builder for the origin `foobar.de`, the password [`PASSWORD`] and `provider`.
+pub(crate) fn builder(provider: &Arc<MemoryProvider>) -> DynDnsServerBuilder {
+    DynDnsServer::builder()
+        .origin(Origin::parse("foobar.de").unwrap())
+        .provider(provider.clone())
+        .password(ClientPassword::Plain(PASSWORD.to_string()))
+}
+
+/// A request from `192.0.2.100`, as the router expects the connect info of the server.
+pub(crate) fn request(uri: &str) -> axum::http::request::Builder {
+    Request::get(uri).extension(ConnectInfo(SocketAddr::from(([192, 0, 2, 100], 4242))))
+}
+
+pub(crate) fn basic_auth(username: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        STANDARD.encode(format!("{username}:{password}"))
+    )
+}
+
+/// An update request authenticated with [`PASSWORD`].
+pub(crate) fn update(query: &str) -> Request<Body> {
+    request(&format!("/nic/update?{query}"))
+        .header(header::AUTHORIZATION, basic_auth("router", PASSWORD))
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Sends `request` and returns the status and body of the response.
+pub(crate) async fn send(router: &Router, request: Request<Body>) -> (StatusCode, String) {
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+/// Log output captured by [`capture_logs`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Logs {
+    pub(crate) fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl io::Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Captures all events of the current thread at any level, until the guard is dropped.
+pub(crate) fn capture_logs() -> (Logs, DefaultGuard) {
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(Level::TRACE)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    (logs, tracing::subscriber::set_default(subscriber))
+}
@@ -1,29 +1,90 @@
-use crate::provider::{DnsProvider, Origin};
-use rootcause::report;
+use crate::auth::AuthConfig;
+use crate::config::HostnameConfig;
+use crate::negative_cache::NegativeCache;
+use crate::ownership::Ownership;
+use crate::provider::api_usage::ApiUsageSnapshot;
+use crate::provider::{DnsProvider, Origin, compiled_providers};
+use crate::settings;
+use crate::status::StatusTracker;
+use crate::update::UpdateService;
+use rootcause::prelude::ResultExt;
+use rootcause::report_collection::ReportCollection;
+use rootcause::{Report, report};
 use std::collections::HashMap;
 use std::env::VarError;
+use std::fmt::Display;
+use std::str::FromStr;
 use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
+    pub dns: Arc<DnsConfig>,
+    pub auth: Arc<AuthConfig>,
+    pub status: Arc<StatusTracker>,
+    pub updates: UpdateService,
+    /// Whether error responses carry the full, redacted report, see [`crate::debug_errors`].
+    pub debug_errors: bool,
+}
+
+impl AppState {
+    pub fn new(dns: DnsConfig, auth: AuthConfig) -> Self {
+        let dns = Arc::new(dns);
+        let status = Arc::new(StatusTracker::default());
+        Self {
+            updates: UpdateService::new(dns.clone(), status.clone()),
+            dns,
+            auth: Arc::new(auth),
+            status,
+            debug_errors: false,
+        }
+    }
+}
+
+/// The providers updates are applied to and the origin they manage.
+pub struct DnsConfig {
     pub dns_providers: Vec<Arc<dyn DnsProvider + Send + Sync>>,
     dns_origin: Origin,
-    pub client_password: String,
     pub provider_origin_mappings: HashMap<String, Vec<(Origin, Origin)>>,
+    /// Hostnames whose records are checked when validating the providers.
+    pub managed_hostnames: Vec<String>,
+    /// Whether validation fails if a managed hostname has no A or AAAA record.
+    pub require_managed_records: bool,
+    /// Per-hostname settings from the config file.
+    pub hostnames: HashMap<String, HostnameConfig>,
+    /// The aliases updated along with each hostname, see [`UpdateService::aliases`].
+    pub aliases: HashMap<String, Vec<String>>,
+    /// Whether duplicate records of the same name and type are deleted during updates.
+    pub dedupe_records: bool,
+    /// Whether an update carrying a delegated IPv6 prefix updates every hostname with a suffix.
+    pub prefix_fan_out: bool,
+    /// Whether an update carrying a delegated IPv6 prefix moves every AAAA record in the old
+    /// prefix to the new one.
+    pub prefix_rewrite: bool,
+    /// Hostnames recently found to have no record of a type.
+    pub negative_cache: NegativeCache,
+    /// Only records with an ownership marker of this owner are written, if set.
+    pub ownership: Option<Ownership>,
 }
 
-impl AppState {
+impl DnsConfig {
     pub fn new(
         dns_origin: Origin,
-        client_password: String,
         dns_providers: Vec<Arc<dyn DnsProvider + Send + Sync>>,
         provider_origin_mappings: HashMap<String, Vec<(Origin, Origin)>>,
     ) -> Self {
         Self {
             dns_providers,
             dns_origin,
-            client_password,
             provider_origin_mappings,
+            managed_hostnames: Vec::new(),
+            require_managed_records: false,
+            hostnames: HashMap::new(),
+            aliases: HashMap::new(),
+            dedupe_records: false,
+            prefix_fan_out: false,
+            prefix_rewrite: false,
+            negative_cache: NegativeCache::default(),
+            ownership: None,
         }
     }
 
@@ -31,29 +92,53 @@ impl AppState {
         let Some(mappings) = self.provider_origin_mappings.get(provider.name()) else {
             return origin;
         };
-        let mut current_origin = origin.0;
+        let mut current_origin = origin;
+        for (from, to) in mappings {
+            current_origin = current_origin.replace(from, to);
+        }
+
+        current_origin
+    }
+
+    /// Applies the origin mappings of `provider` to a hostname.
+    pub fn map_hostname(&self, hostname: &str, provider: &dyn DnsProvider) -> String {
+        let Some(mappings) = self.provider_origin_mappings.get(provider.name()) else {
+            return hostname.to_string();
+        };
+        let mut current = hostname.to_string();
         for (from, to) in mappings {
-            current_origin = current_origin.replace(&from.0, &to.0);
+            current = current.replace(from.as_str(), to.as_str());
         }
 
-        Origin(current_origin)
+        current
     }
 
     pub fn origin_for(&self, provider: &dyn DnsProvider) -> Origin {
         self.map_origin(self.dns_origin.clone(), provider)
     }
+
+    /// The API usage of every provider tracking it.
+    pub fn api_usage(&self) -> Vec<ApiUsageSnapshot> {
+        self.dns_providers
+            .iter()
+            .filter_map(|it| it.api_usage())
+            .collect()
+    }
 }
 
-pub fn ensure_env_vars(vars: &[&str]) -> Result<(), rootcause::Report> {
+pub fn ensure_env_vars(vars: &[&str]) -> Result<(), Report> {
     let mut error = report!("Missing required environment variable");
     let mut is_error = false;
     for var in vars {
-        match std::env::var(var) {
+        match settings::var(var) {
             Ok(_) => continue,
             Err(VarError::NotPresent) => {
                 error = error.attach(format!("'{}' is not set", var));
                 if *var == "PROVIDERS" {
-                    error = error.attach("  valid providers: cloudflare,netcup".to_string())
+                    error = error.attach(format!(
+                        "  valid providers: {}",
+                        compiled_providers().join(",")
+                    ))
                 }
                 is_error = true;
             }
@@ -65,3 +150,102 @@ pub fn ensure_env_vars(vars: &[&str]) -> Result<(), rootcause::Report> {
     }
     if is_error { Err(error) } else { Ok(()) }
 }
+
+pub fn env_or_default<T>(var: &str, default: T) -> Result<T, Report>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match settings::var(var) {
+        Ok(value) => value
+            .trim()
+            .parse::<T>()
+            .map_err(|e| report!("Invalid value for environment variable").attach(e.to_string()))
+            .attach(format!("{var}='{value}'")),
+        Err(VarError::NotPresent) => Ok(default),
+        Err(VarError::NotUnicode(e)) => Err(report!("Environment variable is not valid unicode")
+            .attach(format!("'{}' is '{}'", var, e.display()))),
+    }
+}
+
+/// Collects configuration problems, so they can all be reported at once.
+#[derive(Default)]
+pub struct ConfigProblems {
+    problems: ReportCollection,
+}
+
+impl ConfigProblems {
+    /// Records the error of `result`, if any, and returns the value otherwise.
+    pub fn check<T>(&mut self, result: Result<T, Report>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.push(e);
+                None
+            }
+        }
+    }
+
+    pub fn push(&mut self, problem: Report) {
+        self.problems.push(problem.into_cloneable());
+    }
+
+    /// Fails with all recorded problems, if there are any.
+    pub fn finish(self) -> Result<(), Report> {
+        if self.problems.is_empty() {
+            return Ok(());
+        }
+        let count = self.problems.len();
+        Err(self
+            .problems
+            .context(format!("Found {count} configuration problem(s)"))
+            .into_dynamic())
+    }
+}
+
+/// Formats `rows` as a table with left-aligned columns, one line per row, including a trailing
+/// newline.
+pub fn format_table<const N: usize>(header: [&str; N], rows: Vec<[String; N]>) -> String {
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let format_row = |cells: [&str; N]| {
+        let line = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        format!("{}\n", line.trim_end())
+    };
+    let mut table = format_row(header);
+    for row in &rows {
+        table.push_str(&format_row(row.each_ref().map(String::as_str)));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_providers_lists_the_compiled_in_ones() {
+        // Assumes the tests run without PROVIDERS set
+        let error = ensure_env_vars(&["PROVIDERS"]).unwrap_err().to_string();
+
+        assert!(error.contains("'PROVIDERS' is not set"), "{error}");
+        assert!(
+            error.contains(&format!(
+                "valid providers: {}",
+                compiled_providers().join(",")
+            )),
+            "{error}"
+        );
+        assert!(error.contains("memory"), "{error}");
+    }
+}
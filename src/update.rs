@@ -0,0 +1,1256 @@
+//! The update pipeline, shared by the HTTP handler and the command line.
+
+use crate::config::HostnameConfig;
+use crate::history::{EventResult, HistoryStore, UpdateEvent};
+use crate::ip_update::ParsedIpUpdate;
+use crate::ownership::{NotOwned, Ownership};
+use crate::propagation::{Propagation, PropagationCheck};
+use crate::provider::{
+    DnsEntry, DnsProvider, DnsRecordType, Origin, RecordConflict, RecordId, RecordNotFound,
+    RecordOptions, RecordRef,
+};
+use crate::retry::RetryQueue;
+use crate::status::StatusTracker;
+use crate::types::DnsConfig;
+use derive_more::Display;
+use ipnet::Ipv6Net;
+use jiff::Timestamp;
+use rootcause::{Report, prelude::ResultExt};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Arc;
+use tracing::{Instrument, debug, info, info_span, warn};
+
+/// An update of the records of one hostname.
+#[derive(Debug, Clone)]
+pub struct UpdateRequest {
+    pub hostname: String,
+    pub ip: ParsedIpUpdate,
+    /// The client requesting the update, if known. Only used for the status.
+    pub client: Option<IpAddr>,
+    /// The ID of the HTTP request, recorded in the history.
+    pub request_id: Option<String>,
+    /// The requested hostname if this is the update of one of its aliases, recorded in the
+    /// history.
+    pub alias_of: Option<String>,
+    /// Only logs what would be written.
+    pub dry_run: bool,
+}
+
+/// The records that were updated, or why the update failed.
+pub type UpdateOutcome = Result<Vec<UpdatedRecord>, UpdateError>;
+
+/// Applies updates to all providers and records their outcome.
+#[derive(Clone)]
+pub struct UpdateService {
+    dns: Arc<DnsConfig>,
+    status: Arc<StatusTracker>,
+    propagation: Option<PropagationCheck>,
+    retry: Option<Arc<RetryQueue>>,
+    history: Option<Arc<HistoryStore>>,
+}
+
+impl UpdateService {
+    pub fn new(dns: Arc<DnsConfig>, status: Arc<StatusTracker>) -> Self {
+        Self {
+            dns,
+            status,
+            propagation: None,
+            retry: None,
+            history: None,
+        }
+    }
+
+    /// Checks that updated records become visible, see [`PropagationCheck`].
+    pub fn with_propagation_check(mut self, check: PropagationCheck) -> Self {
+        self.propagation = Some(check);
+        self
+    }
+
+    /// Retries updates failing at a provider in the background, see [`RetryQueue`].
+    pub fn with_retry_queue(mut self, queue: Arc<RetryQueue>) -> Self {
+        self.retry = Some(queue);
+        self
+    }
+
+    pub fn retry_queue(&self) -> Option<&Arc<RetryQueue>> {
+        self.retry.as_ref()
+    }
+
+    /// Records the outcome of every real update in the database, see [`HistoryStore`].
+    pub fn with_history(mut self, history: Arc<HistoryStore>) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    pub fn history(&self) -> Option<&Arc<HistoryStore>> {
+        self.history.as_ref()
+    }
+
+    /// Applies `request` to every provider. Updates are rejected while startup validation is in
+    /// progress, and the outcome of real updates is recorded in the [`StatusTracker`].
+    pub async fn apply(&self, request: &UpdateRequest) -> UpdateOutcome {
+        if !self.status.accepts_updates() {
+            warn!("Rejecting update, provider validation is still in progress");
+            return Err(UpdateError::NotReady);
+        }
+
+        let changes =
+            update_hostname(&self.dns, &request.hostname, &request.ip, !request.dry_run).await;
+        let deleted = changes
+            .iter()
+            .flatten()
+            .filter(|it| it.action == PlannedAction::Delete)
+            .cloned()
+            .collect::<Vec<_>>();
+        let outcome =
+            changes.map(|changes| changes.iter().filter_map(PlannedChange::updated).collect());
+        if !request.dry_run {
+            self.record_status(request, &outcome);
+            self.record_history(request, &outcome);
+            self.record_deletions(request, &deleted);
+            self.record_retry(request, &outcome);
+            if let (Some(check), Ok(updated)) = (&self.propagation, &outcome) {
+                self.check_propagation(check, request, updated);
+            }
+        }
+        outcome
+    }
+
+    /// The updates of other hostnames that `request` fans out to: with
+    /// [`DnsConfig::prefix_fan_out`] and a delegated prefix in the request, the AAAA record of
+    /// every configured hostname with a suffix is updated as well.
+    pub fn fan_out(&self, request: &UpdateRequest) -> Vec<UpdateRequest> {
+        let Some(prefix) = request.ip.ipv6_prefix().filter(|_| self.dns.prefix_fan_out) else {
+            return Vec::new();
+        };
+        let mut hostnames = self
+            .dns
+            .hostnames
+            .iter()
+            .filter(|(hostname, config)| config.suffix.is_some() && **hostname != request.hostname)
+            .map(|(hostname, _)| hostname.clone())
+            .collect::<Vec<_>>();
+        hostnames.sort();
+        hostnames
+            .into_iter()
+            .map(|hostname| UpdateRequest {
+                hostname,
+                ip: ParsedIpUpdate::from_ipv6_prefix(prefix),
+                ..request.clone()
+            })
+            .collect()
+    }
+
+    /// The updates of the aliases of the requested hostname, with the same address.
+    pub fn aliases(&self, request: &UpdateRequest) -> Vec<UpdateRequest> {
+        self.dns
+            .aliases
+            .get(&request.hostname)
+            .into_iter()
+            .flatten()
+            .map(|alias| UpdateRequest {
+                hostname: alias.clone(),
+                alias_of: Some(request.hostname.clone()),
+                ..request.clone()
+            })
+            .collect()
+    }
+
+    /// With [`DnsConfig::prefix_rewrite`] and a delegated prefix in `request`, moves every AAAA
+    /// record in the zone that is inside the old prefix to the new one, keeping its interface
+    /// identifier. The old prefix is derived from the current AAAA record of the requested
+    /// hostname, so this has to run before [`Self::apply`] changes it.
+    ///
+    /// The requested hostname itself is left to [`Self::apply`] if the request sets its AAAA
+    /// record. Records outside the old prefix, or of hostnames not in `allowed` if given, are
+    /// never touched. Records failing to be rewritten are listed in [`PrefixRewrite::failed`],
+    /// an error means no record of a provider could be rewritten.
+    pub async fn rewrite_prefix(
+        &self,
+        request: &UpdateRequest,
+        allowed: Option<&HashSet<String>>,
+    ) -> Result<Vec<PrefixRewrite>, UpdateError> {
+        let Some(prefix) = request.ip.ipv6_prefix().filter(|_| self.dns.prefix_rewrite) else {
+            return Ok(Vec::new());
+        };
+        if request.dry_run {
+            return Ok(Vec::new());
+        }
+        if !self.status.accepts_updates() {
+            return Err(UpdateError::NotReady);
+        }
+
+        let sets_own_record = effective_update(&self.dns, &request.hostname, &request.ip)
+            .records()
+            .iter()
+            .any(|(typ, _)| *typ == DnsRecordType::AAAA);
+        let mut rewrites = Vec::new();
+        for provider in &self.dns.dns_providers {
+            let mapped = self.dns.map_hostname(&request.hostname, provider.as_ref());
+            let origin = self.dns.origin_for(provider.as_ref());
+            if !origin.is_subdomain(&mapped) {
+                return Err(UpdateError::NotInOrigin {
+                    hostname: request.hostname.clone(),
+                    mapped,
+                    origin,
+                });
+            }
+            let allowed = allowed.map(|it| {
+                it.iter()
+                    .map(|hostname| self.dns.map_hostname(hostname, provider.as_ref()))
+                    .collect::<HashSet<_>>()
+            });
+            let may_rewrite = |name: &str| {
+                !(sets_own_record && name == mapped)
+                    && allowed.as_ref().is_none_or(|it| it.contains(name))
+            };
+            let rewrite = rewrite_zone_prefix(
+                provider.as_ref(),
+                &origin,
+                &mapped,
+                prefix,
+                may_rewrite,
+                self.dns.ownership.as_ref(),
+            )
+            .await
+            .map_err(|report| {
+                warn!(error = %report, provider = provider.name(), "Failed to rewrite the prefix");
+                UpdateError::Provider {
+                    provider: provider.name(),
+                    report,
+                }
+            })?;
+            rewrites.extend(rewrite);
+        }
+        self.record_rewrites(request, &rewrites);
+        Ok(rewrites)
+    }
+
+    /// Runs the pipeline of [`Self::apply`] without writing anything, and returns the decision
+    /// made for every record. Neither the readiness nor the status are touched.
+    pub async fn plan(
+        &self,
+        hostname: &str,
+        ip: &ParsedIpUpdate,
+    ) -> Result<Vec<PlannedChange>, UpdateError> {
+        update_hostname(&self.dns, hostname, ip, false).await
+    }
+
+    fn record_retry(&self, request: &UpdateRequest, outcome: &UpdateOutcome) {
+        let Some(retry) = &self.retry else {
+            return;
+        };
+        let ip = effective_update(&self.dns, &request.hostname, &request.ip);
+        match outcome {
+            Ok(_) => {
+                let record_types = ip
+                    .records()
+                    .iter()
+                    .map(|it| it.0.clone())
+                    .collect::<Vec<_>>();
+                retry.remove(&request.hostname, &record_types);
+            }
+            Err(UpdateError::Provider { .. }) => {
+                retry.record_failure(&request.hostname, ip.records());
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn check_propagation(
+        &self,
+        check: &PropagationCheck,
+        request: &UpdateRequest,
+        updated: &[UpdatedRecord],
+    ) {
+        // Several providers usually write the same address. The name at a provider differs from
+        // the hostname if its origin is mapped, and that is the name the world resolves.
+        let mut checked = Vec::new();
+        for record in updated.iter().filter(|it| it.changed) {
+            let key = (&record.name, &record.record_type, &record.content);
+            if checked.contains(&key) {
+                continue;
+            }
+            checked.push(key);
+            let hostname = &request.hostname;
+            let record_type = record.record_type.clone();
+            let address = record.content.clone();
+            self.status
+                .record_propagation(hostname, &record_type, &address, Propagation::Pending);
+
+            let status = self.status.clone();
+            let history = self.history.clone();
+            let request = request.clone();
+            check.spawn(
+                hostname,
+                record.name.clone(),
+                record_type.clone(),
+                address.clone(),
+                record.old_ttl,
+                move |result| {
+                    status.record_propagation(&request.hostname, &record_type, &address, result);
+                    let (timestamp, result) = match result {
+                        Propagation::Visible { at } => (at, EventResult::Visible),
+                        _ => (Timestamp::now(), EventResult::NotVisible),
+                    };
+                    if let Some(history) = history {
+                        history.record(UpdateEvent {
+                            timestamp,
+                            hostname: request.hostname,
+                            record_type,
+                            provider: None,
+                            old_content: None,
+                            new_content: Some(address),
+                            result,
+                            client: request.client,
+                            error: None,
+                            request_id: request.request_id,
+                            detail: None,
+                        });
+                    }
+                },
+            );
+        }
+    }
+
+    /// Queues an event per record and provider for the [`HistoryStore`], like
+    /// [`Self::record_status`].
+    fn record_history(&self, request: &UpdateRequest, outcome: &UpdateOutcome) {
+        let Some(history) = &self.history else {
+            return;
+        };
+        let timestamp = Timestamp::now();
+        let event = |record_type: &DnsRecordType, result| UpdateEvent {
+            timestamp,
+            hostname: request.hostname.clone(),
+            record_type: record_type.clone(),
+            provider: None,
+            old_content: None,
+            new_content: None,
+            result,
+            client: request.client,
+            error: None,
+            request_id: request.request_id.clone(),
+            detail: request.alias_of.as_ref().map(|it| format!("alias of {it}")),
+        };
+        let (provider, error) = match outcome {
+            Ok(updated) => {
+                for record in updated {
+                    let result = match record.changed {
+                        true => EventResult::Good,
+                        false => EventResult::Nochg,
+                    };
+                    history.record(UpdateEvent {
+                        provider: Some(record.provider.to_string()),
+                        old_content: record.old_content.clone(),
+                        new_content: Some(record.content.clone()),
+                        ..event(&record.record_type, result)
+                    });
+                }
+                return;
+            }
+            Err(UpdateError::Provider { provider, report }) => {
+                (provider, report.format_current_context().to_string())
+            }
+            Err(e @ UpdateError::NotOwned { provider, .. }) => (provider, e.to_string()),
+            Err(UpdateError::NotInOrigin { .. } | UpdateError::NotReady) => return,
+        };
+        let ip = effective_update(&self.dns, &request.hostname, &request.ip);
+        for (record_type, content) in ip.records() {
+            history.record(UpdateEvent {
+                provider: Some(provider.to_string()),
+                new_content: Some(content.clone()),
+                error: Some(error.clone()),
+                ..event(record_type, EventResult::Failed)
+            });
+        }
+    }
+
+    /// Queues an event per duplicate record deleted by `request` for the [`HistoryStore`].
+    fn record_deletions(&self, request: &UpdateRequest, deleted: &[PlannedChange]) {
+        let Some(history) = &self.history else {
+            return;
+        };
+        let timestamp = Timestamp::now();
+        for change in deleted {
+            history.record(UpdateEvent {
+                timestamp,
+                hostname: request.hostname.clone(),
+                record_type: change.record_type.clone(),
+                provider: Some(change.provider.to_string()),
+                old_content: change.old_content.clone(),
+                new_content: None,
+                result: EventResult::Deleted,
+                client: request.client,
+                error: None,
+                request_id: request.request_id.clone(),
+                detail: change
+                    .record_id
+                    .as_ref()
+                    .map(|it| format!("record ID {it}")),
+            });
+        }
+    }
+
+    /// Adds a single event per provider to the history for the records moved by
+    /// [`Self::rewrite_prefix`], listing their names in the detail.
+    fn record_rewrites(&self, request: &UpdateRequest, rewrites: &[PrefixRewrite]) {
+        let Some(history) = &self.history else {
+            return;
+        };
+        let timestamp = Timestamp::now();
+        for rewrite in rewrites {
+            let mut detail = rewrite
+                .rewritten
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            if !rewrite.failed.is_empty() {
+                let failed = rewrite
+                    .failed
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                detail = format!("{detail}; failed: {failed}");
+            }
+            history.record(UpdateEvent {
+                timestamp,
+                hostname: request.hostname.clone(),
+                record_type: DnsRecordType::AAAA,
+                provider: Some(rewrite.provider.to_string()),
+                old_content: Some(rewrite.old_prefix.to_string()),
+                new_content: Some(rewrite.new_prefix.to_string()),
+                result: EventResult::Rewritten,
+                client: request.client,
+                error: None,
+                request_id: request.request_id.clone(),
+                detail: Some(detail),
+            });
+        }
+    }
+
+    /// Records the outcome of an update in the [`StatusTracker`]. Hostnames outside the origin
+    /// are ignored, and so are records that do not exist.
+    fn record_status(&self, request: &UpdateRequest, outcome: &UpdateOutcome) {
+        let UpdateRequest {
+            hostname,
+            ip,
+            client,
+            ..
+        } = request;
+        let now = Timestamp::now();
+        match outcome {
+            Ok(updated) => {
+                for record in updated {
+                    self.status.record_success(
+                        hostname,
+                        record.record_type.clone(),
+                        &record.content,
+                        *client,
+                        now,
+                    );
+                }
+            }
+            Err(UpdateError::Provider { .. } | UpdateError::NotOwned { .. }) => {
+                for (record_type, _) in ip.records() {
+                    self.status
+                        .record_failure(hostname, record_type.clone(), *client, now);
+                }
+            }
+            Err(UpdateError::NotInOrigin { .. } | UpdateError::NotReady) => {}
+        }
+    }
+}
+
+#[derive(Debug, Display)]
+pub enum UpdateError {
+    #[display("domain '{hostname}' (=> '{mapped}') is not a subdomain of '{origin}'")]
+    NotInOrigin {
+        hostname: String,
+        mapped: String,
+        origin: Origin,
+    },
+    #[display("provider validation is still in progress")]
+    NotReady,
+    #[display("{report}")]
+    Provider {
+        provider: &'static str,
+        report: Report,
+    },
+    #[display("domain '{mapped}' has no ownership marker of this server at {provider}")]
+    NotOwned {
+        provider: &'static str,
+        mapped: String,
+    },
+}
+
+/// A record that was (or, in a dry run, would have been) updated.
+#[derive(Debug, Clone)]
+pub struct UpdatedRecord {
+    pub provider: &'static str,
+    pub record_type: DnsRecordType,
+    /// The content before the update, if it was read.
+    pub old_content: Option<String>,
+    pub content: String,
+    /// Whether the content differed. Records already pointing at the address are not written.
+    pub changed: bool,
+    /// The name at the provider, i.e. the hostname after the origin mapping.
+    pub name: String,
+    /// The TTL of the record before the update, if it was read.
+    pub old_ttl: Option<u32>,
+}
+
+/// The AAAA records of one provider moved to a new delegated prefix, see
+/// [`UpdateService::rewrite_prefix`].
+#[derive(Debug)]
+pub struct PrefixRewrite {
+    pub provider: &'static str,
+    pub old_prefix: Ipv6Net,
+    pub new_prefix: Ipv6Net,
+    /// The names of the rewritten records with their new address.
+    pub rewritten: Vec<(String, Ipv6Addr)>,
+    /// The names of the records that could not be rewritten, with the reason. They stay in the
+    /// old prefix, the others are rewritten regardless.
+    pub failed: Vec<(String, Report)>,
+}
+
+/// What the update pipeline decided to do with a single record, see [`UpdateService::plan`].
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedChange {
+    pub provider: &'static str,
+    pub record_type: DnsRecordType,
+    pub action: PlannedAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record_id: Option<RecordId>,
+    /// The current content. Unknown for pinned records during a real update, as they are written
+    /// without reading them first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+    pub rule: PlanRule,
+    /// The name at the provider, i.e. the hostname after the origin mapping.
+    #[serde(skip)]
+    pub name: String,
+    /// The TTL of the record before the update. Resolvers may serve the old content that long.
+    #[serde(skip)]
+    pub old_ttl: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlannedAction {
+    Update,
+    /// The record already has the new content and is not written.
+    Unchanged,
+    Delete,
+    Skip,
+}
+
+/// The reason for a [`PlannedAction`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanRule {
+    /// The record ID is pinned in the config file.
+    PinnedRecord,
+    /// The pinned record does not exist anymore, so the first existing record is used instead.
+    StalePin,
+    /// The first existing record with the name and type.
+    FirstRecord,
+    /// No record with the name and type exists. Records are never created.
+    NoRecord,
+    /// Another record with the same name and type, deleted as `DEDUPE_RECORDS` is enabled.
+    Dedupe,
+    /// Another record with the same name and type, kept as `DEDUPE_RECORDS` is disabled.
+    Duplicate,
+}
+
+impl PlannedChange {
+    fn updated(&self) -> Option<UpdatedRecord> {
+        let changed = match self.action {
+            PlannedAction::Update => true,
+            PlannedAction::Unchanged => false,
+            PlannedAction::Delete | PlannedAction::Skip => return None,
+        };
+        Some(UpdatedRecord {
+            provider: self.provider,
+            record_type: self.record_type.clone(),
+            old_content: self.old_content.clone(),
+            content: self.new_content.clone().unwrap_or_default(),
+            changed,
+            name: self.name.clone(),
+            old_ttl: self.old_ttl,
+        })
+    }
+}
+
+/// The addresses `ip` actually sets for `hostname`, with its configured suffix applied.
+fn effective_update(dns: &DnsConfig, hostname: &str, ip: &ParsedIpUpdate) -> ParsedIpUpdate {
+    match dns.hostnames.get(hostname).and_then(|it| it.suffix) {
+        Some(suffix) => ip.with_ipv6_suffix(suffix),
+        None => ip.clone(),
+    }
+}
+
+/// Points the existing records of `hostname` at the addresses in `ip`, for every provider.
+///
+/// Hostnames outside the origin are rejected. Missing records are skipped, and the first
+/// provider failure aborts the update. Unless `write` is set, nothing is written.
+async fn update_hostname(
+    dns: &DnsConfig,
+    hostname: &str,
+    ip: &ParsedIpUpdate,
+    write: bool,
+) -> Result<Vec<PlannedChange>, UpdateError> {
+    let mut all_changes = Vec::new();
+    let settings = dns.hostnames.get(hostname).cloned().unwrap_or_default();
+    let ip = &effective_update(dns, hostname, ip);
+
+    for provider in &dns.dns_providers {
+        let expected_origin = dns.map_origin(dns.origin_for(provider.as_ref()), provider.as_ref());
+        let mapped = dns.map_hostname(hostname, provider.as_ref());
+        if !expected_origin.is_subdomain(&mapped) {
+            warn!(
+                query = %hostname,
+                mapped = %mapped,
+                expected = %expected_origin,
+                "requested domain is not a subdomain of the configured origin"
+            );
+            return Err(UpdateError::NotInOrigin {
+                hostname: hostname.to_string(),
+                mapped,
+                origin: dns.origin_for(provider.as_ref()),
+            });
+        }
+
+        let changes =
+            match update_record(dns, provider.as_ref(), &mapped, ip, &settings, write).await {
+                Err(e) if NotOwned::is_cause_of(&e) => {
+                    warn!(
+                        query = %hostname,
+                        mapped = %mapped,
+                        provider = provider.name(),
+                        "Refusing to update records without an ownership marker"
+                    );
+                    return Err(UpdateError::NotOwned {
+                        provider: provider.name(),
+                        mapped,
+                    });
+                }
+                Err(e) => {
+                    warn!(
+                        error = %e,
+                        query = %hostname,
+                        mapped = %mapped,
+                        ip = ?ip,
+                        "failed to update DNS record"
+                    );
+                    return Err(UpdateError::Provider {
+                        provider: provider.name(),
+                        report: e,
+                    });
+                }
+                Ok(changes) => changes,
+            };
+        info!(
+            query = %hostname,
+            mapped = %mapped,
+            records = ?changes
+                .iter()
+                .filter_map(PlannedChange::updated)
+                .map(|it| (it.record_type, it.content))
+                .collect::<Vec<_>>(),
+            provider = %provider.name(),
+            dry_run = !write,
+            "successfully updated DNS record"
+        );
+        all_changes.extend(changes);
+    }
+
+    Ok(all_changes)
+}
+
+/// Moves the AAAA records of `provider` from the prefix `hostname` currently is in to `prefix`,
+/// if `may_rewrite` their name and they carry the marker of `ownership`, if any. Returns `None`
+/// if the prefix did not change or `hostname` has no AAAA record to derive the old prefix from.
+async fn rewrite_zone_prefix(
+    provider: &(dyn DnsProvider + Send + Sync),
+    origin: &Origin,
+    hostname: &str,
+    prefix: Ipv6Net,
+    may_rewrite: impl Fn(&str) -> bool,
+    ownership: Option<&Ownership>,
+) -> Result<Option<PrefixRewrite>, Report> {
+    let records = provider
+        .list_records(origin)
+        .instrument(info_span!("list_records", provider = provider.name()))
+        .await?;
+    let address = |record: &DnsEntry| {
+        (record.typ == DnsRecordType::AAAA)
+            .then(|| record.content.parse::<Ipv6Addr>().ok())
+            .flatten()
+    };
+    let Some(current) = records
+        .iter()
+        .filter(|it| it.name == hostname)
+        .find_map(address)
+    else {
+        info!(
+            domain = %hostname,
+            provider = provider.name(),
+            "No AAAA record to derive the old prefix from, not rewriting the zone"
+        );
+        return Ok(None);
+    };
+    let old_prefix = Ipv6Net::new(current, prefix.prefix_len())
+        .expect("prefix length is valid")
+        .trunc();
+    if old_prefix == prefix {
+        debug!(%prefix, provider = provider.name(), "Prefix did not change");
+        return Ok(None);
+    }
+
+    let owned = match ownership {
+        Some(ownership) => Some(
+            ownership
+                .owned_hostnames(provider, origin)
+                .instrument(info_span!("check_ownership", provider = provider.name()))
+                .await?,
+        ),
+        None => None,
+    };
+    let compare_and_set = provider.supports_compare_and_set();
+    let mut rewritten = Vec::new();
+    let mut failed = Vec::new();
+    for record in &records {
+        let Some(address) = address(record).filter(|it| old_prefix.contains(it)) else {
+            continue;
+        };
+        if !may_rewrite(&record.name) {
+            continue;
+        }
+        if let Some(owned) = &owned
+            && !owned.contains(&record.name)
+        {
+            warn!(
+                domain = %record.name,
+                "Record has no ownership marker, not rewriting it"
+            );
+            continue;
+        }
+        let new_address = with_prefix(address, prefix);
+        let result = provider
+            .update_record(
+                origin,
+                &record.to_ref(),
+                compare_and_set.then_some(record.content.as_str()),
+                &new_address.to_string(),
+                &RecordOptions::default(),
+            )
+            .instrument(info_span!("update_record", provider = provider.name()))
+            .await;
+        match result {
+            Ok(()) => rewritten.push((record.name.clone(), new_address)),
+            // Someone else moved it already, it is not ours to touch anymore
+            Err(e) if RecordConflict::find(&e).is_some() => {
+                warn!(
+                    domain = %record.name,
+                    error = %e,
+                    "Record was changed concurrently, not rewriting it"
+                );
+            }
+            // Moving the other records still helps, and the update of the hostname does not
+            // depend on them
+            Err(e) => {
+                warn!(
+                    domain = %record.name,
+                    error = %e,
+                    "Failed to rewrite record to the new prefix"
+                );
+                let report = e
+                    .context("Failed to rewrite record to the new prefix")
+                    .attach(format!("For domain '{}'", record.name))
+                    .into_dynamic();
+                failed.push((record.name.clone(), report));
+            }
+        }
+    }
+
+    let names = rewritten.iter().map(|it| &it.0).collect::<Vec<_>>();
+    let failed_names = failed.iter().map(|it| &it.0).collect::<Vec<_>>();
+    info!(
+        provider = provider.name(),
+        %old_prefix,
+        new_prefix = %prefix,
+        count = names.len(),
+        names = ?names,
+        failed = ?failed_names,
+        "Rewrote AAAA records to the new prefix"
+    );
+    Ok(Some(PrefixRewrite {
+        provider: provider.name(),
+        old_prefix,
+        new_prefix: prefix,
+        rewritten,
+        failed,
+    }))
+}
+
+/// Replaces the bits of `address` covered by `prefix` with those of `prefix`.
+fn with_prefix(address: Ipv6Addr, prefix: Ipv6Net) -> Ipv6Addr {
+    let host_mask = prefix.hostmask().to_bits();
+    Ipv6Addr::from_bits((address.to_bits() & host_mask) | (prefix.network().to_bits() & !host_mask))
+}
+
+async fn update_record(
+    dns: &DnsConfig,
+    provider: &(dyn DnsProvider + Send + Sync),
+    domain: &str,
+    ip: &ParsedIpUpdate,
+    settings: &HostnameConfig,
+    write: bool,
+) -> Result<Vec<PlannedChange>, Report> {
+    let mut changes = Vec::new();
+    let origin = dns.origin_for(provider);
+    let options = settings.record_options();
+    // What the record should look like, including the settings the provider fills in
+    let wanted = options.or(provider.default_options());
+    // Only listed if a record is not pinned, or its pin turns out to be stale
+    let mut records: Option<Vec<DnsEntry>> = None;
+    let mut owned = false;
+
+    for (record_type, new_ip) in ip.records() {
+        let change = |action, record: Option<&DnsEntry>, rule| PlannedChange {
+            provider: provider.name(),
+            record_type: record_type.clone(),
+            action,
+            record_id: record.map(|it| it.id.clone()),
+            old_content: record.map(|it| it.content.clone()),
+            new_content: matches!(action, PlannedAction::Update | PlannedAction::Unchanged)
+                .then(|| new_ip.clone()),
+            ttl: options.ttl.filter(|_| action == PlannedAction::Update),
+            proxied: options.proxied.filter(|_| action == PlannedAction::Update),
+            rule,
+            name: domain.to_string(),
+            old_ttl: record.and_then(|it| it.ttl),
+        };
+        let write_record = async |record: &RecordRef, expected: Option<&str>| {
+            provider
+                .update_record(&origin, record, expected, new_ip, &options)
+                .instrument(info_span!(
+                    "update_record",
+                    provider = provider.name(),
+                    record_type = %record_type
+                ))
+                .await
+                .attach(format!("For domain '{domain}'"))
+                .attach(format!("For {:?} record", record_type))
+        };
+
+        let mut rule = PlanRule::FirstRecord;
+        if let Some(record_id) = settings.pinned_record(record_type) {
+            if write {
+                // Pinned records are written without reading them first
+                let record = RecordRef {
+                    typ: record_type.clone(),
+                    id: record_id.clone(),
+                    name: domain.to_string(),
+                };
+                ensure_owned(dns, provider, &origin, domain, &mut owned).await?;
+                match write_record(&record, None).await {
+                    Ok(()) => {
+                        changes.push(PlannedChange {
+                            record_id: Some(record_id),
+                            ..change(PlannedAction::Update, None, PlanRule::PinnedRecord)
+                        });
+                        continue;
+                    }
+                    Err(e) if RecordNotFound::is_cause_of(&e) => {}
+                    Err(e) => return Err(e),
+                }
+            } else {
+                let records = list_records(dns, provider, &origin, domain, &mut records).await?;
+                if let Some(record) = records.iter().find(|it| it.id == record_id) {
+                    info!(
+                        domain = %domain,
+                        record_type = ?record_type,
+                        record_id = %record_id,
+                        old = %record.content,
+                        new = %new_ip,
+                        "Dry run, not updating pinned record"
+                    );
+                    let action = if record.is_up_to_date(new_ip, &wanted) {
+                        PlannedAction::Unchanged
+                    } else {
+                        ensure_owned(dns, provider, &origin, domain, &mut owned).await?;
+                        PlannedAction::Update
+                    };
+                    changes.push(change(action, Some(record), PlanRule::PinnedRecord));
+                    continue;
+                }
+            }
+            warn!(
+                domain = %domain,
+                record_type = ?record_type,
+                record_id = %record_id,
+                "Pinned record does not exist anymore, the pin is stale. Looking it up instead"
+            );
+            rule = PlanRule::StalePin;
+        }
+
+        if dns
+            .negative_cache
+            .is_missing(provider.name(), &origin, domain, record_type)
+        {
+            debug!(
+                domain = %domain,
+                record_type = ?record_type,
+                "No record found recently, skipping update"
+            );
+            changes.push(change(PlannedAction::Skip, None, PlanRule::NoRecord));
+            continue;
+        }
+        let matching = list_records(dns, provider, &origin, domain, &mut records)
+            .await?
+            .iter()
+            .filter(|it| &it.typ == record_type)
+            .cloned()
+            .collect::<Vec<_>>();
+        let Some((record, duplicates)) = matching.split_first() else {
+            info!(
+                domain = %domain,
+                record_type= ?record_type,
+                "No existing record found, skipping update"
+            );
+            dns.negative_cache
+                .insert(provider.name(), &origin, domain, record_type);
+            changes.push(change(PlannedAction::Skip, None, PlanRule::NoRecord));
+            continue;
+        };
+        if !duplicates.is_empty() {
+            warn!(
+                domain = %domain,
+                record_type = ?record_type,
+                records = ?matching
+                    .iter()
+                    .map(|it| format!("{}={}", it.id, it.content))
+                    .collect::<Vec<_>>(),
+                dedupe = dns.dedupe_records,
+                "Found duplicate records, only the first one is updated"
+            );
+        }
+        if record.is_up_to_date(new_ip, &wanted) {
+            info!(
+                domain = %domain,
+                record_type = ?record_type,
+                content = %record.content,
+                "Record is up to date, not updating it"
+            );
+            changes.push(change(PlannedAction::Unchanged, Some(record), rule));
+        } else {
+            ensure_owned(dns, provider, &origin, domain, &mut owned).await?;
+            if write {
+                // Another writer changing the record in the meantime is retried once with its
+                // content, so its change is not silently overwritten
+                let compare_and_set = provider.supports_compare_and_set();
+                let mut current = record.clone();
+                let mut retried = false;
+                while let Err(e) = write_record(
+                    &current.to_ref(),
+                    compare_and_set.then_some(current.content.as_str()),
+                )
+                .await
+                {
+                    let Some(conflict) = RecordConflict::find(&e) else {
+                        return Err(e);
+                    };
+                    if retried {
+                        warn!(
+                            domain = %domain,
+                            record_type = ?record_type,
+                            expected = %conflict.expected,
+                            actual = %conflict.actual,
+                            "Record was changed concurrently again, giving up"
+                        );
+                        return Err(e);
+                    }
+                    info!(
+                        domain = %domain,
+                        record_type = ?record_type,
+                        expected = %conflict.expected,
+                        actual = %conflict.actual,
+                        "Record was changed concurrently, reading it again"
+                    );
+                    records = None;
+                    let Some(reread) = list_records(dns, provider, &origin, domain, &mut records)
+                        .await?
+                        .iter()
+                        .find(|it| it.id == current.id)
+                        .cloned()
+                    else {
+                        return Err(e);
+                    };
+                    current = reread;
+                    retried = true;
+                }
+            } else {
+                info!(
+                    domain = %domain,
+                    record_type = ?record_type,
+                    old = %record.content,
+                    new = %new_ip,
+                    "Dry run, not updating record"
+                );
+            }
+            changes.push(change(PlannedAction::Update, Some(record), rule));
+        }
+
+        if dns.dedupe_records && !duplicates.is_empty() {
+            ensure_owned(dns, provider, &origin, domain, &mut owned).await?;
+            delete_duplicates(provider, &origin, domain, duplicates, write).await?;
+        }
+        for duplicate in duplicates {
+            changes.push(if dns.dedupe_records {
+                change(PlannedAction::Delete, Some(duplicate), PlanRule::Dedupe)
+            } else {
+                change(PlannedAction::Skip, Some(duplicate), PlanRule::Duplicate)
+            });
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Fails unless `domain` has the marker of [`DnsConfig::ownership`], if set. Only checked before
+/// the first write of an update, records that are up to date need no further API call.
+async fn ensure_owned(
+    dns: &DnsConfig,
+    provider: &(dyn DnsProvider + Send + Sync),
+    origin: &Origin,
+    domain: &str,
+    owned: &mut bool,
+) -> Result<(), Report> {
+    let Some(ownership) = dns.ownership.as_ref().filter(|_| !*owned) else {
+        return Ok(());
+    };
+    ownership
+        .check(provider, origin, domain)
+        .instrument(info_span!("check_ownership", provider = provider.name()))
+        .await?;
+    *owned = true;
+    Ok(())
+}
+
+/// The records of `domain`, listed on first use and cached in `records` afterwards. Listed
+/// records are dropped from the [`NegativeCache`](crate::negative_cache::NegativeCache).
+async fn list_records<'a>(
+    dns: &DnsConfig,
+    provider: &(dyn DnsProvider + Send + Sync),
+    origin: &Origin,
+    domain: &str,
+    records: &'a mut Option<Vec<DnsEntry>>,
+) -> Result<&'a [DnsEntry], Report> {
+    if records.is_none() {
+        let listed = provider
+            .list_records(origin)
+            .instrument(info_span!("list_records", provider = provider.name()))
+            .await?
+            .into_iter()
+            .filter(|r| r.name == domain)
+            .collect::<Vec<_>>();
+        for record in &listed {
+            dns.negative_cache
+                .remove(provider.name(), origin, domain, &record.typ);
+        }
+        *records = Some(listed);
+    }
+    Ok(records.as_deref().unwrap_or_default())
+}
+
+/// Deletes the records in `duplicates`, after their first sibling was updated.
+async fn delete_duplicates(
+    provider: &(dyn DnsProvider + Send + Sync),
+    origin: &Origin,
+    domain: &str,
+    duplicates: &[DnsEntry],
+    write: bool,
+) -> Result<(), Report> {
+    for duplicate in duplicates {
+        if !write {
+            info!(
+                domain = %domain,
+                record_id = %duplicate.id,
+                content = %duplicate.content,
+                "Dry run, not deleting duplicate record"
+            );
+            continue;
+        }
+        provider
+            .delete_record(origin, &duplicate.id)
+            .instrument(info_span!("delete_record", provider = provider.name()))
+            .await
+            .context("Failed to delete duplicate record")
+            .attach(format!("For domain '{domain}'"))
+            .attach(format!("record_id: '{}'", duplicate.id))?;
+        info!(
+            domain = %domain,
+            record_type = ?duplicate.typ,
+            record_id = %duplicate.id,
+            content = %duplicate.content,
+            provider = provider.name(),
+            "Deleted duplicate record"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::TxtRecord;
+    use crate::provider::memory::MemoryProvider;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn moved(address: &str, prefix: &str) -> Ipv6Addr {
+        with_prefix(address.parse().unwrap(), prefix.parse().unwrap())
+    }
+
+    #[test]
+    fn only_the_delegated_bits_are_replaced() {
+        assert_eq!(
+            moved("2001:db8:1100:1::7", "2001:db8:3400::/56"),
+            "2001:db8:3400:1::7".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            moved("2001:db8:1100:f::7", "2001:db8:3400:50::/60"),
+            "2001:db8:3400:5f::7".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            moved("2001:db8:1100:1:aaaa::7", "2001:db8:3400:2::/64"),
+            "2001:db8:3400:2:aaaa::7".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn prefix_lengths_off_the_nibble_boundary_are_masked_exactly() {
+        // A /57 keeps the lowest 7 bits of the fourth group
+        assert_eq!(
+            moved("2001:db8:1100:ff::1", "2001:db8:3400:80::/57"),
+            "2001:db8:3400:ff::1".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            moved("2001:db8:1100:7f::1", "2001:db8:3400:80::/57"),
+            "2001:db8:3400:ff::1".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    /// A memory provider counting how often all TXT records are listed.
+    #[derive(Default)]
+    struct CountingProvider {
+        inner: MemoryProvider,
+        txt_listings: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl DnsProvider for CountingProvider {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        async fn list_records(&self, origin: &Origin) -> Result<Vec<DnsEntry>, Report> {
+            self.inner.list_records(origin).await
+        }
+
+        async fn update_record(
+            &self,
+            origin: &Origin,
+            record: &RecordRef,
+            expected: Option<&str>,
+            new_content: &str,
+            options: &RecordOptions,
+        ) -> Result<(), Report> {
+            self.inner
+                .update_record(origin, record, expected, new_content, options)
+                .await
+        }
+
+        async fn delete_record(&self, origin: &Origin, record_id: &RecordId) -> Result<(), Report> {
+            self.inner.delete_record(origin, record_id).await
+        }
+
+        async fn validate(&self, origin: &Origin) -> Result<(), Report> {
+            self.inner.validate(origin).await
+        }
+
+        async fn list_all_txt_records(&self, origin: &Origin) -> Result<Vec<TxtRecord>, Report> {
+            self.txt_listings.fetch_add(1, Ordering::Relaxed);
+            self.inner.list_all_txt_records(origin).await
+        }
+    }
+
+    fn aaaa(name: &str, content: &str) -> DnsEntry {
+        DnsEntry {
+            typ: DnsRecordType::AAAA,
+            id: RecordId(name.to_string()),
+            name: name.to_string(),
+            content: content.to_string(),
+            ttl: None,
+            proxied: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn prefix_rewrite_lists_the_ownership_markers_once() {
+        let origin = Origin::parse("foobar.de").unwrap();
+        let ownership = Ownership::new("server-a").unwrap();
+        let mut records = vec![aaaa("router.foobar.de", "2001:db8:1100::1")];
+        for host in 1..=20 {
+            records.push(aaaa(
+                &format!("host{host}.foobar.de"),
+                &format!("2001:db8:1100:{host:x}::7"),
+            ));
+        }
+        let provider = CountingProvider {
+            inner: MemoryProvider::new(records.clone()),
+            ..Default::default()
+        };
+        // Every other host is owned
+        for record in records.iter().step_by(2) {
+            ownership
+                .stamp(&provider.inner, &origin, &record.name)
+                .await
+                .unwrap();
+        }
+
+        let rewrite = rewrite_zone_prefix(
+            &provider,
+            &origin,
+            "router.foobar.de",
+            "2001:db8:3400::/56".parse().unwrap(),
+            |_| true,
+            Some(&ownership),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(provider.txt_listings.load(Ordering::Relaxed), 1);
+        assert_eq!(rewrite.rewritten.len(), 11);
+        assert!(rewrite.failed.is_empty());
+        assert!(
+            rewrite
+                .rewritten
+                .iter()
+                .all(|(name, _)| records.iter().step_by(2).any(|it| &it.name == name))
+        );
+    }
+}
@@ -0,0 +1,49 @@
+//! Version and build information embedded at compile time by `build.rs`.
+
+use crate::provider::compiled_providers;
+use jiff::Timestamp;
+use serde::Serialize;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_COMMIT: &str = env!("BUILD_GIT_COMMIT");
+const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+const FEATURES: &str = env!("BUILD_FEATURES");
+
+/// The version including the commit and the compiled-in providers, as shown by `--version`.
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("BUILD_GIT_COMMIT"),
+    ")\nproviders: ",
+    env!("BUILD_PROVIDERS")
+);
+
+/// Build information reported by the `/version` endpoint. Must not contain anything sensitive,
+/// as the endpoint is unauthenticated.
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    pub commit: &'static str,
+    pub build_timestamp: Option<Timestamp>,
+    pub features: Vec<&'static str>,
+    /// The configured providers.
+    pub providers: Vec<String>,
+    /// The providers this binary supports, e.g. `cloudflare` or `memory`.
+    pub compiled_providers: Vec<&'static str>,
+}
+
+impl VersionInfo {
+    pub fn new(providers: Vec<String>) -> Self {
+        Self {
+            version: VERSION,
+            commit: GIT_COMMIT,
+            build_timestamp: BUILD_TIMESTAMP
+                .parse()
+                .ok()
+                .and_then(|it| Timestamp::from_second(it).ok()),
+            features: FEATURES.split(',').filter(|it| !it.is_empty()).collect(),
+            providers,
+            compiled_providers: compiled_providers(),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! A self-contained updater for hosts without a usable dyndns client on the router: `watch`
+//! periodically looks up the public addresses of this host and pushes changes through the
+//! [`UpdateService`], without going through the HTTP server.
+
+use crate::ip_update::ParsedIpUpdate;
+use crate::metrics;
+use crate::provider::DnsRecordType;
+use crate::status::StatusTracker;
+use crate::types::DnsConfig;
+use crate::update::{UpdateRequest, UpdateService};
+use axum::Router;
+use axum::routing::get;
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+pub mod interface;
+
+/// Services answering with the address of the caller in plain text. All of them are reachable
+/// via IPv4 and IPv6.
+pub const DEFAULT_IP_URLS: &[&str] = &[
+    "https://icanhazip.com",
+    "https://api64.ipify.org",
+    "https://ifconfig.co/ip",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+/// Where the IPv6 address is taken from. IPv4 is always looked up via HTTP, as interfaces
+/// usually only have a private IPv4 address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Ipv6Source {
+    /// The lookup URLs of [`IpDiscovery`].
+    #[default]
+    Http,
+    /// The named interface, see [`interface`].
+    Interface(String),
+}
+
+impl FromStr for Ipv6Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "http" => Ok(Self::Http),
+            Some(("interface", name)) if !name.is_empty() => Ok(Self::Interface(name.to_string())),
+            _ => Err(format!(
+                "invalid address source '{s}', expected 'http' or 'interface:<name>'"
+            )),
+        }
+    }
+}
+
+/// Looks up the public addresses of this host via "what is my IP" services.
+pub struct IpDiscovery {
+    urls: Vec<String>,
+    /// Bound to the unspecified address of their family, so the services see that one.
+    ipv4: reqwest::Client,
+    ipv6: reqwest::Client,
+}
+
+impl IpDiscovery {
+    pub fn new(urls: Vec<String>, timeout: Duration) -> Result<Self, Report> {
+        if urls.is_empty() {
+            bail!("No address lookup URL configured");
+        }
+        let client = |local: IpAddr| {
+            reqwest::Client::builder()
+                .local_address(local)
+                .timeout(timeout)
+                .build()
+                .context("Failed to create HTTP client")
+        };
+        Ok(Self {
+            urls,
+            ipv4: client(Ipv4Addr::UNSPECIFIED.into())?,
+            ipv6: client(Ipv6Addr::UNSPECIFIED.into())?,
+        })
+    }
+
+    /// Queries every URL and returns the address a majority of the answering ones agree on.
+    /// Failing URLs are ignored, so a single answer is enough if only one URL responds.
+    pub async fn discover(&self, family: IpFamily) -> Option<IpAddr> {
+        let mut answers = Vec::new();
+        for url in &self.urls {
+            match self.query(url, family).await {
+                Ok(ip) => answers.push(ip),
+                Err(e) => debug!(%url, ?family, error = %e, "Address lookup failed"),
+            }
+        }
+
+        let address = majority(&answers);
+        if address.is_none() && !answers.is_empty() {
+            warn!(?family, ?answers, "Address lookups disagree, ignoring them");
+        }
+        address
+    }
+
+    async fn query(&self, url: &str, family: IpFamily) -> Result<IpAddr, Report> {
+        let client = match family {
+            IpFamily::V4 => &self.ipv4,
+            IpFamily::V6 => &self.ipv6,
+        };
+        let response = client.get(url).send().await.context("Requesting address")?;
+        if !response.status().is_success() {
+            bail!("Address lookup returned status {}", response.status());
+        }
+        let body = response.text().await.context("Reading address")?;
+        let ip = body
+            .trim()
+            .parse::<IpAddr>()
+            .context("Invalid address")
+            .attach(format!("response: {:?}", body.trim()))?;
+        if ip.is_ipv4() != (family == IpFamily::V4) {
+            bail!("Address lookup returned {ip}, which is of the wrong family");
+        }
+        Ok(ip)
+    }
+}
+
+/// The address more than half of `answers` agree on.
+fn majority(answers: &[IpAddr]) -> Option<IpAddr> {
+    answers
+        .iter()
+        .find(|candidate| answers.iter().filter(|it| it == candidate).count() * 2 > answers.len())
+        .copied()
+}
+
+/// The addresses last pushed for every hostname, persisted so restarts do not update again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WatchState {
+    hostnames: BTreeMap<String, PushedAddresses>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct PushedAddresses {
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+}
+
+impl WatchState {
+    /// Loads the state from `path`, starting empty if it does not exist yet.
+    pub fn load(path: &Path) -> Result<Self, Report> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .context("Failed to read watch state")
+            .attach(format!("path: {}", path.display()))?;
+        Ok(serde_json::from_str(&content)
+            .context("Invalid watch state")
+            .attach(format!("path: {}", path.display()))?)
+    }
+
+    /// Replaces the file at `path` atomically.
+    pub fn save(&self, path: &Path) -> Result<(), Report> {
+        let content = serde_json::to_string_pretty(self).context("Failed to serialize state")?;
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, content)
+            .context("Failed to write watch state")
+            .attach(format!("path: {}", temporary.display()))?;
+        std::fs::rename(&temporary, path)
+            .context("Failed to replace watch state")
+            .attach(format!("path: {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Settings of the `watch` command.
+pub struct WatchConfig {
+    pub hostnames: Vec<String>,
+    pub interval: Duration,
+    pub families: Vec<IpFamily>,
+    pub ipv6_source: Ipv6Source,
+    /// Selects the interface address ending in this interface ID, see [`interface`].
+    pub interface_id: Option<Ipv6Addr>,
+    pub state_file: PathBuf,
+    pub dry_run: bool,
+}
+
+/// Checks the addresses every [`WatchConfig::interval`] until `shutdown` completes.
+pub async fn run(
+    config: &WatchConfig,
+    updates: &UpdateService,
+    discovery: &IpDiscovery,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), Report> {
+    let mut state = WatchState::load(&config.state_file)?;
+    info!(
+        hostnames = ?config.hostnames,
+        interval = ?config.interval,
+        state_file = %config.state_file.display(),
+        dry_run = config.dry_run,
+        "Watching the public addresses"
+    );
+
+    tokio::pin!(shutdown);
+    loop {
+        check(config, updates, discovery, &mut state).await;
+        tokio::select! {
+            _ = tokio::time::sleep(config.interval) => {}
+            _ = &mut shutdown => return Ok(()),
+        }
+    }
+}
+
+/// Looks up the addresses once and updates the hostnames whose pushed addresses differ.
+async fn check(
+    config: &WatchConfig,
+    updates: &UpdateService,
+    discovery: &IpDiscovery,
+    state: &mut WatchState,
+) {
+    let mut current = PushedAddresses::default();
+    for family in &config.families {
+        let address = match (family, &config.ipv6_source) {
+            (IpFamily::V6, Ipv6Source::Interface(name)) => {
+                match interface::interface_ipv6(name, config.interface_id) {
+                    Ok(address) => address.map(IpAddr::V6),
+                    Err(e) => {
+                        warn!(error = %e, "Failed to read the interface addresses");
+                        None
+                    }
+                }
+            }
+            _ => discovery.discover(*family).await,
+        };
+        match (family, address) {
+            (IpFamily::V4, Some(IpAddr::V4(ip))) => current.ipv4 = Some(ip),
+            (IpFamily::V6, Some(IpAddr::V6(ip))) => current.ipv6 = Some(ip),
+            _ => warn!(?family, "Could not determine the public address"),
+        }
+    }
+
+    let mut changed_state = false;
+    for hostname in &config.hostnames {
+        let pushed = state.hostnames.get(hostname).cloned().unwrap_or_default();
+        let ipv4 = current.ipv4.filter(|it| pushed.ipv4 != Some(*it));
+        let ipv6 = current.ipv6.filter(|it| pushed.ipv6 != Some(*it));
+        let Some(ip) = ParsedIpUpdate::new(ipv4, ipv6) else {
+            debug!(%hostname, "Addresses did not change");
+            continue;
+        };
+
+        info!(%hostname, ?ipv4, ?ipv6, "Addresses changed, updating");
+        let request = UpdateRequest {
+            hostname: hostname.clone(),
+            ip,
+            client: None,
+            request_id: None,
+            alias_of: None,
+            dry_run: config.dry_run,
+        };
+        let updated = match updates.apply(&request).await {
+            Ok(updated) => updated,
+            Err(e) => {
+                warn!(%hostname, error = %e, "Update failed, retrying with the next check");
+                continue;
+            }
+        };
+        if updated.is_empty() {
+            warn!(%hostname, "No existing records found, nothing was updated");
+        }
+        if config.dry_run {
+            continue;
+        }
+
+        let has = |typ: DnsRecordType| updated.iter().any(|it| it.record_type == typ);
+        let entry = state.hostnames.entry(hostname.clone()).or_default();
+        if has(DnsRecordType::A) {
+            entry.ipv4 = ipv4;
+        }
+        if has(DnsRecordType::AAAA) {
+            entry.ipv6 = ipv6;
+        }
+        changed_state = true;
+    }
+
+    if changed_state && let Err(e) = state.save(&config.state_file) {
+        warn!(error = %e, "Failed to save the watch state");
+    }
+}
+
+/// Serves `/metrics` for the updates of `watch`.
+pub fn metrics_router(dns: Arc<DnsConfig>, status: Arc<StatusTracker>) -> Router {
+    Router::new().route(
+        "/metrics",
+        get(move || async move { metrics::render(&status, &dns, &dns.api_usage(), false) }),
+    )
+}
@@ -0,0 +1,212 @@
+//! Finds the public IPv6 address configured on a network interface, which is more reliable than
+//! a lookup service on hosts using privacy extensions: those see the temporary address.
+//!
+//! Only global unicast addresses (`2000::/3`) of the interface are candidates, so link-local and
+//! unique local addresses are never used. Of the candidates,
+//!
+//! 1. addresses that are not deprecated win over deprecated ones,
+//! 2. then stable addresses win over temporary ones,
+//! 3. then the order reported by the operating system decides.
+//!
+//! Whether an address is temporary or deprecated is only known on Linux, where it is read from
+//! `/proc/net/if_inet6`. Elsewhere, all addresses count as stable and current.
+//!
+//! With an interface ID, only the candidate ending in it (in its lower 64 bits) is used,
+//! regardless of its flags.
+
+use if_addrs::IfAddr;
+use rootcause::prelude::ResultExt;
+use rootcause::{Report, bail};
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+
+/// `IFA_F_TEMPORARY` of the kernel.
+const TEMPORARY_FLAG: u32 = 0x01;
+/// `IFA_F_DEPRECATED` of the kernel.
+const DEPRECATED_FLAG: u32 = 0x20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub address: Ipv6Addr,
+    /// A privacy extension address, see RFC 8981.
+    pub temporary: bool,
+    /// Its preferred lifetime expired, it is only kept for existing connections.
+    pub deprecated: bool,
+}
+
+/// The address of `interface` to use for the AAAA record, if it has a suitable one.
+pub fn interface_ipv6(
+    interface: &str,
+    interface_id: Option<Ipv6Addr>,
+) -> Result<Option<Ipv6Addr>, Report> {
+    let interfaces = if_addrs::get_if_addrs().context("Failed to list network interfaces")?;
+    if !interfaces.iter().any(|it| it.name == interface) {
+        let mut names = interfaces
+            .iter()
+            .map(|it| it.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        bail!(
+            "Interface '{interface}' not found, available: {}",
+            names.join(", ")
+        );
+    }
+
+    let flags = address_flags();
+    let candidates = interfaces
+        .iter()
+        .filter(|it| it.name == interface)
+        .filter_map(|it| match &it.addr {
+            IfAddr::V6(addr) => Some(addr.ip),
+            IfAddr::V4(_) => None,
+        })
+        .map(|address| {
+            let flags = flags
+                .get(&(interface.to_string(), address))
+                .copied()
+                .unwrap_or_default();
+            Candidate {
+                address,
+                temporary: flags & TEMPORARY_FLAG != 0,
+                deprecated: flags & DEPRECATED_FLAG != 0,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(select(&candidates, interface_id))
+}
+
+/// Picks the address to use from `candidates` in the order described in the module docs.
+pub fn select(candidates: &[Candidate], interface_id: Option<Ipv6Addr>) -> Option<Ipv6Addr> {
+    const HOST_MASK: u128 = u64::MAX as u128;
+    candidates
+        .iter()
+        .filter(|it| is_global_unicast(it.address))
+        .filter(|it| {
+            interface_id
+                .is_none_or(|id| it.address.to_bits() & HOST_MASK == id.to_bits() & HOST_MASK)
+        })
+        .min_by_key(|it| (it.deprecated, it.temporary))
+        .map(|it| it.address)
+}
+
+fn is_global_unicast(address: Ipv6Addr) -> bool {
+    address.segments()[0] & 0xe000 == 0x2000
+}
+
+/// The kernel flags of every IPv6 address by interface, empty if they are not available.
+fn address_flags() -> HashMap<(String, Ipv6Addr), u32> {
+    std::fs::read_to_string("/proc/net/if_inet6")
+        .map(|it| parse_flags(&it))
+        .unwrap_or_default()
+}
+
+/// Parses the content of `/proc/net/if_inet6`. Lines look like
+/// `20010db8000000000000000000000001 02 40 00 80 eth0`.
+fn parse_flags(content: &str) -> HashMap<(String, Ipv6Addr), u32> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let [address, _index, _prefix, _scope, flags, name] = fields[..] else {
+                return None;
+            };
+            let address = Ipv6Addr::from_bits(u128::from_str_radix(address, 16).ok()?);
+            let flags = u32::from_str_radix(flags, 16).ok()?;
+            Some(((name.to_string(), address), flags))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STABLE: &str = "2001:db8:1200:1:12:34ff:fe56:7890";
+    const TEMPORARY: &str = "2001:db8:1200:1:a1b2:c3d4:e5f6:1";
+    const ULA: &str = "fd00::12:34ff:fe56:7890";
+    const LINK_LOCAL: &str = "fe80::12:34ff:fe56:7890";
+
+    fn candidate(address: &str, temporary: bool, deprecated: bool) -> Candidate {
+        Candidate {
+            address: address.parse().unwrap(),
+            temporary,
+            deprecated,
+        }
+    }
+
+    fn stable(address: &str) -> Candidate {
+        candidate(address, false, false)
+    }
+
+    fn address(address: &str) -> Option<Ipv6Addr> {
+        Some(address.parse().unwrap())
+    }
+
+    #[test]
+    fn only_global_unicast_addresses_are_used() {
+        assert_eq!(select(&[stable(ULA), stable(LINK_LOCAL)], None), None);
+        assert_eq!(
+            select(&[stable(LINK_LOCAL), stable(ULA), stable(STABLE)], None),
+            address(STABLE)
+        );
+    }
+
+    #[test]
+    fn stable_address_wins_over_temporary() {
+        let candidates = [candidate(TEMPORARY, true, false), stable(STABLE)];
+        assert_eq!(select(&candidates, None), address(STABLE));
+    }
+
+    #[test]
+    fn temporary_address_is_used_if_there_is_no_other() {
+        let candidates = [stable(LINK_LOCAL), candidate(TEMPORARY, true, false)];
+        assert_eq!(select(&candidates, None), address(TEMPORARY));
+    }
+
+    #[test]
+    fn current_address_wins_over_deprecated() {
+        let candidates = [
+            candidate(STABLE, false, true),
+            candidate(TEMPORARY, true, false),
+        ];
+        assert_eq!(select(&candidates, None), address(TEMPORARY));
+    }
+
+    #[test]
+    fn first_address_wins_between_equals() {
+        let other = "2001:db8:1200:2::1";
+        assert_eq!(
+            select(&[stable(other), stable(STABLE)], None),
+            address(other)
+        );
+    }
+
+    #[test]
+    fn interface_id_overrides_the_preference() {
+        let candidates = [stable(STABLE), candidate(TEMPORARY, true, true)];
+        let interface_id = "::a1b2:c3d4:e5f6:1".parse().ok();
+        assert_eq!(select(&candidates, interface_id), address(TEMPORARY));
+
+        let unknown_id = "::1".parse().ok();
+        assert_eq!(select(&candidates, unknown_id), None);
+    }
+
+    #[test]
+    fn kernel_flags_are_parsed() {
+        let content = "\
+20010db812000001001234fffe567890 02 40 00 80 eth0
+20010db812000001a1b2c3d4e5f60001 02 40 00 01 eth0
+fe80000000000000001234fffe567890 02 40 20 80 eth0
+garbage
+";
+        let flags = parse_flags(content);
+
+        let flag = |address: &str| flags.get(&("eth0".to_string(), address.parse().unwrap()));
+        assert_eq!(flag(STABLE), Some(&0x80));
+        assert_eq!(flag(TEMPORARY), Some(&TEMPORARY_FLAG));
+        assert_eq!(flag(LINK_LOCAL), Some(&0x80));
+        assert_eq!(flags.len(), 3);
+    }
+}
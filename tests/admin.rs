@@ -0,0 +1,73 @@
+//! Tests of the `/admin` endpoints and the commands sharing their output.
+
+#![allow(unused_crate_dependencies)]
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{StatusCode, header};
+use common::*;
+use speedport_custom_dyndns::provider::memory::MemoryProvider;
+use std::sync::Arc;
+
+const ADMIN_TOKEN: &str = "admin-token-0123456789";
+
+#[tokio::test]
+async fn admin_records_endpoint_matches_list_records() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = builder(&provider)
+        .admin_token(ADMIN_TOKEN)
+        .build()
+        .unwrap()
+        .router();
+
+    let request = request("/admin/records")
+        .header(header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&router, request).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    let served: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    let golden: serde_json::Value =
+        serde_json::from_str(include_str!("golden/list_records.json")).unwrap();
+    assert_eq!(served, golden);
+}
+
+/// Records with an explicit, an automatic and no TTL, at the apex and nested below it.
+fn export_records() -> Vec<speedport_custom_dyndns::DnsEntry> {
+    use speedport_custom_dyndns::DnsRecordType::{A, AAAA};
+    let with_ttl = |mut record: speedport_custom_dyndns::DnsEntry, ttl| {
+        record.ttl = Some(ttl);
+        record
+    };
+    vec![
+        with_ttl(record("apex", A, "foobar.de", "192.0.2.10"), 3600),
+        record("a", A, "nas.foobar.de", "192.0.2.1"),
+        with_ttl(record("aaaa", AAAA, "nas.foobar.de", "2001:db8::1"), 1),
+        with_ttl(record("nested", A, "a.b.foobar.de", "192.0.2.20"), 60),
+    ]
+}
+
+#[tokio::test]
+async fn bind_export_matches_golden_file() {
+    let provider = Arc::new(MemoryProvider::new(export_records()));
+    let router = builder(&provider)
+        .admin_token(ADMIN_TOKEN)
+        .build()
+        .unwrap()
+        .router();
+
+    let request = request("/admin/export?format=bind")
+        .header(header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&router, request).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.headers[header::CONTENT_TYPE],
+        "text/plain; charset=utf-8"
+    );
+    assert_eq!(response.body, include_str!("golden/export.zone"));
+}
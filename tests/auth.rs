@@ -0,0 +1,331 @@
+//! End-to-end tests of the auth middleware.
+
+#![allow(unused_crate_dependencies)]
+
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{HeaderValue, Request, StatusCode, header};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use common::*;
+use speedport_custom_dyndns::provider::memory::MemoryProvider;
+use speedport_custom_dyndns::{ApiToken, LockoutConfig};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+#[test]
+fn query_auth_requires_https() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+
+    assert!(builder(&provider).allow_query_auth(true).build().is_err());
+    assert!(
+        builder(&provider)
+            .allow_query_auth(true)
+            .require_https(true)
+            .build()
+            .is_ok()
+    );
+    assert!(
+        builder(&provider)
+            .allow_query_auth(true)
+            .allow_insecure_query_auth(true)
+            .build()
+            .is_ok()
+    );
+}
+
+const TOKEN: &str = "query-token-0123456789";
+
+fn query_auth_router(provider: &Arc<MemoryProvider>) -> Router {
+    builder(provider)
+        .api_token(ApiToken {
+            token: TOKEN.to_string(),
+            allowed_hostnames: None,
+        })
+        .allow_query_auth(true)
+        .require_https(true)
+        .trusted_proxy("192.0.2.100/32".parse().unwrap())
+        .build()
+        .unwrap()
+        .router()
+}
+
+/// An unauthenticated update forwarded via HTTPS by the trusted proxy.
+fn forwarded(query: &str) -> Request<Body> {
+    request(&format!("/nic/update?{query}"))
+        .header("X-Forwarded-Proto", "https")
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn query_key_authenticates() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = query_auth_router(&provider);
+    let (logs, _guard) = capture_logs();
+
+    let response = send(
+        &router,
+        forwarded(&format!(
+            "hostname=nas.foobar.de&key={TOKEN}&myip=198.51.100.7"
+        )),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "good 198.51.100.7");
+    assert_eq!(content(&provider, "a").as_deref(), Some("198.51.100.7"));
+
+    let logs = logs.contents();
+    assert!(logs.contains("request completed"), "{logs}");
+    assert!(logs.contains("handling update"), "{logs}");
+    assert!(!logs.contains(TOKEN), "{logs}");
+}
+
+#[tokio::test]
+async fn query_password_authenticates() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = query_auth_router(&provider);
+    let (logs, _guard) = capture_logs();
+
+    let response = send(
+        &router,
+        forwarded(&format!(
+            "password={TOKEN}&hostname=nas.foobar.de&myip=198.51.100.7"
+        )),
+    )
+    .await;
+
+    assert_eq!(response.body, "good 198.51.100.7");
+    assert!(!logs.contents().contains(TOKEN));
+}
+
+#[tokio::test]
+async fn encoded_query_key_is_stripped_and_redacted() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = query_auth_router(&provider);
+    let (logs, _guard) = capture_logs();
+
+    let response = send(
+        &router,
+        forwarded(&format!(
+            "hostname=nas.foobar.de&%6Bey={TOKEN}&myip=198.51.100.7"
+        )),
+    )
+    .await;
+
+    assert_eq!(response.body, "good 198.51.100.7");
+    let logs = logs.contents();
+    assert!(!logs.contains(TOKEN), "{logs}");
+    assert!(
+        logs.contains("path=/nic/update?hostname=nas.foobar.de&%6Bey=redacted&myip=198.51.100.7"),
+        "{logs}"
+    );
+}
+
+#[tokio::test]
+async fn wrong_query_key_is_badauth_and_not_logged() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = query_auth_router(&provider);
+    let (logs, _guard) = capture_logs();
+
+    let response = send(
+        &router,
+        forwarded("hostname=nas.foobar.de&key=wrong-secret-value&myip=198.51.100.7"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(response.body, "badauth");
+    assert_eq!(provider.records(), nas_records());
+    assert!(!logs.contents().contains("wrong-secret-value"));
+}
+
+#[tokio::test]
+async fn query_key_is_ignored_when_disabled() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = builder(&provider)
+        .api_token(ApiToken {
+            token: TOKEN.to_string(),
+            allowed_hostnames: None,
+        })
+        .build()
+        .unwrap()
+        .router();
+
+    let response = send(
+        &router,
+        forwarded(&format!(
+            "hostname=nas.foobar.de&key={TOKEN}&myip=198.51.100.7"
+        )),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(provider.records(), nas_records());
+}
+
+#[tokio::test]
+async fn query_auth_rejects_plain_http() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = query_auth_router(&provider);
+
+    let request = request(&format!(
+        "/nic/update?hostname=nas.foobar.de&key={TOKEN}&myip=198.51.100.7"
+    ))
+    .body(Body::empty())
+    .unwrap();
+    let response = send(&router, request).await;
+
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(provider.records(), nas_records());
+}
+
+/// Sends an update with `authorization` as the raw header value and checks that it is answered
+/// like wrong credentials, logging `reason` but not the header value.
+async fn assert_malformed_basic_auth(authorization: &[u8], reason: &str) {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+    let (logs, _guard) = capture_logs();
+
+    let response = send(
+        &router,
+        request("/nic/update?hostname=nas.foobar.de&myip=198.51.100.7")
+            .header(
+                header::AUTHORIZATION,
+                HeaderValue::from_bytes(authorization).unwrap(),
+            )
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(response.body, "badauth");
+    assert!(
+        response.headers.contains_key(header::WWW_AUTHENTICATE),
+        "{:?}",
+        response.headers
+    );
+    assert_eq!(provider.records(), nas_records());
+    let logs = logs.contents();
+    assert!(logs.contains("Malformed Basic auth header"), "{logs}");
+    assert!(logs.contains(reason), "{logs}");
+    let value = String::from_utf8_lossy(authorization);
+    let credentials = value.trim().get(6..).unwrap_or_default();
+    assert!(
+        credentials.is_empty() || !logs.contains(credentials),
+        "{logs}"
+    );
+}
+
+fn encoded(credentials: &[u8]) -> Vec<u8> {
+    [
+        b"Basic ".as_slice(),
+        STANDARD.encode(credentials).as_bytes(),
+    ]
+    .concat()
+}
+
+#[tokio::test]
+async fn invalid_base64_is_badauth() {
+    assert_malformed_basic_auth(b"Basic hunter2!!", "invalid base64").await;
+}
+
+#[tokio::test]
+async fn missing_colon_is_badauth() {
+    assert_malformed_basic_auth(&encoded(b"routerhunter2"), "missing colon").await;
+}
+
+#[tokio::test]
+async fn non_utf8_credentials_are_badauth() {
+    assert_malformed_basic_auth(&encoded(b"router:hunter\xff2"), "not UTF-8").await;
+}
+
+#[tokio::test]
+async fn empty_header_is_badauth() {
+    assert_malformed_basic_auth(b"", "empty header value").await;
+}
+
+#[tokio::test]
+async fn url_encoded_header_is_badauth() {
+    let value = encoded(b"router:hunter2");
+    let url_encoded = [b"Basic%20".as_slice(), &value[6..]].concat();
+    assert_malformed_basic_auth(&url_encoded, "no space after the scheme").await;
+}
+
+#[tokio::test]
+async fn lowercase_scheme_is_accepted() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+    let value = String::from_utf8(encoded(b"router:hunter2"))
+        .unwrap()
+        .replace("Basic", "basic");
+
+    let response = send(
+        &router,
+        request("/nic/update?hostname=nas.foobar.de&myip=198.51.100.7")
+            .header(header::AUTHORIZATION, value)
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(content(&provider, "a").as_deref(), Some("198.51.100.7"));
+}
+
+/// A request from `ip` with the given `Authorization` header.
+fn request_from(ip: [u8; 4], uri: &str, authorization: &str) -> Request<Body> {
+    Request::get(uri)
+        .extension(ConnectInfo(SocketAddr::from((ip, 4242))))
+        .header(header::AUTHORIZATION, authorization)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn lockout_follows_the_username_to_other_addresses() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = builder(&provider)
+        .lockout(LockoutConfig {
+            threshold: 2,
+            ..LockoutConfig::default()
+        })
+        .admin_token("admin-token-0123456789")
+        .build()
+        .unwrap()
+        .router();
+    let uri = "/nic/update?hostname=nas.foobar.de&myip=198.51.100.7";
+    for _ in 0..2 {
+        let wrong = basic_auth("router", "wrong");
+        let response = send(&router, request_from([192, 0, 2, 100], uri, &wrong)).await;
+        assert_eq!(response.body, "badauth");
+    }
+
+    let right = basic_auth("router", PASSWORD);
+    let response = send(&router, request_from([192, 0, 2, 200], uri, &right)).await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.body, "abuse");
+    let other_user = basic_auth("nas", PASSWORD);
+    let response = send(&router, request_from([192, 0, 2, 200], uri, &other_user)).await;
+    assert_eq!(response.body, "good 198.51.100.7");
+
+    let admin = "Bearer admin-token-0123456789";
+    let response = send(
+        &router,
+        request_from([192, 0, 2, 200], "/admin/lockouts", admin),
+    )
+    .await;
+    let lockouts: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    let keys = lockouts
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|it| it["key"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, ["ip:192.0.2.100", "user:router"]);
+}
@@ -0,0 +1,146 @@
+//! Tests of the router nested below `BASE_PATH`.
+
+#![allow(unused_crate_dependencies)]
+
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{StatusCode, header};
+use common::*;
+use speedport_custom_dyndns::provider::memory::MemoryProvider;
+use std::sync::Arc;
+
+const ADMIN_TOKEN: &str = "admin-token-0123456789abcdefghijklmnop";
+
+fn router_below(provider: &Arc<MemoryProvider>, base_path: Option<&str>) -> Router {
+    let mut builder = builder(provider).admin_token(ADMIN_TOKEN).dashboard(true);
+    if let Some(base_path) = base_path {
+        builder = builder.base_path(base_path);
+    }
+    builder.build().unwrap().router()
+}
+
+async fn status(router: &Router, uri: &str, authorization: Option<String>) -> StatusCode {
+    let mut request = request(uri);
+    if let Some(authorization) = authorization {
+        request = request.header(header::AUTHORIZATION, authorization);
+    }
+    send(router, request.body(Body::empty()).unwrap())
+        .await
+        .status
+}
+
+/// Checks that every endpoint is served below `prefix`.
+async fn assert_served_below(router: &Router, prefix: &str) {
+    let client = || Some(basic_auth("router", PASSWORD));
+    let admin = || Some(format!("Bearer {ADMIN_TOKEN}"));
+    let update = format!("{prefix}/nic/update?hostname=nas.foobar.de&myip=198.51.100.7");
+
+    assert_eq!(status(router, &update, client()).await, StatusCode::OK);
+    assert_eq!(
+        status(router, &format!("{prefix}/status"), client()).await,
+        StatusCode::OK
+    );
+    let dashboard = if prefix.is_empty() { "/" } else { prefix };
+    assert_eq!(status(router, dashboard, client()).await, StatusCode::OK);
+    assert_eq!(
+        status(router, &format!("{prefix}/admin/records"), admin()).await,
+        StatusCode::OK
+    );
+    for endpoint in ["healthz", "readyz", "version", "metrics"] {
+        assert_eq!(
+            status(router, &format!("{prefix}/{endpoint}"), None).await,
+            StatusCode::OK,
+            "{endpoint}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn endpoints_are_served_below_the_base_path() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router_below(&provider, Some("/dyndns"));
+
+    assert_served_below(&router, "/dyndns").await;
+    assert_eq!(content(&provider, "a").as_deref(), Some("198.51.100.7"));
+}
+
+#[tokio::test]
+async fn endpoints_are_not_served_at_the_root_with_a_base_path() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router_below(&provider, Some("/dyndns"));
+
+    let update = "/nic/update?hostname=nas.foobar.de&myip=198.51.100.7";
+    let client = Some(basic_auth("router", PASSWORD));
+    assert_eq!(status(&router, update, client).await, StatusCode::NOT_FOUND);
+    for endpoint in ["/healthz", "/metrics", "/admin/records", "/dyndnsx/healthz"] {
+        assert_eq!(
+            status(&router, endpoint, None).await,
+            StatusCode::NOT_FOUND,
+            "{endpoint}"
+        );
+    }
+    assert_eq!(provider.records(), nas_records());
+}
+
+#[tokio::test]
+async fn nested_base_paths_work() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router_below(&provider, Some("/services/dyndns"));
+
+    assert_served_below(&router, "/services/dyndns").await;
+}
+
+#[tokio::test]
+async fn endpoints_are_served_at_the_root_without_a_base_path() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router_below(&provider, None);
+
+    assert_served_below(&router, "").await;
+    // Unknown paths pass the auth middleware first
+    assert_ne!(
+        status(&router, "/dyndns/healthz", None).await,
+        StatusCode::OK
+    );
+}
+
+#[test]
+fn invalid_base_paths_are_rejected() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let error = |base_path: &str| {
+        builder(&provider)
+            .base_path(base_path)
+            .build()
+            .err()
+            .map(|it| it.to_string())
+            .unwrap_or_else(|| panic!("'{base_path}' was accepted"))
+    };
+
+    assert!(error("dyndns").contains("must start with a slash"));
+    assert!(error("/dyndns/").contains("must not end with a slash"));
+    assert!(error("/").contains("must not end with a slash"));
+    assert!(error("/dyn dns").contains("invalid character ' '"));
+    assert!(error("/dyndns?x=1").contains("invalid character '?'"));
+    assert!(error("/a//b").contains("empty segment"));
+}
+
+#[tokio::test]
+async fn health_checks_below_the_base_path_are_logged_quietly() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router_below(&provider, Some("/dyndns"));
+    let (logs, _guard) = capture_logs();
+
+    status(&router, "/dyndns/healthz", None).await;
+    status(&router, "/dyndns/version", None).await;
+
+    let logs = logs.contents();
+    let completed = |path: &str| {
+        logs.lines()
+            .find(|it| it.contains("request completed") && it.contains(&format!("path={path} ")))
+            .unwrap_or_else(|| panic!("{logs}"))
+            .to_string()
+    };
+    assert!(completed("/dyndns/healthz").contains("DEBUG"), "{logs}");
+    assert!(completed("/dyndns/version").contains("INFO"), "{logs}");
+}
@@ -0,0 +1,683 @@
+//! Tests of the Cloudflare provider against a mocked API.
+
+#![allow(unused_crate_dependencies)]
+#![cfg(feature = "provider-cloudflare")]
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{Request, StatusCode, header as http_header};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::{Value, json};
+use speedport_custom_dyndns::provider::cloudflare::{CloudflareProvider, ManagedMarker};
+use speedport_custom_dyndns::provider::{
+    ContentTypeMismatch, RecordConflict, RecordNotFound, RecordOptions,
+};
+use speedport_custom_dyndns::{
+    ClientPassword, DnsEntry, DnsProvider, DnsRecordType, DynDnsServer, Origin, RecordId, RecordRef,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tower::ServiceExt;
+use wiremock::matchers::{body_json, header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const TOKEN: &str = "cloudflare-token";
+
+fn origin() -> Origin {
+    Origin::parse("foobar.de").unwrap()
+}
+
+fn provider(server: &MockServer) -> CloudflareProvider {
+    // A trailing slash must not lead to double slashes in the paths
+    CloudflareProvider::new(TOKEN.to_string(), format!("{}/", server.uri()))
+}
+
+fn success(result: Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "success": true,
+        "errors": [],
+        "result": result,
+    }))
+}
+
+fn cloudflare_record(id: &str, typ: &str, name: &str, content: &str) -> Value {
+    json!({
+        "id": id,
+        "type": typ,
+        "name": name,
+        "content": content,
+        "ttl": 300,
+        "proxied": false,
+    })
+}
+
+fn nas_a_record() -> RecordRef {
+    RecordRef {
+        typ: DnsRecordType::A,
+        id: RecordId("record-a".to_string()),
+        name: "nas.foobar.de".to_string(),
+    }
+}
+
+/// Answers the zone lookup for `foobar.de` with `zones`.
+async fn mock_zones(server: &MockServer, zones: Value) {
+    Mock::given(method("GET"))
+        .and(path("/zones"))
+        .and(query_param("domain", "foobar.de"))
+        .and(header("authorization", format!("Bearer {TOKEN}")))
+        .respond_with(success(zones))
+        .mount(server)
+        .await;
+}
+
+async fn mock_zone(server: &MockServer) {
+    mock_zones(server, json!([{ "id": "zone-1", "name": "foobar.de" }])).await;
+}
+
+#[tokio::test]
+async fn lists_the_records_of_the_zone() {
+    let server = MockServer::start().await;
+    mock_zone(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone-1/dns_records"))
+        .and(header("authorization", format!("Bearer {TOKEN}")))
+        .respond_with(success(json!([
+            cloudflare_record("record-a", "A", "nas.foobar.de", "192.0.2.1"),
+            cloudflare_record("record-aaaa", "AAAA", "nas.foobar.de", "2001:db8::1"),
+            cloudflare_record("record-mx", "MX", "foobar.de", "mail.foobar.de"),
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let records = provider(&server).list_records(&origin()).await.unwrap();
+
+    assert_eq!(
+        records,
+        vec![
+            DnsEntry {
+                typ: DnsRecordType::A,
+                id: RecordId("record-a".to_string()),
+                name: "nas.foobar.de".to_string(),
+                content: "192.0.2.1".to_string(),
+                ttl: Some(300),
+                proxied: Some(false),
+            },
+            DnsEntry {
+                typ: DnsRecordType::AAAA,
+                id: RecordId("record-aaaa".to_string()),
+                name: "nas.foobar.de".to_string(),
+                content: "2001:db8::1".to_string(),
+                ttl: Some(300),
+                proxied: Some(false),
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn update_only_sends_the_content() {
+    let server = MockServer::start().await;
+    mock_zone(&server).await;
+    Mock::given(method("PATCH"))
+        .and(path("/zones/zone-1/dns_records/record-a"))
+        .and(body_json(json!({ "content": "198.51.100.7" })))
+        .respond_with(success(cloudflare_record(
+            "record-a",
+            "A",
+            "nas.foobar.de",
+            "198.51.100.7",
+        )))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    provider(&server)
+        .update_record(
+            &origin(),
+            &nas_a_record(),
+            None,
+            "198.51.100.7",
+            &RecordOptions::default(),
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn update_sends_the_requested_options() {
+    let server = MockServer::start().await;
+    mock_zone(&server).await;
+    Mock::given(method("PATCH"))
+        .and(path("/zones/zone-1/dns_records/record-a"))
+        .and(body_json(
+            json!({ "content": "198.51.100.7", "ttl": 60, "proxied": true }),
+        ))
+        .respond_with(success(cloudflare_record(
+            "record-a",
+            "A",
+            "nas.foobar.de",
+            "198.51.100.7",
+        )))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = RecordOptions {
+        ttl: Some(60),
+        proxied: Some(true),
+    };
+    provider(&server)
+        .update_record(&origin(), &nas_a_record(), None, "198.51.100.7", &options)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn update_with_mismatched_content_sends_nothing() {
+    let server = MockServer::start().await;
+
+    let error = provider(&server)
+        .update_record(
+            &origin(),
+            &nas_a_record(),
+            None,
+            "2001:db8::7",
+            &RecordOptions::default(),
+        )
+        .await
+        .unwrap_err();
+
+    assert!(ContentTypeMismatch::is_cause_of(&error), "{error}");
+    assert!(server.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn update_of_a_missing_record_is_not_found() {
+    let server = MockServer::start().await;
+    mock_zone(&server).await;
+    Mock::given(method("PATCH"))
+        .and(path("/zones/zone-1/dns_records/record-a"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let error = provider(&server)
+        .update_record(
+            &origin(),
+            &nas_a_record(),
+            None,
+            "198.51.100.7",
+            &RecordOptions::default(),
+        )
+        .await
+        .unwrap_err();
+
+    assert!(RecordNotFound::is_cause_of(&error), "{error}");
+}
+
+#[tokio::test]
+async fn update_is_refused_if_the_record_changed() {
+    let server = MockServer::start().await;
+    mock_zone(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone-1/dns_records/record-a"))
+        .respond_with(success(cloudflare_record(
+            "record-a",
+            "A",
+            "nas.foobar.de",
+            "203.0.113.9",
+        )))
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let error = provider(&server)
+        .update_record(
+            &origin(),
+            &nas_a_record(),
+            Some("192.0.2.1"),
+            "198.51.100.7",
+            &RecordOptions::default(),
+        )
+        .await
+        .unwrap_err();
+
+    let conflict = RecordConflict::find(&error).unwrap();
+    assert_eq!(conflict.actual, "203.0.113.9");
+}
+
+#[tokio::test]
+async fn updates_are_not_read_back_before_writing() {
+    let server = MockServer::start().await;
+    mock_zone(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone-1/dns_records"))
+        .respond_with(success(json!([cloudflare_record(
+            "record-a",
+            "A",
+            "nas.foobar.de",
+            "192.0.2.1",
+        )])))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone-1/dns_records/record-a"))
+        .respond_with(success(cloudflare_record(
+            "record-a",
+            "A",
+            "nas.foobar.de",
+            "192.0.2.1",
+        )))
+        .expect(0)
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .and(path("/zones/zone-1/dns_records/record-a"))
+        .and(body_json(json!({ "content": "198.51.100.7" })))
+        .respond_with(success(cloudflare_record(
+            "record-a",
+            "A",
+            "nas.foobar.de",
+            "198.51.100.7",
+        )))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let provider = provider(&server);
+    // Reading before writing still races, so it is not offered as compare-and-set
+    assert!(!provider.supports_compare_and_set());
+    let router = DynDnsServer::builder()
+        .origin(origin())
+        .provider(Arc::new(provider))
+        .password(ClientPassword::Plain("hunter2".to_string()))
+        .build()
+        .unwrap()
+        .router();
+    let credentials = STANDARD.encode("router:hunter2");
+    let response = router
+        .oneshot(
+            Request::get("/nic/update?hostname=nas.foobar.de&myip=198.51.100.7")
+                .extension(ConnectInfo(SocketAddr::from(([192, 0, 2, 100], 4242))))
+                .header(http_header::AUTHORIZATION, format!("Basic {credentials}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn creates_quoted_txt_records() {
+    let server = MockServer::start().await;
+    mock_zone(&server).await;
+    Mock::given(method("POST"))
+        .and(path("/zones/zone-1/dns_records"))
+        .and(body_json(json!({
+            "type": "TXT",
+            "name": "_acme-challenge.nas.foobar.de",
+            "content": "\"token\"",
+            "ttl": 1,
+        })))
+        .respond_with(success(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    provider(&server)
+        .create_txt_record(&origin(), "_acme-challenge.nas.foobar.de", "token")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn missing_zone_is_an_error() {
+    let server = MockServer::start().await;
+    mock_zones(&server, json!([])).await;
+
+    let error = provider(&server)
+        .list_records(&origin())
+        .await
+        .unwrap_err()
+        .to_string();
+
+    assert!(error.contains("No zone found for origin"), "{error}");
+    assert!(error.contains("lacks Zone:Read on foobar.de"), "{error}");
+}
+
+#[tokio::test]
+async fn multiple_zones_are_an_error() {
+    let server = MockServer::start().await;
+    mock_zones(
+        &server,
+        json!([
+            { "id": "zone-1", "name": "foobar.de" },
+            { "id": "zone-2", "name": "foobar.de" },
+        ]),
+    )
+    .await;
+
+    let error = provider(&server)
+        .list_records(&origin())
+        .await
+        .unwrap_err()
+        .to_string();
+
+    assert!(error.contains("Multiple zones found for origin"), "{error}");
+}
+
+#[tokio::test]
+async fn failed_listing_reports_the_api_errors() {
+    let server = MockServer::start().await;
+    mock_zone(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone-1/dns_records"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "success": false,
+            "errors": [{ "code": 10000, "message": "Authentication error" }],
+            "result": null,
+        })))
+        .mount(&server)
+        .await;
+
+    let error = provider(&server)
+        .list_records(&origin())
+        .await
+        .unwrap_err()
+        .to_string();
+
+    assert!(
+        error.contains("Failed to list DNS records from Cloudflare"),
+        "{error}"
+    );
+    assert!(error.contains("status: 403 Forbidden"), "{error}");
+    assert!(
+        error.contains("error 10000: Authentication error"),
+        "{error}"
+    );
+    assert!(
+        error.contains("lacks DNS:Read on zone foobar.de"),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn malformed_listing_is_an_error() {
+    let server = MockServer::start().await;
+    mock_zone(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone-1/dns_records"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{\"result\": [{\"id\": "))
+        .mount(&server)
+        .await;
+
+    let error = provider(&server)
+        .list_records(&origin())
+        .await
+        .unwrap_err()
+        .to_string();
+
+    assert!(
+        error.contains("Parsing Cloudflare DNS records response"),
+        "{error}"
+    );
+}
+
+/// Error bodies as returned by the Cloudflare API.
+const AUTHENTICATION_ERROR: &str = r#"{"success":false,"errors":[{"code":10000,"message":"Authentication error"}],"messages":[],"result":null}"#;
+const INVALID_TOKEN: &str = r#"{"success":false,"errors":[{"code":9109,"message":"Invalid access token"}],"messages":[],"result":null}"#;
+const INVALID_HEADERS: &str = r#"{"success":false,"errors":[{"code":6003,"message":"Invalid request headers","error_chain":[{"code":6111,"message":"Invalid format for Authorization header"}]}],"messages":[],"result":null}"#;
+const RATE_LIMITED: &str = r#"{"success":false,"errors":[{"code":971,"message":"Please wait and consider throttling your request speed"}],"messages":[],"result":null}"#;
+
+/// The error of an update answered with `status` and `body`.
+async fn update_error(status: u16, body: &str) -> String {
+    let server = MockServer::start().await;
+    mock_zone(&server).await;
+    Mock::given(method("PATCH"))
+        .respond_with(ResponseTemplate::new(status).set_body_string(body))
+        .mount(&server)
+        .await;
+
+    provider(&server)
+        .update_record(
+            &origin(),
+            &nas_a_record(),
+            None,
+            "198.51.100.7",
+            &RecordOptions::default(),
+        )
+        .await
+        .unwrap_err()
+        .to_string()
+}
+
+#[tokio::test]
+async fn missing_edit_permission_has_a_hint() {
+    let error = update_error(403, AUTHENTICATION_ERROR).await;
+
+    assert!(
+        error.contains("error 10000: Authentication error"),
+        "{error}"
+    );
+    assert!(
+        error.contains(
+            "hint: the token is valid but lacks DNS:Edit on zone foobar.de, edit the token's \
+             permissions in the Cloudflare dashboard"
+        ),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn invalid_token_has_a_hint() {
+    let error = update_error(401, INVALID_TOKEN).await;
+
+    assert!(
+        error.contains("hint: the API token is invalid or expired"),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn malformed_token_has_a_hint() {
+    let error = update_error(400, INVALID_HEADERS).await;
+
+    assert!(
+        error.contains("error 6003: Invalid request headers"),
+        "{error}"
+    );
+    assert!(
+        error.contains("check CLOUDFLARE_API_TOKEN for stray quotes or whitespace"),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn rate_limit_has_a_hint() {
+    let error = update_error(429, RATE_LIMITED).await;
+
+    assert!(error.contains("hint: rate limited"), "{error}");
+    assert!(!error.contains("permissions"), "{error}");
+}
+
+#[tokio::test]
+async fn unknown_errors_have_no_hint() {
+    let error = update_error(500, "upstream connect error").await;
+
+    assert!(
+        error.contains(r#"response: "upstream connect error""#),
+        "{error}"
+    );
+    assert!(!error.contains("hint:"), "{error}");
+}
+
+#[tokio::test]
+async fn validation_names_the_read_permission() {
+    let server = MockServer::start().await;
+    mock_zone(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone-1/dns_records"))
+        .respond_with(ResponseTemplate::new(403).set_body_string(AUTHENTICATION_ERROR))
+        .mount(&server)
+        .await;
+
+    let error = provider(&server)
+        .validate(&origin())
+        .await
+        .unwrap_err()
+        .to_string();
+
+    assert!(
+        error.contains("Failed to list DNS records on startup"),
+        "{error}"
+    );
+    assert!(
+        error.contains("lacks DNS:Read on zone foobar.de"),
+        "{error}"
+    );
+}
+
+fn tagged(mut record: Value, tags: &[&str]) -> Value {
+    record["tags"] = json!(tags);
+    record
+}
+
+/// A zone where only `nas` carries the `dyndns` tag among others, `tv` only similar tags and
+/// `vps` none at all.
+async fn mock_mixed_tags(server: &MockServer) {
+    mock_zone(server).await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone-1/dns_records"))
+        .respond_with(success(json!([
+            tagged(
+                cloudflare_record("record-a", "A", "nas.foobar.de", "192.0.2.1"),
+                &["terraform", "dyndns:home"],
+            ),
+            tagged(
+                cloudflare_record("record-tv", "A", "tv.foobar.de", "192.0.2.2"),
+                &["dyndnsx", "owner:dyndns"],
+            ),
+            cloudflare_record("record-vps", "A", "vps.foobar.de", "192.0.2.3"),
+        ])))
+        .mount(server)
+        .await;
+}
+
+fn managed_provider(server: &MockServer) -> CloudflareProvider {
+    provider(server).with_managed_marker(ManagedMarker::Tag("dyndns".to_string()))
+}
+
+#[tokio::test]
+async fn only_tagged_records_are_listed() {
+    let server = MockServer::start().await;
+    mock_mixed_tags(&server).await;
+
+    let records = managed_provider(&server)
+        .list_records(&origin())
+        .await
+        .unwrap();
+
+    let ids = records
+        .iter()
+        .map(|it| it.id.0.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, ["record-a"]);
+}
+
+#[tokio::test]
+async fn untagged_records_are_not_written() {
+    let server = MockServer::start().await;
+    mock_mixed_tags(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone-1/dns_records/record-tv"))
+        .respond_with(success(tagged(
+            cloudflare_record("record-tv", "A", "tv.foobar.de", "192.0.2.2"),
+            &["dyndnsx", "owner:dyndns"],
+        )))
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+    let record = RecordRef {
+        typ: DnsRecordType::A,
+        id: RecordId("record-tv".to_string()),
+        name: "tv.foobar.de".to_string(),
+    };
+    let provider = managed_provider(&server);
+
+    let error = provider
+        .update_record(
+            &origin(),
+            &record,
+            None,
+            "198.51.100.7",
+            &RecordOptions::default(),
+        )
+        .await
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("without the tag 'dyndns'"),
+        "{error}"
+    );
+
+    let error = provider
+        .delete_record(&origin(), &record.id)
+        .await
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("without the tag 'dyndns'"),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn tagged_records_are_written() {
+    let server = MockServer::start().await;
+    mock_mixed_tags(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/zones/zone-1/dns_records/record-a"))
+        .respond_with(success(tagged(
+            cloudflare_record("record-a", "A", "nas.foobar.de", "192.0.2.1"),
+            &["terraform", "dyndns:home"],
+        )))
+        .mount(&server)
+        .await;
+    Mock::given(method("PATCH"))
+        .and(path("/zones/zone-1/dns_records/record-a"))
+        .respond_with(success(cloudflare_record(
+            "record-a",
+            "A",
+            "nas.foobar.de",
+            "198.51.100.7",
+        )))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    managed_provider(&server)
+        .update_record(
+            &origin(),
+            &nas_a_record(),
+            None,
+            "198.51.100.7",
+            &RecordOptions::default(),
+        )
+        .await
+        .unwrap();
+}
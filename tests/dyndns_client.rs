@@ -0,0 +1,145 @@
+//! Round trips of the dyndns2 client against this server on an ephemeral port.
+
+#![allow(unused_crate_dependencies)]
+
+mod common;
+
+use common::*;
+use speedport_custom_dyndns::dyndns_client::{DyndnsClient, ErrorCode, UpdateResult};
+use speedport_custom_dyndns::provider::memory::MemoryProvider;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Serves the test router for `provider` until the returned sender is dropped.
+async fn serve(provider: &Arc<MemoryProvider>) -> (SocketAddr, oneshot::Sender<()>) {
+    let server = builder(provider).build().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    tokio::spawn(server.serve(listener, async {
+        stopped.await.ok();
+    }));
+    (addr, stop)
+}
+
+fn client(addr: SocketAddr, password: &str) -> DyndnsClient {
+    DyndnsClient::new(format!("http://{addr}/"), "router", password, TIMEOUT).unwrap()
+}
+
+#[tokio::test]
+async fn update_then_nochg() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let (addr, _stop) = serve(&provider).await;
+    let client = client(addr, PASSWORD);
+
+    let results = client
+        .update(&["nas.foobar.de"], "198.51.100.7")
+        .await
+        .unwrap();
+    assert_eq!(
+        results,
+        vec![UpdateResult::Good {
+            address: Some("198.51.100.7".parse().unwrap())
+        }]
+    );
+    assert_eq!(content(&provider, "a").as_deref(), Some("198.51.100.7"));
+
+    let results = client
+        .update(&["nas.foobar.de"], "198.51.100.7")
+        .await
+        .unwrap();
+    assert!(
+        matches!(results.as_slice(), [UpdateResult::NoChg { .. }]),
+        "{results:?}"
+    );
+}
+
+#[tokio::test]
+async fn both_families_in_one_update() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let (addr, _stop) = serve(&provider).await;
+
+    let results = client(addr, PASSWORD)
+        .update(&["nas.foobar.de"], "198.51.100.7,2001:db8::7")
+        .await
+        .unwrap();
+    assert!(!results.is_empty());
+    assert!(results.iter().all(UpdateResult::is_success), "{results:?}");
+    assert_eq!(content(&provider, "a").as_deref(), Some("198.51.100.7"));
+    assert_eq!(content(&provider, "aaaa").as_deref(), Some("2001:db8::7"));
+}
+
+#[tokio::test]
+async fn wrong_password_is_badauth() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let (addr, _stop) = serve(&provider).await;
+
+    let results = client(addr, "wrong")
+        .update(&["nas.foobar.de"], "198.51.100.7")
+        .await
+        .unwrap();
+    assert_eq!(
+        results,
+        vec![UpdateResult::Failed {
+            code: ErrorCode::BadAuth,
+            detail: None,
+        }]
+    );
+    assert_eq!(content(&provider, "a").as_deref(), Some("192.0.2.1"));
+}
+
+#[tokio::test]
+async fn unknown_hostname_is_nohost() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let (addr, _stop) = serve(&provider).await;
+
+    let results = client(addr, PASSWORD)
+        .update(&["missing.foobar.de"], "198.51.100.7")
+        .await
+        .unwrap();
+    assert!(
+        matches!(
+            results.as_slice(),
+            [UpdateResult::Failed {
+                code: ErrorCode::NoHost,
+                ..
+            }]
+        ),
+        "{results:?}"
+    );
+}
+
+#[tokio::test]
+async fn foreign_zone_is_rejected() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let (addr, _stop) = serve(&provider).await;
+
+    let results = client(addr, PASSWORD)
+        .update(&["nas.example.com"], "198.51.100.7")
+        .await
+        .unwrap();
+    assert!(
+        matches!(results.as_slice(), [UpdateResult::Failed { .. }]),
+        "{results:?}"
+    );
+}
+
+#[tokio::test]
+async fn unreachable_server_is_an_error() {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let error = client(addr, PASSWORD)
+        .update(&["nas.foobar.de"], "198.51.100.7")
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("/nic/update"), "{error}");
+}
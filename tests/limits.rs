@@ -0,0 +1,152 @@
+//! Tests of the request limits, which reject requests before routing, auth or any handler runs.
+
+#![allow(unused_crate_dependencies)]
+
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, StatusCode, header};
+use common::*;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use speedport_custom_dyndns::RequestLimits;
+use speedport_custom_dyndns::provider::memory::MemoryProvider;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+
+async fn rejections(router: &Router, reason: &str) -> String {
+    let metrics = send(router, request("/metrics").body(Body::empty()).unwrap()).await;
+    let prefix = format!(r#"dyndns_rejected_requests_total{{reason="{reason}"}} "#);
+    metrics
+        .body
+        .lines()
+        .find_map(|line| line.strip_prefix(&prefix))
+        .unwrap_or("0")
+        .to_string()
+}
+
+#[tokio::test]
+async fn over_long_query_is_rejected() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+    let padding = "x".repeat(RequestLimits::default().max_uri_length);
+
+    // Without credentials, so the auth middleware would answer with a 401
+    let response = send(
+        &router,
+        request(&format!(
+            "/nic/update?hostname=nas.foobar.de&myip=198.51.100.7&pad={padding}"
+        ))
+        .body(Body::empty())
+        .unwrap(),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::URI_TOO_LONG);
+    assert_eq!(response.body, "URI Too Long");
+    assert_eq!(provider.records(), nas_records());
+    assert_eq!(rejections(&router, "uri_too_long").await, "1");
+}
+
+#[tokio::test]
+async fn oversized_body_is_rejected() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+    let body = format!(
+        "hostname=nas.foobar.de&myip=198.51.100.7&pad={}",
+        "x".repeat(RequestLimits::default().max_body_bytes)
+    );
+
+    // There is no POST route, so the router would answer with a 405
+    let response = send(
+        &router,
+        request("/nic/update")
+            .method(Method::POST)
+            .header(header::AUTHORIZATION, basic_auth("router", PASSWORD))
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(Body::from(body))
+            .unwrap(),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.body, "Payload Too Large");
+    assert_eq!(provider.records(), nas_records());
+    assert_eq!(rejections(&router, "body_too_large").await, "1");
+}
+
+#[tokio::test]
+async fn unexpected_content_type_is_rejected() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+
+    let response = send(
+        &router,
+        request("/nic/update?hostname=nas.foobar.de&myip=198.51.100.7")
+            .header(header::AUTHORIZATION, basic_auth("router", PASSWORD))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_LENGTH, 2)
+            .body(Body::from("{}"))
+            .unwrap(),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(provider.records(), nas_records());
+    assert_eq!(rejections(&router, "unsupported_content_type").await, "1");
+}
+
+#[test]
+fn zero_header_read_timeout_is_rejected() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let limits = RequestLimits {
+        header_read_timeout: Duration::ZERO,
+        ..RequestLimits::default()
+    };
+
+    let result = builder(&provider).request_limits(limits).build();
+
+    let error = result.err().unwrap().to_string();
+    assert!(error.contains("header read timeout"), "{error}");
+}
+
+#[tokio::test]
+async fn server_speaks_http2() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let server = builder(&provider).build().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let serve = tokio::spawn(server.serve(listener, async {
+        stopped.await.ok();
+    }));
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut sender, connection) =
+        hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+    let connection = tokio::spawn(connection);
+    let request = axum::http::Request::get(format!(
+        "http://{addr}/nic/update?hostname=nas.foobar.de&myip=198.51.100.7"
+    ))
+    .header(header::AUTHORIZATION, basic_auth("router", PASSWORD))
+    .body(Body::empty())
+    .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(Body::new(response.into_body()), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(body, "good 198.51.100.7");
+    assert_eq!(content(&provider, "a").as_deref(), Some("198.51.100.7"));
+
+    drop(sender);
+    connection.await.unwrap().unwrap();
+    stop.send(()).unwrap();
+    serve.await.unwrap().unwrap();
+}
@@ -0,0 +1,114 @@
+//! Scrape tests of `/metrics`.
+
+#![allow(unused_crate_dependencies)]
+
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::StatusCode;
+use common::*;
+use speedport_custom_dyndns::provider::memory::MemoryProvider;
+use std::sync::Arc;
+
+async fn scrape(router: &Router) -> String {
+    let response = send(router, request("/metrics").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status, StatusCode::OK);
+    response.body
+}
+
+/// The value of the sample of `name` with exactly `labels`.
+fn sample(metrics: &str, name: &str, labels: &str) -> Option<String> {
+    let prefix = format!("{name}{{{labels}}} ");
+    metrics
+        .lines()
+        .find_map(|it| it.strip_prefix(&prefix))
+        .map(str::to_string)
+}
+
+const NAS_A: &str = r#"hostname="nas.foobar.de",type="A""#;
+
+#[tokio::test]
+async fn gauges_follow_updates() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+
+    let before = scrape(&router).await;
+    assert_eq!(sample(&before, "dyndns_consecutive_failures", NAS_A), None);
+
+    send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+    let first = scrape(&router).await;
+    let last_success = sample(&first, "dyndns_last_success_timestamp_seconds", NAS_A)
+        .expect("last success after the first update");
+    assert!(sample(&first, "dyndns_last_attempt_timestamp_seconds", NAS_A).is_some());
+    assert_eq!(
+        sample(&first, "dyndns_consecutive_failures", NAS_A).as_deref(),
+        Some("0")
+    );
+
+    provider.fail(true);
+    send(&router, update("hostname=nas.foobar.de&myip=198.51.100.8")).await;
+    let second = scrape(&router).await;
+    assert_eq!(
+        sample(&second, "dyndns_consecutive_failures", NAS_A).as_deref(),
+        Some("1")
+    );
+    assert_eq!(
+        sample(&second, "dyndns_last_success_timestamp_seconds", NAS_A),
+        Some(last_success)
+    );
+}
+
+#[tokio::test]
+async fn addresses_are_not_exported() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+
+    send(
+        &router,
+        update("hostname=nas.foobar.de&myip=198.51.100.7,2001:db8::7"),
+    )
+    .await;
+    let metrics = scrape(&router).await;
+
+    assert!(metrics.contains("nas.foobar.de"));
+    assert!(!metrics.contains("198.51.100.7"), "{metrics}");
+    assert!(!metrics.contains("2001:db8::7"), "{metrics}");
+}
+
+#[tokio::test]
+async fn hostnames_can_be_hashed() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = builder(&provider)
+        .hash_metric_hostnames(true)
+        .build()
+        .unwrap()
+        .router();
+
+    send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+    let metrics = scrape(&router).await;
+
+    assert!(!metrics.contains("nas.foobar.de"), "{metrics}");
+    assert!(metrics.contains("dyndns_consecutive_failures{hostname=\""));
+}
+
+#[tokio::test]
+async fn hostnames_outside_the_origin_are_not_labels() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+
+    send(
+        &router,
+        update("hostname=evil.example.org&myip=198.51.100.7"),
+    )
+    .await;
+    send(
+        &router,
+        update("hostname=unknown.foobar.de&myip=198.51.100.7"),
+    )
+    .await;
+    let metrics = scrape(&router).await;
+
+    assert!(!metrics.contains("evil.example.org"), "{metrics}");
+    assert!(!metrics.contains("unknown.foobar.de"), "{metrics}");
+}
@@ -0,0 +1,240 @@
+//! End-to-end tests of ownership markers.
+
+#![allow(unused_crate_dependencies)]
+
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode, header};
+use common::*;
+use speedport_custom_dyndns::Ownership;
+use speedport_custom_dyndns::provider::memory::MemoryProvider;
+use speedport_custom_dyndns::{DnsProvider, DnsRecordType, Origin};
+use std::sync::Arc;
+
+const ADMIN_TOKEN: &str = "admin-token-0123456789";
+const OWNER_ID: &str = "server-a";
+
+fn owned_router(provider: &Arc<MemoryProvider>) -> Router {
+    builder(provider)
+        .ownership(Ownership::new(OWNER_ID).unwrap())
+        .admin_token(ADMIN_TOKEN)
+        .build()
+        .unwrap()
+        .router()
+}
+
+async fn marker(provider: &MemoryProvider, hostname: &str, owner_id: &str) {
+    provider
+        .create_txt_record(
+            &Origin::parse("foobar.de").unwrap(),
+            &Ownership::marker_name(hostname),
+            owner_id,
+        )
+        .await
+        .unwrap();
+}
+
+fn admin(method: Method, hostnames: &str) -> Request<Body> {
+    request(&format!("/admin/ownership?hostnames={hostnames}"))
+        .method(method)
+        .header(header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// The outcomes of an `/admin/ownership` response.
+fn outcomes(body: &str) -> Vec<(String, String)> {
+    serde_json::from_str::<Vec<serde_json::Value>>(body)
+        .unwrap()
+        .into_iter()
+        .map(|it| {
+            (
+                it["hostname"].as_str().unwrap().to_string(),
+                it["outcome"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+fn outcome(hostname: &str, outcome: &str) -> (String, String) {
+    (hostname.to_string(), outcome.to_string())
+}
+
+#[tokio::test]
+async fn update_without_marker_is_refused() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = owned_router(&provider);
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+
+    assert_eq!(
+        response.body,
+        "nohost\ndomain 'nas.foobar.de' has no ownership marker of this server at memory"
+    );
+    assert_eq!(content(&provider, "a").as_deref(), Some("192.0.2.1"));
+    assert!(provider.txt_records().is_empty());
+}
+
+#[tokio::test]
+async fn update_with_marker_of_another_owner_is_refused() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    marker(&provider, "nas.foobar.de", "server-b").await;
+    let router = owned_router(&provider);
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+
+    assert_eq!(
+        response.body,
+        "nohost\ndomain 'nas.foobar.de' has no ownership marker of this server at memory"
+    );
+    assert_eq!(content(&provider, "a").as_deref(), Some("192.0.2.1"));
+}
+
+#[tokio::test]
+async fn update_with_marker_is_applied() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    marker(&provider, "nas.foobar.de", OWNER_ID).await;
+    let router = owned_router(&provider);
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+
+    assert_eq!(response.body, "good 198.51.100.7");
+    assert_eq!(content(&provider, "a").as_deref(), Some("198.51.100.7"));
+}
+
+#[tokio::test]
+async fn unchanged_update_without_marker_is_not_refused() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = owned_router(&provider);
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=192.0.2.1")).await;
+
+    assert_eq!(response.body, "nochg 192.0.2.1");
+}
+
+#[tokio::test]
+async fn stamped_hostname_can_be_updated_until_released() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = owned_router(&provider);
+
+    let response = send(&router, admin(Method::POST, "nas.foobar.de,tv.foobar.de")).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        outcomes(&response.body),
+        [
+            outcome("nas.foobar.de", "created"),
+            outcome("tv.foobar.de", "no_records"),
+        ]
+    );
+    let response = send(&router, admin(Method::POST, "nas.foobar.de")).await;
+    assert_eq!(
+        outcomes(&response.body),
+        [outcome("nas.foobar.de", "exists")]
+    );
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+    assert_eq!(response.body, "good 198.51.100.7");
+
+    let response = send(&router, admin(Method::DELETE, "nas.foobar.de")).await;
+    assert_eq!(
+        outcomes(&response.body),
+        [outcome("nas.foobar.de", "removed")]
+    );
+    assert!(provider.txt_records().is_empty());
+    let response = send(&router, admin(Method::DELETE, "nas.foobar.de")).await;
+    assert_eq!(
+        outcomes(&response.body),
+        [outcome("nas.foobar.de", "absent")]
+    );
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.8")).await;
+    assert_eq!(
+        response.body,
+        "nohost\ndomain 'nas.foobar.de' has no ownership marker of this server at memory"
+    );
+    assert_eq!(content(&provider, "a").as_deref(), Some("198.51.100.7"));
+}
+
+#[tokio::test]
+async fn release_keeps_markers_of_other_owners() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    marker(&provider, "nas.foobar.de", OWNER_ID).await;
+    marker(&provider, "nas.foobar.de", "server-b").await;
+    let router = owned_router(&provider);
+
+    let response = send(&router, admin(Method::DELETE, "nas.foobar.de")).await;
+
+    assert_eq!(
+        outcomes(&response.body),
+        [outcome("nas.foobar.de", "removed")]
+    );
+    let markers = provider.txt_records();
+    assert_eq!(markers.len(), 1);
+    assert_eq!(markers[0].content, "server-b");
+}
+
+#[tokio::test]
+async fn ownership_endpoint_is_not_found_without_owner_id() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = builder(&provider)
+        .admin_token(ADMIN_TOKEN)
+        .build()
+        .unwrap()
+        .router();
+
+    let response = send(&router, admin(Method::POST, "nas.foobar.de")).await;
+
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert!(provider.txt_records().is_empty());
+}
+
+#[tokio::test]
+async fn prefix_rewrite_skips_records_without_marker() {
+    let provider = Arc::new(MemoryProvider::new(vec![
+        record(
+            "router",
+            DnsRecordType::AAAA,
+            "router.foobar.de",
+            "2001:db8:1100::1",
+        ),
+        record(
+            "nas",
+            DnsRecordType::AAAA,
+            "nas.foobar.de",
+            "2001:db8:1100:1::7",
+        ),
+        record(
+            "tv",
+            DnsRecordType::AAAA,
+            "tv.foobar.de",
+            "2001:db8:1100:2::8",
+        ),
+    ]));
+    marker(&provider, "router.foobar.de", OWNER_ID).await;
+    marker(&provider, "nas.foobar.de", OWNER_ID).await;
+    marker(&provider, "tv.foobar.de", "server-b").await;
+    let router = builder(&provider)
+        .ownership(Ownership::new(OWNER_ID).unwrap())
+        .prefix_rewrite(true)
+        .build()
+        .unwrap()
+        .router();
+
+    let response = send(
+        &router,
+        update("hostname=router.foobar.de&myip=2001:db8:3400::1&ip6lanprefix=2001:db8:3400::/56"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(
+        content(&provider, "nas").as_deref(),
+        Some("2001:db8:3400:1::7")
+    );
+    assert_eq!(
+        content(&provider, "tv").as_deref(),
+        Some("2001:db8:1100:2::8")
+    );
+}
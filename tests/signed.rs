@@ -0,0 +1,126 @@
+//! End-to-end tests of signed updates.
+
+#![allow(unused_crate_dependencies)]
+
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, header};
+use common::*;
+use jiff::{SignedDuration, Timestamp};
+use speedport_custom_dyndns::ApiToken;
+use speedport_custom_dyndns::AuthMode;
+use speedport_custom_dyndns::dyndns_client::sign;
+use speedport_custom_dyndns::provider::memory::MemoryProvider;
+use std::sync::Arc;
+
+const SECRET: &str = "abcdefghijklmnopqrstuvwx";
+
+fn signed_router(provider: &Arc<MemoryProvider>, mode: AuthMode) -> Router {
+    builder(provider)
+        .auth_mode(mode)
+        .signing_secret(ApiToken {
+            token: SECRET.to_string(),
+            allowed_hostnames: None,
+        })
+        .signature_max_skew(SignedDuration::from_secs(60))
+        .build()
+        .unwrap()
+        .router()
+}
+
+/// An update of `nas.foobar.de` to `myip`, signed with [`SECRET`] at `ts`.
+fn signed(myip: &str, ts: i64) -> Request<Body> {
+    let sig = sign(SECRET, "nas.foobar.de", myip, &ts.to_string());
+    request(&format!(
+        "/nic/update?hostname=nas.foobar.de&myip={myip}&ts={ts}&sig={sig}"
+    ))
+    .body(Body::empty())
+    .unwrap()
+}
+
+fn now() -> i64 {
+    Timestamp::now().as_second()
+}
+
+#[tokio::test]
+async fn signed_update_is_applied() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = signed_router(&provider, AuthMode::Signed);
+
+    let response = send(&router, signed("198.51.100.7", now())).await;
+
+    assert_eq!(response.body, "good 198.51.100.7");
+    assert_eq!(content(&provider, "a").as_deref(), Some("198.51.100.7"));
+}
+
+#[tokio::test]
+async fn replayed_update_is_rejected() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = signed_router(&provider, AuthMode::Signed);
+    let ts = now();
+
+    assert_eq!(
+        send(&router, signed("198.51.100.7", ts)).await.body,
+        "good 198.51.100.7"
+    );
+    assert_eq!(
+        send(&router, signed("198.51.100.7", ts)).await.body,
+        "badauth"
+    );
+}
+
+#[tokio::test]
+async fn stale_update_is_rejected() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = signed_router(&provider, AuthMode::Signed);
+
+    for ts in [now() - 120, now() + 120] {
+        let response = send(&router, signed("198.51.100.7", ts)).await;
+
+        assert_eq!(response.body, "badauth", "ts {ts}");
+    }
+    assert_eq!(content(&provider, "a").as_deref(), Some("192.0.2.1"));
+}
+
+#[tokio::test]
+async fn tampered_update_is_rejected() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = signed_router(&provider, AuthMode::Signed);
+    let ts = now();
+    let sig = sign(SECRET, "nas.foobar.de", "198.51.100.7", &ts.to_string());
+    let request = request(&format!(
+        "/nic/update?hostname=nas.foobar.de&myip=198.51.100.8&ts={ts}&sig={sig}"
+    ))
+    .body(Body::empty())
+    .unwrap();
+
+    let response = send(&router, request).await;
+
+    assert_eq!(response.body, "badauth");
+    assert_eq!(content(&provider, "a").as_deref(), Some("192.0.2.1"));
+}
+
+#[tokio::test]
+async fn signed_mode_rejects_passwords_without_a_challenge() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = signed_router(&provider, AuthMode::Signed);
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+
+    assert_eq!(response.body, "badauth");
+    assert!(!response.headers.contains_key(header::WWW_AUTHENTICATE));
+}
+
+#[tokio::test]
+async fn both_mode_accepts_passwords_and_signatures() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = signed_router(&provider, AuthMode::Both);
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+    assert_eq!(response.body, "good 198.51.100.7");
+
+    let response = send(&router, signed("198.51.100.8", now())).await;
+    assert_eq!(response.body, "good 198.51.100.8");
+}
@@ -0,0 +1,148 @@
+#![allow(unused_crate_dependencies)]
+
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::StatusCode;
+use common::{builder, content, nas_records, request, send, update};
+use speedport_custom_dyndns::provider::memory::MemoryProvider;
+use speedport_custom_dyndns::{StartupValidation, ValidationRetry, ValidationState};
+use std::sync::Arc;
+use std::time::Duration;
+
+async fn readyz(router: &Router) -> StatusCode {
+    send(router, request("/readyz").body(Body::empty()).unwrap())
+        .await
+        .status
+}
+
+async fn validation_metric(router: &Router) -> String {
+    let metrics = send(router, request("/metrics").body(Body::empty()).unwrap()).await;
+    metrics
+        .body
+        .lines()
+        .find_map(|line| line.strip_prefix("dyndns_provider_validation_succeeded "))
+        .unwrap()
+        .to_string()
+}
+
+#[tokio::test(start_paused = true)]
+async fn warn_mode_keeps_running_and_retries_in_the_background() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    provider.fail_times(1);
+    let server = builder(&provider).build().unwrap();
+    let router = server.router();
+
+    server
+        .startup_validation(StartupValidation::Warn, ValidationRetry::default())
+        .await
+        .unwrap();
+    assert_eq!(server.validation(), ValidationState::Failed);
+    assert_eq!(readyz(&router).await, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(validation_metric(&router).await, "0");
+
+    // The background retry runs after five seconds
+    tokio::time::sleep(Duration::from_secs(6)).await;
+    assert_eq!(server.validation(), ValidationState::Succeeded);
+    assert_eq!(readyz(&router).await, StatusCode::OK);
+    assert_eq!(validation_metric(&router).await, "1");
+}
+
+#[tokio::test]
+async fn warn_mode_is_ready_after_a_successful_validation() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let server = builder(&provider).build().unwrap();
+
+    server
+        .startup_validation(StartupValidation::Warn, ValidationRetry::default())
+        .await
+        .unwrap();
+    assert_eq!(readyz(&server.router()).await, StatusCode::OK);
+    assert_eq!(validation_metric(&server.router()).await, "1");
+}
+
+#[tokio::test]
+async fn off_mode_skips_validation() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    provider.fail(true);
+    let server = builder(&provider).build().unwrap();
+
+    server
+        .startup_validation(StartupValidation::Off, ValidationRetry::default())
+        .await
+        .unwrap();
+    assert_eq!(server.validation(), ValidationState::NotValidated);
+    assert_eq!(readyz(&server.router()).await, StatusCode::OK);
+    assert_eq!(validation_metric(&server.router()).await, "0");
+}
+
+fn retry(attempts: u32) -> ValidationRetry {
+    ValidationRetry {
+        attempts,
+        initial_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(3),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn strict_mode_retries_transient_failures() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    provider.fail_times(3);
+    let server = builder(&provider).build().unwrap();
+
+    let start = tokio::time::Instant::now();
+    server
+        .startup_validation(StartupValidation::Strict, retry(5))
+        .await
+        .unwrap();
+    // Backs off for 1, 2 and then the capped 3 seconds
+    assert_eq!(start.elapsed(), Duration::from_secs(6));
+    assert_eq!(server.validation(), ValidationState::Succeeded);
+    assert_eq!(readyz(&server.router()).await, StatusCode::OK);
+}
+
+#[tokio::test(start_paused = true)]
+async fn strict_mode_gives_up_after_the_last_attempt() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    provider.fail(true);
+    let server = builder(&provider).build().unwrap();
+
+    let start = tokio::time::Instant::now();
+    let result = server
+        .startup_validation(StartupValidation::Strict, retry(3))
+        .await;
+    assert!(result.is_err());
+    assert_eq!(start.elapsed(), Duration::from_secs(3));
+    assert_eq!(server.validation(), ValidationState::Failed);
+    assert_eq!(
+        readyz(&server.router()).await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn updates_are_rejected_while_strict_validation_retries() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    provider.fail_times(1);
+    let server = builder(&provider).build().unwrap();
+    let router = server.router();
+
+    let validation = tokio::spawn({
+        let server = server.clone();
+        async move {
+            server
+                .startup_validation(StartupValidation::Strict, retry(5))
+                .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(server.validation(), ValidationState::Pending);
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+    assert!(response.body.starts_with("911"), "{}", response.body);
+    assert_eq!(content(&provider, "a").as_deref(), Some("192.0.2.1"));
+
+    validation.await.unwrap().unwrap();
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+    assert_eq!(response.body, "good 198.51.100.7");
+}
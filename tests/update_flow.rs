@@ -0,0 +1,427 @@
+//! End-to-end tests of the update endpoint: routing, the auth middleware and the update handler
+//! against the in-memory provider.
+
+#![allow(unused_crate_dependencies)]
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{StatusCode, header};
+use common::*;
+use speedport_custom_dyndns::HostnameConfig;
+use speedport_custom_dyndns::provider::memory::MemoryProvider;
+use speedport_custom_dyndns::{DnsEntry, DnsRecordType, RecordId};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn correct_password_updates_the_record() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "good 198.51.100.7");
+    assert_eq!(content(&provider, "a").as_deref(), Some("198.51.100.7"));
+    assert_eq!(content(&provider, "aaaa").as_deref(), Some("2001:db8::1"));
+}
+
+#[tokio::test]
+async fn unchanged_address_is_nochg() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=192.0.2.1")).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "nochg 192.0.2.1");
+}
+
+#[tokio::test]
+async fn hostname_is_canonicalized() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+
+    let response = send(&router, update("hostname=NAS.Foobar.de.&myip=198.51.100.7")).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "good 198.51.100.7");
+    assert_eq!(content(&provider, "a").as_deref(), Some("198.51.100.7"));
+}
+
+#[tokio::test]
+async fn invalid_hostname_is_nohost() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+
+    let response = send(&router, update("hostname=nas..foobar.de&myip=198.51.100.7")).await;
+
+    assert!(response.body.starts_with("nohost"), "{}", response.body);
+    assert_eq!(provider.records(), nas_records());
+}
+
+#[tokio::test]
+async fn wrong_password_is_badauth() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+
+    let request = request("/nic/update?hostname=nas.foobar.de&myip=198.51.100.7")
+        .header(header::AUTHORIZATION, basic_auth("router", "wrong"))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&router, request).await;
+
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(response.body, "badauth");
+    assert!(response.headers.contains_key(header::WWW_AUTHENTICATE));
+    assert_eq!(provider.records(), nas_records());
+}
+
+#[tokio::test]
+async fn missing_credentials_are_badauth() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+
+    let request = request("/nic/update?hostname=nas.foobar.de&myip=198.51.100.7")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&router, request).await;
+
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(response.body, "badauth");
+    assert_eq!(provider.records(), nas_records());
+}
+
+#[tokio::test]
+async fn hostname_outside_the_origin_is_rejected() {
+    let mut records = nas_records();
+    records.push(record(
+        "other",
+        DnsRecordType::A,
+        "nas.example.org",
+        "192.0.2.9",
+    ));
+    let provider = Arc::new(MemoryProvider::new(records.clone()));
+    let router = router(&provider);
+
+    let response = send(
+        &router,
+        update("hostname=nas.example.org&myip=198.51.100.7"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert!(response.body.starts_with("nohost"), "{}", response.body);
+    assert_eq!(provider.records(), records);
+}
+
+#[tokio::test]
+async fn dual_stack_update_changes_both_records() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+
+    let response = send(
+        &router,
+        update("hostname=nas.foobar.de&myip=198.51.100.7,2001:db8::7"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "good 198.51.100.7\ngood 2001:db8::7");
+    assert_eq!(content(&provider, "a").as_deref(), Some("198.51.100.7"));
+    assert_eq!(content(&provider, "aaaa").as_deref(), Some("2001:db8::7"));
+}
+
+#[tokio::test]
+async fn provider_failure_is_a_server_error() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+    provider.fail(true);
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+
+    assert!(response.status.is_server_error(), "{}", response.status);
+    assert!(response.body.starts_with("911"), "{}", response.body);
+    provider.fail(false);
+    assert_eq!(provider.records(), nas_records());
+}
+
+fn duplicate_records() -> Vec<DnsEntry> {
+    vec![
+        record("a-1", DnsRecordType::A, "nas.foobar.de", "192.0.2.1"),
+        record("a-2", DnsRecordType::A, "nas.foobar.de", "192.0.2.1"),
+        record("a-3", DnsRecordType::A, "nas.foobar.de", "192.0.2.2"),
+    ]
+}
+
+#[tokio::test]
+async fn duplicates_are_deleted_with_dedupe_records() {
+    let provider = Arc::new(MemoryProvider::new(duplicate_records()));
+    let router = builder(&provider)
+        .dedupe_records(true)
+        .build()
+        .unwrap()
+        .router();
+    let (logs, _guard) = capture_logs();
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, "good 198.51.100.7");
+    let remaining = provider.records();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].id.0, "a-1");
+    assert_eq!(remaining[0].content, "198.51.100.7");
+    let logs = logs.contents();
+    assert_eq!(
+        logs.matches("Deleted duplicate record").count(),
+        2,
+        "{logs}"
+    );
+    assert!(logs.contains("record_id=a-2"), "{logs}");
+    assert!(logs.contains("record_id=a-3"), "{logs}");
+}
+
+#[tokio::test]
+async fn duplicates_are_kept_by_default() {
+    let provider = Arc::new(MemoryProvider::new(duplicate_records()));
+    let router = router(&provider);
+    let (logs, _guard) = capture_logs();
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=198.51.100.7")).await;
+
+    assert_eq!(response.body, "good 198.51.100.7");
+    assert_eq!(content(&provider, "a-1").as_deref(), Some("198.51.100.7"));
+    assert_eq!(content(&provider, "a-2").as_deref(), Some("192.0.2.1"));
+    assert_eq!(content(&provider, "a-3").as_deref(), Some("192.0.2.2"));
+    let logs = logs.contents();
+    assert!(logs.contains("Found duplicate records"), "{logs}");
+    assert!(!logs.contains("Deleted duplicate record"), "{logs}");
+}
+
+#[tokio::test]
+async fn differing_ttl_is_written_even_if_the_address_matches() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let config = HostnameConfig {
+        ttl: Some(60),
+        ..HostnameConfig::default()
+    };
+    let router = builder(&provider)
+        .hostname("nas.foobar.de", config)
+        .build()
+        .unwrap()
+        .router();
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=192.0.2.1")).await;
+    assert_eq!(response.body, "good 192.0.2.1");
+    let record = provider.records().into_iter().find(|it| it.id.0 == "a");
+    assert_eq!(record.unwrap().ttl, Some(60));
+
+    let response = send(&router, update("hostname=nas.foobar.de&myip=192.0.2.1")).await;
+    assert_eq!(response.body, "nochg 192.0.2.1");
+}
+
+#[tokio::test]
+async fn prefix_without_suffix_is_nohost() {
+    let provider = Arc::new(MemoryProvider::new(nas_records()));
+    let router = router(&provider);
+
+    let response = send(
+        &router,
+        update("hostname=nas.foobar.de&myip=2001:db8:1200::/56"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        response.body,
+        "nohost\nno record of 'nas.foobar.de' matches the given addresses"
+    );
+    assert_eq!(provider.records(), nas_records());
+}
+
+#[tokio::test]
+async fn prefix_fans_out_to_hosts_with_a_suffix() {
+    let provider = Arc::new(MemoryProvider::new(vec![
+        record(
+            "nas",
+            DnsRecordType::AAAA,
+            "nas.foobar.de",
+            "2001:db8:1100::1",
+        ),
+        record(
+            "tv",
+            DnsRecordType::AAAA,
+            "tv.foobar.de",
+            "2001:db8:1200::2",
+        ),
+    ]));
+    let suffix = |suffix: &str| HostnameConfig {
+        suffix: Some(suffix.parse().unwrap()),
+        ..HostnameConfig::default()
+    };
+    let router = builder(&provider)
+        .prefix_fan_out(true)
+        .hostname("nas.foobar.de", suffix("::1"))
+        .hostname("tv.foobar.de", suffix("::2"))
+        .build()
+        .unwrap()
+        .router();
+
+    let response = send(
+        &router,
+        update("hostname=router.foobar.de&myip=2001:db8:1200::/56"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.body,
+        "good 2001:db8:1200::1\nnochg 2001:db8:1200::2"
+    );
+    assert_eq!(
+        content(&provider, "nas").as_deref(),
+        Some("2001:db8:1200::1")
+    );
+}
+
+/// The router itself and two hosts in `2001:db8:1100::/56`, and one host outside of it.
+fn prefix_records() -> Vec<DnsEntry> {
+    vec![
+        record(
+            "router-a",
+            DnsRecordType::A,
+            "router.foobar.de",
+            "192.0.2.1",
+        ),
+        record(
+            "router",
+            DnsRecordType::AAAA,
+            "router.foobar.de",
+            "2001:db8:1100::1",
+        ),
+        record(
+            "nas",
+            DnsRecordType::AAAA,
+            "nas.foobar.de",
+            "2001:db8:1100:1::7",
+        ),
+        record(
+            "tv",
+            DnsRecordType::AAAA,
+            "tv.foobar.de",
+            "2001:db8:1100:2::8",
+        ),
+        record(
+            "vps",
+            DnsRecordType::AAAA,
+            "vps.foobar.de",
+            "2001:db8:9900::5",
+        ),
+    ]
+}
+
+const PREFIX_UPDATE: &str =
+    "hostname=router.foobar.de&myip=192.0.2.9&ip6lanprefix=2001:db8:3400::/56";
+
+#[tokio::test]
+async fn prefix_rewrite_reports_every_rewritten_record() {
+    let provider = Arc::new(MemoryProvider::new(prefix_records()));
+    let router = builder(&provider)
+        .prefix_rewrite(true)
+        .build()
+        .unwrap()
+        .router();
+
+    let response = send(&router, update(PREFIX_UPDATE)).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.body,
+        "good 192.0.2.9\ngood 2001:db8:3400::1\ngood 2001:db8:3400:1::7\ngood 2001:db8:3400:2::8"
+    );
+    assert_eq!(
+        content(&provider, "nas").as_deref(),
+        Some("2001:db8:3400:1::7")
+    );
+    assert_eq!(
+        content(&provider, "vps").as_deref(),
+        Some("2001:db8:9900::5")
+    );
+
+    // The old prefix is gone, so repeating the update rewrites nothing
+    let response = send(&router, update(PREFIX_UPDATE)).await;
+    assert_eq!(response.body, "nochg 192.0.2.9");
+}
+
+#[tokio::test]
+async fn failed_prefix_rewrite_keeps_rewriting_the_others() {
+    let provider = Arc::new(MemoryProvider::new(prefix_records()));
+    provider.fail_record(RecordId("nas".to_string()));
+    let router = builder(&provider)
+        .prefix_rewrite(true)
+        .build()
+        .unwrap()
+        .router();
+
+    let response = send(&router, update(PREFIX_UPDATE)).await;
+
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    let lines = response.body.lines().collect::<Vec<_>>();
+    assert_eq!(
+        lines[..4],
+        [
+            "good 192.0.2.9",
+            "good 2001:db8:3400::1",
+            "good 2001:db8:3400:2::8",
+            "911"
+        ]
+    );
+    assert!(
+        response
+            .body
+            .contains("Failed to rewrite record to the new prefix"),
+        "{}",
+        response.body
+    );
+    assert!(
+        response.body.contains("For domain 'nas.foobar.de'"),
+        "{}",
+        response.body
+    );
+    assert_eq!(content(&provider, "router-a").as_deref(), Some("192.0.2.9"));
+    assert_eq!(
+        content(&provider, "nas").as_deref(),
+        Some("2001:db8:1100:1::7")
+    );
+    assert_eq!(
+        content(&provider, "tv").as_deref(),
+        Some("2001:db8:3400:2::8")
+    );
+}
+
+#[tokio::test]
+async fn unreadable_zone_does_not_block_the_update() {
+    let provider = Arc::new(MemoryProvider::new(prefix_records()));
+    let router = builder(&provider)
+        .prefix_rewrite(true)
+        .build()
+        .unwrap()
+        .router();
+    // Only listing the zone for the rewrite fails
+    provider.fail_times(1);
+
+    let response = send(&router, update(PREFIX_UPDATE)).await;
+
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(
+        response.body.starts_with("good 192.0.2.9\n911\n"),
+        "{}",
+        response.body
+    );
+    assert_eq!(content(&provider, "router-a").as_deref(), Some("192.0.2.9"));
+    assert_eq!(
+        content(&provider, "nas").as_deref(),
+        Some("2001:db8:1100:1::7")
+    );
+}